@@ -1,7 +1,7 @@
 use crate::math::*;
 use crate::ray::*;
-use crate::hittable::*;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone)]
 pub struct AABB {
     pub minimum: Point3,
@@ -32,31 +32,13 @@ impl AABB {
         AABB::new(small, big)
     }
 
-    fn box_compare(a: &Hittable, b: &Hittable, axis: i32) -> std::cmp::Ordering {
-        if let (Some(box_a), Some(box_b)) = (a.bounding_box(0.0, 0.0), b.bounding_box(0.0, 0.0)) {
-            let a_min = box_a.minimum.as_array();
-            let b_min = box_b.minimum.as_array();
-
-            if a_min[axis as usize] < b_min[axis as usize] { 
-                std::cmp::Ordering::Less 
-            } else {
-                std::cmp::Ordering::Greater
-            }
-        } else {
-            std::cmp::Ordering::Equal
-        }
-    }
-
-    pub fn box_x_compare(a: &Hittable, b: &Hittable) -> std::cmp::Ordering {
-        Self::box_compare(a, b, 0)
-    }
-
-    pub fn box_y_compare(a: &Hittable, b: &Hittable) -> std::cmp::Ordering {
-        Self::box_compare(a, b, 1)
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.minimum + self.maximum)
     }
 
-    pub fn box_z_compare(a: &Hittable, b: &Hittable) -> std::cmp::Ordering {
-        Self::box_compare(a, b, 2)
+    pub fn surface_area(&self) -> f64 {
+        let extent = self.maximum - self.minimum;
+        2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
     }
 
     #[allow(dead_code)]
@@ -77,15 +59,11 @@ impl AABB {
     pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
         let mut min = t_min;
         let mut max = t_max;
-        let minimum = self.minimum.as_array();
-        let maximum = self.maximum.as_array();
-        let ray_origin = ray.origin.as_array();
-        let ray_direction = ray.direction.as_array();
 
         for a in 0..3 {
-            let inv_d = 1.0 / ray_direction[a];
-            let mut t0 = (minimum[a] - ray_origin[a]) * inv_d;
-            let mut t1 = (maximum[a] - ray_origin[a]) * inv_d;
+            let inv_d = 1.0 / ray.direction[a];
+            let mut t0 = (self.minimum[a] - ray.origin[a]) * inv_d;
+            let mut t1 = (self.maximum[a] - ray.origin[a]) * inv_d;
 
             if inv_d < 0.0 {
                 std::mem::swap(&mut t0, &mut t1);
@@ -102,3 +80,85 @@ impl AABB {
         true
     }
 }
+
+// Four boxes stored coordinate by coordinate, so that a ray can be tested against all of them at
+// once. On an x86_64 CPU with AVX the four slab tests run in parallel.
+#[cfg(feature = "simd")]
+#[derive(Copy, Clone, Default)]
+pub struct AABB4 {
+    minimum: [[f64; 4]; 3],
+    maximum: [[f64; 4]; 3]
+}
+
+#[cfg(feature = "simd")]
+impl AABB4 {
+    pub fn set(&mut self, lane: usize, aabb: &AABB) {
+        for axis in 0..3 {
+            self.minimum[axis][lane] = aabb.minimum[axis];
+            self.maximum[axis][lane] = aabb.maximum[axis];
+        }
+    }
+
+    // Distance at which the ray enters each box, or infinity for the boxes it misses
+    pub fn hit(&self, origin: &Point3, inv_direction: &Vector3, t_min: f64, t_max: f64) -> [f64; 4] {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx") {
+                return unsafe { self.hit_avx(origin, inv_direction, t_min, t_max) };
+            }
+        }
+
+        self.hit_scalar(origin, inv_direction, t_min, t_max)
+    }
+
+    fn hit_scalar(&self, origin: &Point3, inv_direction: &Vector3, t_min: f64, t_max: f64) -> [f64; 4] {
+        let mut min = [t_min; 4];
+        let mut max = [t_max; 4];
+
+        for axis in 0..3 {
+            for lane in 0..4 {
+                let t0 = (self.minimum[axis][lane] - origin[axis]) * inv_direction[axis];
+                let t1 = (self.maximum[axis][lane] - origin[axis]) * inv_direction[axis];
+                let (near, far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+                min[lane] = if near > min[lane] { near } else { min[lane] };
+                max[lane] = if far < max[lane] { far } else { max[lane] };
+            }
+        }
+
+        let mut distances = [INFINITY; 4];
+        for lane in 0..4 {
+            if min[lane] < max[lane] {
+                distances[lane] = min[lane];
+            }
+        }
+
+        distances
+    }
+
+    // Same as the scalar version. The running bounds are passed as the second operand of max and
+    // min, which the instructions return when the other one is NaN, so a NaN slab leaves them as is.
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx")]
+    unsafe fn hit_avx(&self, origin: &Point3, inv_direction: &Vector3, t_min: f64, t_max: f64) -> [f64; 4] {
+        use std::arch::x86_64::*;
+
+        let mut min = _mm256_set1_pd(t_min);
+        let mut max = _mm256_set1_pd(t_max);
+
+        for axis in 0..3 {
+            let o = _mm256_set1_pd(origin[axis]);
+            let inv_d = _mm256_set1_pd(inv_direction[axis]);
+            let t0 = _mm256_mul_pd(_mm256_sub_pd(_mm256_loadu_pd(self.minimum[axis].as_ptr()), o), inv_d);
+            let t1 = _mm256_mul_pd(_mm256_sub_pd(_mm256_loadu_pd(self.maximum[axis].as_ptr()), o), inv_d);
+            min = _mm256_max_pd(_mm256_min_pd(t0, t1), min);
+            max = _mm256_min_pd(_mm256_max_pd(t0, t1), max);
+        }
+
+        let hit = _mm256_cmp_pd::<_CMP_LT_OQ>(min, max);
+        let distances = _mm256_blendv_pd(_mm256_set1_pd(INFINITY), min, hit);
+
+        let mut result = [0.0; 4];
+        _mm256_storeu_pd(result.as_mut_ptr(), distances);
+        result
+    }
+}
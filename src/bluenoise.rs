@@ -0,0 +1,111 @@
+use crate::sampler::hash;
+use std::sync::OnceLock;
+
+// Side of the square blue noise mask, which repeats across the image
+pub const MASK_SIZE: usize = 64;
+
+// Spread of the gaussian used to find clusters and voids, in pixels
+const SIGMA: f64 = 1.5;
+
+static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+
+// Blue noise value in [0, 1) for a pixel. Neighboring pixels get very different values, so errors that
+// depend on them look like fine grain instead of blotches.
+pub fn blue_noise(x: usize, y: usize) -> f64 {
+    let mask = MASK.get_or_init(generate_mask);
+    mask[(y % MASK_SIZE) * MASK_SIZE + x % MASK_SIZE]
+}
+
+// Ulichney's void and cluster method. Every pixel gets a rank by the order it is added to a pattern
+// that keeps its points as evenly spread as possible, and the ranks become the values of the mask.
+fn generate_mask() -> Vec<f64> {
+    let count = MASK_SIZE * MASK_SIZE;
+    let kernel = gaussian_kernel();
+
+    // A sparse pseudo random starting pattern, always the same so renders are repeatable
+    let mut pattern: Vec<bool> = (0..count).map(|i| hash(i as u32).is_multiple_of(10)).collect();
+    let mut energy = vec![0.0; count];
+    for (i, _) in pattern.iter().enumerate().filter(|(_, set)| **set) {
+        splat(&mut energy, &kernel, i, 1.0);
+    }
+
+    // Move points from the tightest cluster to the largest void until the pattern settles
+    loop {
+        let cluster = tightest_cluster(&pattern, &energy);
+        pattern[cluster] = false;
+        splat(&mut energy, &kernel, cluster, -1.0);
+
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        splat(&mut energy, &kernel, void, 1.0);
+
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; count];
+    let initial_pattern = pattern.clone();
+    let initial_energy = energy.clone();
+    let point_count = pattern.iter().filter(|set| **set).count();
+
+    // The points of the starting pattern are ranked by taking them out, tightest cluster first
+    for rank in (0..point_count).rev() {
+        let cluster = tightest_cluster(&pattern, &energy);
+        pattern[cluster] = false;
+        splat(&mut energy, &kernel, cluster, -1.0);
+        ranks[cluster] = rank;
+    }
+
+    // The others by filling the largest void until every pixel is set
+    pattern = initial_pattern;
+    energy = initial_energy;
+    for rank in point_count..count {
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        splat(&mut energy, &kernel, void, 1.0);
+        ranks[void] = rank;
+    }
+
+    ranks.iter().map(|rank| (*rank as f64 + 0.5) / count as f64).collect()
+}
+
+// Gaussian falloff for every offset on the wrapping mask
+fn gaussian_kernel() -> Vec<f64> {
+    let mut kernel = vec![0.0; MASK_SIZE * MASK_SIZE];
+    for dy in 0..MASK_SIZE {
+        for dx in 0..MASK_SIZE {
+            let x = usize::min(dx, MASK_SIZE - dx) as f64;
+            let y = usize::min(dy, MASK_SIZE - dy) as f64;
+            kernel[dy * MASK_SIZE + dx] = (-(x * x + y * y) / (2.0 * SIGMA * SIGMA)).exp();
+        }
+    }
+
+    kernel
+}
+
+// Adds or removes the energy of a point at the given index
+fn splat(energy: &mut [f64], kernel: &[f64], index: usize, sign: f64) {
+    let (px, py) = (index % MASK_SIZE, index / MASK_SIZE);
+    for y in 0..MASK_SIZE {
+        for x in 0..MASK_SIZE {
+            let dx = (x + MASK_SIZE - px) % MASK_SIZE;
+            let dy = (y + MASK_SIZE - py) % MASK_SIZE;
+            energy[y * MASK_SIZE + x] += sign * kernel[dy * MASK_SIZE + dx];
+        }
+    }
+}
+
+fn tightest_cluster(pattern: &[bool], energy: &[f64]) -> usize {
+    (0..pattern.len())
+        .filter(|i| pattern[*i])
+        .max_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+        .expect("Pattern has no points")
+}
+
+fn largest_void(pattern: &[bool], energy: &[f64]) -> usize {
+    (0..pattern.len())
+        .filter(|i| !pattern[*i])
+        .min_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+        .expect("Pattern is full")
+}
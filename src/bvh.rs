@@ -0,0 +1,468 @@
+use crate::math::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::aabb::*;
+
+// Deepest tree the builder makes. Below half of it the builder stops trusting the surface area
+// heuristic and splits at the median, which bounds the depth for any object count.
+const MAX_DEPTH: usize = 64;
+
+// Every node visited during traversal pushes at most four children and pops itself, and collapsing
+// the binary tree into a wide one never makes it deeper
+const STACK_SIZE: usize = 3 * MAX_DEPTH + 1;
+
+// Number of buckets the centroids are binned into along each axis when looking for a split
+const SAH_BINS: usize = 16;
+
+// Relative costs of visiting a node and of intersecting an object, and the largest leaf that is
+// worth making instead of splitting further
+const TRAVERSAL_COST: f64 = 0.5;
+const INTERSECTION_COST: f64 = 1.0;
+const MAX_LEAF_SIZE: usize = 4;
+
+// Node of a BVH flattened into an array in depth first order. The first child of an interior node
+// directly follows it, so only the offset of the second child is stored.
+#[derive(Copy, Clone)]
+struct BvhNode {
+    aabb: AABB,
+    offset: usize,  // Index of the first object for leaves, of the second child for interior nodes
+    count: usize,   // Number of objects in a leaf, zero for interior nodes
+    #[cfg_attr(feature = "simd", allow(dead_code))]
+    axis: usize     // Axis an interior node was split along, to visit the nearer child first
+}
+
+// Node of a 4-wide tree made by collapsing levels of the binary one, so that a ray is tested
+// against all the child boxes of a node at once. Only worth it with SIMD box tests, a scalar
+// traversal of the binary tree is faster.
+#[cfg(feature = "simd")]
+#[derive(Copy, Clone)]
+struct WideNode {
+    boxes: AABB4,
+    offsets: [usize; 4],    // Index of the first object for leaves, of the child node otherwise
+    counts: [usize; 4],     // Number of objects in a leaf child, zero for interior children
+    len: usize              // Number of children in use, the remaining boxes are ignored
+}
+
+// Nodes over a list of boxes, telling which of them a ray may hit. Shared by the BVH over hittables
+// and the ones meshes build over their triangles, which store what the boxes stand for themselves.
+#[derive(Clone, Default)]
+pub struct BvhTree {
+    nodes: Vec<BvhNode>,
+    #[cfg(feature = "simd")]
+    wide_nodes: Vec<WideNode>,
+    order: Vec<usize>   // Position in the list of boxes of each entry the leaves refer to
+}
+
+// Objects without a bounding box cannot be placed in the tree, so they are kept after the ones the
+// nodes refer to and tested against every ray
+#[derive(Clone, Default)]
+pub struct Bvh {
+    tree: BvhTree,
+    objects: Vec<Hittable>,
+    indices: Vec<usize>,    // Position of each object in the list the BVH was built from
+    bounded_count: usize
+}
+
+// Bounds of an object computed once before building, so the builder does not keep asking for them
+#[derive(Copy, Clone)]
+struct BuildObject {
+    index: usize,
+    aabb: AABB,
+    centroid: Point3
+}
+
+#[derive(Copy, Clone)]
+struct Bin {
+    aabb: Option<AABB>,
+    count: usize
+}
+
+impl Bvh {
+    pub fn build(hittables: &[Hittable], time_0: f64, time_1: f64) -> Bvh {
+        let mut boxes = Vec::with_capacity(hittables.len());
+        let mut bounded = Vec::with_capacity(hittables.len());
+        let mut unbounded = Vec::new();
+        for (index, hittable) in hittables.iter().enumerate() {
+            match hittable.bounding_box(time_0, time_1) {
+                Some(aabb) => {
+                    boxes.push(aabb);
+                    bounded.push(index);
+                },
+                None => unbounded.push(index)
+            }
+        }
+
+        let tree = BvhTree::build(&boxes);
+        let indices: Vec<usize> = tree.order().iter().map(|&i| bounded[i]).chain(unbounded).collect();
+        let objects = indices.iter().map(|&index| hittables[index].clone()).collect();
+
+        Bvh { tree, objects, indices, bounded_count: boxes.len() }
+    }
+
+    // Finds the closest hit, with the object ID set to the position of the object in the list the
+    // BVH was built from
+    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut closest_so_far = t_max;
+        let mut rec = None;
+
+        for i in self.bounded_count..self.objects.len() {
+            self.hit_object(i, ray, t_min, &mut closest_so_far, &mut rec);
+        }
+
+        self.tree.hit(ray, t_min, &mut closest_so_far, |i, closest_so_far| self.hit_object(i, ray, t_min, closest_so_far, &mut rec));
+
+        rec
+    }
+
+    fn hit_object(&self, i: usize, ray: &Ray, t_min: f64, closest_so_far: &mut f64, rec: &mut Option<HitRecord>) {
+        if let Some(mut record) = self.objects[i].hit(ray, t_min, *closest_so_far) {
+            *closest_so_far = record.t;
+            record.object_id = self.indices[i];
+            *rec = Some(record);
+        }
+    }
+
+    pub fn bounding_box(&self) -> Option<AABB> {
+        if self.bounded_count < self.objects.len() {
+            return None;
+        }
+        self.tree.bounding_box()
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.tree.node_count()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.tree.depth()
+    }
+
+    pub fn objects(&self) -> &[Hittable] {
+        &self.objects
+    }
+}
+
+impl BvhTree {
+    pub fn build(boxes: &[AABB]) -> BvhTree {
+        let mut tree = BvhTree {
+            nodes: Vec::with_capacity(2 * boxes.len()),
+            #[cfg(feature = "simd")]
+            wide_nodes: Vec::new(),
+            order: Vec::with_capacity(boxes.len())
+        };
+
+        let mut objects: Vec<BuildObject> = boxes.iter()
+            .enumerate()
+            .map(|(index, aabb)| BuildObject { index, aabb: *aabb, centroid: aabb.centroid() })
+            .collect();
+
+        if !objects.is_empty() {
+            tree.build_node(&mut objects, 1);
+
+            #[cfg(feature = "simd")]
+            tree.collapse(0);
+        }
+
+        tree
+    }
+
+    // Appends the node for the given objects and everything below it, and returns its index
+    fn build_node(&mut self, objects: &mut [BuildObject], depth: usize) -> usize {
+        let index = self.nodes.len();
+        let aabb = objects.iter().skip(1).fold(objects[0].aabb, |aabb, object| AABB::surrounding_box(&aabb, &object.aabb));
+
+        let split = if objects.len() == 1 {
+            None
+        } else if depth < MAX_DEPTH / 2 {
+            sah_split(objects, &aabb)
+        } else {
+            Some(median_split(objects))
+        };
+
+        let (axis, mid) = match split {
+            Some(split) => split,
+            None => {
+                self.nodes.push(BvhNode { aabb, offset: self.order.len(), count: objects.len(), axis: 0 });
+                self.order.extend(objects.iter().map(|object| object.index));
+                return index;
+            }
+        };
+
+        // The offset of the second child is only known once the first one is built
+        self.nodes.push(BvhNode { aabb, offset: 0, count: 0, axis });
+
+        let (left, right) = objects.split_at_mut(mid);
+        self.build_node(left, depth + 1);
+        self.nodes[index].offset = self.build_node(right, depth + 1);
+
+        index
+    }
+
+    // Appends the wide node replacing the given binary node and the levels below it, and returns its
+    // index. Interior children with the largest surface area are opened up first, since they are the
+    // ones rays are most likely to visit.
+    #[cfg(feature = "simd")]
+    fn collapse(&mut self, index: usize) -> usize {
+        let nodes = &self.nodes;
+        let mut children = if nodes[index].count > 0 {
+            vec![index]
+        } else {
+            vec![index + 1, nodes[index].offset]
+        };
+
+        while children.len() < 4 {
+            let largest = children.iter()
+                .enumerate()
+                .filter(|(_, &child)| nodes[child].count == 0)
+                .max_by(|(_, &a), (_, &b)| nodes[a].aabb.surface_area().partial_cmp(&nodes[b].aabb.surface_area()).unwrap_or(std::cmp::Ordering::Equal))
+                .map(|(slot, _)| slot);
+
+            match largest {
+                Some(slot) => {
+                    let child = children.swap_remove(slot);
+                    children.push(child + 1);
+                    children.push(nodes[child].offset);
+                },
+                None => break
+            }
+        }
+
+        let wide_index = self.wide_nodes.len();
+        self.wide_nodes.push(WideNode { boxes: AABB4::default(), offsets: [0; 4], counts: [0; 4], len: children.len() });
+
+        for (slot, &child) in children.iter().enumerate() {
+            let node = self.nodes[child];
+            let offset = if node.count > 0 { node.offset } else { self.collapse(child) };
+
+            let wide_node = &mut self.wide_nodes[wide_index];
+            wide_node.boxes.set(slot, &node.aabb);
+            wide_node.offsets[slot] = offset;
+            wide_node.counts[slot] = node.count;
+        }
+
+        wide_index
+    }
+
+    // Calls hit_leaf with the position in the order of each entry whose box the ray may hit before
+    // the closest hit so far, which it updates when it finds a closer one
+    pub fn hit<F: FnMut(usize, &mut f64)>(&self, ray: &Ray, t_min: f64, closest_so_far: &mut f64, mut hit_leaf: F) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        #[cfg(feature = "simd")]
+        self.traverse_wide(ray, t_min, closest_so_far, &mut hit_leaf);
+
+        #[cfg(not(feature = "simd"))]
+        self.traverse(ray, t_min, closest_so_far, &mut hit_leaf);
+    }
+
+    #[cfg(not(feature = "simd"))]
+    fn traverse<F: FnMut(usize, &mut f64)>(&self, ray: &Ray, t_min: f64, closest_so_far: &mut f64, hit_leaf: &mut F) {
+        let mut stack = [0; STACK_SIZE];
+        let mut stack_size = 1;
+
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node = &self.nodes[stack[stack_size]];
+
+            if !node.aabb.hit(ray, t_min, *closest_so_far) {
+                continue;
+            }
+
+            if node.count > 0 {
+                for i in node.offset..node.offset + node.count {
+                    hit_leaf(i, closest_so_far);
+                }
+            } else {
+                // Push the farther child first so that the nearer one is popped next, which finds
+                // close hits early and lets them cull more of the tree
+                let first_child = stack[stack_size] + 1;
+                let (near, far) = if ray.direction[node.axis] < 0.0 {
+                    (node.offset, first_child)
+                } else {
+                    (first_child, node.offset)
+                };
+
+                stack[stack_size] = far;
+                stack[stack_size + 1] = near;
+                stack_size += 2;
+            }
+        }
+    }
+
+    #[cfg(feature = "simd")]
+    fn traverse_wide<F: FnMut(usize, &mut f64)>(&self, ray: &Ray, t_min: f64, closest_so_far: &mut f64, hit_leaf: &mut F) {
+        let inv_direction = Vector3::new(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+
+        let mut stack = [0; STACK_SIZE];
+        let mut stack_size = 1;
+
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node = &self.wide_nodes[stack[stack_size]];
+            let distances = node.boxes.hit(&ray.origin, &inv_direction, t_min, *closest_so_far);
+
+            // Sort the children that were hit from near to far
+            let mut order = [0; 4];
+            let mut hits = 0;
+            for slot in 0..node.len {
+                if distances[slot] < *closest_so_far {
+                    let mut i = hits;
+                    while i > 0 && distances[order[i - 1]] > distances[slot] {
+                        order[i] = order[i - 1];
+                        i -= 1;
+                    }
+                    order[i] = slot;
+                    hits += 1;
+                }
+            }
+
+            // Leaves are intersected right away, nearest first, and interior children are pushed
+            // farthest first so that the nearest one is popped next
+            for &slot in order[..hits].iter() {
+                if node.counts[slot] > 0 && distances[slot] < *closest_so_far {
+                    for i in node.offsets[slot]..node.offsets[slot] + node.counts[slot] {
+                        hit_leaf(i, closest_so_far);
+                    }
+                }
+            }
+
+            for &slot in order[..hits].iter().rev() {
+                if node.counts[slot] == 0 && distances[slot] < *closest_so_far {
+                    stack[stack_size] = node.offsets[slot];
+                    stack_size += 1;
+                }
+            }
+        }
+    }
+
+    // Position in the list of boxes the tree was built from of each entry the leaves refer to
+    pub fn order(&self) -> &[usize] {
+        &self.order
+    }
+
+    pub fn bounding_box(&self) -> Option<AABB> {
+        self.nodes.first().map(|node| node.aabb)
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn depth(&self) -> usize {
+        if self.nodes.is_empty() { 0 } else { self.node_depth(0) }
+    }
+
+    fn node_depth(&self, index: usize) -> usize {
+        let node = &self.nodes[index];
+        if node.count > 0 {
+            1
+        } else {
+            1 + usize::max(self.node_depth(index + 1), self.node_depth(node.offset))
+        }
+    }
+}
+
+// Bins the centroids along each axis and picks the bucket boundary with the lowest surface area
+// heuristic cost. Partitions the objects and returns the axis and the size of the first half, or
+// None if a leaf is cheaper than any split.
+fn sah_split(objects: &mut [BuildObject], aabb: &AABB) -> Option<(usize, usize)> {
+    let centroid_bounds = centroid_bounds(objects);
+
+    let leaf_cost = INTERSECTION_COST * objects.len() as f64;
+    let mut best: Option<(f64, usize, usize)> = None;
+
+    for axis in 0..3 {
+        let min = centroid_bounds.minimum[axis];
+        let extent = centroid_bounds.maximum[axis] - min;
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let mut bins = [Bin { aabb: None, count: 0 }; SAH_BINS];
+        for object in objects.iter() {
+            let bin = &mut bins[bin_index(object.centroid[axis], min, extent)];
+            bin.count += 1;
+            bin.aabb = Some(bin.aabb.map_or(object.aabb, |aabb| AABB::surrounding_box(&aabb, &object.aabb)));
+        }
+
+        // Sweep from the right to get the area and count of everything past each boundary, then
+        // from the left to evaluate the cost of splitting there
+        let mut right_area = [0.0; SAH_BINS];
+        let mut right_count = [0; SAH_BINS];
+        let mut right: Option<AABB> = None;
+        let mut count = 0;
+        for bin in (1..SAH_BINS).rev() {
+            count += bins[bin].count;
+            right = merge_boxes(right, bins[bin].aabb);
+            right_area[bin] = right.map_or(0.0, |aabb| aabb.surface_area());
+            right_count[bin] = count;
+        }
+
+        let mut left: Option<AABB> = None;
+        let mut count = 0;
+        for bin in 1..SAH_BINS {
+            count += bins[bin - 1].count;
+            left = merge_boxes(left, bins[bin - 1].aabb);
+            if count == 0 || right_count[bin] == 0 {
+                continue;
+            }
+
+            let left_area = left.map_or(0.0, |aabb| aabb.surface_area());
+            let cost = TRAVERSAL_COST
+                + INTERSECTION_COST * (left_area * count as f64 + right_area[bin] * right_count[bin] as f64) / aabb.surface_area();
+
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, bin));
+            }
+        }
+    }
+
+    match best {
+        Some((cost, axis, bin)) if cost < leaf_cost || objects.len() > MAX_LEAF_SIZE => {
+            let min = centroid_bounds.minimum[axis];
+            let extent = centroid_bounds.maximum[axis] - min;
+            let mut mid = 0;
+            for i in 0..objects.len() {
+                if bin_index(objects[i].centroid[axis], min, extent) < bin {
+                    objects.swap(i, mid);
+                    mid += 1;
+                }
+            }
+            Some((axis, mid))
+        },
+        // All centroids in the same spot, no boundary can separate them
+        None if objects.len() > MAX_LEAF_SIZE => Some(median_split(objects)),
+        _ => None
+    }
+}
+
+// Sorts along the longest axis of the centroids and splits in the middle
+fn median_split(objects: &mut [BuildObject]) -> (usize, usize) {
+    let centroid_bounds = centroid_bounds(objects);
+    let extent = centroid_bounds.maximum - centroid_bounds.minimum;
+    let axis = if extent.x > extent.y && extent.x > extent.z { 0 } else if extent.y > extent.z { 1 } else { 2 };
+
+    objects.sort_by(|a, b| a.centroid[axis].partial_cmp(&b.centroid[axis]).unwrap_or(std::cmp::Ordering::Equal));
+    (axis, objects.len() / 2)
+}
+
+fn centroid_bounds(objects: &[BuildObject]) -> AABB {
+    objects.iter().skip(1).fold(
+        AABB::new(objects[0].centroid, objects[0].centroid),
+        |bounds, object| AABB::surrounding_box(&bounds, &AABB::new(object.centroid, object.centroid))
+        )
+}
+
+fn bin_index(centroid: f64, min: f64, extent: f64) -> usize {
+    usize::min(((centroid - min) / extent * SAH_BINS as f64) as usize, SAH_BINS - 1)
+}
+
+fn merge_boxes(a: Option<AABB>, b: Option<AABB>) -> Option<AABB> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(AABB::surrounding_box(&a, &b)),
+        (a, None) => a,
+        (None, b) => b
+    }
+}
@@ -1,6 +1,216 @@
 use crate::math::*;
 use crate::ray::*;
+use crate::sampler::*;
+use crate::spectrum::*;
+use crate::scene::World;
+use crate::lens::RealisticLens;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::sync::Arc;
 
+// How the image maps to directions out of the camera
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Projection {
+    // A flat image through a thin lens, seeing vfov degrees from bottom to top
+    Perspective,
+    // Equidistant fisheye, the angle from the view direction growing evenly with the distance from the
+    // center of the image up to half the field of view in degrees at its left and right edges
+    Fisheye { fov: f64 },
+    // All around panorama for environment maps and VR backgrounds, 360 degrees across and 180 from the
+    // bottom to the top at an aspect ratio of 2. Level with the up vector and centered on the view
+    // direction around it, so looking towards +z lines it up with EnvironmentMap.
+    Equirectangular
+}
+
+impl FromStr for Projection {
+    type Err = String;
+
+    // A projection name, the fisheye optionally with its field of view like fisheye:220
+    fn from_str(s: &str) -> Result<Projection, String> {
+        match s.split_once(':') {
+            Some(("fisheye", fov)) => match fov.parse::<f64>() {
+                Ok(fov) if fov > 0.0 && fov <= 360.0 => Ok(Projection::Fisheye { fov }),
+                _ => Err(format!("Invalid fisheye field of view {}", fov))
+            },
+            Some(_) => Err(format!("Unknown projection {}", s)),
+            None => match s {
+                "perspective" => Ok(Projection::Perspective),
+                "fisheye" => Ok(Projection::Fisheye { fov: 180.0 }),
+                "equirectangular" => Ok(Projection::Equirectangular),
+                _ => Err(format!("Unknown projection {}", s))
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Projection {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Projection, String> {
+        s.parse()
+    }
+}
+
+// Where the camera focuses instead of at a fixed distance
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Autofocus {
+    // On the point the camera looks at
+    LookAt,
+    // On whatever is seen through the center of the image, or the look at point if nothing is
+    Center
+}
+
+impl Autofocus {
+    pub fn focus_distance(&self, look_from: &Point3, look_at: &Point3, world: &World, time: f64) -> f64 {
+        let distance = (*look_at - *look_from).length();
+        match self {
+            Autofocus::LookAt => distance,
+            Autofocus::Center => {
+                let ray = Ray::with_time(*look_from, *look_at - *look_from, time);
+                world.hit_objects(&ray, 0.001, INFINITY).map_or(distance, |rec| rec.t * distance)
+            }
+        }
+    }
+}
+
+impl FromStr for Autofocus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Autofocus, String> {
+        match s {
+            "look_at" => Ok(Autofocus::LookAt),
+            "center" => Ok(Autofocus::Center),
+            _ => Err(format!("Unknown autofocus mode {}", s))
+        }
+    }
+}
+
+impl TryFrom<String> for Autofocus {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Autofocus, String> {
+        s.parse()
+    }
+}
+
+// Renders a left and a right eye next to each other or the left over the right, for viewing in a VR
+// headset. The eyes are the separation apart, in meters for a scene in meters, and look at the same
+// image in the plane in focus. Panoramas are seen from a circle of the separation across, which keeps
+// the eyes apart in every direction around (omni-directional stereo).
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Stereo {
+    pub layout: StereoLayout,
+    pub separation: f64
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StereoLayout {
+    SideBySide,
+    OverUnder
+}
+
+impl Stereo {
+    // Aspect ratio of the whole image with both eyes in it
+    pub fn image_aspect_ratio(&self, eye_aspect_ratio: f64) -> f64 {
+        match self.layout {
+            StereoLayout::SideBySide => 2.0 * eye_aspect_ratio,
+            StereoLayout::OverUnder => 0.5 * eye_aspect_ratio
+        }
+    }
+
+    // Where in the image of its eye a point of the whole image is, and the eye, -1 for left and 1 for right
+    fn eye(&self, s: f64, t: f64) -> (f64, f64, f64) {
+        match self.layout {
+            StereoLayout::SideBySide if s < 0.5 => (2.0 * s, t, -1.0),
+            StereoLayout::SideBySide => (2.0 * s - 1.0, t, 1.0),
+            StereoLayout::OverUnder if t >= 0.5 => (s, 2.0 * t - 1.0, -1.0),
+            StereoLayout::OverUnder => (s, 2.0 * t, 1.0)
+        }
+    }
+}
+
+impl FromStr for Stereo {
+    type Err = String;
+
+    // The layout, optionally with the separation like side-by-side,0.065
+    fn from_str(s: &str) -> Result<Stereo, String> {
+        let (layout, separation) = match s.split_once(',') {
+            Some((layout, separation)) => match separation.trim().parse::<f64>() {
+                Ok(separation) if separation >= 0.0 => (layout, separation),
+                _ => return Err(format!("Invalid eye separation {}", separation))
+            },
+            None => (s, 0.064)
+        };
+
+        let layout = match layout {
+            "side-by-side" => StereoLayout::SideBySide,
+            "over-under" => StereoLayout::OverUnder,
+            _ => return Err(format!("Unknown stereo layout {}", layout))
+        };
+
+        Ok(Stereo { layout, separation })
+    }
+}
+
+impl TryFrom<String> for Stereo {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Stereo, String> {
+        s.parse()
+    }
+}
+
+// Movements of a view camera lens. Shifting moves the image across by fractions of its width and height
+// without turning the camera, which keeps the verticals of a building straight when shifting up from
+// a level camera. Tilting swings the plane in focus by degrees around the horizontal axis. Negative
+// angles lay it down along the ground, so it is sharp from front to back, and positive angles stand it
+// up towards the camera, so only a band across is sharp, which looks like a miniature. Being the angle
+// of the plane rather than of the lens, it takes tens of degrees to show.
+#[derive(Copy, Clone, Default)]
+pub struct TiltShift {
+    pub tilt: f64,
+    pub shift_x: f64,
+    pub shift_y: f64
+}
+
+// Imperfections of a real lens, all off by default. Lateral chromatic aberration magnifies red more
+// than blue by the given fraction, which fringes edges towards the corners, and longitudinal
+// aberration moves the focus of red further away than blue by the given fraction of the focus
+// distance, which fringes what is out of focus. Distortion bends straight lines, outwards for barrel
+// distortion above 0 and inwards for pincushion distortion below, by the fraction the image is
+// stretched at its corners.
+#[derive(Copy, Clone, Default)]
+pub struct LensEffects {
+    pub lateral_aberration: f64,
+    pub longitudinal_aberration: f64,
+    pub distortion: f64
+}
+
+impl LensEffects {
+    fn has_aberration(&self) -> bool {
+        self.lateral_aberration != 0.0 || self.longitudinal_aberration != 0.0
+    }
+}
+
+// How far the frame of the camera moves from time_0 to time_1, for motion blur of pans and dolly moves.
+// The frame is interpolated linearly, which is close enough for how little it turns within a frame.
+#[derive(Copy, Clone)]
+pub struct CameraMotion {
+    pub origin: Vector3,
+    pub lower_left_corner: Vector3,
+    pub horizontal: Vector3,
+    pub vertical: Vector3,
+    pub u: Vector3,
+    pub v: Vector3,
+    pub w: Vector3
+}
+
+#[derive(Clone)]
 pub struct Camera {
     pub origin: Point3,
     pub lower_left_corner: Point3,
@@ -9,12 +219,26 @@ pub struct Camera {
     pub u: Vector3,
     pub v: Vector3,
     pub w: Vector3,
+    pub vup: Vector3,
     pub lense_radius: f64,
     pub time_0: f64,
-    pub time_1: f64
+    pub time_1: f64,
+    // Share of the shutter interval it takes to open and again to close, from 0 for a shutter that is
+    // fully open all the time to 0.5 for one that is only fully open in the middle. Moving objects fade
+    // in and out at the ends of their blur instead of stopping sharply.
+    pub shutter_ramp: f64,
+    pub lens: LensEffects,
+    pub tilt_shift: TiltShift,
+    pub projection: Projection,
+    pub motion: Option<CameraMotion>,
+    // Traces the rays through the elements of a real lens instead of the thin lens of the aperture, for
+    // the perspective projection
+    pub realistic_lens: Option<Arc<RealisticLens>>,
+    pub stereo: Option<Stereo>
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
             look_from: &Point3,
             look_at: &Point3,
@@ -32,7 +256,7 @@ impl Camera {
         let viewport_width = aspect_ratio * viewport_height;
 
         let w = Vector3::normalize(&(*look_from - *look_at));
-        let u = Vector3::normalize(&Vector3::cross(&vup, &w));
+        let u = Vector3::normalize(&Vector3::cross(vup, &w));
         let v = Vector3::cross(&w, &u);
 
         let origin = *look_from; 
@@ -49,19 +273,187 @@ impl Camera {
             u,
             v,
             w,
+            vup: Vector3::normalize(vup),
             lense_radius,
             time_0,
-            time_1
+            time_1,
+            shutter_ramp: 0.0,
+            lens: LensEffects::default(),
+            tilt_shift: TiltShift::default(),
+            projection: Projection::Perspective,
+            motion: None,
+            realistic_lens: None,
+            stereo: None
+        }
+    }
+
+    // The lens and time dimensions are taken even for a pinhole camera without motion, so the
+    // scattering at the first bounce always gets the same sampler dimensions. A lens with chromatic
+    // aberration bends each color its own way, so the ray only carries one of them, see Ray::channel.
+    // Fisheye and panorama cameras are pinholes without lens effects. Comes with the weight of the ray,
+    // which a realistic lens lowers towards the edges of the image, and none where it blocks the ray.
+    pub fn get_ray(&self, s: f64, t: f64, wavelengths: Option<Wavelengths>, sampler: &mut dyn Sampler) -> Option<(Ray, f64)> {
+        let (s, t, eye) = self.stereo.map_or((s, t, 0.0), |stereo| stereo.eye(s, t));
+        let disk = sampler.in_unit_disk();
+        let time = self.sample_time(sampler.next_1d());
+
+        let camera = self.at_time(time);
+        let (mut ray, weight) = camera.posed_ray(s, t, disk, time, wavelengths, sampler)?;
+        if let Some(stereo) = &self.stereo {
+            camera.move_to_eye(&mut ray, 0.5 * eye * stereo.separation);
         }
+        Some((ray, weight))
+    }
+
+    // Moves a ray of the camera sideways by the offset to where an eye sees it from
+    fn move_to_eye(&self, ray: &mut Ray, offset: f64) {
+        match self.projection {
+            // Still through the same point in focus, so the eyes agree on what is at the focus distance
+            Projection::Perspective if self.realistic_lens.is_none() => {
+                let shift = offset * self.u;
+                ray.origin += shift;
+                ray.direction = ray.direction - shift;
+            },
+            // Sideways from the direction of the ray, less so up and down where the eyes could be on
+            // either side of it
+            Projection::Equirectangular => {
+                ray.origin += offset * Vector3::cross(&Vector3::normalize(&ray.direction), &self.vup);
+            },
+            _ => ray.origin += offset * self.u
+        }
+    }
+
+    // Moves the camera over the shutter interval from where it is at time_0 to where the end camera is
+    pub fn set_motion(&mut self, end: &Camera) {
+        let motion = CameraMotion {
+            origin: end.origin - self.origin,
+            lower_left_corner: end.lower_left_corner - self.lower_left_corner,
+            horizontal: end.horizontal - self.horizontal,
+            vertical: end.vertical - self.vertical,
+            u: end.u - self.u,
+            v: end.v - self.v,
+            w: end.w - self.w
+        };
+
+        // A camera standing still is left without motion, so it is not moved for every ray
+        let deltas = [motion.origin, motion.lower_left_corner, motion.horizontal, motion.vertical, motion.u, motion.v, motion.w];
+        self.motion = if deltas.iter().all(|delta| delta.near_zero()) { None } else { Some(motion) };
+    }
+
+    // Where the camera is at a time during the shutter interval
+    fn at_time(&self, time: f64) -> Cow<'_, Camera> {
+        let motion = match &self.motion {
+            Some(motion) if self.time_1 > self.time_0 => motion,
+            _ => return Cow::Borrowed(self)
+        };
+
+        let f = (time - self.time_0) / (self.time_1 - self.time_0);
+        Cow::Owned(Camera {
+            origin: self.origin + f * motion.origin,
+            lower_left_corner: self.lower_left_corner + f * motion.lower_left_corner,
+            horizontal: self.horizontal + f * motion.horizontal,
+            vertical: self.vertical + f * motion.vertical,
+            u: Vector3::normalize(&(self.u + f * motion.u)),
+            v: Vector3::normalize(&(self.v + f * motion.v)),
+            w: Vector3::normalize(&(self.w + f * motion.w)),
+            motion: None,
+            ..self.clone()
+        })
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lense_radius * Vector3::random_in_unit_disk();
+    fn posed_ray(&self, s: f64, t: f64, disk: Vector3, time: f64, wavelengths: Option<Wavelengths>, sampler: &mut dyn Sampler) -> Option<(Ray, f64)> {
+        if let (Some(lens), Projection::Perspective) = (&self.realistic_lens, self.projection) {
+            // The lens turns the image upside down, so the film is read the other way around
+            let (origin, direction, weight) = lens.trace_from_film((0.5 - s) * lens.film_width, (0.5 - t) * lens.film_height, &disk)?;
+
+            // From millimeters along the axis of the lens towards -z to meters along the view direction
+            let to_world = |a: &Vector3| a.x * self.u + a.y * self.v + a.z * self.w;
+            let ray = Ray::with_time(self.origin + 0.001 * to_world(&origin), to_world(&direction), time);
+            return Some((Ray { wavelengths, ..ray }, weight));
+        }
+
+        let rd = self.lense_radius * disk;
         let offset = self.u * rd.x + self.v * rd.y;
-        Ray::with_time(
-            self.origin + offset,
-            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
-            random_double_range(self.time_0, self.time_1)
-            )
+
+        let direction = match self.projection {
+            Projection::Perspective => return Some((self.perspective_ray(s, t, offset, time, wavelengths, sampler), 1.0)),
+            Projection::Fisheye { fov } => {
+                let aspect_ratio = self.horizontal.length() / self.vertical.length();
+                let (x, y) = (2.0 * s - 1.0, (2.0 * t - 1.0) / aspect_ratio);
+                let theta = (x * x + y * y).sqrt() * degrees_to_radians(fov) / 2.0;
+                let phi = f64::atan2(y, x);
+                theta.sin() * (phi.cos() * self.u + phi.sin() * self.v) - theta.cos() * self.w
+            },
+            Projection::Equirectangular => {
+                let phi = 2.0 * PI * (s - 0.5);
+                let theta = PI * (1.0 - t);
+                let forward = Vector3::cross(&self.vup, &self.u);
+                theta.sin() * (phi.sin() * self.u + phi.cos() * forward) + theta.cos() * self.vup
+            }
+        };
+
+        Some((Ray { wavelengths, ..Ray::with_time(self.origin, direction, time) }, 1.0))
+    }
+
+    // Time of a ray through the shutter, as often as the shutter is open at it. Rises linearly while it
+    // opens and falls while it closes, which the random number is mapped through the inverse of.
+    pub fn sample_time(&self, r: f64) -> f64 {
+        let ramp = clamp(self.shutter_ramp, 0.0, 0.5);
+        let height = 1.0 / (1.0 - ramp);
+        let ramp_area = 0.5 * height * ramp;
+
+        let fraction = if r < ramp_area {
+            (2.0 * ramp * r / height).sqrt()
+        } else if r > 1.0 - ramp_area {
+            1.0 - (2.0 * ramp * (1.0 - r) / height).sqrt()
+        } else {
+            r / height + 0.5 * ramp
+        };
+
+        self.time_0 + fraction * (self.time_1 - self.time_0)
+    }
+
+    fn perspective_ray(&self, s: f64, t: f64, offset: Vector3, time: f64, wavelengths: Option<Wavelengths>, sampler: &mut dyn Sampler) -> Ray {
+        let (channel, wavelength) = match wavelengths {
+            _ if !self.lens.has_aberration() => (None, 550.0),
+            Some(wavelengths) => (Some(0), wavelengths.0[0]),
+            None => {
+                let channel = usize::min((3.0 * sampler.next_1d()) as usize, 2);
+                (Some(channel), RGB_WAVELENGTHS[channel])
+            }
+        };
+        // From -1 for blue to 1 for red
+        let color_shift = (wavelength - 550.0) / 100.0;
+
+        // Distortion and lateral aberration scale the image around its center, the distortion more the
+        // further out, with the corners at a radius of 1
+        let (x, y) = (s - 0.5, t - 0.5);
+        let radius_squared = 2.0 * (x * x + y * y);
+        let scale = 1.0 + self.lens.distortion * radius_squared + self.lens.lateral_aberration * color_shift;
+
+        let (x, y) = (self.tilt_shift.shift_x + scale * x, self.tilt_shift.shift_y + scale * y);
+        let mut focus_point = self.lower_left_corner + (0.5 + x) * self.horizontal + (0.5 + y) * self.vertical;
+
+        // The image lies in the plane in focus before it is tilted, so the point in focus moves along the
+        // ray through the middle of the lens to where it meets the tilted plane
+        if self.tilt_shift.tilt != 0.0 {
+            let focus_center = self.lower_left_corner + 0.5 * self.horizontal + 0.5 * self.vertical;
+            let tilt = degrees_to_radians(self.tilt_shift.tilt);
+            let normal = tilt.cos() * self.w - tilt.sin() * self.v;
+            let direction = focus_point - self.origin;
+
+            let cosine = Vector3::dot(&direction, &normal);
+            if cosine < 0.0 {
+                focus_point = self.origin + Vector3::dot(&(focus_center - self.origin), &normal) / cosine * direction;
+            }
+        }
+
+        let focus_point = self.origin + (1.0 + self.lens.longitudinal_aberration * color_shift) * (focus_point - self.origin);
+
+        Ray {
+            wavelengths,
+            channel,
+            ..Ray::with_time(self.origin + offset, focus_point - self.origin - offset, time)
+        }
     }
 }
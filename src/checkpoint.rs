@@ -0,0 +1,74 @@
+use raytracer::film::Film;
+use raytracer::render::Crop;
+use serde::{Serialize, Deserialize};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+
+// Everything needed to pick up an interrupted render where it left off: the accumulated film and
+// the settings it was rendered with, so a resume with different settings can be refused.
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub scene: String,
+    pub image_width: usize,
+    pub image_height: usize,
+    pub crop: Crop,
+    pub max_depth: i32,
+    pub seed: u32,
+    pub samples_done: usize,
+    pub film: Film
+}
+
+impl Checkpoint {
+    // Writes to a temporary file first, so a crash while saving never destroys the previous checkpoint
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let temp_path = format!("{}.tmp", path);
+        {
+            let mut out = BufWriter::new(File::create(&temp_path)?);
+            bincode::serialize_into(&mut out, self).map_err(io::Error::other)?;
+            out.flush()?;
+        }
+
+        std::fs::rename(&temp_path, path)
+    }
+
+    pub fn load(path: &str) -> Result<Checkpoint, String> {
+        let file = File::open(path).map_err(|err| format!("Could not open checkpoint {}: {}", path, err))?;
+        bincode::deserialize_from(BufReader::new(file)).map_err(|err| format!("Could not read checkpoint {}: {}", path, err))
+    }
+
+    // Adds the samples of another checkpoint of the same render, or describes why it does not fit
+    pub fn merge(&mut self, other: &Checkpoint) -> Result<(), String> {
+        if let Some(reason) = other.mismatch(&self.scene, self.image_width, self.image_height, &self.crop, self.max_depth, &self.film) {
+            return Err(reason);
+        }
+
+        // Renders with the same seed trace exactly the same samples, so adding them up gains nothing
+        if other.seed == self.seed {
+            return Err(format!("it was rendered with the same seed {}", other.seed));
+        }
+
+        self.film.add_tile(&other.film, 0, 0);
+        self.samples_done += other.samples_done;
+
+        Ok(())
+    }
+
+    // Describes the first setting that differs from the current render, if any
+    pub fn mismatch(&self, scene: &str, image_width: usize, image_height: usize, crop: &Crop, max_depth: i32, film: &Film) -> Option<String> {
+        if self.scene != scene {
+            Some(format!("it is of scene {}", self.scene))
+        } else if self.image_width != image_width || self.image_height != image_height {
+            Some(format!("it is {}x{}", self.image_width, self.image_height))
+        } else if self.crop != *crop {
+            Some(String::from("it covers a different crop region"))
+        } else if self.max_depth != max_depth {
+            Some(format!("it has a max depth of {}", self.max_depth))
+        } else if self.film.filter != film.filter {
+            Some(format!("it uses the {:?} filter", self.film.filter))
+        } else if self.film.aov_list() != film.aov_list() {
+            Some(format!("it records the {:?} output variables", self.film.aov_list()))
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use raytracer::film::Aov;
+use raytracer::filter::Filter;
+use raytracer::logging::Verbosity;
+use raytracer::output::{ImageFormat, Transfer};
+use raytracer::render::{Crop, SampleClamp};
+use raytracer::sampler::SamplerKind;
+use raytracer::sky::SkyParams;
+use raytracer::fog::FogParams;
+use raytracer::camera::{Autofocus, Projection, Stereo};
+use raytracer::tonemap::ToneMap;
+use std::str::FromStr;
+
+pub const USAGE: &str = "Usage: raytracer [SCENE] [OPTIONS]
+       raytracer merge <CHECKPOINT>... [OPTIONS]
+       raytracer serve [OPTIONS]
+
+The scene is the name of a built-in scene and defaults to final, or a glTF file (.gltf or .glb),
+e.g. exported from Blender. The merge command adds up the samples of checkpoints of the same
+render, e.g. rendered on several machines, and writes the image using the output and post
+processing options. The serve command runs an HTTP service that renders scenes posted to it, see
+the README for the API.
+
+Options:
+    --width <pixels>        Image width, the height follows from the scene aspect ratio
+    --samples <count>       Samples per pixel
+    --depth <count>         Maximum number of ray bounces
+    --threads <count>       Number of render threads, defaults to the number of logical CPUs
+    --oversubscribe <factor>
+                            Multiply the thread count, e.g. 2 to run two threads per CPU (default 1)
+    --output <path>         Write the image to a file instead of stdout
+    --format <format>       Image format: p6 (binary PPM), p3 (ASCII PPM), png (16 bit) or pfm (float).
+                            Defaults to the output file extension, or p6
+    --filter <name[:radius]>
+                            Pixel filter: box (default), tent, gaussian or mitchell, optionally with a
+                            radius in pixels, e.g. gaussian:1.5
+    --clamp <value>         Suppress fireflies, same as both --clamp-direct and --clamp-indirect
+    --clamp-direct <value>  Limit the light reaching the first hit straight from an emitter or the sky
+    --clamp-indirect <value>
+                            Limit the light reaching the first hit after bouncing at least once more
+    --sampler <name>        Where pixel, lens, time and scattering samples come from: random (default),
+                            or the low discrepancy sobol or halton, which converge faster at low sample
+                            counts. bluenoise spreads the noise of quick previews into a fine grain
+    --adaptive <threshold>  Stop sampling pixels once their noise falls below this relative error, e.g.
+                            0.01. The samples per pixel become an average that noisy regions may get up
+                            to 4 times of
+    --importance <path>     Grayscale image stretched over the render, scaling the samples per pixel by
+                            its brightness relative to the average, e.g. white over glass for more samples
+                            there
+    --autofocus <mode>      Focus on the look at point of the scene (look_at), or on what is seen through
+                            the center of the image (center) instead of at the focus distance
+    --lens <path>           Trace the camera rays through the elements of a real lens, read from a lens file
+                            like lenses/double_gauss_50mm.dat, instead of through an ideal thin lens. Gives
+                            vignetting, distortion and bokeh, with the scene in meters
+    --projection <name>     Camera projection: perspective (default), fisheye, optionally with its field of
+                            view across the image in degrees like fisheye:220 (default 180), or
+                            equirectangular for a 360 degree panorama at an aspect ratio of 2
+    --stereo <layout[,separation]>
+                            Render both eyes for a VR headset, side-by-side or over-under with the left eye
+                            on top, the given distance apart (default 0.064, for a scene in meters)
+    --spectral              Trace every path at wavelengths of its own rather than in red, green and blue,
+                            which colors light passing through tinted glass more accurately
+    --seed <number>         Seed of the random numbers, including the layout of random scenes. The same
+                            seed renders the same image, so give each render to be merged its own (default 0)
+    --environment <path>    Light the scene with an equirectangular image, an HDR file or an sRGB picture,
+                            seen wherever rays leave the scene instead of the background color
+    --environment-rotation <degrees>
+                            Turn the environment around the up axis (default 0)
+    --sky <elevation,azimuth[,turbidity]>
+                            Light the scene with a daylight sky and its sun, the sun the given degrees above
+                            the horizon and around from -z towards +x. Turbidity goes from 2 for clear air to
+                            10 for haze (default 3)
+    --fog <density[,scale height]>
+                            Fill the scene with white fog scattering this share of the light per unit
+                            distance at height 0, thinning out by a factor of e every scale height up
+                            (default the same everywhere). Fades the distance and the sky into haze
+    --tonemap <operator>    Tone mapping: none (clamp, default), reinhard, aces or filmic
+    --exposure <stops>      Brighten or darken the image before tone mapping (default 0)
+    --gamma <value|srgb>    Gamma of the output encoding, or the sRGB transfer curve (default 2.0)
+    --snapshot-every <seconds>
+                            Save the image in progress to the output file this often
+    --snapshot-samples <count>
+                            Save the image in progress every time this many samples per pixel are added
+    --aov <list>            Also write first hit passes next to the output, a comma separated list of
+                            normal, depth, albedo, object_id, material_id, variance and samples.
+                            Saved as e.g. cornell.normal.png
+    --crop <x0,y0,x1,y1>    Only render this region of the image, in pixels from the top left corner
+    --checkpoint <path>     Periodically save the render state to this file, so it can be resumed
+    --checkpoint-every <seconds>
+                            How often to save the checkpoint (default 300)
+    --resume <path>         Continue a render from a checkpoint, which is then kept up to date
+    --frames <first-last>   Render an animation, one image per frame numbered like frame_0001.png after
+                            the output path. A single number renders frames 1 up to it
+    --fps <rate>            Frames per second of the animation, each frame spans 1/fps of scene time
+                            (default 24)
+    --preview               Quick low quality render: quarter width, 8 samples and a depth of 8
+    --window                Show the image in a window as it renders, closing it stops the render early.
+                            Needs a build with --features window
+    --listen <address>      Address the serve command listens on (default 127.0.0.1:8080)
+    --params-file <path>    Write the render parameters as JSON to a file
+    --stats-file <path>     Write timings, ray counts and memory use as JSON to a file
+    --config <path>         Read render settings from a TOML file (defaults to render.toml if present)
+    -q, --quiet             Only print errors
+    -v, --verbose           Print more details about the render
+    --trace                 Print everything, including BVH builds and texture loads
+    -h, --help              Print this message";
+
+// Settings given on the command line. Anything left as None falls back to the scene defaults.
+pub struct Options {
+    pub scene: Option<String>,
+    pub merge: bool,
+    pub merge_inputs: Vec<String>,
+    pub serve: bool,
+    pub listen: String,
+    pub width: Option<usize>,
+    pub samples: Option<usize>,
+    pub depth: Option<i32>,
+    pub threads: Option<usize>,
+    pub oversubscribe: f64,
+    pub output: Option<String>,
+    pub format: Option<ImageFormat>,
+    pub filter: Filter,
+    pub clamp: SampleClamp,
+    pub adaptive: Option<f64>,
+    pub importance_map: Option<String>,
+    pub seed: u32,
+    pub environment: Option<String>,
+    pub environment_rotation: f64,
+    pub sky: Option<SkyParams>,
+    pub fog: Option<FogParams>,
+    pub sampler: SamplerKind,
+    pub autofocus: Option<Autofocus>,
+    pub lens: Option<String>,
+    pub projection: Option<Projection>,
+    pub stereo: Option<Stereo>,
+    pub spectral: bool,
+    pub tone_map: ToneMap,
+    pub exposure: f64,
+    pub transfer: Transfer,
+    pub snapshot_seconds: Option<f64>,
+    pub snapshot_samples: Option<usize>,
+    pub aovs: Vec<Aov>,
+    pub crop: Option<Crop>,
+    pub checkpoint: Option<String>,
+    pub checkpoint_seconds: f64,
+    pub resume: Option<String>,
+    pub frames: Option<FrameRange>,
+    pub fps: f64,
+    pub params_file: Option<String>,
+    pub stats_file: Option<String>,
+    pub config: Option<String>,
+    pub preview: bool,
+    pub window: bool,
+    pub verbosity: Verbosity,
+    pub help: bool
+}
+
+impl Options {
+    // Parses the arguments following the program name
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            scene: None,
+            merge: false,
+            merge_inputs: Vec::new(),
+            serve: false,
+            listen: String::from("127.0.0.1:8080"),
+            width: None,
+            samples: None,
+            depth: None,
+            threads: None,
+            oversubscribe: 1.0,
+            output: None,
+            format: None,
+            filter: Filter::Box(0.5),
+            clamp: SampleClamp { direct: None, indirect: None },
+            adaptive: None,
+            importance_map: None,
+            seed: 0,
+            environment: None,
+            environment_rotation: 0.0,
+            sky: None,
+            fog: None,
+            sampler: SamplerKind::Random,
+            autofocus: None,
+            lens: None,
+            projection: None,
+            stereo: None,
+            spectral: false,
+            tone_map: ToneMap::Clamp,
+            exposure: 0.0,
+            transfer: Transfer::Gamma(2.0),
+            snapshot_seconds: None,
+            snapshot_samples: None,
+            aovs: Vec::new(),
+            crop: None,
+            checkpoint: None,
+            checkpoint_seconds: 300.0,
+            resume: None,
+            frames: None,
+            fps: 24.0,
+            params_file: None,
+            stats_file: None,
+            config: None,
+            preview: false,
+            window: false,
+            verbosity: Verbosity::Normal,
+            help: false
+        };
+
+        let mut args = args.iter().peekable();
+
+        match args.peek().map(|arg| arg.as_str()) {
+            Some("merge") => {
+                options.merge = true;
+                args.next();
+            },
+            Some("serve") => {
+                options.serve = true;
+                args.next();
+            },
+            _ => {}
+        }
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--width" => options.width = Some(parse_value(arg, args.next())?),
+                "--samples" => options.samples = Some(parse_value(arg, args.next())?),
+                "--depth" => options.depth = Some(parse_value(arg, args.next())?),
+                "--threads" => options.threads = Some(parse_value(arg, args.next())?),
+                "--oversubscribe" => options.oversubscribe = parse_value(arg, args.next())?,
+                "--output" => options.output = Some(parse_value(arg, args.next())?),
+                "--format" => options.format = Some(parse_value(arg, args.next())?),
+                "--filter" => options.filter = parse_value(arg, args.next())?,
+                "--clamp" => {
+                    let limit = parse_value(arg, args.next())?;
+                    options.clamp = SampleClamp { direct: Some(limit), indirect: Some(limit) };
+                },
+                "--clamp-direct" => options.clamp.direct = Some(parse_value(arg, args.next())?),
+                "--clamp-indirect" => options.clamp.indirect = Some(parse_value(arg, args.next())?),
+                "--sampler" => options.sampler = parse_value(arg, args.next())?,
+                "--importance" => options.importance_map = Some(parse_value(arg, args.next())?),
+                "--autofocus" => options.autofocus = Some(parse_value(arg, args.next())?),
+                "--lens" => options.lens = Some(parse_value(arg, args.next())?),
+                "--projection" => options.projection = Some(parse_value(arg, args.next())?),
+                "--stereo" => options.stereo = Some(parse_value(arg, args.next())?),
+                "--spectral" => options.spectral = true,
+                "--seed" => options.seed = parse_value(arg, args.next())?,
+                "--environment" => options.environment = Some(parse_value(arg, args.next())?),
+                "--environment-rotation" => options.environment_rotation = parse_value(arg, args.next())?,
+                "--sky" => options.sky = Some(parse_value(arg, args.next())?),
+                "--fog" => options.fog = Some(parse_value(arg, args.next())?),
+                "--adaptive" => options.adaptive = Some(parse_value(arg, args.next())?),
+                "--tonemap" => options.tone_map = parse_value(arg, args.next())?,
+                "--exposure" => options.exposure = parse_value(arg, args.next())?,
+                "--gamma" => options.transfer = parse_value(arg, args.next())?,
+                "--snapshot-every" => options.snapshot_seconds = Some(parse_value(arg, args.next())?),
+                "--snapshot-samples" => options.snapshot_samples = Some(parse_value(arg, args.next())?),
+                "--aov" => options.aovs = parse_list(arg, args.next())?,
+                "--crop" => options.crop = Some(parse_value(arg, args.next())?),
+                "--checkpoint" => options.checkpoint = Some(parse_value(arg, args.next())?),
+                "--checkpoint-every" => options.checkpoint_seconds = parse_value(arg, args.next())?,
+                "--resume" => options.resume = Some(parse_value(arg, args.next())?),
+                "--frames" => options.frames = Some(parse_value(arg, args.next())?),
+                "--fps" => options.fps = parse_value(arg, args.next())?,
+                "--listen" => options.listen = parse_value(arg, args.next())?,
+                "--params-file" => options.params_file = Some(parse_value(arg, args.next())?),
+                "--stats-file" => options.stats_file = Some(parse_value(arg, args.next())?),
+                "--config" => options.config = Some(parse_value(arg, args.next())?),
+                "--preview" => options.preview = true,
+                "--window" => options.window = true,
+                "-q" | "--quiet" => options.verbosity = Verbosity::Quiet,
+                "-v" | "--verbose" => options.verbosity = Verbosity::Verbose,
+                "--trace" => options.verbosity = Verbosity::Trace,
+                "-h" | "--help" => options.help = true,
+                _ if arg.starts_with('-') => return Err(format!("Unknown option {}", arg)),
+                _ if options.merge => options.merge_inputs.push(arg.clone()),
+                _ if options.serve => return Err(format!("Unexpected argument {}, scenes are picked per request", arg)),
+                _ => {
+                    if options.scene.is_some() {
+                        return Err(format!("Unexpected argument {}", arg));
+                    }
+                    options.scene = Some(arg.clone());
+                }
+            }
+        }
+
+        if (options.snapshot_seconds.is_some() || options.snapshot_samples.is_some()) && options.output.is_none() {
+            return Err(String::from("Snapshots need an --output file"));
+        }
+
+        if !options.aovs.is_empty() && options.output.is_none() {
+            return Err(String::from("Output variables need an --output file"));
+        }
+
+        if options.frames.is_some() && options.output.is_none() {
+            return Err(String::from("Animations need an --output file to number the frames after"));
+        }
+
+        if options.frames.is_some() && (options.checkpoint.is_some() || options.resume.is_some()) {
+            return Err(String::from("Checkpoints cannot be used when rendering an animation"));
+        }
+
+        if options.environment.is_some() && options.sky.is_some() {
+            return Err(String::from("Use either an --environment map or a --sky, not both"));
+        }
+
+        if options.width == Some(0) || options.samples == Some(0) {
+            return Err(String::from("The width and samples per pixel must be positive"));
+        }
+
+        if options.depth.is_some_and(|depth| depth < 1) {
+            return Err(String::from("The max depth must be at least 1"));
+        }
+
+        if options.fps <= 0.0 {
+            return Err(String::from("The frame rate must be positive"));
+        }
+
+        if options.adaptive.is_some_and(|threshold| threshold <= 0.0) {
+            return Err(String::from("The adaptive sampling threshold must be positive"));
+        }
+
+        if options.adaptive.is_some() && options.importance_map.is_some() {
+            return Err(String::from("Adaptive sampling and an importance map cannot be combined"));
+        }
+
+        if options.oversubscribe <= 0.0 {
+            return Err(String::from("The oversubscription factor must be positive"));
+        }
+
+        // A resumed render keeps writing to the checkpoint it came from unless told otherwise
+        if options.checkpoint.is_none() {
+            options.checkpoint = options.resume.clone();
+        }
+
+        Ok(options)
+    }
+}
+
+// Inclusive range of animation frames
+#[derive(Copy, Clone)]
+pub struct FrameRange {
+    pub first: usize,
+    pub last: usize
+}
+
+impl FrameRange {
+    pub fn len(&self) -> usize {
+        self.last - self.first + 1
+    }
+
+    // How far along the animation a point in time is, in frames, from 0 at the start of the first frame
+    // to 1 at the start of the last
+    pub fn fraction(&self, frame: f64) -> f64 {
+        if self.last > self.first {
+            (frame - self.first as f64) / (self.last - self.first) as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+impl FromStr for FrameRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FrameRange, String> {
+        let parse = |v: &str| v.trim().parse::<usize>().map_err(|_| format!("Invalid frame number {}", v));
+
+        let (first, last) = match s.split_once('-') {
+            Some((first, last)) => (parse(first)?, parse(last)?),
+            None => (1, parse(s)?)
+        };
+
+        if first > last {
+            return Err(format!("Invalid frame range {}", s));
+        }
+
+        Ok(FrameRange { first, last })
+    }
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<&String>) -> Result<T, String> {
+    match value {
+        Some(value) => value.parse().map_err(|_| format!("Invalid value {} for {}", value, flag)),
+        None => Err(format!("Missing value for {}", flag))
+    }
+}
+
+fn parse_list<T: FromStr>(flag: &str, value: Option<&String>) -> Result<Vec<T>, String> {
+    match value {
+        Some(value) => value.split(',')
+            .map(|item| item.trim().parse().map_err(|_| format!("Invalid value {} for {}", item, flag)))
+            .collect(),
+        None => Err(format!("Missing value for {}", flag))
+    }
+}
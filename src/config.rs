@@ -0,0 +1,190 @@
+use raytracer::math::*;
+use raytracer::camera::{Autofocus, LensEffects, Projection, Stereo, TiltShift};
+use raytracer::scene::Scene;
+use serde::Deserialize;
+use std::path::Path;
+
+pub const DEFAULT_CONFIG_PATH: &str = "render.toml";
+
+// Render preset loaded from a TOML file. Every setting is optional and overrides the scene defaults,
+// while command line options in turn override the file.
+//
+//     scene = "cornell"
+//     width = 600
+//     samples_per_pixel = 200
+//     max_depth = 50
+//     background = [0.0, 0.0, 0.0]
+//
+//     [camera]
+//     look_from = [278.0, 278.0, -800.0]
+//     look_at = [278.0, 278.0, 0.0]
+//     vfov = 40.0
+//     aperture = 0.0
+//     focus_dist = 800.0
+//     autofocus = "center"
+//     tilt = 5.0
+//     shift = [0.0, 0.1]
+//     projection = "fisheye:180"
+//     stereo = "side-by-side,0.064"
+//     shutter_open = 0.0
+//     shutter_close = 0.5
+//     shutter_ramp = 0.1
+//     lateral_aberration = 0.005
+//     longitudinal_aberration = 0.02
+//     distortion = 0.05
+//
+// When rendering an animation with --frames, the camera moves in a straight line from look_from
+// and look_at at the first frame to look_from_end and look_at_end at the last one, and for a still
+// over the time the scene moves over, blurring while the shutter is open. The shutter opens
+// and closes at these fractions of each frame, or of the time the scene moves over for a still, see
+// Camera::shutter_ramp for the ramp.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RenderConfig {
+    pub scene: Option<String>,
+    pub width: Option<usize>,
+    pub aspect_ratio: Option<f64>,
+    pub samples_per_pixel: Option<usize>,
+    pub max_depth: Option<i32>,
+    pub background: Option<[f64; 3]>,
+    pub camera: Option<CameraConfig>
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CameraConfig {
+    pub look_from: Option<[f64; 3]>,
+    pub look_at: Option<[f64; 3]>,
+    pub look_from_end: Option<[f64; 3]>,
+    pub look_at_end: Option<[f64; 3]>,
+    pub vfov: Option<f64>,
+    pub aperture: Option<f64>,
+    pub focus_dist: Option<f64>,
+    pub autofocus: Option<Autofocus>,
+    pub tilt: Option<f64>,
+    pub shift: Option<[f64; 2]>,
+    pub projection: Option<Projection>,
+    pub stereo: Option<Stereo>,
+    pub shutter_open: Option<f64>,
+    pub shutter_close: Option<f64>,
+    pub shutter_ramp: Option<f64>,
+    pub lateral_aberration: Option<f64>,
+    pub longitudinal_aberration: Option<f64>,
+    pub distortion: Option<f64>
+}
+
+impl RenderConfig {
+    pub fn load(path: &str) -> Result<RenderConfig, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("Could not read {}: {}", path, err))?;
+        toml::from_str(&text).map_err(|err| format!("Could not parse {}: {}", path, err))
+    }
+
+    // Loads the given config file, or render.toml from the working directory if there is one
+    pub fn load_or_default(path: Option<&str>) -> Result<RenderConfig, String> {
+        match path {
+            Some(path) => Self::load(path),
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Self::load(DEFAULT_CONFIG_PATH),
+            None => Ok(RenderConfig::default())
+        }
+    }
+
+    // Applies the settings that change the scene itself. Returns the aperture and focus distance of the
+    // camera, which the scenes leave to the renderer.
+    pub fn apply(&self, scene: &mut Scene) -> (f64, f64) {
+        let mut aperture = 0.1;
+        let mut dist_to_focus = 10.0;
+
+        if let Some(aspect_ratio) = self.aspect_ratio {
+            scene.aspect_ratio = aspect_ratio;
+        }
+
+        if let Some(background) = self.background {
+            scene.background = vector_from_array(background);
+        }
+
+        if let Some(camera) = &self.camera {
+            if let Some(look_from) = camera.look_from {
+                scene.look_from = vector_from_array(look_from);
+            }
+
+            if let Some(look_at) = camera.look_at {
+                scene.look_at = vector_from_array(look_at);
+            }
+
+            if let Some(vfov) = camera.vfov {
+                scene.vfov = vfov;
+            }
+
+            if let Some(camera_aperture) = camera.aperture {
+                aperture = camera_aperture;
+            }
+
+            if let Some(focus_dist) = camera.focus_dist {
+                dist_to_focus = focus_dist;
+            }
+        }
+
+        if let Some(width) = self.width {
+            scene.image_width = width;
+        }
+
+        if let Some(samples) = self.samples_per_pixel {
+            scene.samples_per_pixel = samples;
+        }
+
+        (aperture, dist_to_focus)
+    }
+
+    // Focusing on look_at or what is in the middle of the image, which takes over from focus_dist
+    pub fn autofocus(&self) -> Option<Autofocus> {
+        self.camera.as_ref().and_then(|camera| camera.autofocus)
+    }
+
+    pub fn projection(&self) -> Option<Projection> {
+        self.camera.as_ref().and_then(|camera| camera.projection)
+    }
+
+    pub fn stereo(&self) -> Option<Stereo> {
+        self.camera.as_ref().and_then(|camera| camera.stereo)
+    }
+
+    // When the shutter opens and closes and how long that takes, the times as fractions of the frame
+    pub fn shutter(&self) -> (f64, f64, f64) {
+        match &self.camera {
+            Some(camera) => (camera.shutter_open.unwrap_or(0.0), camera.shutter_close.unwrap_or(1.0), camera.shutter_ramp.unwrap_or(0.0)),
+            None => (0.0, 1.0, 0.0)
+        }
+    }
+
+    // Tilt of the plane in focus and shift of the image, see TiltShift
+    pub fn tilt_shift(&self) -> TiltShift {
+        let mut tilt_shift = TiltShift::default();
+
+        if let Some(camera) = &self.camera {
+            tilt_shift.tilt = camera.tilt.unwrap_or(0.0);
+            if let Some([x, y]) = camera.shift {
+                tilt_shift.shift_x = x;
+                tilt_shift.shift_y = y;
+            }
+        }
+
+        tilt_shift
+    }
+
+    // Chromatic aberration and distortion of the camera lens, see LensEffects
+    pub fn lens_effects(&self) -> LensEffects {
+        let mut lens = LensEffects::default();
+
+        if let Some(camera) = &self.camera {
+            lens.lateral_aberration = camera.lateral_aberration.unwrap_or(0.0);
+            lens.longitudinal_aberration = camera.longitudinal_aberration.unwrap_or(0.0);
+            lens.distortion = camera.distortion.unwrap_or(0.0);
+        }
+
+        lens
+    }
+}
+
+pub fn vector_from_array(v: [f64; 3]) -> Vector3 {
+    Vector3::new(v[0], v[1], v[2])
+}
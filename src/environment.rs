@@ -0,0 +1,81 @@
+use crate::math::*;
+use crate::sky::*;
+
+// Light from far away in every direction, seen by rays that leave the scene
+pub enum Environment {
+    Map(EnvironmentMap),
+    Sky(Sky)
+}
+
+impl Environment {
+    pub fn radiance(&self, direction: &Vector3) -> Color {
+        match self {
+            Environment::Map(map) => map.radiance(direction),
+            Environment::Sky(sky) => sky.radiance(direction)
+        }
+    }
+}
+
+// Environment from an equirectangular image: longitude across, from the top of the sky at the first
+// row to straight down at the last
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,    // Linear radiance, row by row from the top
+    rotation: f64          // Turn around the up axis in radians, to move the sun or a window where it is needed
+}
+
+impl EnvironmentMap {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>, rotation: f64) -> EnvironmentMap {
+        EnvironmentMap { width, height, pixels, rotation: degrees_to_radians(rotation) }
+    }
+
+    // HDR files are used as they are, 8 bit images are taken to be sRGB and made linear. The rotation
+    // is in degrees.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &str, rotation: f64) -> Result<EnvironmentMap, String> {
+        let (width, height, depth, values) = match stb_image::image::load(path) {
+            stb_image::image::LoadResult::ImageF32(image) => (image.width, image.height, image.depth, image.data),
+            stb_image::image::LoadResult::ImageU8(image) => {
+                let values = image.data.iter().map(|value| (*value as f32 / 255.0).powf(2.2)).collect();
+                (image.width, image.height, image.depth, values)
+            },
+            stb_image::image::LoadResult::Error(err) => return Err(format!("Could not read environment map {}: {}", path, err))
+        };
+
+        let pixels = (0..width * height)
+            .map(|i| {
+                let channel = |c: usize| values[i * depth + usize::min(c, depth - 1)] as f64;
+                Color::new(channel(0), channel(1), channel(2))
+            })
+            .collect();
+
+        trace!("Loaded environment map {} ({}x{})", path, width, height);
+
+        Ok(EnvironmentMap::new(width, height, pixels, rotation))
+    }
+
+    // Radiance arriving from the direction, bilinearly filtered and wrapping around horizontally
+    pub fn radiance(&self, direction: &Vector3) -> Color {
+        let d = Vector3::normalize(direction);
+        let phi = f64::atan2(d.x, -d.z) + self.rotation;
+        let theta = f64::acos(clamp(d.y, -1.0, 1.0));
+
+        let x = (phi / (2.0 * PI)).rem_euclid(1.0) * self.width as f64 - 0.5;
+        let y = theta / PI * self.height as f64 - 0.5;
+
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+
+        let pixel = |px: f64, py: f64| {
+            let px = (px as isize).rem_euclid(self.width as isize) as usize;
+            let py = (py.max(0.0) as usize).min(self.height - 1);
+            self.pixels[py * self.width + px]
+        };
+
+        (1.0 - ty) * ((1.0 - tx) * pixel(x0, y0) + tx * pixel(x0 + 1.0, y0))
+            + ty * ((1.0 - tx) * pixel(x0, y0 + 1.0) + tx * pixel(x0 + 1.0, y0 + 1.0))
+    }
+}
@@ -0,0 +1,279 @@
+use crate::math::*;
+use crate::output::*;
+use crate::filter::*;
+use serde::{Serialize, Deserialize};
+use std::str::FromStr;
+
+// Extra per pixel outputs, mostly recorded at the first surface a camera ray hits
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Aov {
+    Normal,     // World space normal, zero where nothing was hit
+    Depth,      // Distance from the camera, zero where nothing was hit
+    Albedo,     // Surface color without lighting, the background where nothing was hit
+    ObjectId,   // A distinct color per top level hittable, black where nothing was hit
+    MaterialId, // A distinct color per material, black where nothing was hit
+    Variance,   // Variance of the pixel estimate, computed from the luminance of all samples
+    SampleCount // Number of samples each pixel received, which varies with adaptive sampling
+}
+
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Variance => "variance",
+            Aov::SampleCount => "samples"
+        }
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Aov, String> {
+        match s {
+            "normal" => Ok(Aov::Normal),
+            "depth" => Ok(Aov::Depth),
+            "albedo" => Ok(Aov::Albedo),
+            "object_id" => Ok(Aov::ObjectId),
+            "material_id" => Ok(Aov::MaterialId),
+            "variance" => Ok(Aov::Variance),
+            "samples" => Ok(Aov::SampleCount),
+            _ => Err(format!("Unknown output variable {}", s))
+        }
+    }
+}
+
+// What a single camera ray saw at its first hit
+#[derive(Copy, Clone, Default)]
+pub struct AovSample {
+    pub normal: Vector3,
+    pub depth: f64,
+    pub albedo: Color,
+    pub object_id: Option<usize>,
+    pub material_id: Option<usize>
+}
+
+impl AovSample {
+    fn get(&self, aov: Aov) -> Color {
+        match aov {
+            Aov::Normal => self.normal,
+            Aov::Depth => Color::new(self.depth, self.depth, self.depth),
+            Aov::Albedo => self.albedo,
+            Aov::ObjectId => self.object_id.map_or(Color::new(0.0, 0.0, 0.0), id_color),
+            Aov::MaterialId => self.material_id.map_or(Color::new(0.0, 0.0, 0.0), id_color),
+            Aov::Variance | Aov::SampleCount => Color::new(0.0, 0.0, 0.0)
+        }
+    }
+}
+
+// Hashes an ID into a bright, stable color. Averaging the colors of all samples in a pixel gives
+// the coverage of each ID along edges, so objects can be keyed out in post.
+fn id_color(id: usize) -> Color {
+    let mut h = (id as u64).wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    h ^= h >> 31;
+    h = h.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    h ^= h >> 27;
+
+    let channel = |shift: u32| 0.25 + 0.75 * ((h >> shift) & 0xFF) as f64 / 255.0;
+    Color::new(channel(0), channel(8), channel(16))
+}
+
+// Accumulates the radiance samples of a render, along with how many samples each pixel received.
+// Pixels are stored row by row starting at the top of the image, like Image.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    pub filter: Filter,
+    sums: Vec<Color>,           // Filter weighted sum of the samples around each pixel
+    weights: Vec<f64>,
+    luminance_sums: Vec<f64>,   // Unweighted sums of the samples inside each pixel, for the variance
+    squared_sums: Vec<f64>,
+    sample_counts: Vec<u32>,
+    aovs: Vec<(Aov, Vec<Color>)>
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Filter, aovs: &[Aov]) -> Film {
+        Film {
+            width,
+            height,
+            filter,
+            sums: vec![Color::new(0.0, 0.0, 0.0); width * height],
+            weights: vec![0.0; width * height],
+            luminance_sums: vec![0.0; width * height],
+            squared_sums: vec![0.0; width * height],
+            sample_counts: vec![0; width * height],
+            // The variance and sample counts come from the beauty samples and need no buffer of their own
+            aovs: aovs.iter()
+                .filter(|aov| **aov != Aov::Variance && **aov != Aov::SampleCount)
+                .map(|aov| (*aov, vec![Color::new(0.0, 0.0, 0.0); width * height]))
+                .collect()
+        }
+    }
+
+    pub fn has_aovs(&self) -> bool {
+        !self.aovs.is_empty()
+    }
+
+    pub fn aov_list(&self) -> Vec<Aov> {
+        self.aovs.iter().map(|(aov, _)| *aov).collect()
+    }
+
+    // Adds a sample at a continuous position on the film, where pixel x, y covers [x, x + 1) x [y, y + 1).
+    // The sample is splatted into every pixel whose center lies within the filter radius.
+    pub fn add_sample(&mut self, x: f64, y: f64, color: Color) {
+        let pixel_x = usize::min(x as usize, self.width - 1);
+        let pixel_y = usize::min(y as usize, self.height - 1);
+        let index = pixel_y * self.width + pixel_x;
+        let luminance = color.luminance();
+        self.luminance_sums[index] += luminance;
+        self.squared_sums[index] += luminance * luminance;
+        self.sample_counts[index] += 1;
+
+        let radius = self.filter.radius();
+        let x0 = f64::max((x - 0.5 - radius).ceil(), 0.0) as usize;
+        let y0 = f64::max((y - 0.5 - radius).ceil(), 0.0) as usize;
+        let x1 = usize::min((x - 0.5 + radius).floor().max(0.0) as usize, self.width - 1);
+        let y1 = usize::min((y - 0.5 + radius).floor().max(0.0) as usize, self.height - 1);
+
+        for splat_y in y0..=y1 {
+            for splat_x in x0..=x1 {
+                let weight = self.filter.weight(splat_x as f64 + 0.5 - x, splat_y as f64 + 0.5 - y);
+                if weight != 0.0 {
+                    let index = splat_y * self.width + splat_x;
+                    self.sums[index] += weight * color;
+                    self.weights[index] += weight;
+                }
+            }
+        }
+    }
+
+    // Records the output variables of a sample, call alongside add_sample. They are not filtered.
+    pub fn add_aov_sample(&mut self, x: usize, y: usize, sample: &AovSample) {
+        let index = y * self.width + x;
+        for (aov, sums) in self.aovs.iter_mut() {
+            sums[index] += sample.get(*aov);
+        }
+    }
+
+    // Adds all samples of a smaller film whose top left corner sits at x0, y0 in this one. Parts of the
+    // tile outside this film, like the margin filters splat into around a tile, are dropped.
+    pub fn add_tile(&mut self, tile: &Film, x0: isize, y0: isize) {
+        for tile_y in 0..tile.height {
+            let y = y0 + tile_y as isize;
+            if y < 0 || y >= self.height as isize {
+                continue;
+            }
+
+            for tile_x in 0..tile.width {
+                let x = x0 + tile_x as isize;
+                if x < 0 || x >= self.width as isize {
+                    continue;
+                }
+
+                let index = y as usize * self.width + x as usize;
+                let tile_index = tile_y * tile.width + tile_x;
+
+                self.sums[index] += tile.sums[tile_index];
+                self.weights[index] += tile.weights[tile_index];
+                self.luminance_sums[index] += tile.luminance_sums[tile_index];
+                self.squared_sums[index] += tile.squared_sums[tile_index];
+                self.sample_counts[index] += tile.sample_counts[tile_index];
+
+                for ((_, sums), (_, tile_sums)) in self.aovs.iter_mut().zip(tile.aovs.iter()) {
+                    sums[index] += tile_sums[tile_index];
+                }
+            }
+        }
+    }
+
+    // Number of samples added to the whole film, not counting dropped ones
+    pub fn total_samples(&self) -> u64 {
+        self.sample_counts.iter().map(|count| *count as u64).sum()
+    }
+
+    pub fn sample_count(&self, x: usize, y: usize) -> u32 {
+        self.sample_counts[y * self.width + x]
+    }
+
+    // Filtered average of the samples around a pixel, black if there are none yet
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        let index = y * self.width + x;
+        if self.weights[index].abs() < 1e-12 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        self.sums[index] / self.weights[index]
+    }
+
+    // Variance of the mean luminance of a pixel. It shrinks as samples are added, so high values
+    // point at regions that need more samples.
+    pub fn variance(&self, x: usize, y: usize) -> f64 {
+        let index = y * self.width + x;
+        let n = self.sample_counts[index] as f64;
+        if n < 2.0 {
+            return 0.0;
+        }
+
+        let mean = self.luminance_sums[index] / n;
+        let sample_variance = (self.squared_sums[index] - n * mean * mean) / (n - 1.0);
+
+        f64::max(sample_variance, 0.0) / n
+    }
+
+    // Averages every pixel into a linear image, ready for post processing
+    pub fn develop(&self) -> Image {
+        let mut image = Image::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                image.set_pixel(x, y, self.pixel(x, y));
+            }
+        }
+
+        image
+    }
+
+    // Averages an output variable into a raw image, or None if the film does not record it
+    pub fn develop_aov(&self, aov: Aov) -> Option<Image> {
+        if aov == Aov::Variance {
+            let mut image = Image::new(self.width, self.height);
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let variance = self.variance(x, y);
+                    image.set_pixel(x, y, Color::new(variance, variance, variance));
+                }
+            }
+
+            return Some(image);
+        }
+
+        if aov == Aov::SampleCount {
+            let mut image = Image::new(self.width, self.height);
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let count = self.sample_count(x, y) as f64;
+                    image.set_pixel(x, y, Color::new(count, count, count));
+                }
+            }
+
+            return Some(image);
+        }
+
+        let (_, sums) = self.aovs.iter().find(|(recorded, _)| *recorded == aov)?;
+
+        let mut image = Image::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let count = u32::max(self.sample_count(x, y), 1);
+                image.set_pixel(x, y, sums[y * self.width + x] / count as f64);
+            }
+        }
+
+        Some(image)
+    }
+}
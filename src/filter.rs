@@ -0,0 +1,78 @@
+use serde::{Serialize, Deserialize};
+use std::str::FromStr;
+
+// Pixel reconstruction filters, weighting each sample by its distance to the pixel centers around it.
+// The value is the filter radius in pixels.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Filter {
+    Box(f64),       // The default with a radius of 0.5, each sample only counts for its own pixel
+    Tent(f64),
+    Gaussian(f64),
+    Mitchell(f64)   // Mitchell-Netravali with B = C = 1/3, sharper but can ring around edges
+}
+
+impl Filter {
+    pub fn radius(&self) -> f64 {
+        match self {
+            Filter::Box(radius) | Filter::Tent(radius) | Filter::Gaussian(radius) | Filter::Mitchell(radius) => *radius
+        }
+    }
+
+    // Weight of a sample at offset dx, dy from a pixel center
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: f64) -> f64 {
+        let d = d.abs();
+
+        match self {
+            Filter::Box(radius) => if d <= *radius { 1.0 } else { 0.0 },
+            Filter::Tent(radius) => f64::max(radius - d, 0.0),
+            Filter::Gaussian(radius) => {
+                // Shifted down so that the weight reaches zero at the radius instead of being cut off
+                let sigma = radius / 3.0;
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                f64::max(gaussian(d) - gaussian(*radius), 0.0)
+            },
+            Filter::Mitchell(radius) => mitchell(2.0 * d / radius)
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    // Either a filter name, or a name and a radius like gaussian:1.5
+    fn from_str(s: &str) -> Result<Filter, String> {
+        let (name, radius) = match s.split_once(':') {
+            Some((name, radius)) => match radius.parse::<f64>() {
+                Ok(radius) if radius > 0.0 => (name, Some(radius)),
+                _ => return Err(format!("Invalid filter radius {}", radius))
+            },
+            None => (s, None)
+        };
+
+        match name {
+            "box" => Ok(Filter::Box(radius.unwrap_or(0.5))),
+            "tent" => Ok(Filter::Tent(radius.unwrap_or(1.0))),
+            "gaussian" => Ok(Filter::Gaussian(radius.unwrap_or(1.5))),
+            "mitchell" => Ok(Filter::Mitchell(radius.unwrap_or(2.0))),
+            _ => Err(format!("Unknown filter {}", name))
+        }
+    }
+}
+
+// Mitchell-Netravali cubic over [0, 2]
+fn mitchell(x: f64) -> f64 {
+    const B: f64 = 1.0 / 3.0;
+    const C: f64 = 1.0 / 3.0;
+
+    if x < 1.0 {
+        ((12.0 - 9.0 * B - 6.0 * C) * x * x * x + (-18.0 + 12.0 * B + 6.0 * C) * x * x + (6.0 - 2.0 * B)) / 6.0
+    } else if x < 2.0 {
+        ((-B - 6.0 * C) * x * x * x + (6.0 * B + 30.0 * C) * x * x + (-12.0 * B - 48.0 * C) * x + (8.0 * B + 24.0 * C)) / 6.0
+    } else {
+        0.0
+    }
+}
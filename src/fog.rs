@@ -0,0 +1,87 @@
+use crate::math::*;
+use crate::ray::*;
+use crate::material::*;
+use std::str::FromStr;
+
+// Fog as given on the command line: density[,scale height]
+#[derive(Copy, Clone, Debug)]
+pub struct FogParams {
+    pub density: f64,
+    pub scale_height: f64
+}
+
+impl FromStr for FogParams {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FogParams, String> {
+        let values = s.split(',')
+            .map(|value| value.trim().parse::<f64>().map_err(|_| format!("Invalid number {}", value)))
+            .collect::<Result<Vec<f64>, String>>()?;
+
+        let params = match values[..] {
+            [density] => FogParams { density, scale_height: INFINITY },
+            [density, scale_height] => FogParams { density, scale_height },
+            _ => return Err(format!("Expected density[,scale height] but got {}", s))
+        };
+
+        if params.density < 0.0 {
+            return Err(String::from("The fog density cannot be negative"));
+        }
+
+        if params.scale_height <= 0.0 {
+            return Err(String::from("The fog scale height must be above 0"));
+        }
+
+        Ok(params)
+    }
+}
+
+// Medium filling all the space around the objects. It scatters by the density per unit distance at
+// height 0, thinning out by a factor of e every scale height up and thickening the same way down, like
+// haze lying over the ground. An infinite scale height makes it the same everywhere.
+pub struct Fog {
+    pub density: f64,
+    pub scale_height: f64,
+    pub phase_function: MaterialHandle
+}
+
+impl Fog {
+    // How far along the ray from t_min it scatters, in units of t, if at all. The optical depth to t
+    // has a closed form under an exponential falloff, which is inverted for -ln(1 - r).
+    pub fn sample_distance(&self, ray: &Ray, t_min: f64, r: f64) -> Option<f64> {
+        if self.density <= 0.0 {
+            return None;
+        }
+
+        let a = self.density_at(&ray.at(t_min)) * ray.direction.length();
+        let k = ray.direction.y / self.scale_height;
+        let optical_depth = -f64::ln(1.0 - r);
+
+        if k.abs() < 1.0e-9 {
+            return Some(optical_depth / a);
+        }
+
+        // Going up, the fog thins out quickly enough that a ray may never scatter
+        let remaining = 1.0 - optical_depth * k / a;
+        if remaining <= 0.0 {
+            return None;
+        }
+
+        Some(-remaining.ln() / k)
+    }
+
+    // Scattering per unit distance at a point
+    pub fn density_at(&self, p: &Point3) -> f64 {
+        self.density * (-p.y / self.scale_height).exp()
+    }
+
+    // Share of the light making it through the fog between two points of the ray
+    pub fn transmittance(&self, ray: &Ray, t_0: f64, t_1: f64) -> f64 {
+        let a = self.density_at(&ray.at(t_0)) * ray.direction.length();
+        let k = ray.direction.y / self.scale_height;
+        let t = t_1 - t_0;
+
+        let optical_depth = if k.abs() < 1.0e-9 { a * t } else { a * (1.0 - (-k * t).exp()) / k };
+        (-optical_depth).exp()
+    }
+}
@@ -0,0 +1,74 @@
+use crate::math::*;
+
+// Complex index of refraction of a metal at the red, green and blue wavelengths. The extinction
+// coefficient k is how quickly light dies out inside, and together with eta it decides how much is
+// reflected at each angle, more and whiter towards grazing angles.
+#[derive(Copy, Clone, Debug)]
+pub struct Conductor {
+    pub eta: Color,
+    pub k: Color
+}
+
+impl Conductor {
+    // Measured values at 650, 550 and 450 nm
+    pub fn gold() -> Conductor {
+        Conductor { eta: Color::new(0.143, 0.374, 1.442), k: Color::new(3.983, 2.385, 1.603) }
+    }
+
+    pub fn copper() -> Conductor {
+        Conductor { eta: Color::new(0.200, 0.924, 1.102), k: Color::new(3.912, 2.452, 2.142) }
+    }
+
+    pub fn aluminium() -> Conductor {
+        Conductor { eta: Color::new(1.657, 0.880, 0.521), k: Color::new(9.224, 6.270, 4.837) }
+    }
+
+    pub fn silver() -> Conductor {
+        Conductor { eta: Color::new(0.155, 0.117, 0.138), k: Color::new(4.828, 3.122, 2.147) }
+    }
+
+    // A metal reflecting the given color straight on, with the edge tint of the same color, from
+    // Gulbrandsen, Artist Friendly Metallic Fresnel
+    pub fn from_reflectivity(reflectivity: &Color) -> Conductor {
+        let mut eta = Color::new(0.0, 0.0, 0.0);
+        let mut k = Color::new(0.0, 0.0, 0.0);
+
+        for channel in 0..3 {
+            let r = clamp(reflectivity[channel], 0.0, 0.99);
+            let g = r;
+            let n = g * (1.0 - r) / (1.0 + r) + (1.0 - g) * (1.0 + r.sqrt()) / (1.0 - r.sqrt());
+
+            eta[channel] = n;
+            k[channel] = f64::max((r * (n + 1.0) * (n + 1.0) - (n - 1.0) * (n - 1.0)) / (1.0 - r), 0.0).sqrt();
+        }
+
+        Conductor { eta, k }
+    }
+
+    // Share of unpolarized light reflected at this cosine of the angle to the normal
+    pub fn reflectance(&self, cos_theta: f64) -> Color {
+        let cos2 = clamp(cos_theta, 0.0, 1.0).powi(2);
+        let sin2 = 1.0 - cos2;
+
+        let mut result = Color::new(0.0, 0.0, 0.0);
+        for channel in 0..3 {
+            let eta2 = self.eta[channel] * self.eta[channel];
+            let k2 = self.k[channel] * self.k[channel];
+
+            let t0 = eta2 - k2 - sin2;
+            let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+            let t1 = a2_plus_b2 + cos2;
+            let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+            let t2 = 2.0 * cos2.sqrt() * a;
+            let rs = (t1 - t2) / (t1 + t2);
+
+            let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+            let t4 = t2 * sin2;
+            let rp = rs * (t3 - t4) / (t3 + t4);
+
+            result[channel] = 0.5 * (rs + rp);
+        }
+
+        result
+    }
+}
@@ -0,0 +1,780 @@
+use crate::math::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::texture::*;
+use crate::mesh::*;
+use crate::scene::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+// The parts of a glTF 2.0 file the importer uses, everything else is ignored
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    scene: Option<usize>,
+    #[serde(default)]
+    scenes: Vec<SceneDef>,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    meshes: Vec<MeshDef>,
+    #[serde(default)]
+    accessors: Vec<Accessor>,
+    #[serde(default)]
+    buffer_views: Vec<BufferView>,
+    #[serde(default)]
+    buffers: Vec<Buffer>,
+    #[serde(default)]
+    materials: Vec<MaterialDef>,
+    #[serde(default)]
+    textures: Vec<TextureDef>,
+    #[serde(default)]
+    images: Vec<ImageDef>,
+    #[serde(default)]
+    cameras: Vec<CameraDef>,
+    #[serde(default)]
+    extensions: DocumentExtensions
+}
+
+#[derive(Deserialize)]
+struct SceneDef {
+    #[serde(default)]
+    nodes: Vec<usize>
+}
+
+#[derive(Deserialize)]
+struct Node {
+    #[serde(default)]
+    children: Vec<usize>,
+    mesh: Option<usize>,
+    camera: Option<usize>,
+    matrix: Option<[f64; 16]>,
+    translation: Option<[f64; 3]>,
+    rotation: Option<[f64; 4]>,
+    scale: Option<[f64; 3]>,
+    #[serde(default)]
+    extensions: NodeExtensions
+}
+
+#[derive(Deserialize)]
+struct MeshDef {
+    primitives: Vec<Primitive>
+}
+
+#[derive(Deserialize)]
+struct Primitive {
+    attributes: HashMap<String, usize>,
+    indices: Option<usize>,
+    material: Option<usize>,
+    #[serde(default = "triangles_mode")]
+    mode: u32
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Accessor {
+    buffer_view: Option<usize>,
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32,
+    #[serde(default)]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: String,
+    sparse: Option<serde_json::Value>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: Option<usize>
+}
+
+#[derive(Deserialize)]
+struct Buffer {
+    uri: Option<String>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MaterialDef {
+    pbr_metallic_roughness: Option<Pbr>,
+    normal_texture: Option<TextureRef>,
+    emissive_texture: Option<TextureRef>,
+    #[serde(default)]
+    emissive_factor: [f64; 3],
+    #[serde(default)]
+    extensions: MaterialExtensions
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Pbr {
+    #[serde(default = "white")]
+    base_color_factor: [f64; 4],
+    base_color_texture: Option<TextureRef>,
+    #[serde(default = "one")]
+    metallic_factor: f64,
+    #[serde(default = "one")]
+    roughness_factor: f64,
+    metallic_roughness_texture: Option<TextureRef>
+}
+
+#[derive(Deserialize)]
+struct TextureRef {
+    index: usize
+}
+
+#[derive(Deserialize)]
+struct TextureDef {
+    source: Option<usize>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ImageDef {
+    uri: Option<String>,
+    buffer_view: Option<usize>
+}
+
+#[derive(Deserialize)]
+struct CameraDef {
+    perspective: Option<Perspective>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Perspective {
+    yfov: f64,
+    aspect_ratio: Option<f64>
+}
+
+#[derive(Deserialize, Default)]
+struct DocumentExtensions {
+    #[serde(rename = "KHR_lights_punctual")]
+    lights_punctual: Option<LightsPunctual>
+}
+
+#[derive(Deserialize)]
+struct LightsPunctual {
+    lights: Vec<LightDef>
+}
+
+#[derive(Deserialize)]
+struct LightDef {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default = "white_rgb")]
+    color: [f64; 3],
+    #[serde(default = "one")]
+    intensity: f64,
+    spot: Option<SpotDef>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SpotDef {
+    #[serde(default)]
+    inner_cone_angle: f64,
+    #[serde(default = "quarter_pi")]
+    outer_cone_angle: f64
+}
+
+#[derive(Deserialize, Default)]
+struct NodeExtensions {
+    #[serde(rename = "KHR_lights_punctual")]
+    light: Option<LightRef>
+}
+
+#[derive(Deserialize)]
+struct LightRef {
+    light: usize
+}
+
+#[derive(Deserialize, Default)]
+struct MaterialExtensions {
+    #[serde(rename = "KHR_materials_emissive_strength")]
+    emissive_strength: Option<EmissiveStrength>,
+    #[serde(rename = "KHR_materials_transmission")]
+    transmission: Option<Transmission>,
+    #[serde(rename = "KHR_materials_ior")]
+    ior: Option<Ior>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EmissiveStrength {
+    #[serde(default = "one")]
+    emissive_strength: f64
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Transmission {
+    #[serde(default)]
+    transmission_factor: f64
+}
+
+#[derive(Deserialize)]
+struct Ior {
+    #[serde(default = "default_ior")]
+    ior: f64
+}
+
+fn triangles_mode() -> u32 { 4 }
+fn one() -> f64 { 1.0 }
+fn white() -> [f64; 4] { [1.0; 4] }
+fn white_rgb() -> [f64; 3] { [1.0; 3] }
+fn quarter_pi() -> f64 { PI / 4.0 }
+fn default_ior() -> f64 { 1.5 }
+
+// Column major like glTF, so the last four values are the translation
+type Matrix = [f64; 16];
+
+const IDENTITY: Matrix = [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0];
+
+// A camera found in the file, placed by its node
+struct CameraPlacement {
+    look_from: Point3,
+    look_at: Point3,
+    vfov: f64,
+    aspect_ratio: Option<f64>
+}
+
+struct Importer {
+    document: Document,
+    directory: String,
+    buffers: Vec<Vec<u8>>,
+    world: World,
+    materials: HashMap<usize, MaterialHandle>,
+    textures: HashMap<usize, Texture>,
+    default_material: Option<MaterialHandle>,
+    camera: Option<CameraPlacement>
+}
+
+// Loads a glTF 2.0 file, as JSON with its buffers and images in separate files or embedded, or as a
+// binary .glb, into a scene that renders as it was laid out, e.g. in Blender. Every mesh placed by a
+// node becomes a mesh of triangles moved into the world by the transforms of the nodes above it, and
+// the materials become principled ones, see World::principled, or glass where they transmit light.
+// Lights come from KHR_lights_punctual with their photometric units taken as they are, and the view
+// from the first perspective camera, level with the horizon, or from the front of the scene if there
+// is none. Without lights it is lit by a sky.
+pub fn load_gltf(path: &str) -> Result<Scene, String> {
+    let bytes = std::fs::read(path).map_err(|err| format!("Could not read {}: {}", path, err))?;
+    let directory = Path::new(path).parent().map_or_else(String::new, |directory| directory.to_string_lossy().into_owned());
+
+    let (json, binary) = if bytes.starts_with(b"glTF") { split_glb(&bytes, path)? } else { (&bytes[..], None) };
+    let document: Document = serde_json::from_slice(json).map_err(|err| format!("Invalid glTF {}: {}", path, err))?;
+
+    let mut buffers = Vec::with_capacity(document.buffers.len());
+    for buffer in &document.buffers {
+        buffers.push(match &buffer.uri {
+            Some(uri) => read_uri(uri, &directory)?,
+            None => binary.map(|binary| binary.to_vec()).ok_or_else(|| format!("{} has a buffer without data", path))?
+        });
+    }
+
+    let mut importer = Importer {
+        document,
+        directory,
+        buffers,
+        world: World::new(),
+        materials: HashMap::new(),
+        textures: HashMap::new(),
+        default_material: None,
+        camera: None
+    };
+
+    let roots = match importer.document.scene.or(if importer.document.scenes.is_empty() { None } else { Some(0) }) {
+        Some(scene) => importer.document.scenes.get(scene).ok_or_else(|| format!("{} has no scene {}", path, scene))?.nodes.clone(),
+        // Without scenes every node that is not a child of another is a root
+        None => {
+            let children: Vec<usize> = importer.document.nodes.iter().flat_map(|node| node.children.iter().copied()).collect();
+            (0..importer.document.nodes.len()).filter(|node| !children.contains(node)).collect()
+        }
+    };
+
+    for root in roots {
+        importer.add_node(root, &IDENTITY, 0)?;
+    }
+
+    trace!("Loaded {} with {} meshes and {} materials", path, importer.world.hittables.len(), importer.world.materials.len());
+
+    let has_lights = !importer.world.analytic_lights.is_empty();
+    let (look_from, look_at, vfov, aspect_ratio) = match importer.camera {
+        Some(camera) => (camera.look_from, camera.look_at, camera.vfov, camera.aspect_ratio.unwrap_or(16.0 / 9.0)),
+        None => {
+            let (look_from, look_at) = frame(&importer.world.hittables, 40.0);
+            (look_from, look_at, 40.0, 16.0 / 9.0)
+        }
+    };
+
+    Ok(Scene {
+        name: "gltf",
+        aspect_ratio,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: if has_lights { Color::new(0.0, 0.0, 0.0) } else { Color::new(0.7, 0.8, 1.0) },
+        look_from,
+        look_at,
+        vfov,
+        world: Arc::new(importer.world)
+    })
+}
+
+impl Importer {
+    fn add_node(&mut self, index: usize, parent: &Matrix, depth: usize) -> Result<(), String> {
+        // The format forbids cycles, this only keeps a broken file from overflowing the stack
+        if depth > 256 {
+            return Err(String::from("Nodes of the glTF file are nested too deep"));
+        }
+
+        let node = self.document.nodes.get(index).ok_or_else(|| format!("Missing node {}", index))?;
+        let local = match node.matrix {
+            Some(matrix) => matrix,
+            None => from_trs(node.translation.unwrap_or([0.0; 3]), node.rotation.unwrap_or([0.0, 0.0, 0.0, 1.0]), node.scale.unwrap_or([1.0; 3]))
+        };
+        let transform = multiply(parent, &local);
+        let (mesh, camera, light, children) = (node.mesh, node.camera, node.extensions.light.as_ref().map(|light| light.light), node.children.clone());
+
+        if let Some(mesh) = mesh {
+            self.add_mesh(mesh, &transform)?;
+        }
+
+        if let Some(camera) = camera {
+            self.place_camera(camera, &transform);
+        }
+
+        if let Some(light) = light {
+            self.add_light(light, &transform)?;
+        }
+
+        for child in children {
+            self.add_node(child, &transform, depth + 1)?;
+        }
+
+        Ok(())
+    }
+
+    fn add_mesh(&mut self, index: usize, transform: &Matrix) -> Result<(), String> {
+        let primitive_count = self.document.meshes.get(index).ok_or_else(|| format!("Missing mesh {}", index))?.primitives.len();
+
+        // A mirroring transform turns the triangles inside out, which swapping two corners undoes
+        let mirrored = determinant(transform) < 0.0;
+
+        for p in 0..primitive_count {
+            let primitive = &self.document.meshes[index].primitives[p];
+            if primitive.mode != 4 {
+                error!("Skipping a primitive of mesh {} drawn as mode {}, only triangles are supported", index, primitive.mode);
+                continue;
+            }
+
+            let position_accessor = *primitive.attributes.get("POSITION").ok_or_else(|| format!("Mesh {} has a primitive without positions", index))?;
+            let (normal_accessor, uv_accessor) = (primitive.attributes.get("NORMAL").copied(), primitive.attributes.get("TEXCOORD_0").copied());
+            let (index_accessor, material) = (primitive.indices, primitive.material);
+
+            let positions: Vec<Point3> = self.read_accessor(position_accessor, 3)?
+                .chunks(3)
+                .map(|p| transform_point(transform, &Point3::new(p[0], p[1], p[2])))
+                .collect();
+
+            let normals = match normal_accessor {
+                Some(accessor) => Some(self.read_accessor(accessor, 3)?
+                    .chunks(3)
+                    .map(|n| transform_normal(transform, &Vector3::new(n[0], n[1], n[2])))
+                    .collect::<Vec<Vector3>>()),
+                None => None
+            };
+
+            // Texture coordinates of glTF go down from the top of the image, here they go up from the bottom
+            let uvs = match uv_accessor {
+                Some(accessor) => Some(self.read_accessor(accessor, 2)?.chunks(2).map(|uv| (uv[0], 1.0 - uv[1])).collect::<Vec<(f64, f64)>>()),
+                None => None
+            };
+
+            let flat_indices: Vec<u32> = match index_accessor {
+                Some(accessor) => self.read_accessor(accessor, 1)?.iter().map(|&index| index as u32).collect(),
+                None => (0..positions.len() as u32).collect()
+            };
+            let indices: Vec<[u32; 3]> = flat_indices.chunks_exact(3)
+                .map(|triangle| if mirrored { [triangle[0], triangle[2], triangle[1]] } else { [triangle[0], triangle[1], triangle[2]] })
+                .collect();
+
+            if indices.is_empty() {
+                continue;
+            }
+
+            let mat_handle = match material {
+                Some(material) => self.material(material)?,
+                None => self.default_material()
+            };
+
+            let mesh = Mesh::new(positions, normals, uvs, indices, mat_handle).map_err(|err| format!("Mesh {}: {}", index, err))?;
+            self.world.hittables.push(Hittable::new_mesh(mesh));
+        }
+
+        Ok(())
+    }
+
+    fn place_camera(&mut self, index: usize, transform: &Matrix) {
+        if self.camera.is_some() {
+            return;
+        }
+
+        let perspective = match self.document.cameras.get(index).and_then(|camera| camera.perspective.as_ref()) {
+            Some(perspective) => perspective,
+            None => {
+                error!("Skipping camera {}, only perspective cameras are supported", index);
+                return;
+            }
+        };
+
+        // Cameras look down their -z axis
+        let look_from = transform_point(transform, &Point3::new(0.0, 0.0, 0.0));
+        let forward = Vector3::normalize(&transform_vector(transform, &Vector3::new(0.0, 0.0, -1.0)));
+        self.camera = Some(CameraPlacement {
+            look_from,
+            look_at: look_from + forward,
+            vfov: perspective.yfov.to_degrees(),
+            aspect_ratio: perspective.aspect_ratio
+        });
+    }
+
+    fn add_light(&mut self, index: usize, transform: &Matrix) -> Result<(), String> {
+        let light = self.document.extensions.lights_punctual.as_ref()
+            .and_then(|lights| lights.lights.get(index))
+            .ok_or_else(|| format!("Missing light {}", index))?;
+
+        let color = light.intensity * Color::new(light.color[0], light.color[1], light.color[2]);
+        // Lights shine down their -z axis
+        let position = transform_point(transform, &Point3::new(0.0, 0.0, 0.0));
+        let forward = Vector3::normalize(&transform_vector(transform, &Vector3::new(0.0, 0.0, -1.0)));
+
+        match light.kind.as_str() {
+            "point" => self.world.add_point_light(position, color),
+            "spot" => {
+                let (inner, outer) = light.spot.as_ref().map_or((0.0, PI / 4.0), |spot| (spot.inner_cone_angle, spot.outer_cone_angle));
+                self.world.add_spot_light(position, position + forward, inner.to_degrees(), outer.to_degrees(), color);
+            },
+            // About the size of the real sun
+            "directional" => self.world.add_sun(-forward, 0.53, color),
+            kind => error!("Skipping light {} of unknown type {}", index, kind)
+        }
+
+        Ok(())
+    }
+
+    fn material(&mut self, index: usize) -> Result<MaterialHandle, String> {
+        if let Some(mat_handle) = self.materials.get(&index) {
+            return Ok(*mat_handle);
+        }
+
+        let (pbr, normal_texture, emissive_texture, emissive_factor, emissive_strength, transmission, ior) = {
+            let material = self.document.materials.get(index).ok_or_else(|| format!("Missing material {}", index))?;
+            let pbr = material.pbr_metallic_roughness.as_ref();
+            (
+                (
+                    pbr.map_or([1.0; 4], |pbr| pbr.base_color_factor),
+                    pbr.and_then(|pbr| pbr.base_color_texture.as_ref().map(|texture| texture.index)),
+                    pbr.map_or(1.0, |pbr| pbr.metallic_factor),
+                    pbr.map_or(1.0, |pbr| pbr.roughness_factor),
+                    pbr.and_then(|pbr| pbr.metallic_roughness_texture.as_ref().map(|texture| texture.index))
+                ),
+                material.normal_texture.as_ref().map(|texture| texture.index),
+                material.emissive_texture.as_ref().map(|texture| texture.index),
+                material.emissive_factor,
+                material.extensions.emissive_strength.as_ref().map_or(1.0, |strength| strength.emissive_strength),
+                material.extensions.transmission.as_ref().map_or(0.0, |transmission| transmission.transmission_factor),
+                material.extensions.ior.as_ref().map_or(1.5, |ior| ior.ior)
+            )
+        };
+        let (base_color_factor, base_color_texture, metallic_factor, roughness_factor, metallic_roughness_texture) = pbr;
+
+        let emission = emissive_strength * Color::new(emissive_factor[0], emissive_factor[1], emissive_factor[2]);
+        let mat_handle = if emission.length_squared() > 0.0 {
+            let emit = self.scaled_texture(emissive_texture, emission)?;
+            self.world.diffuse_light(emit)
+        } else if transmission > 0.0 {
+            let tint = Color::new(base_color_factor[0], base_color_factor[1], base_color_factor[2]);
+            self.world.tinted_dielectric(ior, tint, 1.0)
+        } else {
+            let base_color = self.scaled_texture(base_color_texture, Color::new(base_color_factor[0], base_color_factor[1], base_color_factor[2]))?;
+            let channel = |importer: &mut Importer, channel: usize, factor: f64| -> Result<Texture, String> {
+                Ok(match metallic_roughness_texture {
+                    Some(texture) => Texture::Range { texture: Box::new(Texture::Channel { texture: Box::new(importer.texture(texture)?), channel }), low: 0.0, high: factor },
+                    None => Texture::solid(factor, factor, factor)
+                })
+            };
+            // Roughness is in the green channel and metalness in the blue
+            let roughness = channel(self, 1, roughness_factor)?;
+            let metallic = channel(self, 2, metallic_factor)?;
+            self.world.principled(base_color, metallic, roughness, ior)
+        };
+
+        let mat_handle = match normal_texture {
+            Some(texture) => {
+                let normal_map = self.texture(texture)?;
+                self.world.normal_mapped(mat_handle, normal_map)
+            },
+            None => mat_handle
+        };
+
+        self.materials.insert(index, mat_handle);
+        Ok(mat_handle)
+    }
+
+    // What glTF shows for primitives without a material, though as the diffuse gray of Blender rather
+    // than the rough white metal of the defaults of its material
+    fn default_material(&mut self) -> MaterialHandle {
+        match self.default_material {
+            Some(mat_handle) => mat_handle,
+            None => {
+                let mat_handle = self.world.lambertian(Texture::solid(0.8, 0.8, 0.8));
+                self.default_material = Some(mat_handle);
+                mat_handle
+            }
+        }
+    }
+
+    fn scaled_texture(&mut self, texture: Option<usize>, scale: Color) -> Result<Texture, String> {
+        Ok(match texture {
+            Some(texture) if scale.x == 1.0 && scale.y == 1.0 && scale.z == 1.0 => self.texture(texture)?,
+            Some(texture) => Texture::Scaled { texture: Box::new(self.texture(texture)?), scale },
+            None => Texture::SolidColor(scale)
+        })
+    }
+
+    fn texture(&mut self, index: usize) -> Result<Texture, String> {
+        let image = self.document.textures.get(index).and_then(|texture| texture.source).ok_or_else(|| format!("Texture {} has no image", index))?;
+        if let Some(texture) = self.textures.get(&image) {
+            return Ok(texture.clone());
+        }
+
+        let definition = self.document.images.get(image).ok_or_else(|| format!("Missing image {}", image))?;
+        let (bytes, name) = match (&definition.uri, definition.buffer_view) {
+            (Some(uri), _) => (read_uri(uri, &self.directory)?, uri.clone()),
+            (None, Some(view)) => (self.read_view(view)?.to_vec(), format!("image {}", image)),
+            (None, None) => return Err(format!("Image {} has no data", image))
+        };
+
+        let texture = Texture::load_image_from_memory(&bytes, &name);
+        self.textures.insert(image, texture.clone());
+        Ok(texture)
+    }
+
+    fn read_view(&self, index: usize) -> Result<&[u8], String> {
+        let view = self.document.buffer_views.get(index).ok_or_else(|| format!("Missing buffer view {}", index))?;
+        let buffer = self.buffers.get(view.buffer).ok_or_else(|| format!("Missing buffer {}", view.buffer))?;
+        buffer.get(view.byte_offset..view.byte_offset + view.byte_length).ok_or_else(|| format!("Buffer view {} is out of its buffer", index))
+    }
+
+    // The values of an accessor as floats, checked to have the expected number of components each
+    fn read_accessor(&self, index: usize, components: usize) -> Result<Vec<f64>, String> {
+        let accessor = self.document.accessors.get(index).ok_or_else(|| format!("Missing accessor {}", index))?;
+        if accessor.sparse.is_some() {
+            return Err(format!("Accessor {} is sparse, which is not supported", index));
+        }
+
+        let expected = match components { 1 => "SCALAR", 2 => "VEC2", _ => "VEC3" };
+        if accessor.kind != expected {
+            return Err(format!("Accessor {} is a {} where a {} was expected", index, accessor.kind, expected));
+        }
+
+        let size = match accessor.component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            other => return Err(format!("Accessor {} has unknown component type {}", index, other))
+        };
+
+        // Without a buffer view all the values are zero
+        let view_index = match accessor.buffer_view {
+            Some(view) => view,
+            None => return Ok(vec![0.0; accessor.count * components])
+        };
+        let view = self.read_view(view_index)?;
+        let stride = self.document.buffer_views[view_index].byte_stride.unwrap_or(size * components);
+
+        if accessor.count > 0 && accessor.byte_offset + (accessor.count - 1) * stride + size * components > view.len() {
+            return Err(format!("Accessor {} is out of its buffer view", index));
+        }
+
+        let mut values = Vec::with_capacity(accessor.count * components);
+        for i in 0..accessor.count {
+            for c in 0..components {
+                let offset = accessor.byte_offset + i * stride + c * size;
+                let b = &view[offset..offset + size];
+                let value = match accessor.component_type {
+                    5120 => b[0] as i8 as f64,
+                    5121 => b[0] as f64,
+                    5122 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    5123 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64
+                };
+
+                // Normalized integers stand for 0 to 1, or -1 to 1 when signed
+                values.push(if accessor.normalized {
+                    match accessor.component_type {
+                        5120 => f64::max(value / 127.0, -1.0),
+                        5121 => value / 255.0,
+                        5122 => f64::max(value / 32767.0, -1.0),
+                        5123 => value / 65535.0,
+                        _ => value
+                    }
+                } else {
+                    value
+                });
+            }
+        }
+
+        Ok(values)
+    }
+}
+
+// The JSON and binary chunks of a .glb: a 12 byte header, then chunks of their length, their type and
+// their data
+fn split_glb<'a>(bytes: &'a [u8], path: &str) -> Result<(&'a [u8], Option<&'a [u8]>), String> {
+    let int = |offset: usize| bytes.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+
+    if int(4) != Some(2) {
+        return Err(format!("{} is not a version 2 glTF binary", path));
+    }
+
+    let mut json = None;
+    let mut binary = None;
+    let mut offset = 12;
+    while let (Some(length), Some(kind)) = (int(offset), int(offset + 4)) {
+        let data = bytes.get(offset + 8..offset + 8 + length).ok_or_else(|| format!("{} ends inside a chunk", path))?;
+        match kind {
+            0x4E4F534A => json = Some(data),
+            0x004E4942 => binary = Some(data),
+            _ => {}
+        }
+        offset += 8 + length;
+    }
+
+    Ok((json.ok_or_else(|| format!("{} has no JSON chunk", path))?, binary))
+}
+
+// Data of a buffer or image, embedded as base64 or in a file next to the glTF file
+fn read_uri(uri: &str, directory: &str) -> Result<Vec<u8>, String> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, encoded) = data.split_once(";base64,").ok_or_else(|| String::from("Embedded glTF data is not base64"))?;
+        return decode_base64(encoded);
+    }
+
+    let path = Path::new(directory).join(decode_percent(uri));
+    std::fs::read(&path).map_err(|err| format!("Could not read {}: {}", path.display(), err))
+}
+
+fn decode_base64(encoded: &str) -> Result<Vec<u8>, String> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None
+    };
+
+    let mut bytes = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in encoded.bytes().filter(|&c| c != b'=' && !c.is_ascii_whitespace()) {
+        bits = (bits << 6) | value(c).ok_or_else(|| String::from("Invalid base64 in glTF data"))? as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+
+    Ok(bytes)
+}
+
+// File names in URIs have spaces and such escaped as %20
+fn decode_percent(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' { uri.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok()) } else { None };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Looks at the middle of the bounds of the objects from in front of them, far enough to see them all
+fn frame(hittables: &[Hittable], vfov: f64) -> (Point3, Point3) {
+    let bounds = hittables_bounding_box(hittables, 0.0, 1.0);
+    let (center, radius) = match bounds {
+        Some(aabb) => (0.5 * (aabb.minimum + aabb.maximum), 0.5 * (aabb.maximum - aabb.minimum).length()),
+        None => (Point3::new(0.0, 0.0, 0.0), 1.0)
+    };
+
+    let distance = 1.1 * radius / degrees_to_radians(vfov / 2.0).sin();
+    let direction = Vector3::normalize(&Vector3::new(0.0, 0.3, 1.0));
+    (center + distance * direction, center)
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut result = [0.0; 16];
+    for column in 0..4 {
+        for row in 0..4 {
+            result[4 * column + row] = (0..4).map(|k| a[4 * k + row] * b[4 * column + k]).sum();
+        }
+    }
+    result
+}
+
+// Scales, then rotates by the unit quaternion x, y, z, w, then translates
+fn from_trs(t: [f64; 3], q: [f64; 4], s: [f64; 3]) -> Matrix {
+    let [x, y, z, w] = q;
+    [
+        (1.0 - 2.0 * (y * y + z * z)) * s[0], 2.0 * (x * y + z * w) * s[0], 2.0 * (x * z - y * w) * s[0], 0.0,
+        2.0 * (x * y - z * w) * s[1], (1.0 - 2.0 * (x * x + z * z)) * s[1], 2.0 * (y * z + x * w) * s[1], 0.0,
+        2.0 * (x * z + y * w) * s[2], 2.0 * (y * z - x * w) * s[2], (1.0 - 2.0 * (x * x + y * y)) * s[2], 0.0,
+        t[0], t[1], t[2], 1.0
+    ]
+}
+
+fn column(m: &Matrix, i: usize) -> Vector3 {
+    Vector3::new(m[4 * i], m[4 * i + 1], m[4 * i + 2])
+}
+
+fn transform_point(m: &Matrix, p: &Point3) -> Point3 {
+    transform_vector(m, p) + column(m, 3)
+}
+
+fn transform_vector(m: &Matrix, v: &Vector3) -> Vector3 {
+    v.x * column(m, 0) + v.y * column(m, 1) + v.z * column(m, 2)
+}
+
+// By the inverse transpose, which is the matrix of cofactors divided by the determinant, so that normals
+// stay perpendicular to surfaces that are scaled unevenly
+fn transform_normal(m: &Matrix, n: &Vector3) -> Vector3 {
+    let (a, b, c) = (column(m, 0), column(m, 1), column(m, 2));
+    let normal = n.x * Vector3::cross(&b, &c) + n.y * Vector3::cross(&c, &a) + n.z * Vector3::cross(&a, &b);
+    Vector3::normalize(&(determinant(m).signum() * normal))
+}
+
+fn determinant(m: &Matrix) -> f64 {
+    Vector3::dot(&column(m, 0), &Vector3::cross(&column(m, 1), &column(m, 2)))
+}
@@ -2,16 +2,25 @@ use crate::math::*;
 use crate::ray::*;
 use crate::material::*;
 use crate::aabb::*;
+use crate::bvh::*;
+use crate::logging::*;
+use crate::sampler::*;
+use crate::volume::*;
+use crate::mesh::*;
+use std::sync::Arc;
 
 #[derive(Default)]
 pub struct HitRecord {
     pub point: Point3,
     pub normal: Vector3,
+    pub tangent: Vector3,   // Unit direction along the surface in which u grows
+    pub bitangent: Vector3, // And in which v grows, for normal maps
     pub t: f64,
     pub front_face: bool,
     pub mat_handle: MaterialHandle,
     pub u: f64,
-    pub v: f64
+    pub v: f64,
+    pub object_id: usize    // Index of the top level hittable that was hit
 }
 
 impl HitRecord {
@@ -21,7 +30,7 @@ impl HitRecord {
         }
     }
     pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: &Vector3) {
-        self.front_face = Vector3::dot(&ray.direction, &outward_normal) < 0.0;
+        self.front_face = Vector3::dot(&ray.direction, outward_normal) < 0.0;
         self.normal = if self.front_face { *outward_normal } else { -outward_normal };
     }
 }
@@ -30,23 +39,37 @@ impl HitRecord {
 pub enum Hittable {
     Sphere          { mat_handle: MaterialHandle, center: Point3, radius: f64 },
     MovingSphere    { mat_handle: MaterialHandle, center_0: Point3, center_1: Point3, time_0: f64, time_1: f64, radius: f64 },
-    BvhNode         { left: Box<Hittable>, right: Box<Hittable>, aabb_box: AABB },
+    Bvh             { bvh: Arc<Bvh> },
     XYRect          { mat_handle: MaterialHandle, x0: f64, x1: f64, y0: f64, y1: f64, k: f64 },
     XZRect          { mat_handle: MaterialHandle, x0: f64, x1: f64, z0: f64, z1: f64, k: f64 },
     YZRect          { mat_handle: MaterialHandle, y0: f64, y1: f64, z0: f64, z1: f64, k: f64 },
-    Box             { mat_handle: MaterialHandle, min: Point3, max: Point3, sides: Vec<Hittable> },
+    // Front facing where the vertices go around counterclockwise. Normals at the vertices smooth the
+    // shading across it, and the texture coordinates at them map textures onto it.
+    Triangle        { mat_handle: MaterialHandle, vertices: [Point3; 3], normals: Option<[Vector3; 3]>, uvs: [(f64, f64); 3] },
+    Mesh            { mesh: Arc<Mesh> },
+    // Flat ring facing along its unit normal, or a full disk with an inner radius of 0. u goes around the
+    // normal and v runs from the outer edge in to the inner one.
+    Disk            { mat_handle: MaterialHandle, center: Point3, normal: Vector3, inner_radius: f64, outer_radius: f64 },
+    // Round tube of the height along its unit axis from the center of its base, closed at both ends by
+    // disks when capped. u goes around the axis and v up it, and the caps are mapped like disks.
+    Cylinder        { mat_handle: MaterialHandle, base: Point3, axis: Vector3, height: f64, radius: f64, capped: bool },
+    Box             { min: Point3, max: Point3, sides: Vec<Hittable> },
     Translate       { offset: Vector3, ptr: Box<Hittable> },
-    RotateY         { sin_theta: f64, cos_theta: f64, has_box: bool, bbox: AABB, ptr: Box<Hittable> },
-    ConstantMedium  { phase_function: MaterialHandle, boundary: Box<Hittable>, neg_inv_density: f64 }
+    FlipFace        { ptr: Box<Hittable> }, // Swaps the front and back faces, e.g. to point a one sided light into a room
+    RotateY         { sin_theta: f64, cos_theta: f64, ptr: Box<Hittable> },
+    ConstantMedium  { phase_function: MaterialHandle, boundary: Box<Hittable>, neg_inv_density: f64 },
+    // Medium whose density is read from a voxel grid and scaled, filling the grid's box
+    VoxelMedium     { phase_function: MaterialHandle, grid: Arc<VoxelGrid>, density_scale: f64 }
 }
 
-pub fn hit_hittables(hittables: &Vec<Hittable>, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+pub fn hit_hittables(hittables: &[Hittable], ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
     let mut closest_so_far = t_max;
     let mut rec: Option<HitRecord> = None;
 
-    for hittable in hittables {
-        if let Some(record) = hittable.hit(ray, t_min, closest_so_far) {
+    for (object_id, hittable) in hittables.iter().enumerate() {
+        if let Some(mut record) = hittable.hit(ray, t_min, closest_so_far) {
             closest_so_far = record.t;
+            record.object_id = object_id;
             rec = Some(record)
         }
     }
@@ -54,18 +77,17 @@ pub fn hit_hittables(hittables: &Vec<Hittable>, ray: &Ray, t_min: f64, t_max: f6
     rec
 }
 
-pub fn hittables_bounding_box(hittables: &Vec<Hittable>, time_0: f64, time_1: f64) -> Option<AABB> {
-    if hittables.len() == 0 {
-        return None;
-    }
-
-    let mut final_box = None;
+pub fn hittables_bounding_box(hittables: &[Hittable], time_0: f64, time_1: f64) -> Option<AABB> {
+    let mut final_box: Option<AABB> = None;
 
     for h in hittables {
         match h.bounding_box(time_0, time_1) {
             None => { return None; },
             Some(b) => {
-                final_box = Some(b);
+                final_box = match final_box {
+                    Some(f) => Some(AABB::surrounding_box(&f, &b)),
+                    None => Some(b)
+                };
             }
         }
     }
@@ -74,126 +96,76 @@ pub fn hittables_bounding_box(hittables: &Vec<Hittable>, time_0: f64, time_1: f6
 }
 
 impl Hittable {
-    pub fn new_bvh_node(list: &Vec<Hittable>, start: usize, end: usize, time_0: f64, time_1: f64) -> Hittable {
-        let mut cpy = list.clone();
-        let left;
-        let right;
+    // Builds a BVH over all the given hittables. The time range should match the camera shutter
+    // so that moving objects get bounding boxes covering their whole motion.
+    pub fn new_bvh(hittables: &[Hittable], time_0: f64, time_1: f64) -> Hittable {
+        let bvh = Bvh::build(hittables, time_0, time_1);
+        trace!("Built BVH over {} objects with {} nodes and a depth of {}", hittables.len(), bvh.node_count(), bvh.depth());
 
-        let axis = random_int_range(0, 2);
-        let comparator = match axis { 
-            0 => {
-                AABB::box_x_compare
-            },
-            1 => {
-                AABB::box_y_compare
-            },
-            _ => {
-                AABB::box_z_compare
-            }
-        };
-
-        let object_span = end - start;
-        if object_span == 1 { 
-            left = Box::new(cpy[start].clone());
-            right = Box::new(cpy[start].clone()); 
-        } else if object_span == 2 {
-            if comparator(&cpy[start], &cpy[start + 1]) == std::cmp::Ordering::Less {
-                left = Box::new(cpy[start].clone());
-                right = Box::new(cpy[start + 1].clone());
-            } else {
-                left = Box::new(cpy[start + 1].clone());
-                right = Box::new(cpy[start].clone());
-            }
-        } else {
-            unsafe {
-                cpy[start..end].sort_by(comparator);
-            }
-            let mid = start + object_span / 2;
-            left = Box::new(Self::new_bvh_node(&cpy, start, mid, time_0, time_1));
-            right = Box::new(Self::new_bvh_node(&cpy, mid, end, time_0, time_1));
-        }
+        Hittable::Bvh { bvh: Arc::new(bvh) }
+    }
 
-        let aabb_box = {
-            if let (Some(box_left), Some(box_right)) = (left.bounding_box(time_0, time_1), right.bounding_box(time_0, time_1)) {
-                AABB::surrounding_box(&box_left, &box_right)
-            } else {
-                eprintln!("No bounding box in BVHNode");
-                AABB::new(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0))
-            }
-        };
-        
-        Hittable::BvhNode {
-            left,
-            right,
-            aabb_box
+    // Places a copy of an object rotated around the y axis and moved by the offset. Cloning a BVH
+    // only shares its nodes, so an object can be instanced many times without building or storing
+    // its BVH again, and the top level BVH of the world then only sees the instance bounds.
+    pub fn new_instance(hittable: &Hittable, angle: f64, offset: Vector3) -> Hittable {
+        Hittable::Translate {
+            offset,
+            ptr: Box::new(Hittable::new_rotate_y(angle, hittable.clone()))
         }
     }
 
-    pub fn new_box(min: Point3, max: Point3, mat_handle: MaterialHandle) -> Hittable {
-        let mut sides = Vec::new();
-
-        sides.push(Hittable::XYRect { mat_handle, x0: min.x, x1: max.x, y0: min.y, y1: max.y, k: max.z });
-        sides.push(Hittable::XYRect { mat_handle, x0: min.x, x1: max.x, y0: min.y, y1: max.y, k: min.z });
+    pub fn new_flip_face(hittable: Hittable) -> Hittable {
+        Hittable::FlipFace { ptr: Box::new(hittable) }
+    }
 
-        sides.push(Hittable::XZRect { mat_handle, x0: min.x, x1: max.x, z0: min.z, z1: max.z, k: max.y });
-        sides.push(Hittable::XZRect { mat_handle, x0: min.x, x1: max.x, z0: min.z, z1: max.z, k: min.y });
+    // Flat shaded, with u and v running from the first vertex to the second and the third
+    pub fn new_triangle(v0: Point3, v1: Point3, v2: Point3, mat_handle: MaterialHandle) -> Hittable {
+        Hittable::Triangle { mat_handle, vertices: [v0, v1, v2], normals: None, uvs: [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)] }
+    }
 
-        sides.push(Hittable::YZRect { mat_handle, y0: min.y, y1: max.y, z0: min.z, z1: max.z, k: max.x });
-        sides.push(Hittable::YZRect { mat_handle, y0: min.y, y1: max.y, z0: min.z, z1: max.z, k: min.x });
+    // Cloning it only shares the vertex buffers and the BVH, like instancing a BVH
+    pub fn new_mesh(mesh: Mesh) -> Hittable {
+        trace!("Built mesh of {} triangles over {} vertices with {} BVH nodes", mesh.triangle_count(), mesh.vertex_count(), mesh.node_count());
 
-        Hittable::Box { mat_handle, min, max, sides }
+        Hittable::Mesh { mesh: Arc::new(mesh) }
     }
 
-    pub fn new_rotate_y(angle: f64, hittable: Hittable) -> Hittable {
-        let radians = degrees_to_radians(angle);
-        let sin_theta = f64::sin(radians);
-        let cos_theta = f64::cos(radians);
-
-        let has_box;
-        let aabb;
-        
-        if let Some(bbox) = hittable.bounding_box(0.0, 1.0) {
-            has_box = true;
-            aabb = bbox;
-        } else {
-            has_box = false;
-            aabb = AABB::new(Point3::new(0.0, 0.0, 0.0,), Point3::new(0.0, 0.0, 0.0));
-        }
+    pub fn new_disk(center: Point3, normal: Vector3, radius: f64, mat_handle: MaterialHandle) -> Hittable {
+        Hittable::new_annulus(center, normal, 0.0, radius, mat_handle)
+    }
 
-        let mut min = [f64::INFINITY; 3];
-        let mut max = [-f64::INFINITY; 3];
+    pub fn new_annulus(center: Point3, normal: Vector3, inner_radius: f64, outer_radius: f64, mat_handle: MaterialHandle) -> Hittable {
+        Hittable::Disk { mat_handle, center, normal: Vector3::normalize(&normal), inner_radius, outer_radius }
+    }
 
-        for i in 0..2 {
-            for j in 0..2 {
-                for k in 0..2 {
-                    let i = i as f64;
-                    let j = j as f64;
-                    let k = k as f64;
+    // From the center of one end to the center of the other, open to see inside unless capped
+    pub fn new_cylinder(base: Point3, top: Point3, radius: f64, capped: bool, mat_handle: MaterialHandle) -> Hittable {
+        let axis = top - base;
+        Hittable::Cylinder { mat_handle, base, axis: Vector3::normalize(&axis), height: axis.length(), radius, capped }
+    }
 
-                    let x = i * aabb.maximum.x + (1.0 - i) * aabb.minimum.x;
-                    let y = j * aabb.maximum.y + (1.0 - j) * aabb.minimum.y;
-                    let z = k * aabb.maximum.z + (1.0 - k) * aabb.minimum.z;
+    pub fn new_box(min: Point3, max: Point3, mat_handle: MaterialHandle) -> Hittable {
+        let sides = vec![
+            Hittable::XYRect { mat_handle, x0: min.x, x1: max.x, y0: min.y, y1: max.y, k: max.z },
+            Hittable::XYRect { mat_handle, x0: min.x, x1: max.x, y0: min.y, y1: max.y, k: min.z },
 
-                    let newx = cos_theta * x + sin_theta * z;
-                    let newz = -sin_theta * x + cos_theta * z;
+            Hittable::XZRect { mat_handle, x0: min.x, x1: max.x, z0: min.z, z1: max.z, k: max.y },
+            Hittable::XZRect { mat_handle, x0: min.x, x1: max.x, z0: min.z, z1: max.z, k: min.y },
 
-                    let tester = [newx, y, newz];
+            Hittable::YZRect { mat_handle, y0: min.y, y1: max.y, z0: min.z, z1: max.z, k: max.x },
+            Hittable::YZRect { mat_handle, y0: min.y, y1: max.y, z0: min.z, z1: max.z, k: min.x }
+        ];
 
-                    for c in 0..3 {
-                        min[c] = f64::min(min[c], tester[c]);
-                        max[c] = f64::max(max[c], tester[c]);
-                    }
-                }
-            }
-        }
+        Hittable::Box { min, max, sides }
+    }
 
-        let aabb = AABB::new(Point3::new(min[0], min[1], min[2]), Point3::new(max[0], max[1], max[2]));
+    pub fn new_rotate_y(angle: f64, hittable: Hittable) -> Hittable {
+        let radians = degrees_to_radians(angle);
 
         Hittable::RotateY {
-            sin_theta,
-            cos_theta,
-            has_box,
-            bbox: aabb,
+            sin_theta: f64::sin(radians),
+            cos_theta: f64::cos(radians),
             ptr: Box::new(hittable)
         }
     }
@@ -206,16 +178,26 @@ impl Hittable {
         }
     }
 
+    // The densities of the grid are multiplied by d, which sets how thick the medium looks for the units
+    // the grid was saved in
+    pub fn new_voxel_medium(grid: VoxelGrid, d: f64, mat_handle: MaterialHandle) -> Hittable {
+        Hittable::VoxelMedium {
+            phase_function: mat_handle,
+            grid: Arc::new(grid),
+            density_scale: d
+        }
+    }
+
     pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         match self {
             Hittable::Sphere { mat_handle, center, radius } => {
-                Self::sphere_hit(&center, *radius, ray, t_min, t_max, *mat_handle)
+                Self::sphere_hit(center, *radius, ray, t_min, t_max, *mat_handle)
             },
             Hittable::MovingSphere { mat_handle, center_0, center_1, time_0, time_1, radius } => {
                 Self::sphere_hit(&Self::get_center_at_time(center_0, center_1, *time_0, *time_1, ray.time), *radius, ray, t_min, t_max, *mat_handle)
             },
-            Hittable::BvhNode { left, right, aabb_box } => {
-                Self::bvh_node_hit(left, right, aabb_box, ray, t_min, t_max)
+            Hittable::Bvh { bvh } => {
+                bvh.hit(ray, t_min, t_max)
             },
             Hittable::XYRect { mat_handle, x0, x1, y0, y1, k } => {
                 Self::xy_rect_hit(*x0, *x1, *y0, *y1, *k, ray, t_min, t_max, *mat_handle)
@@ -226,27 +208,44 @@ impl Hittable {
             Hittable::YZRect { mat_handle, y0, y1, z0, z1, k } => {
                 Self::yz_rect_hit(*y0, *y1, *z0, *z1, *k, ray, t_min, t_max, *mat_handle)
             },
-            Hittable::Box { mat_handle, min, max, sides } => {
+            Hittable::Triangle { mat_handle, vertices, normals, uvs } => {
+                Self::triangle_hit(vertices, normals, uvs, ray, t_min, t_max, *mat_handle)
+            },
+            Hittable::Mesh { mesh } => {
+                mesh.hit(ray, t_min, t_max)
+            },
+            Hittable::Disk { mat_handle, center, normal, inner_radius, outer_radius } => {
+                Self::disk_hit(center, normal, *inner_radius, *outer_radius, ray, t_min, t_max, *mat_handle)
+            },
+            Hittable::Cylinder { mat_handle, base, axis, height, radius, capped } => {
+                Self::cylinder_hit(base, axis, *height, *radius, *capped, ray, t_min, t_max, *mat_handle)
+            },
+            Hittable::Box { sides, .. } => {
                 hit_hittables(sides, ray, t_min, t_max)
             },
             Hittable::Translate { offset, ptr } => {
                 let moved_ray = Ray::with_time(ray.origin - *offset, ray.direction, ray.time);
 
-                if let Some(mut rec) = ptr.hit(&moved_ray, t_min, t_max) {
+                // Moving does not turn the normal, and the object already knew which face was hit
+                ptr.hit(&moved_ray, t_min, t_max).map(|mut rec| {
                     rec.point += *offset;
-                    let normal = rec.normal;
-                    rec.set_face_normal(&moved_ray, &normal);
-
-                    Some(rec)
-                } else {
-                    None
-                }
+                    rec
+                })
             },
-            Hittable::RotateY { sin_theta, cos_theta, has_box: _, bbox: _, ptr } => {
+            Hittable::FlipFace { ptr } => {
+                ptr.hit(ray, t_min, t_max).map(|mut rec| {
+                    rec.front_face = !rec.front_face;
+                    rec
+                })
+            },
+            Hittable::RotateY { sin_theta, cos_theta, ptr } => {
                 Self::hit_rotate_y(*sin_theta, *cos_theta, ptr, ray, t_min, t_max)
             },
             Hittable::ConstantMedium { phase_function, boundary, neg_inv_density } => {
                 Self::hit_constant_medium(boundary, *phase_function, *neg_inv_density, ray, t_min, t_max)
+            },
+            Hittable::VoxelMedium { phase_function, grid, density_scale } => {
+                Self::hit_voxel_medium(grid, *phase_function, *density_scale, ray, t_min, t_max)
             }
         }
     }
@@ -284,27 +283,15 @@ impl Hittable {
         rec.u = u;
         rec.v = v;
 
-        Some(rec)
-    }
-
-    fn bvh_node_hit(left: &Box<Hittable>, right: &Box<Hittable>, aabb: &AABB, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        if !aabb.hit(ray, t_min, t_max) {
-            return None;
-        }
+        // Around the y axis, along the parallels, and up along the meridians
+        let tangent = Vector3::new(outward_normal.z, 0.0, -outward_normal.x);
+        rec.tangent = if tangent.length_squared() > 0.0 { Vector3::normalize(&tangent) } else { Vector3::new(1.0, 0.0, 0.0) };
+        rec.bitangent = Vector3::cross(&outward_normal, &rec.tangent);
 
-        if let Some(hit_left) = left.hit(ray, t_min, t_max) {
-            if let Some(hit_right) = right.hit(ray, t_min, hit_left.t) {
-                Some(hit_right)
-            } else {
-                Some(hit_left)
-            }
-        } else if let Some(hit_right) = right.hit(ray, t_min, t_max) {
-            Some(hit_right)
-        } else {
-            None
-        }
+        Some(rec)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn xy_rect_hit(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, ray: &Ray, t_min: f64, t_max: f64, mat_handle: MaterialHandle) -> Option<HitRecord> {
         let t = (k - ray.origin.z) / ray.direction.z;
         
@@ -325,12 +312,15 @@ impl Hittable {
         rec.t = t;
         let outward_normal = Vector3::new(0.0, 0.0, 1.0);
         rec.set_face_normal(ray, &outward_normal);
+        rec.tangent = Vector3::new(1.0, 0.0, 0.0);
+        rec.bitangent = Vector3::new(0.0, 1.0, 0.0);
         rec.mat_handle = mat_handle;
         rec.point = ray.at(t);
 
         Some(rec)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn xz_rect_hit(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, ray: &Ray, t_min: f64, t_max: f64, mat_handle: MaterialHandle) -> Option<HitRecord> {
         let t = (k - ray.origin.y) / ray.direction.y;
 
@@ -351,12 +341,15 @@ impl Hittable {
         rec.t = t;
         let outward_normal = Vector3::new(0.0, 1.0, 0.0);
         rec.set_face_normal(ray, &outward_normal);
+        rec.tangent = Vector3::new(1.0, 0.0, 0.0);
+        rec.bitangent = Vector3::new(0.0, 0.0, 1.0);
         rec.mat_handle = mat_handle;
         rec.point = ray.at(t);
 
         Some(rec)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn yz_rect_hit(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, ray: &Ray, t_min: f64, t_max: f64, mat_handle: MaterialHandle) -> Option<HitRecord> {
         let t = (k - ray.origin.x) / ray.direction.x;
 
@@ -377,13 +370,201 @@ impl Hittable {
         rec.t = t;
         let outward_normal = Vector3::new(1.0, 0.0, 0.0);
         rec.set_face_normal(ray, &outward_normal);
+        rec.tangent = Vector3::new(0.0, 1.0, 0.0);
+        rec.bitangent = Vector3::new(0.0, 0.0, 1.0);
         rec.mat_handle = mat_handle;
         rec.point = ray.at(t);
 
         Some(rec)
     }
 
-    fn hit_rotate_y(sin_theta: f64, cos_theta: f64, ptr: &Box<Hittable>, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    #[allow(clippy::too_many_arguments)]
+    fn triangle_hit(vertices: &[Point3; 3], normals: &Option<[Vector3; 3]>, uvs: &[(f64, f64); 3], ray: &Ray, t_min: f64, t_max: f64, mat_handle: MaterialHandle) -> Option<HitRecord> {
+        let (t, barycentric) = Self::triangle_intersect(vertices, ray, t_min, t_max)?;
+        Some(Self::triangle_record(vertices, normals.as_ref(), uvs, t, &barycentric, ray, mat_handle))
+    }
+
+    // Watertight intersection of Woop et al., Watertight Ray/Triangle Intersection: the vertices are moved
+    // into a space where the ray runs along z from the origin, where the edge functions decide the hit
+    // exactly the same for triangles sharing an edge, so rays never slip through between them. Gives the
+    // distance and the barycentric coordinates of the hit.
+    pub fn triangle_intersect(vertices: &[Point3; 3], ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, [f64; 3])> {
+        let d = ray.direction;
+        let kz = if d.x.abs() > d.y.abs() { if d.x.abs() > d.z.abs() { 0 } else { 2 } } else if d.y.abs() > d.z.abs() { 1 } else { 2 };
+        let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+        if d[kz] < 0.0 {
+            std::mem::swap(&mut kx, &mut ky);
+        }
+
+        let shear_x = d[kx] / d[kz];
+        let shear_y = d[ky] / d[kz];
+        let shear_z = 1.0 / d[kz];
+
+        let a = vertices[0] - ray.origin;
+        let b = vertices[1] - ray.origin;
+        let c = vertices[2] - ray.origin;
+        let (ax, ay) = (a[kx] - shear_x * a[kz], a[ky] - shear_y * a[kz]);
+        let (bx, by) = (b[kx] - shear_x * b[kz], b[ky] - shear_y * b[kz]);
+        let (cx, cy) = (c[kx] - shear_x * c[kz], c[ky] - shear_y * c[kz]);
+
+        // Each is twice the area of the triangle the ray makes with the edge opposite a vertex
+        let e0 = cx * by - cy * bx;
+        let e1 = ax * cy - ay * cx;
+        let e2 = bx * ay - by * ax;
+        if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+            return None;
+        }
+
+        let det = e0 + e1 + e2;
+        if det == 0.0 {
+            return None;
+        }
+
+        let t = (e0 * shear_z * a[kz] + e1 * shear_z * b[kz] + e2 * shear_z * c[kz]) / det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        Some((t, [e0 / det, e1 / det, e2 / det]))
+    }
+
+    // Shading data of a hit on a triangle, interpolated from its vertices
+    pub fn triangle_record(vertices: &[Point3; 3], normals: Option<&[Vector3; 3]>, uvs: &[(f64, f64); 3], t: f64, barycentric: &[f64; 3], ray: &Ray, mat_handle: MaterialHandle) -> HitRecord {
+        let interpolate = |values: [Vector3; 3]| barycentric[0] * values[0] + barycentric[1] * values[1] + barycentric[2] * values[2];
+
+        let mut rec = HitRecord::new();
+        rec.t = t;
+        rec.point = interpolate(*vertices);
+        rec.mat_handle = mat_handle;
+        rec.u = barycentric[0] * uvs[0].0 + barycentric[1] * uvs[1].0 + barycentric[2] * uvs[2].0;
+        rec.v = barycentric[0] * uvs[0].1 + barycentric[1] * uvs[1].1 + barycentric[2] * uvs[2].1;
+
+        let edge_1 = vertices[1] - vertices[0];
+        let edge_2 = vertices[2] - vertices[0];
+        let geometric_normal = Vector3::normalize(&Vector3::cross(&edge_1, &edge_2));
+        rec.set_face_normal(ray, &geometric_normal);
+
+        // Which side is the front follows the actual surface, the shading normal only bends the shading
+        let outward_normal = match normals {
+            Some(normals) => {
+                let shading_normal = Vector3::normalize(&interpolate(*normals));
+                rec.normal = if Vector3::dot(&shading_normal, &rec.normal) < 0.0 { -shading_normal } else { shading_normal };
+                shading_normal
+            },
+            None => geometric_normal
+        };
+
+        // Along u as the texture coordinates change over the edges, made perpendicular to the normal
+        let (du_1, dv_1) = (uvs[1].0 - uvs[0].0, uvs[1].1 - uvs[0].1);
+        let (du_2, dv_2) = (uvs[2].0 - uvs[0].0, uvs[2].1 - uvs[0].1);
+        let uv_det = du_1 * dv_2 - dv_1 * du_2;
+        let dpdu = if uv_det.abs() > 1.0e-12 { (dv_2 * edge_1 - dv_1 * edge_2) / uv_det } else { edge_1 };
+        let tangent = dpdu - Vector3::dot(&dpdu, &outward_normal) * outward_normal;
+        rec.tangent = if tangent.length_squared() > 0.0 { Vector3::normalize(&tangent) } else { Onb::new(&outward_normal).u };
+        rec.bitangent = Vector3::cross(&outward_normal, &rec.tangent);
+
+        rec
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn disk_hit(center: &Point3, normal: &Vector3, inner_radius: f64, outer_radius: f64, ray: &Ray, t_min: f64, t_max: f64, mat_handle: MaterialHandle) -> Option<HitRecord> {
+        let t = Vector3::dot(&(*center - ray.origin), normal) / Vector3::dot(&ray.direction, normal);
+
+        // Also false for rays along the plane, where t is not a number
+        if !(t >= t_min && t <= t_max) {
+            return None;
+        }
+
+        let point = ray.at(t);
+        let offset = point - *center;
+        let distance_squared = offset.length_squared();
+        if distance_squared > outer_radius * outer_radius || distance_squared < inner_radius * inner_radius {
+            return None;
+        }
+
+        // The angle around the normal starts at the u axis of the basis around it
+        let onb = Onb::new(normal);
+        let distance = distance_squared.sqrt();
+        let phi = f64::atan2(Vector3::dot(&offset, &onb.v), Vector3::dot(&offset, &onb.u));
+        let radial = if distance > 0.0 { offset / distance } else { onb.u };
+
+        let mut rec = HitRecord::new();
+        rec.t = t;
+        rec.point = point;
+        rec.mat_handle = mat_handle;
+        rec.u = (if phi < 0.0 { phi + 2.0 * PI } else { phi }) / (2.0 * PI);
+        rec.v = if outer_radius > inner_radius { (outer_radius - distance) / (outer_radius - inner_radius) } else { 0.0 };
+        rec.set_face_normal(ray, normal);
+        rec.tangent = Vector3::cross(normal, &radial);
+        rec.bitangent = -radial;
+
+        Some(rec)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn cylinder_hit(base: &Point3, axis: &Vector3, height: f64, radius: f64, capped: bool, ray: &Ray, t_min: f64, t_max: f64, mat_handle: MaterialHandle) -> Option<HitRecord> {
+        // The caps first, so the tube only has to be closer than the closest of them
+        let mut closest = None;
+        let mut closest_so_far = t_max;
+        if capped {
+            let top = *base + height * *axis;
+            for (center, normal) in [(*base, -*axis), (top, *axis)] {
+                if let Some(rec) = Self::disk_hit(&center, &normal, 0.0, radius, ray, t_min, closest_so_far, mat_handle) {
+                    closest_so_far = rec.t;
+                    closest = Some(rec);
+                }
+            }
+        }
+
+        // Across the axis the tube is a circle, so only the parts of the ray across it are intersected
+        let oc = ray.origin - *base;
+        let o = oc - Vector3::dot(&oc, axis) * *axis;
+        let d = ray.direction - Vector3::dot(&ray.direction, axis) * *axis;
+        let a = d.length_squared();
+        if a == 0.0 {
+            return closest;
+        }
+
+        let half_b = Vector3::dot(&o, &d);
+        let c = o.length_squared() - radius * radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return closest;
+        }
+
+        // The nearer root, or the farther one where the nearer misses the height, which from inside an open
+        // tube is its inner wall
+        let sqrtd = discriminant.sqrt();
+        let t = [(-half_b - sqrtd) / a, (-half_b + sqrtd) / a].iter().copied().find(|&t| {
+            let along = Vector3::dot(&(ray.at(t) - *base), axis);
+            t >= t_min && t <= closest_so_far && (0.0..=height).contains(&along)
+        });
+        let t = match t {
+            Some(t) => t,
+            None => return closest
+        };
+
+        let point = ray.at(t);
+        let along = Vector3::dot(&(point - *base), axis);
+        let outward_normal = (point - *base - along * *axis) / radius;
+
+        let onb = Onb::new(axis);
+        let phi = f64::atan2(Vector3::dot(&outward_normal, &onb.v), Vector3::dot(&outward_normal, &onb.u));
+
+        let mut rec = HitRecord::new();
+        rec.t = t;
+        rec.point = point;
+        rec.mat_handle = mat_handle;
+        rec.u = (if phi < 0.0 { phi + 2.0 * PI } else { phi }) / (2.0 * PI);
+        rec.v = if height > 0.0 { along / height } else { 0.0 };
+        rec.set_face_normal(ray, &outward_normal);
+        rec.tangent = Vector3::cross(axis, &outward_normal);
+        rec.bitangent = *axis;
+
+        Some(rec)
+    }
+
+    fn hit_rotate_y(sin_theta: f64, cos_theta: f64, ptr: &Hittable, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let mut origin = ray.origin;
         let mut direction = ray.direction;
 
@@ -396,17 +577,13 @@ impl Hittable {
         let rotated_ray = Ray::with_time(origin, direction, ray.time);
 
         if let Some(mut rec) = ptr.hit(&rotated_ray, t_min, t_max) {
-            let mut p = rec.point;
-            let mut normal = rec.normal;
-
-            p.x = cos_theta * rec.point.x + sin_theta * rec.point.z;
-            p.z = -sin_theta * rec.point.x + cos_theta * rec.point.z;
+            let rotate = |a: &Vector3| Vector3::new(cos_theta * a.x + sin_theta * a.z, a.y, -sin_theta * a.x + cos_theta * a.z);
 
-            normal.x = cos_theta * rec.normal.x + sin_theta * rec.normal.z;
-            normal.z = -sin_theta * rec.normal.x + cos_theta * rec.normal.z;
-
-            rec.point = p;
-            rec.set_face_normal(&rotated_ray, &normal);
+            rec.point = rotate(&rec.point);
+            rec.tangent = rotate(&rec.tangent);
+            rec.bitangent = rotate(&rec.bitangent);
+            // Already facing the ray, which only turned with it, so the object's front_face still holds
+            rec.normal = rotate(&rec.normal);
 
             Some(rec)
         } else {
@@ -414,15 +591,14 @@ impl Hittable {
         }
     }
 
-    fn hit_constant_medium(boundary: &Box<Hittable>, phase_function: MaterialHandle, neg_inv_density: f64, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        // Print occasional samples when debugging. To enable, set enable_debug true.
-        const ENABLE_DEBUG: bool = false;
-        let debugging : bool = ENABLE_DEBUG && random_double() < 0.00001;
+    fn hit_constant_medium(boundary: &Hittable, phase_function: MaterialHandle, neg_inv_density: f64, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // Print occasional samples when tracing
+        let debugging = log_enabled(Verbosity::Trace) && random_double() < 0.00001;
 
         if let Some(mut rec1) = boundary.hit(ray, -f64::INFINITY, f64::INFINITY) {
             if let Some(mut rec2) = boundary.hit(ray, rec1.t + 0.0001, f64::INFINITY) {
                 if debugging {
-                    eprintln!("t_min={}, t_max={}", rec1.t, rec2.t);
+                    trace!("t_min={}, t_max={}", rec1.t, rec2.t);
                 }
 
                 if rec1.t < t_min {
@@ -454,77 +630,190 @@ impl Hittable {
                 rec.point = ray.at(rec.t);
 
                 if debugging {
-                    eprintln!("hit_distance = {}\nrec.t = {}\nrec.point = {}", hit_distance, rec.t, rec.point);
+                    trace!("hit_distance = {}\nrec.t = {}\nrec.point = {}", hit_distance, rec.t, rec.point);
                 }
 
                 rec.normal = Vector3::new(1.0, 0.0, 0.0);
+                rec.tangent = Vector3::new(0.0, 1.0, 0.0);
+                rec.bitangent = Vector3::new(0.0, 0.0, 1.0);
                 rec.front_face = true;
                 rec.mat_handle = phase_function;
 
                 Some(rec)
             } else {
-                return None;
+                None
             }
         } else {
+            None
+        }
+    }
+
+    // Delta tracking of Woodcock et al.: steps through the box as if the medium was as dense as its densest
+    // voxel everywhere, and takes each collision as real by how dense it actually is there, which leaves
+    // the distances distributed as in the varying medium
+    fn hit_voxel_medium(grid: &VoxelGrid, phase_function: MaterialHandle, density_scale: f64, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let max_density = grid.max_density * density_scale;
+        if max_density <= 0.0 {
             return None;
         }
 
+        // Where the ray is inside the box, by the slabs along each axis
+        let mut t0 = f64::max(t_min, 0.0);
+        let mut t1 = t_max;
+        for axis in 0..3 {
+            let inv_d = 1.0 / ray.direction[axis];
+            let mut near = (grid.minimum[axis] - ray.origin[axis]) * inv_d;
+            let mut far = (grid.maximum[axis] - ray.origin[axis]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut near, &mut far);
+            }
+            t0 = f64::max(t0, near);
+            t1 = f64::min(t1, far);
+            if t1 <= t0 {
+                return None;
+            }
+        }
+
+        let ray_length = ray.direction.length();
+        let mut t = t0;
+        loop {
+            t -= f64::ln(1.0 - random_double()) / (max_density * ray_length);
+            if t >= t1 {
+                return None;
+            }
+
+            let point = ray.at(t);
+            if grid.density(&point) * density_scale > random_double() * max_density {
+                let mut rec = HitRecord::new();
+                rec.t = t;
+                rec.point = point;
+                rec.normal = Vector3::new(1.0, 0.0, 0.0);
+                rec.tangent = Vector3::new(0.0, 1.0, 0.0);
+                rec.bitangent = Vector3::new(0.0, 0.0, 1.0);
+                rec.front_face = true;
+                rec.mat_handle = phase_function;
 
+                return Some(rec);
+            }
+        }
     }
 
     pub fn bounding_box(&self, time_0: f64, time_1: f64) -> Option<AABB> {
         match self {
             Hittable::Sphere { mat_handle: _, center, radius } => {
-                Self::sphere_bounding_box(&center, *radius)
+                Self::sphere_bounding_box(center, *radius)
             },
-            Hittable::MovingSphere { mat_handle: _, center_0, center_1, time_0, time_1, radius } => {
-                Self::moving_sphere_bounding_box(&center_0, &center_1, *radius, *time_0, *time_1)
+            Hittable::MovingSphere { mat_handle: _, center_0, center_1, time_0: sphere_time_0, time_1: sphere_time_1, radius } => {
+                Self::moving_sphere_bounding_box(center_0, center_1, *radius, *sphere_time_0, *sphere_time_1, time_0, time_1)
             },
-            Hittable::BvhNode { left: _, right: _, aabb_box } => {
-                Some(*aabb_box)
+            Hittable::Bvh { bvh } => {
+                bvh.bounding_box()
             },
-            Hittable::XYRect { mat_handle, x0, x1, y0, y1, k } => {
+            Hittable::XYRect { mat_handle: _, x0, x1, y0, y1, k } => {
                 Some(AABB::new(
                     Point3::new(*x0, *y0, *k - 0.0001),
                     Point3::new(*x1, *y1, *k + 0.0001)
                 ))
             },
-            Hittable::XZRect { mat_handle, x0, x1, z0, z1, k } => {
+            Hittable::XZRect { mat_handle: _, x0, x1, z0, z1, k } => {
                 Some(AABB::new(
                     Point3::new(*x0, *k - 0.0001, *z0),
                     Point3::new(*x1, *k + 0.0001, *z1)
                 ))
             },
-            Hittable::YZRect { mat_handle, y0, y1, z0, z1, k } => {
+            Hittable::YZRect { mat_handle: _, y0, y1, z0, z1, k } => {
                 Some(AABB::new(
                     Point3::new(*k - 0.0001, *y0, *z0),
                     Point3::new(*k + 0.0001, *y1, *z1)
                 ))
             },
-            Hittable::Box { mat_handle, min, max, sides } => {
+            Hittable::Triangle { vertices, .. } => {
+                // Padded like the rects, for triangles lying flat along an axis
+                let padding = Vector3::new(0.0001, 0.0001, 0.0001);
+                let minimum = Point3::new(
+                    vertices[0].x.min(vertices[1].x).min(vertices[2].x),
+                    vertices[0].y.min(vertices[1].y).min(vertices[2].y),
+                    vertices[0].z.min(vertices[1].z).min(vertices[2].z)
+                );
+                let maximum = Point3::new(
+                    vertices[0].x.max(vertices[1].x).max(vertices[2].x),
+                    vertices[0].y.max(vertices[1].y).max(vertices[2].y),
+                    vertices[0].z.max(vertices[1].z).max(vertices[2].z)
+                );
+                Some(AABB::new(minimum - padding, maximum + padding))
+            },
+            Hittable::Mesh { mesh } => mesh.bounding_box(),
+            Hittable::Disk { center, normal, outer_radius, .. } => {
+                // The rim reaches out along each axis as far as the axis lies in the plane of the disk, padded
+                // like the rects for disks facing along an axis
+                let extent = |axis: f64| outer_radius * (1.0 - axis * axis).max(0.0).sqrt() + 0.0001;
+                let half = Vector3::new(extent(normal.x), extent(normal.y), extent(normal.z));
+                Some(AABB::new(*center - half, *center + half))
+            },
+            Hittable::Cylinder { base, axis, height, radius, .. } => {
+                // Both end circles reach out along each axis as far as it lies across the cylinder's axis
+                let extent = |axis: f64| radius * (1.0 - axis * axis).max(0.0).sqrt();
+                let half = Vector3::new(extent(axis.x), extent(axis.y), extent(axis.z));
+                let top = *base + *height * *axis;
+                Some(AABB::new(
+                    Point3::new(base.x.min(top.x), base.y.min(top.y), base.z.min(top.z)) - half,
+                    Point3::new(base.x.max(top.x), base.y.max(top.y), base.z.max(top.z)) + half
+                ))
+            },
+            Hittable::Box { min, max, .. } => {
                 Some(AABB::new(*min, *max))
             },
             Hittable::Translate { offset, ptr } => {
-                if let Some(aabb) = ptr.bounding_box(time_0, time_1) {
-                    Some(AABB::new(
+                ptr.bounding_box(time_0, time_1).map(|aabb| {
+                    AABB::new(
                         aabb.minimum + *offset,
                         aabb.maximum + *offset
-                    ))
-                } else {
-                    None
-                }
+                    )
+                })
             },
-            Hittable::RotateY { sin_theta: _, cos_theta: _, has_box, bbox, ptr: _ } => {
-                if *has_box {
-                    Some(*bbox)
-                } else {
-                    None
-                }
+            Hittable::FlipFace { ptr } => ptr.bounding_box(time_0, time_1),
+            Hittable::RotateY { sin_theta, cos_theta, ptr } => {
+                // Of the object over the same time range, as moving objects inside have boxes for it
+                ptr.bounding_box(time_0, time_1).map(|aabb| Self::rotate_y_bounding_box(*sin_theta, *cos_theta, &aabb))
             },
-            Hittable::ConstantMedium { phase_function: _, boundary, neg_inv_density: _ } => {
+            Hittable::ConstantMedium { boundary, .. } => {
+                // The medium fills exactly the volume of its boundary, so it can live inside a BVH
                 boundary.bounding_box(time_0, time_1)
+            },
+            Hittable::VoxelMedium { grid, .. } => Some(AABB::new(grid.minimum, grid.maximum))
+        }
+    }
+
+    // Box around the corners of the given box turned around the y axis
+    fn rotate_y_bounding_box(sin_theta: f64, cos_theta: f64, aabb: &AABB) -> AABB {
+        let mut min = [f64::INFINITY; 3];
+        let mut max = [-f64::INFINITY; 3];
+
+        for i in 0..2 {
+            for j in 0..2 {
+                for k in 0..2 {
+                    let i = i as f64;
+                    let j = j as f64;
+                    let k = k as f64;
+
+                    let x = i * aabb.maximum.x + (1.0 - i) * aabb.minimum.x;
+                    let y = j * aabb.maximum.y + (1.0 - j) * aabb.minimum.y;
+                    let z = k * aabb.maximum.z + (1.0 - k) * aabb.minimum.z;
+
+                    let newx = cos_theta * x + sin_theta * z;
+                    let newz = -sin_theta * x + cos_theta * z;
+
+                    let tester = [newx, y, newz];
+
+                    for c in 0..3 {
+                        min[c] = f64::min(min[c], tester[c]);
+                        max[c] = f64::max(max[c], tester[c]);
+                    }
+                }
             }
         }
+
+        AABB::new(Point3::new(min[0], min[1], min[2]), Point3::new(max[0], max[1], max[2]))
     }
 
     fn sphere_bounding_box(center: &Point3, radius: f64) -> Option<AABB> {
@@ -536,9 +825,11 @@ impl Hittable {
         )
     }
 
-    fn moving_sphere_bounding_box(center_0: &Point3, center_1: &Point3, radius: f64, time_0: f64, time_1: f64) -> Option<AABB> {
-        let c0 = Self::get_center_at_time(center_0, center_1, time_0, time_1, time_0);
-        let c1 = Self::get_center_at_time(center_0, center_1, time_0, time_1, time_1);
+    // The box covers the sphere's whole path between the requested times, not just its own
+    // time_0 and time_1 keyframes
+    fn moving_sphere_bounding_box(center_0: &Point3, center_1: &Point3, radius: f64, sphere_time_0: f64, sphere_time_1: f64, time_0: f64, time_1: f64) -> Option<AABB> {
+        let c0 = Self::get_center_at_time(center_0, center_1, sphere_time_0, sphere_time_1, time_0);
+        let c1 = Self::get_center_at_time(center_0, center_1, sphere_time_0, sphere_time_1, time_1);
 
         let box0 = AABB::new(
             c0 - Vector3::new(radius, radius, radius),
@@ -556,4 +847,204 @@ impl Hittable {
     fn get_center_at_time(center_0: &Point3, center_1: &Point3, time_0: f64, time_1: f64, time: f64) -> Point3 {
         *center_0 + ((time - time_0) / (time_1 - time_0)) * (*center_1 - *center_0)
     }
+
+    // Number of primitive shapes making up this hittable
+    pub fn primitive_count(&self) -> usize {
+        match self {
+            Hittable::Bvh { bvh } => bvh.objects().iter().map(|object| object.primitive_count()).sum(),
+            Hittable::Mesh { mesh } => mesh.triangle_count(),
+            Hittable::Box { sides, .. } => sides.len(),
+            Hittable::Translate { ptr, .. } | Hittable::RotateY { ptr, .. } | Hittable::FlipFace { ptr } => ptr.primitive_count(),
+            Hittable::ConstantMedium { boundary, .. } => boundary.primitive_count(),
+            _ => 1
+        }
+    }
+
+    // Whether pdf_value, random_direction_to_surface and random_point_on work for this hittable
+    pub fn can_sample(&self) -> bool {
+        match self {
+            Hittable::Sphere { .. } | Hittable::XYRect { .. } | Hittable::XZRect { .. } | Hittable::YZRect { .. } | Hittable::Triangle { .. } | Hittable::Disk { .. } => true,
+            Hittable::Translate { ptr, .. } | Hittable::FlipFace { ptr } => ptr.can_sample(),
+            _ => false
+        }
+    }
+
+    // Density, over solid angle seen from origin, of picking direction v when sampling this hittable directly
+    pub fn pdf_value(&self, origin: &Point3, v: &Vector3) -> f64 {
+        match self {
+            Hittable::Sphere { center, radius, .. } => {
+                Self::sphere_pdf_value(center, *radius, origin, v)
+            },
+            Hittable::XYRect { x0, x1, y0, y1, .. } => self.rect_pdf_value((x1 - x0) * (y1 - y0), origin, v),
+            Hittable::XZRect { x0, x1, z0, z1, .. } => self.rect_pdf_value((x1 - x0) * (z1 - z0), origin, v),
+            Hittable::YZRect { y0, y1, z0, z1, .. } => self.rect_pdf_value((y1 - y0) * (z1 - z0), origin, v),
+            Hittable::Triangle { vertices, .. } => {
+                let area = 0.5 * Vector3::cross(&(vertices[1] - vertices[0]), &(vertices[2] - vertices[0])).length();
+                self.rect_pdf_value(area, origin, v)
+            },
+            Hittable::Disk { inner_radius, outer_radius, .. } => {
+                self.rect_pdf_value(PI * (outer_radius * outer_radius - inner_radius * inner_radius), origin, v)
+            },
+            Hittable::Translate { offset, ptr } => ptr.pdf_value(&(*origin - *offset), v),
+            Hittable::FlipFace { ptr } => ptr.pdf_value(origin, v),
+            _ => 0.0
+        }
+    }
+
+    // Random direction from origin towards the visible part of this hittable
+    pub fn random_direction_to_surface(&self, origin: &Point3, sampler: &mut dyn Sampler) -> Vector3 {
+        match self {
+            Hittable::Sphere { center, radius, .. } => {
+                Self::sphere_random_direction(center, *radius, origin, sampler)
+            },
+            Hittable::XYRect { .. } | Hittable::XZRect { .. } | Hittable::YZRect { .. } | Hittable::Triangle { .. } | Hittable::Disk { .. } => {
+                self.random_point_on(sampler) - *origin
+            },
+            Hittable::Translate { offset, ptr } => ptr.random_direction_to_surface(&(*origin - *offset), sampler),
+            Hittable::FlipFace { ptr } => ptr.random_direction_to_surface(origin, sampler),
+            _ => Vector3::new(1.0, 0.0, 0.0)
+        }
+    }
+
+    // Random point spread uniformly over the area of this hittable
+    pub fn random_point_on(&self, sampler: &mut dyn Sampler) -> Point3 {
+        match self {
+            Hittable::Sphere { center, radius, .. } => *center + *radius * sampler.unit_vector(),
+            Hittable::XYRect { x0, x1, y0, y1, k, .. } => {
+                let (u, v) = sampler.next_2d();
+                Point3::new(x0 + u * (x1 - x0), y0 + v * (y1 - y0), *k)
+            },
+            Hittable::XZRect { x0, x1, z0, z1, k, .. } => {
+                let (u, v) = sampler.next_2d();
+                Point3::new(x0 + u * (x1 - x0), *k, z0 + v * (z1 - z0))
+            },
+            Hittable::YZRect { y0, y1, z0, z1, k, .. } => {
+                let (u, v) = sampler.next_2d();
+                Point3::new(*k, y0 + u * (y1 - y0), z0 + v * (z1 - z0))
+            },
+            Hittable::Triangle { vertices, .. } => {
+                // Folding the unit square onto the triangle by the square root keeps the density even
+                let (r1, r2) = sampler.next_2d();
+                let su = r1.sqrt();
+                (1.0 - su) * vertices[0] + su * (1.0 - r2) * vertices[1] + su * r2 * vertices[2]
+            },
+            Hittable::Disk { center, normal, inner_radius, outer_radius, .. } => {
+                // Uniform in the square of the radius, as the area grows with it
+                let (r1, r2) = sampler.next_2d();
+                let radius = (inner_radius * inner_radius + r1 * (outer_radius * outer_radius - inner_radius * inner_radius)).sqrt();
+                let phi = 2.0 * PI * r2;
+                let onb = Onb::new(normal);
+                *center + radius * (phi.cos() * onb.u + phi.sin() * onb.v)
+            },
+            Hittable::Translate { offset, ptr } => ptr.random_point_on(sampler) + *offset,
+            Hittable::FlipFace { ptr } => ptr.random_point_on(sampler),
+            _ => Point3::new(0.0, 0.0, 0.0)
+        }
+    }
+
+    // Points are picked uniformly over the area of a rect, triangle or disk, which seen from the origin is a
+    // density of distance squared over the projected area
+    fn rect_pdf_value(&self, area: f64, origin: &Point3, v: &Vector3) -> f64 {
+        let ray = Ray::with_time(*origin, *v, 0.0);
+        match self.hit(&ray, 0.001, INFINITY) {
+            Some(rec) => {
+                let distance_squared = rec.t * rec.t * v.length_squared();
+                let cosine = f64::abs(Vector3::dot(v, &rec.normal) / v.length());
+
+                distance_squared / (cosine * area)
+            },
+            None => 0.0
+        }
+    }
+
+    fn sphere_pdf_value(center: &Point3, radius: f64, origin: &Point3, v: &Vector3) -> f64 {
+        let ray = Ray::with_time(*origin, *v, 0.0);
+        if Self::sphere_hit(center, radius, &ray, 0.001, INFINITY, MaterialHandle::default()).is_none() {
+            return 0.0;
+        }
+
+        // The visible cap of the sphere subtends a cone with half angle theta_max, which from inside
+        // the sphere is the whole sphere of directions
+        let cos_theta_max = sphere_cos_theta_max(radius, (*center - *origin).length_squared());
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+
+        1.0 / solid_angle
+    }
+
+    fn sphere_random_direction(center: &Point3, radius: f64, origin: &Point3, sampler: &mut dyn Sampler) -> Vector3 {
+        let direction = *center - *origin;
+        let distance_squared = direction.length_squared();
+
+        // From inside, directions are uniform so any frame will do, even when the origin is the center
+        let axis = if distance_squared <= radius * radius { Vector3::new(0.0, 0.0, 1.0) } else { direction };
+
+        let (r1, r2) = sampler.next_2d();
+        Onb::new(&axis).local(&random_to_sphere(radius, distance_squared, r1, r2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_sphere_bvh_box_covers_its_motion() {
+        let resting = Hittable::Sphere { mat_handle: MaterialHandle::default(), center: Point3::new(0.0, 0.0, 0.0), radius: 1.0 };
+        let moving = Hittable::MovingSphere {
+            mat_handle: MaterialHandle::default(),
+            center_0: Point3::new(0.0, 0.0, 0.0),
+            center_1: Point3::new(10.0, 0.0, 0.0),
+            time_0: 0.0,
+            time_1: 1.0,
+            radius: 1.0
+        };
+
+        let resting_box = Hittable::new_bvh(&[resting], 0.0, 1.0).bounding_box(0.0, 1.0).unwrap();
+        let moving_box = Hittable::new_bvh(&[moving], 0.0, 1.0).bounding_box(0.0, 1.0).unwrap();
+
+        let width = |aabb: &AABB| aabb.maximum.x - aabb.minimum.x;
+        assert!((width(&moving_box) - width(&resting_box) - 10.0).abs() < 1.0e-9);
+    }
+
+    #[test]
+    fn constant_medium_box_is_its_boundary_box() {
+        let boundary = Hittable::Sphere { mat_handle: MaterialHandle::default(), center: Point3::new(0.0, 0.0, 0.0), radius: 5.0 };
+        let medium = Hittable::new_constant_medium(boundary, 0.01, MaterialHandle::default());
+
+        let aabb = medium.bounding_box(0.0, 1.0).unwrap();
+        for axis in 0..3 {
+            assert_eq!(aabb.minimum[axis], -5.0);
+            assert_eq!(aabb.maximum[axis], 5.0);
+        }
+    }
+
+    #[test]
+    fn sphere_light_seen_from_inside_is_sampled_uniformly() {
+        let sphere = Hittable::Sphere { mat_handle: MaterialHandle::default(), center: Point3::new(0.0, 0.0, 0.0), radius: 2.0 };
+
+        for origin in [Point3::new(0.5, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0)] {
+            let pdf = sphere.pdf_value(&origin, &Vector3::new(0.0, 1.0, 0.0));
+            assert!((pdf - 1.0 / (4.0 * PI)).abs() < 1.0e-9);
+
+            let direction = sphere.random_direction_to_surface(&origin, &mut RandomSampler);
+            assert!(direction.x.is_finite() && direction.y.is_finite() && direction.z.is_finite());
+        }
+    }
+
+    #[test]
+    fn moved_and_rotated_objects_keep_the_face_that_was_hit() {
+        let sphere = Hittable::Sphere { mat_handle: MaterialHandle::default(), center: Point3::new(0.0, 0.0, 0.0), radius: 1.0 };
+        let moved = Hittable::new_instance(&sphere, 30.0, Vector3::new(5.0, 0.0, 0.0));
+        let rotated = Hittable::new_rotate_y(30.0, sphere);
+
+        for (hittable, center) in [(&moved, Point3::new(5.0, 0.0, 0.0)), (&rotated, Point3::new(0.0, 0.0, 0.0))] {
+            let from_outside = Ray::with_time(center + Vector3::new(0.0, 0.0, 3.0), Vector3::new(0.0, 0.0, -1.0), 0.0);
+            let from_inside = Ray::with_time(center, Vector3::new(0.0, 0.0, -1.0), 0.0);
+
+            let outside = hittable.hit(&from_outside, 0.001, INFINITY).unwrap();
+            let inside = hittable.hit(&from_inside, 0.001, INFINITY).unwrap();
+            assert!(outside.front_face && !inside.front_face);
+            assert!(outside.normal.z > 0.999 && inside.normal.z > 0.999);
+        }
+    }
 }
@@ -0,0 +1,216 @@
+use crate::math::*;
+
+// One surface of a lens prescription, in millimeters, listed from the front of the lens to the back.
+// A radius of 0 makes it the aperture stop rather than a glass surface.
+#[derive(Copy, Clone, Debug)]
+pub struct LensElement {
+    pub radius: f64,         // Of curvature, positive for a center of curvature behind the surface
+    pub thickness: f64,      // Distance along the axis to the next surface, or to the film for the last
+    pub ir: f64,             // Of what is between this surface and the next, 1 for air
+    pub aperture_radius: f64
+}
+
+// A camera lens made of real elements like in Kolb et al., A Realistic Camera Model for Computer
+// Graphics. Rays from the film are traced through the glass, which gives the vignetting, distortion
+// and bokeh of the lens. Lens space has the film at z = 0 and the lens in front of it towards -z, and
+// is in millimeters while the scene is taken to be in meters.
+pub struct RealisticLens {
+    elements: Vec<LensElement>,
+    pub film_width: f64,
+    pub film_height: f64,
+    // Share of the rays from the center of the film through the rear element that make it out the
+    // front, which the brightness is divided by so the center of the image is not darkened
+    center_transmission: f64
+}
+
+impl LensElement {
+    // Reads a prescription in the format of the lens files of pbrt: one surface per line with its
+    // radius of curvature, thickness, index of refraction (0 for air) and aperture diameter, all in
+    // millimeters, and # for comments
+    pub fn load(path: &str) -> Result<Vec<LensElement>, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("Could not read lens {}: {}", path, err))?;
+
+        let mut elements = Vec::new();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let values = line.split_whitespace()
+                .map(|value| value.parse::<f64>().map_err(|_| format!("Invalid number {} in lens {}", value, path)))
+                .collect::<Result<Vec<f64>, String>>()?;
+
+            match values[..] {
+                [radius, thickness, ir, aperture] => elements.push(LensElement {
+                    radius,
+                    thickness,
+                    ir: if ir == 0.0 { 1.0 } else { ir },
+                    aperture_radius: aperture / 2.0
+                }),
+                _ => return Err(format!("Expected radius, thickness, ir and aperture in lens {} but got {}", path, line))
+            }
+        }
+
+        if elements.is_empty() {
+            return Err(format!("Lens {} has no elements", path));
+        }
+
+        Ok(elements)
+    }
+}
+
+impl RealisticLens {
+    // Focuses the lens at the distance in front of it by moving it away from the film, and sizes the
+    // film so it sees vfov degrees from bottom to top when focused far away
+    pub fn new(elements: &[LensElement], vfov: f64, aspect_ratio: f64, focus_dist: f64) -> Result<RealisticLens, String> {
+        let mut lens = RealisticLens { elements: elements.to_vec(), film_width: 0.0, film_height: 0.0, center_transmission: 1.0 };
+        lens.elements.last_mut().unwrap().thickness = 0.0;
+
+        let (focal_length, focal_point) = lens.focal_length()?;
+        lens.elements.last_mut().unwrap().thickness = focal_point;
+        lens.focus(focus_dist * 1000.0)?;
+
+        lens.film_height = 2.0 * focal_length * (degrees_to_radians(vfov) / 2.0).tan();
+        lens.film_width = aspect_ratio * lens.film_height;
+
+        // Rays on a grid over the rear element, which is also how get_ray picks them
+        const STEPS: usize = 64;
+        let mut transmitted = 0;
+        for i in 0..STEPS {
+            for j in 0..STEPS {
+                let disk = Vector3::new(2.0 * (i as f64 + 0.5) / STEPS as f64 - 1.0, 2.0 * (j as f64 + 0.5) / STEPS as f64 - 1.0, 0.0);
+                if disk.length_squared() <= 1.0 && lens.trace_from_film(0.0, 0.0, &disk).is_some() {
+                    transmitted += 1;
+                }
+            }
+        }
+        if transmitted == 0 {
+            return Err(String::from("No light gets through the lens"));
+        }
+        lens.center_transmission = transmitted as f64 / (PI / 4.0 * (STEPS * STEPS) as f64);
+
+        Ok(lens)
+    }
+
+    // Traces a ray from the film point in millimeters through the point of the unit disk scaled over the
+    // rear element. Gives the ray leaving the front of the lens in lens space and its weight, none if the
+    // lens blocks it.
+    pub fn trace_from_film(&self, film_x: f64, film_y: f64, disk: &Vector3) -> Option<(Point3, Vector3, f64)> {
+        let rear = self.elements.last().unwrap();
+        let rear_point = Point3::new(rear.aperture_radius * disk.x, rear.aperture_radius * disk.y, -rear.thickness);
+        let origin = Point3::new(film_x, film_y, 0.0);
+        let direction = Vector3::normalize(&(rear_point - origin));
+
+        // The light a film point gets through a small patch of the rear element falls off with the
+        // fourth power of the cosine of the angle to the axis
+        let weight = direction.z.powi(4) / self.center_transmission;
+
+        let (origin, direction) = self.trace(origin, direction, true)?;
+        Some((origin, direction, weight))
+    }
+
+    // Through all the surfaces from the back to the front when coming from the film, otherwise from the
+    // front to the back
+    fn trace(&self, mut origin: Point3, mut direction: Vector3, from_film: bool) -> Option<(Point3, Vector3)> {
+        let depths = self.depths();
+        let count = self.elements.len();
+
+        for step in 0..count {
+            let i = if from_film { count - 1 - step } else { step };
+            let element = &self.elements[i];
+            let z = depths[i];
+
+            let (t, normal) = if element.radius == 0.0 {
+                ((z - origin.z) / direction.z, Vector3::new(0.0, 0.0, 1.0))
+            } else {
+                self.intersect_surface(element.radius, z + element.radius, &origin, &direction)?
+            };
+            if t <= 0.0 {
+                return None;
+            }
+
+            origin += t * direction;
+            if origin.x * origin.x + origin.y * origin.y > element.aperture_radius * element.aperture_radius {
+                return None;
+            }
+
+            if element.radius != 0.0 {
+                // The element's ir is behind the surface, the one of the element before it in front
+                let ir_front = if i == 0 { 1.0 } else { self.elements[i - 1].ir };
+                let (ir_in, ir_out) = if from_film { (element.ir, ir_front) } else { (ir_front, element.ir) };
+                let ratio = ir_in / ir_out;
+
+                let normal = if Vector3::dot(&normal, &direction) > 0.0 { -normal } else { normal };
+                let cos_theta = Vector3::dot(&-direction, &normal);
+                if ratio * ratio * (1.0 - cos_theta * cos_theta) > 1.0 {
+                    return None;
+                }
+                direction = Vector3::normalize(&Vector3::refract(&direction, &normal, ratio));
+            }
+        }
+
+        Some((origin, direction))
+    }
+
+    // Where along the ray it meets the sphere of the surface, taking the side of the sphere the surface
+    // is on, and the normal there
+    fn intersect_surface(&self, radius: f64, center_z: f64, origin: &Point3, direction: &Vector3) -> Option<(f64, Vector3)> {
+        let oc = *origin - Point3::new(0.0, 0.0, center_z);
+        let a = direction.length_squared();
+        let half_b = Vector3::dot(&oc, direction);
+        let c = oc.length_squared() - radius * radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let root = discriminant.sqrt();
+        let (t_0, t_1) = ((-half_b - root) / a, (-half_b + root) / a);
+        let t = if (direction.z > 0.0) != (radius < 0.0) { f64::min(t_0, t_1) } else { f64::max(t_0, t_1) };
+
+        let normal = Vector3::normalize(&(*origin + t * *direction - Point3::new(0.0, 0.0, center_z)));
+        Some((t, normal))
+    }
+
+    // Depth of the vertex of each surface along the axis
+    fn depths(&self) -> Vec<f64> {
+        let mut depths = vec![0.0; self.elements.len()];
+        let mut z = 0.0;
+        for (i, element) in self.elements.iter().enumerate().rev() {
+            z -= element.thickness;
+            depths[i] = z;
+        }
+        depths
+    }
+
+    // Traces a ray coming in parallel to the axis just off it, which the lens bends to cross the axis
+    // at the focal point behind it. The focal length is measured from where it bends as a whole.
+    fn focal_length(&self) -> Result<(f64, f64), String> {
+        let height = 0.01 * self.elements[0].aperture_radius;
+        let start = Point3::new(0.0, height, self.depths()[0] - 1.0);
+        let (origin, direction) = self.trace(start, Vector3::new(0.0, 0.0, 1.0), false)
+            .ok_or_else(|| String::from("Could not trace a ray through the lens to find its focal length"))?;
+
+        if direction.y >= 0.0 {
+            return Err(String::from("The lens does not bring light to a focus"));
+        }
+
+        let focal_point = origin.z - origin.y / direction.y * direction.z;
+        let principal_plane = origin.z + (height - origin.y) / direction.y * direction.z;
+        Ok((focal_point - principal_plane, focal_point))
+    }
+
+    // Moves the lens so that a point at the distance in front of it comes to a focus on the film
+    fn focus(&mut self, distance: f64) -> Result<(), String> {
+        let height = 0.01 * self.elements[0].aperture_radius;
+        let front = self.depths()[0];
+        let object = Point3::new(0.0, 0.0, front - distance);
+        let direction = Vector3::normalize(&(Point3::new(0.0, height, front) - object));
+        let (origin, direction) = self.trace(object, direction, false)
+            .ok_or_else(|| String::from("Could not trace a ray through the lens to focus it"))?;
+
+        if direction.y >= 0.0 {
+            return Err(format!("The lens cannot focus at {}", distance / 1000.0));
+        }
+
+        let image = origin.z - origin.y / direction.y * direction.z;
+        self.elements.last_mut().unwrap().thickness += image;
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+// The renderer itself, shared by the command line program and the WebAssembly build. Nothing in
+// here starts threads, so it compiles for wasm32 as well. Saving images to files or stdout only
+// works natively, the browser build draws to a canvas instead.
+#[macro_use]
+pub mod logging;
+pub mod math;
+pub mod ray;
+pub mod camera;
+pub mod lens;
+pub mod hittable;
+pub mod material;
+pub mod pdf;
+pub mod microfacet;
+pub mod thinfilm;
+pub mod fresnel;
+pub mod spectrum;
+pub mod light;
+pub mod environment;
+pub mod sky;
+pub mod aabb;
+pub mod bvh;
+pub mod mesh;
+pub mod obj;
+pub mod stl;
+pub mod ply;
+pub mod gltf;
+pub mod volume;
+pub mod fog;
+pub mod texture;
+pub mod perlin;
+pub mod output;
+pub mod stats;
+pub mod tonemap;
+pub mod film;
+pub mod filter;
+pub mod scene;
+pub mod render;
+pub mod sampler;
+pub mod bluenoise;
+
+#[cfg(target_arch = "wasm32")]
+mod wasm;
@@ -0,0 +1,99 @@
+use crate::math::*;
+use crate::sampler::*;
+
+// Lights without a surface, which rays never hit, not even a sun in the sky. The renderer samples all of
+// them at every diffuse hit instead, so they light a scene without the noise of finding an area light.
+pub enum Light {
+    Point { position: Point3, intensity: Color }, // Light per unit solid angle, sent out the same in every direction
+    // A point light only shining into a cone around the unit direction. Full intensity inside the inner
+    // cone fades out smoothly towards the outer one, the angles given by their cosines.
+    Spot { position: Point3, direction: Vector3, cos_inner: f64, cos_outer: f64, intensity: Color },
+    // Light from far away in a cone around the unit direction towards it, as wide as the angular radius
+    // in radians. A wider sun casts softer shadows. The irradiance is the light falling on a surface
+    // facing the sun, so the size does not change how bright it is.
+    Sun { direction: Vector3, angular_radius: f64, irradiance: Color }
+}
+
+// Light arriving at a point from a light
+pub struct LightSample {
+    pub direction: Vector3, // Unit direction towards the light
+    pub distance: f64,      // How far a shadow ray has to reach to get to the light
+    pub radiance: Color     // Light arriving from that direction, already divided by the density it was sampled with
+}
+
+impl Light {
+    // The cone angles are in degrees from the axis to its edge
+    pub fn new_spot(position: Point3, target: Point3, inner_angle: f64, outer_angle: f64, intensity: Color) -> Light {
+        let outer_angle = f64::max(outer_angle, inner_angle);
+
+        Light::Spot {
+            position,
+            direction: Vector3::normalize(&(target - position)),
+            cos_inner: degrees_to_radians(inner_angle).cos(),
+            cos_outer: degrees_to_radians(outer_angle).cos(),
+            intensity
+        }
+    }
+
+    // The direction points towards the sun and the angular diameter is in degrees, the real sun being
+    // about half a degree across
+    pub fn new_sun(direction: Vector3, angular_diameter: f64, irradiance: Color) -> Light {
+        Light::Sun {
+            direction: Vector3::normalize(&direction),
+            angular_radius: degrees_to_radians(0.5 * angular_diameter),
+            irradiance
+        }
+    }
+
+    pub fn sample(&self, point: &Point3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        match self {
+            Light::Point { position, intensity } => Self::point_sample(position, *intensity, point),
+            Light::Spot { position, direction, cos_inner, cos_outer, intensity } => {
+                let sample = Self::point_sample(position, *intensity, point)?;
+                let cos_theta = Vector3::dot(&-sample.direction, direction);
+                let falloff = smoothstep(*cos_outer, *cos_inner, cos_theta);
+                if falloff <= 0.0 {
+                    return None;
+                }
+
+                Some(LightSample { radiance: falloff * sample.radiance, ..sample })
+            },
+            Light::Sun { direction, angular_radius, irradiance } => {
+                // Uniform over the disk of the sun, whose radiance is the irradiance over its solid
+                // angle, so dividing by the density leaves just the irradiance
+                let (r1, r2) = sampler.next_2d();
+                let local = random_to_sphere(angular_radius.sin(), 1.0, r1, r2);
+
+                Some(LightSample {
+                    direction: Vector3::normalize(&Onb::new(direction).local(&local)),
+                    distance: INFINITY,
+                    radiance: *irradiance
+                })
+            }
+        }
+    }
+
+    fn point_sample(position: &Point3, intensity: Color, point: &Point3) -> Option<LightSample> {
+        let to_light = *position - *point;
+        let distance = to_light.length();
+        if distance <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction: to_light / distance,
+            distance,
+            radiance: intensity / (distance * distance)
+        })
+    }
+}
+
+// Eases from 0 at edge_0 to 1 at edge_1, flat at both ends
+fn smoothstep(edge_0: f64, edge_1: f64, x: f64) -> f64 {
+    if edge_1 <= edge_0 {
+        return if x >= edge_1 { 1.0 } else { 0.0 };
+    }
+
+    let t = clamp((x - edge_0) / (edge_1 - edge_0), 0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
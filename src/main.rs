@@ -1,599 +1,847 @@
-mod math;
-mod ray;
-mod camera;
-mod hittable;
-mod material;
-mod aabb;
-mod texture;
-mod perlin;
-
-//use aabb::*;
-use math::*;
-use ray::*;
-use camera::*;
-use hittable::*;
-use material::*;
-use texture::*;
-use perlin::*;
-
-fn ray_color(ray: &Ray, background_color: &Color, hittables: &Vec<Hittable>, depth: i32, materials: &Vec<Material>) -> Color {
-    // If we've exceeded the ray bounce limit, no more light is gathered
-    if depth <= 0 {
-        return Color::new(0.0, 0.0, 0.0);
-    }
-
-    if let Some(rec) = hit_hittables(hittables, ray, 0.001, INFINITY) {
-        let material = &materials[rec.mat_handle.0 - 1];
-        
-        let emitted = material.emitted(rec.u, rec.v, &rec.point);
-
-        if let Some((scattered, attenuation)) = material.scatter(ray, &rec) {
-            return emitted + attenuation * ray_color(&scattered, background_color, hittables, depth - 1, materials);
-        } else {
-            return emitted;
-        }
-    } 
+#[macro_use]
+extern crate raytracer;
+
+mod cli;
+mod config;
+mod checkpoint;
+mod progress;
+mod server;
+mod window;
+
+use raytracer::math::*;
+use raytracer::camera::*;
+use raytracer::lens::{LensElement, RealisticLens};
+use raytracer::gltf::load_gltf;
+use raytracer::output::*;
+use raytracer::stats::*;
+use raytracer::film::*;
+use raytracer::scene::*;
+use raytracer::render::*;
+use raytracer::sampler::frame_seed;
+use raytracer::environment::{Environment, EnvironmentMap};
+use raytracer::sky::Sky;
+use raytracer::logging::*;
+use cli::*;
+use config::*;
+use checkpoint::*;
+use progress::*;
+use window::*;
+
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Averages the accumulated samples and applies post processing, going from linear radiance to display
+// values. The accumulation buffer is stored column by column with rows counted from the bottom.
+fn develop_image(film: &Film, options: &Options, format: ImageFormat) -> Image {
+    let mut image = film.develop();
+
+    if !format.is_hdr() {
+        image.expose(options.exposure);
+        image.tone_map(options.tone_map);
+        image.apply_transfer(options.transfer);
+    }
 
-    *background_color
+    image
 }
 
-struct World {
-    pub materials: Vec<Material>,
-    pub hittables: Vec<Hittable>
+// Output variables are saved raw in floating point formats, and remapped to something viewable otherwise
+fn develop_aov_image(film: &Film, aov: Aov, options: &Options, format: ImageFormat) -> Option<Image> {
+    let mut image = film.develop_aov(aov)?;
+
+    if !format.is_hdr() {
+        match aov {
+            Aov::Normal => image.map(|n| 0.5 * (n + Color::new(1.0, 1.0, 1.0))),
+            Aov::Depth => image.normalize(),
+            Aov::Albedo => image.apply_transfer(options.transfer),
+            Aov::ObjectId | Aov::MaterialId => {},
+            Aov::Variance => {
+                // The square root spreads out the low end, which would otherwise be all black
+                image.normalize();
+                image.map(|v| heat_color(v.x.sqrt()));
+            },
+            Aov::SampleCount => {
+                image.normalize();
+                image.map(|v| heat_color(v.x));
+            }
+        }
+    }
+
+    Some(image)
 }
 
-impl World {
-    pub fn register_material(&mut self, material: Material) -> MaterialHandle {
-        self.materials.push(material);
-        MaterialHandle(self.materials.len())
+// Writes the final image, and the requested output variables next to it
+fn write_images(film: &Film, options: &Options, output: Option<&str>, format: ImageFormat) -> Result<(), String> {
+    let image = develop_image(film, options, format);
+
+    image.save(output, format).map_err(|err| format!("Could not write image: {}", err))?;
+
+    if let Some(output) = output {
+        for aov in &options.aovs {
+            let aov_image = develop_aov_image(film, *aov, options, format)
+                .ok_or_else(|| format!("The film does not record the {} pass", aov.name()))?;
+            let path = sibling_path(output, aov.name());
+            aov_image.save(Some(&path), format).map_err(|err| format!("Could not write {}: {}", path, err))?;
+        }
     }
+
+    Ok(())
 }
 
-fn two_spheres_scene() -> World {
-    let mut world = World {
-        materials: Vec::new(),
-        hittables: Vec::new()
-    };
+// The camera settings of a render, from the config file with the command line options on top. The command
+// line renderer and the render server both set up their cameras from this, so a settings file frames the
+// same image through either.
+struct CameraSetup {
+    aperture: f64,
+    dist_to_focus: f64,
+    autofocus: Option<Autofocus>,
+    look_from_end: Point3,
+    look_at_end: Point3,
+    lens_elements: Option<Vec<LensElement>>,
+    lens: LensEffects,
+    tilt_shift: TiltShift,
+    stereo: Option<Stereo>,
+    projection: Projection,
+    shutter_ramp: f64
+}
+
+impl CameraSetup {
+    // Also applies the config to the scene, and widens it for an equirectangular projection, so this comes
+    // before the image is sized
+    fn new(config: &RenderConfig, options: &Options, scene: &mut Scene) -> Result<CameraSetup, String> {
+        let (aperture, dist_to_focus) = config.apply(scene);
+
+        let projection = options.projection.or_else(|| config.projection()).unwrap_or(Projection::Perspective);
+        if projection == Projection::Equirectangular {
+            scene.aspect_ratio = 2.0;
+        }
+
+        let camera = config.camera.as_ref();
+        let lens_elements = match &options.lens {
+            Some(path) => Some(LensElement::load(path)?),
+            None => None
+        };
+
+        Ok(CameraSetup {
+            aperture,
+            dist_to_focus,
+            autofocus: options.autofocus.or_else(|| config.autofocus()),
+            look_from_end: camera.and_then(|camera| camera.look_from_end).map_or(scene.look_from, vector_from_array),
+            look_at_end: camera.and_then(|camera| camera.look_at_end).map_or(scene.look_at, vector_from_array),
+            lens_elements,
+            lens: config.lens_effects(),
+            tilt_shift: config.tilt_shift(),
+            stereo: options.stereo.or_else(|| config.stereo()),
+            projection,
+            shutter_ramp: config.shutter().2
+        })
+    }
+
+    // The camera with its shutter open from time_0 to time_1. fraction gives how far the camera has moved
+    // from look_from towards look_from_end at a time, and it keeps moving while the shutter is open, which
+    // blurs pans and dolly moves.
+    fn camera(&self, scene: &Scene, time_0: f64, time_1: f64, fraction: impl Fn(f64) -> f64) -> Result<Camera, String> {
+        let vup = Vector3::new(0.0, 1.0, 0.0);
+        let camera_at = |time: f64| {
+            let fraction = fraction(time);
+            let look_from = scene.look_from + fraction * (self.look_from_end - scene.look_from);
+            let look_at = scene.look_at + fraction * (self.look_at_end - scene.look_at);
+            let focus_dist = self.autofocus
+                .map_or(self.dist_to_focus, |autofocus| autofocus.focus_distance(&look_from, &look_at, &scene.world, time));
+            let mut camera = Camera::new(&look_from, &look_at, &vup, scene.vfov, scene.aspect_ratio, self.aperture, focus_dist, time_0, time_1);
+
+            if let Some(elements) = &self.lens_elements {
+                camera.realistic_lens = Some(Arc::new(RealisticLens::new(elements, scene.vfov, scene.aspect_ratio, focus_dist)?));
+            }
 
-    let ground_material = world.register_material(Material::Lambertian { albedo: Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)) });
-    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -10.0, 0.0), radius: 10.0 });
-    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, 10.0, 0.0), radius: 10.0 });
+            Ok::<Camera, String>(camera)
+        };
 
-    world
+        let mut camera = camera_at(time_0)?;
+        camera.set_motion(&camera_at(time_1)?);
+        camera.lens = self.lens;
+        camera.tilt_shift = self.tilt_shift;
+        camera.stereo = self.stereo;
+        camera.projection = self.projection;
+        camera.shutter_ramp = self.shutter_ramp;
+
+        Ok(camera)
+    }
 }
 
-fn two_perlin_spheres_scene() -> World {
-    let mut world = World {
-        materials: Vec::new(),
-        hittables: Vec::new()
+// Combines checkpoints of the same render made on different machines or runs into one image
+fn merge_checkpoints(options: &Options) -> Result<(), String> {
+    let (first, rest) = match options.merge_inputs.split_first() {
+        Some(inputs) => inputs,
+        None => return Err(String::from("Nothing to merge, give one or more checkpoint files"))
     };
 
-    let ground_material = world.register_material(Material::Lambertian { albedo: Texture::Noise(Perlin::new(), 4.0) });
-    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
-    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, 2.0, 0.0), radius: 2.0 });
+    let mut merged = Checkpoint::load(first)?;
+    for path in rest {
+        let checkpoint = Checkpoint::load(path)?;
+        merged.merge(&checkpoint).map_err(|reason| format!("Cannot merge {} because {}", path, reason))?;
+    }
+
+    info!("Merged {} checkpoints into {} samples per pixel", options.merge_inputs.len(), merged.samples_done);
+
+    let format = options.format.unwrap_or_else(|| ImageFormat::from_path(options.output.as_deref()));
+    write_images(&merged.film, options, options.output.as_deref(), format)?;
+
+    if let Some(path) = &options.checkpoint {
+        merged.save(path).map_err(|err| format!("Could not write checkpoint {}: {}", path, err))?;
+    }
 
-    world
+    Ok(())
 }
 
-fn earth_scene() -> World {
-    let mut world = World {
-        materials: Vec::new(),
-        hittables: Vec::new()
+// Reads the image for --importance. Only the brightness matters, so any format stb_image reads will do.
+fn load_importance_map(path: &str) -> Result<Image, String> {
+    let loaded = match stb_image::image::load(path) {
+        stb_image::image::LoadResult::ImageU8(loaded) => loaded,
+        stb_image::image::LoadResult::ImageF32(_) => return Err(format!("Cannot use the floating point image {} as an importance map", path)),
+        stb_image::image::LoadResult::Error(err) => return Err(format!("Could not read importance map {}: {}", path, err))
     };
 
-    let earth_texture = Texture::load_image("textures/earthmap.jpg");
-    let earth_material = world.register_material(Material::Lambertian { albedo: earth_texture });
-    world.hittables.push(Hittable::Sphere { mat_handle: earth_material, center: Point3::new(0.0, 0.0, 0.0), radius: 2.0 });
-    
-    world
+    let mut image = Image::new(loaded.width, loaded.height);
+    for y in 0..loaded.height {
+        for x in 0..loaded.width {
+            let offset = (y * loaded.width + x) * loaded.depth;
+            let channel = |c: usize| loaded.data[offset + usize::min(c, loaded.depth - 1)] as f64 / 255.0;
+            image.set_pixel(x, y, Color::new(channel(0), channel(1), channel(2)));
+        }
+    }
+
+    Ok(image)
 }
 
-fn simple_light_scene() -> World {
-    let mut world = World {
-        materials: Vec::new(),
-        hittables: Vec::new()
-    };
+// Blue through green to red for values from 0 to 1
+fn heat_color(t: f64) -> Color {
+    let t = clamp(t, 0.0, 1.0);
+    if t < 0.5 {
+        Color::new(0.0, 2.0 * t, 1.0 - 2.0 * t)
+    } else {
+        Color::new(2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+    }
+}
 
-    let ground_material = world.register_material(Material::Lambertian { albedo: Texture::Noise(Perlin::new(), 4.0) });
-    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
-    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, 2.0, 0.0), radius: 2.0 });
+// What the render threads produced for one frame
+struct FrameResult {
+    film: Film,
+    samples_per_pixel: usize,
+    ray_counter: RayCounter,
+    invalid_samples: usize,
+    render_time: Duration,
+    cancelled: bool
+}
 
-    let diff_light = world.register_material(Material::DiffuseLight { emit: Texture::SolidColor(Color::new(4.0, 4.0, 4.0)) });
-    world.hittables.push(Hittable::XYRect { mat_handle: diff_light, x0: 3.0, x1: 5.0, y0: 1.0, y1: 3.0, k: -2.0 });
+// Accumulated results of a frame, shared between the render threads and whoever watches over them
+struct FrameState {
+    film: Mutex<Film>,
+    samples_done: AtomicUsize,
+    ray_counter: Mutex<RayCounter>,
+    invalid_samples: AtomicUsize,
+    cancelled: AtomicBool
+}
 
-    world
+impl FrameState {
+    fn new(film: Film, samples_done: usize, max_depth: i32) -> FrameState {
+        FrameState {
+            film: Mutex::new(film),
+            samples_done: AtomicUsize::new(samples_done),
+            ray_counter: Mutex::new(RayCounter::new(max_depth)),
+            invalid_samples: AtomicUsize::new(0),
+            cancelled: AtomicBool::new(false)
+        }
+    }
 }
 
-fn cornell_box_scene() -> World {
-    let mut world = World {
-        materials: Vec::new(),
-        hittables: Vec::new()
+// Traces the given passes over all tiles of the job on the pool, adding the finished tiles to the film.
+// Passes are numbered by the sample of each pixel they trace. Returns when all passes are done, the
+// adaptive sampling budget is spent or every pixel converged, or once the tiles in progress finish after
+// a cancel.
+fn trace_passes(job: &RenderJob, state: &FrameState, passes: Range<usize>, sample_budget: Option<usize>, pool: &rayon::ThreadPool, progress: &Progress) {
+    let adaptive = match &job.adaptive {
+        Some(adaptive) => adaptive,
+        None => {
+            trace_tiles(job, state, passes, pool, progress);
+            return;
+        }
     };
 
-    let red = world.register_material(Material::Lambertian { albedo: Texture::SolidColor(Color::new(0.65, 0.05, 0.05)) });
-    let white = world.register_material(Material::Lambertian { albedo: Texture::SolidColor(Color::new(0.73, 0.73, 0.73)) });
-    let green = world.register_material(Material::Lambertian { albedo: Texture::SolidColor(Color::new(0.12, 0.45, 0.15)) });
-    let light = world.register_material(Material::DiffuseLight { emit: Texture::SolidColor(Color::new(15.0, 15.0, 15.0)) });
-
-    world.hittables.push(Hittable::YZRect { mat_handle: green, y0: 0.0,     y1: 555.0, z0: 0.0,     z1: 555.0, k: 555.0 });
-    world.hittables.push(Hittable::YZRect { mat_handle: red,   y0: 0.0,     y1: 555.0, z0: 0.0,     z1: 555.0, k: 0.0 });
-    world.hittables.push(Hittable::XZRect { mat_handle: light, x0: 213.0,   x1: 343.0, z0: 227.0,   z1: 332.0, k: 554.0 });
-    world.hittables.push(Hittable::XZRect { mat_handle: white, x0: 0.0,     x1: 555.0, z0: 0.0,     z1: 555.0, k: 0.0 });
-    world.hittables.push(Hittable::XZRect { mat_handle: white, x0: 0.0,     x1: 555.0, z0: 0.0,     z1: 555.0, k: 555.0 });
-    world.hittables.push(Hittable::XYRect { mat_handle: white, x0: 0.0,     x1: 555.0, y0: 0.0,     y1: 555.0, k: 555.0 });
-
-    let box1 = Hittable::new_box(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 330.0, 165.0), white);
-    let box1 = Hittable::new_rotate_y(15.0, box1);
-    let box1 = Hittable::Translate { offset: Vector3::new(265.0, 0.0, 295.0), ptr: Box::new(box1) };
-    world.hittables.push(box1);
-
-    let box2 = Hittable::new_box(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 165.0, 165.0), white);
-    let box2 = Hittable::new_rotate_y(-18.0, box2);
-    let box2 = Hittable::Translate { offset: Vector3::new(130.0, 0.0, 65.0), ptr: Box::new(box2) };
-    world.hittables.push(box2);
-
-    world
+    // Which pixels converged decides what the next pass traces, so with adaptive sampling a pass only
+    // starts once the one before it is done and checked over the whole film. Otherwise the image would
+    // depend on which tiles of other passes happened to be finished, and so on the number of threads.
+    let pixel_count = job.crop.width() * job.crop.height();
+    let mut samples_traced = 0;
+
+    for pass in passes {
+        if state.cancelled.load(Ordering::SeqCst) || adaptive.converged_count() == pixel_count {
+            break;
+        }
+
+        if sample_budget.is_some_and(|budget| samples_traced >= budget) {
+            break;
+        }
+
+        samples_traced += trace_tiles(job, state, pass..pass + 1, pool, progress);
+        adaptive.update(&state.film.lock().unwrap());
+    }
 }
 
-fn cornell_box_smoke_scene() -> World {
-    let mut world = World {
-        materials: Vec::new(),
-        hittables: Vec::new()
-    };
+// Traces the given passes over all tiles on the pool, returning the number of samples traced
+fn trace_tiles(job: &RenderJob, state: &FrameState, passes: Range<usize>, pool: &rayon::ThreadPool, progress: &Progress) -> usize {
+    let tiles = Tile::split_region(job.crop.width(), job.crop.height());
+    let samples_traced = AtomicUsize::new(0);
 
-    let red = world.register_material(Material::Lambertian { albedo: Texture::SolidColor(Color::new(0.65, 0.05, 0.05)) });
-    let white = world.register_material(Material::Lambertian { albedo: Texture::SolidColor(Color::new(0.73, 0.73, 0.73)) });
-    let green = world.register_material(Material::Lambertian { albedo: Texture::SolidColor(Color::new(0.12, 0.45, 0.15)) });
-    let light = world.register_material(Material::DiffuseLight { emit: Texture::SolidColor(Color::new(7.0, 7.0, 7.0)) });
-
-    world.hittables.push(Hittable::YZRect { mat_handle: green, y0: 0.0,     y1: 555.0, z0: 0.0,     z1: 555.0, k: 555.0 });
-    world.hittables.push(Hittable::YZRect { mat_handle: red,   y0: 0.0,     y1: 555.0, z0: 0.0,     z1: 555.0, k: 0.0 });
-    world.hittables.push(Hittable::XZRect { mat_handle: light, x0: 113.0,   x1: 443.0, z0: 127.0,   z1: 432.0, k: 554.0 });
-    world.hittables.push(Hittable::XZRect { mat_handle: white, x0: 0.0,     x1: 555.0, z0: 0.0,     z1: 555.0, k: 0.0 });
-    world.hittables.push(Hittable::XZRect { mat_handle: white, x0: 0.0,     x1: 555.0, z0: 0.0,     z1: 555.0, k: 555.0 });
-    world.hittables.push(Hittable::XYRect { mat_handle: white, x0: 0.0,     x1: 555.0, y0: 0.0,     y1: 555.0, k: 555.0 });
-
-    let box1_phase = world.register_material(Material::Isotropic { albedo: Texture::SolidColor(Color::new(0.0, 0.0, 0.0)) });
-    let box1 = Hittable::new_box(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 330.0, 165.0), white);
-    let box1 = Hittable::new_rotate_y(15.0, box1);
-    let box1 = Hittable::Translate { offset: Vector3::new(265.0, 0.0, 295.0), ptr: Box::new(box1) };
-    let box1 = Hittable::new_constant_medium(box1, 0.01, box1_phase);
-    world.hittables.push(box1);
-    
-    let box2_phase = world.register_material(Material::Isotropic { albedo: Texture::SolidColor(Color::new(1.0, 1.0, 1.0)) });
-    let box2 = Hittable::new_box(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 165.0, 165.0), white);
-    let box2 = Hittable::new_rotate_y(-18.0, box2);
-    let box2 = Hittable::Translate { offset: Vector3::new(130.0, 0.0, 65.0), ptr: Box::new(box2) };
-    let box2 = Hittable::new_constant_medium(box2, 0.01, box2_phase);
-    world.hittables.push(box2);
-
-    world
+    // Work items are the tiles of every pass in order. Threads take the next item as soon as they are
+    // done with their last one, so a slow tile never keeps the other threads waiting for a pass to end.
+    let next_item = AtomicUsize::new(0);
+    let item_count = passes.len() * tiles.len();
+    let tiles_left: Vec<AtomicUsize> = passes.clone().map(|_| AtomicUsize::new(tiles.len())).collect();
+
+    pool.broadcast(|_| {
+        loop {
+            let item = next_item.fetch_add(1, Ordering::SeqCst);
+            if item >= item_count || state.cancelled.load(Ordering::SeqCst) {
+                break;
+            }
+
+            let pass = item / tiles.len();
+            let tile = &tiles[item % tiles.len()];
+
+            progress.tile_started();
+            let mut tile_ray_counter = RayCounter::new(job.max_depth);
+            let (tile_film, x0, y0) = job.render_tile(tile, passes.start + pass, &mut tile_ray_counter, &state.invalid_samples);
+
+            let tile_samples = tile_film.total_samples() as usize;
+            let mut film = state.film.lock().unwrap();
+            film.add_tile(&tile_film, x0, y0);
+
+            // Passes can finish out of order, but every pixel has at least one sample per finished
+            // pass. The count only changes under the film lock, so the two always agree.
+            let pass_finished = tiles_left[pass].fetch_sub(1, Ordering::SeqCst) == 1;
+            if pass_finished {
+                state.samples_done.fetch_add(1, Ordering::SeqCst);
+            }
+            drop(film);
+
+            samples_traced.fetch_add(tile_samples, Ordering::SeqCst);
+            state.ray_counter.lock().unwrap().merge(&tile_ray_counter);
+            progress.tile_finished(tile_samples, pass_finished);
+        }
+    });
+
+    samples_traced.into_inner()
 }
 
-fn final_scene() -> World {
-    let mut world = World {
-        materials: Vec::new(),
-        hittables: Vec::new()
+// Adds samples to the film until it has the requested number per pixel, saving snapshots to the
+// output and checkpoints along the way
+#[allow(clippy::too_many_arguments)]
+fn render_frame(
+        job: RenderJob,
+        initial_film: Film,
+        initial_samples: usize,
+        samples_per_pixel: usize,
+        pool: &Arc<rayon::ThreadPool>,
+        options: &Options,
+        output: Option<&str>,
+        image_format: ImageFormat,
+        save_checkpoint: &dyn Fn(&Film, usize),
+        mut window: Option<&mut PreviewWindow>
+        ) -> FrameResult {
+    let pixel_count = job.crop.width() * job.crop.height();
+
+    // With adaptive sampling the samples per pixel are an average over the image. Passes skip the pixels
+    // that converged, and the noisy ones may get several times the average before the budget runs out.
+    // A sample map gives each pixel its own count, and the passes go on until the pixel with the most is done
+    let (passes, sample_budget) = match (&job.adaptive, &job.sample_map) {
+        (Some(_), _) => {
+            let budget = (pixel_count * samples_per_pixel).saturating_sub(initial_film.total_samples() as usize);
+            ((ADAPTIVE_MAX_FACTOR * samples_per_pixel).saturating_sub(initial_samples), Some(budget))
+        },
+        (None, Some(map)) => (map.max_samples().saturating_sub(initial_samples), None),
+        (None, None) => (samples_per_pixel.saturating_sub(initial_samples), None)
+    };
+
+    let total_samples = match (sample_budget, &job.sample_map) {
+        (Some(budget), _) => budget,
+        (None, Some(map)) => map.samples_after(initial_samples),
+        (None, None) => pixel_count * passes
     };
 
-    let mut boxes1 = Vec::new();
-    let ground = world.register_material(Material::Lambertian { albedo: Texture::SolidColor(Color::new(0.48, 0.83, 0.53)) });
+    let state = Arc::new(FrameState::new(initial_film, initial_samples, job.max_depth));
+    let tile_count = Tile::split_region(job.crop.width(), job.crop.height()).len();
+    let progress = Arc::new(Progress::new(total_samples, passes, tile_count));
+    let job = Arc::new(job);
 
-    const BOXES_PER_SIDE: usize = 20;
+    let now = Instant::now();
 
-    for i in 0..BOXES_PER_SIDE {
-        for j in 0..BOXES_PER_SIDE {
-            let w = 100.0;
-            let x0 = -1000.0 + i as f64 * w;
-            let z0 = -1000.0 + j as f64 * w;
-            let y0 = 0.0;
-            let x1 = x0 + w;
-            let y1 = random_double_range(1.0, 101.0);
-            let z1 = z0 + w;
+    let render_thread = {
+        let job = Arc::clone(&job);
+        let state = Arc::clone(&state);
+        let progress = Arc::clone(&progress);
+        let pool = Arc::clone(pool);
 
-            boxes1.push(Hittable::new_box(Point3::new(x0, y0, z0), Point3::new(x1, y1, z1), ground));
-        }
-    }
+        thread::spawn(move || trace_passes(&job, &state, initial_samples..initial_samples + passes, sample_budget, &pool, &progress))
+    };
 
-    world.hittables.push(Hittable::new_bvh_node(&boxes1, 0, boxes1.len(), 0.0, 1.0));
+    let reporter = spawn_reporter(Arc::clone(&progress));
 
-    let light = world.register_material(Material::DiffuseLight { emit: Texture::SolidColor(Color::new(7.0, 7.0, 7.0)) });
-    world.hittables.push(Hittable::XZRect { mat_handle: light, x0: 123.0, x1: 423.0, z0: 147.0, z1: 412.0, k: 554.0 });
+    let mut last_snapshot_time = Instant::now();
+    let mut last_snapshot_samples = initial_samples;
+    let mut last_checkpoint_time = Instant::now();
 
-    let center_1 = Point3::new(400.0, 400.0, 200.0);
-    let center_2 = center_1 + Vector3::new(30.0, 0.0, 0.0);
-    let moving_sphere_material = world.register_material(Material::Lambertian { albedo: Texture::SolidColor(Color::new(0.7, 0.3, 0.1)) });
-    world.hittables.push(Hittable::MovingSphere { mat_handle: moving_sphere_material, center_0: center_1, center_1: center_2, time_0: 0.0, time_1: 1.0, radius: 50.0 });
+    while !render_thread.is_finished() {
+        thread::sleep(Duration::from_millis(100));
 
-    let dielectric = world.register_material(Material::Dielectric { ir: 1.5 });
-    world.hittables.push(Hittable::Sphere { mat_handle: dielectric, center: Point3::new(260.0, 150.0, 45.0), radius: 50.0 });
+        let samples = state.samples_done.load(Ordering::SeqCst);
+        let time_due = options.snapshot_seconds.is_some_and(|seconds| last_snapshot_time.elapsed().as_secs_f64() >= seconds);
+        let samples_due = options.snapshot_samples.is_some_and(|count| samples >= last_snapshot_samples + count);
 
-    let metal = world.register_material(Material::Metal { albedo: Color::new(0.8, 0.8, 0.9), fuzz: 1.0 });
-    world.hittables.push(Hittable::Sphere { mat_handle: metal, center: Point3::new(0.0, 150.0, 145.0), radius: 50.0 });
+        if samples > last_snapshot_samples && (time_due || samples_due) {
+            let image = develop_image(&state.film.lock().unwrap(), options, image_format);
+            if let Err(err) = image.save(output, image_format) {
+                error!("Could not write snapshot: {}", err);
+            }
+            verbose!("Wrote snapshot with {} samples per pixel", samples);
 
-    let boundary = Hittable::Sphere { mat_handle: dielectric, center: Point3::new(360.0, 150.0, 145.0), radius: 70.0 };
-    world.hittables.push(boundary.clone());
-    let phase = world.register_material(Material::Isotropic { albedo: Texture::SolidColor(Color::new(0.2, 0.4, 0.9)) });
-    world.hittables.push(Hittable::new_constant_medium(boundary, 0.2, phase));
+            last_snapshot_time = Instant::now();
+            last_snapshot_samples = samples;
+        }
 
-    let boundary = Hittable::Sphere { mat_handle: dielectric, center: Point3::new(0.0, 0.0, 0.0), radius: 5000.0 };
-    let phase = world.register_material(Material::Isotropic { albedo: Texture::SolidColor(Color::new(1.0, 1.0, 1.0)) });
-    world.hittables.push(Hittable::new_constant_medium(boundary, 0.0001, phase));
+        if options.checkpoint.is_some() && last_checkpoint_time.elapsed().as_secs_f64() >= options.checkpoint_seconds {
+            let film = state.film.lock().unwrap();
+            save_checkpoint(&film, state.samples_done.load(Ordering::SeqCst));
+            last_checkpoint_time = Instant::now();
+        }
 
-    let emat = world.register_material(Material::Lambertian { albedo: Texture::load_image("textures/earthmap.jpg") });
-    world.hittables.push(Hittable::Sphere { mat_handle: emat, center: Point3::new(400.0, 200.0, 400.0), radius: 100.0 });
-    let pertext = world.register_material(Material::Lambertian { albedo: Texture::Noise(Perlin::new(), 0.1) });
-    world.hittables.push(Hittable::Sphere { mat_handle: pertext, center: Point3::new(220.0, 280.0, 300.0), radius: 80.0 });
+        if let Some(window) = &mut window {
+            // Always developed for display, whatever the output format
+            if window.is_open() {
+                window.show(&develop_image(&state.film.lock().unwrap(), options, ImageFormat::PpmBinary));
+            } else if !state.cancelled.swap(true, Ordering::SeqCst) {
+                info!("\nWindow closed, stopping the render after the tiles in progress");
+            }
+        }
+    }
 
-    let mut boxes2 = Vec::new();
-    let white = world.register_material(Material::Lambertian { albedo: Texture::SolidColor(Color::new(0.73, 0.73, 0.73)) });
-    let ns = 1000;
+    render_thread.join().unwrap();
+    progress.finish();
+    reporter.join().unwrap();
 
-    for j in 0..ns {
-        boxes2.push(Hittable::Sphere { mat_handle: white, center: Point3::random_range(0.0, 165.0), radius: 10.0 });
+    if let Some(adaptive) = &job.adaptive {
+        verbose!("{} of {} pixels converged", adaptive.converged_count(), pixel_count);
     }
 
-    world.hittables.push(Hittable::Translate {
-                    offset: Vector3::new(-100.0, 270.0, 395.0),
-                    ptr: Box::new(Hittable::new_rotate_y(15.0, Hittable::new_bvh_node(&boxes2, 0, boxes2.len(), 0.0, 1.0)))
-                }
-    );
+    if let Some(window) = &mut window {
+        if window.is_open() {
+            window.show(&develop_image(&state.film.lock().unwrap(), options, ImageFormat::PpmBinary));
+        }
+    }
 
-    world
+    // The render thread is done, so nothing else holds on to the results anymore
+    let state = Arc::try_unwrap(state).ok().expect("Frame state is still shared");
+    FrameResult {
+        film: state.film.into_inner().unwrap(),
+        samples_per_pixel: state.samples_done.into_inner(),
+        ray_counter: state.ray_counter.into_inner().unwrap(),
+        invalid_samples: state.invalid_samples.into_inner(),
+        render_time: now.elapsed(),
+        cancelled: state.cancelled.into_inner()
+    }
 }
 
-fn random_scene() -> World {
-    let mut world = World {
-        materials: Vec::new(),
-        hittables: Vec::new()
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let options = match Options::parse(&args[1..]) {
+        Ok(options) => options,
+        Err(msg) => {
+            error!("{}\n\n{}", msg, USAGE);
+            std::process::exit(1);
+        }
     };
 
-    let ground_material = world.register_material(Material::Lambertian { albedo: Texture::Checker(Color::new(0.2, 0.5, 0.5), Color::new(0.9, 0.9, 0.9)) });
-    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
-
-    for a in -11..11 {
-        for b in -11..11 {
-            let choose_mat = random_double();
-            let center = Point3::new(a as f64 + 0.9 * random_double(), 0.2, b as f64 + 0.9 * random_double());
-
-            if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                
-                if choose_mat  < 0.8 {
-                    let albedo = Color::random();
-                    let sphere_material = world.register_material(Material::Lambertian { albedo: Texture::SolidColor(albedo) });
-                    let center2 = center + Vector3::new(0.0, random_double_range(0.0, 0.5), 0.0);
-                    world.hittables.push(Hittable::MovingSphere { mat_handle: sphere_material, center_0: center, center_1: center2, time_0: 0.0, time_1: 1.0, radius: 0.2 });
-                } else if choose_mat < 0.95 {
-                    let albedo = Color::random_range(0.5, 1.0); 
-                    let fuzz = random_double_range(0.0, 0.5);
-                    let sphere_material = world.register_material(Material::Metal { albedo, fuzz });
-                    world.hittables.push(Hittable::Sphere { mat_handle: sphere_material, center, radius: 0.2 });
-                } else {
-                    let sphere_material = world.register_material(Material::Dielectric { ir: 1.5 });
-                    world.hittables.push(Hittable::Sphere { mat_handle: sphere_material, center, radius: 0.2 });
-                }
-            }
-        }
+    if options.help {
+        println!("{}", USAGE);
+        return;
     }
 
-    let material1 = world.register_material(Material::Dielectric { ir: 1.5 });
-    world.hittables.push(Hittable::Sphere { mat_handle: material1, center: Point3::new(0.0, 1.0, 0.0), radius: 1.0 });
+    set_verbosity(options.verbosity);
 
-    let material2 = world.register_material(Material::Lambertian { albedo: Texture::SolidColor(Color::new(0.4, 0.2, 0.1)) });
-    world.hittables.push(Hittable::Sphere { mat_handle: material2, center: Point3::new(-4.0, 1.0, 0.0), radius: 1.0 });
+    if options.merge {
+        if let Err(msg) = merge_checkpoints(&options) {
+            error!("{}", msg);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let material3 = world.register_material(Material::Metal { albedo: Color::new(0.7, 0.6, 0.5), fuzz: 0.0 });
-    world.hittables.push(Hittable::Sphere { mat_handle: material3, center: Point3::new(4.0, 1.0, 0.0), radius: 1.0 });
+    // One thread per logical CPU unless told otherwise, scaled by the oversubscription factor
+    let base_thread_count = options.threads.unwrap_or_else(|| thread::available_parallelism().map_or(1, |count| count.get()));
+    let thread_count = usize::max((base_thread_count as f64 * options.oversubscribe).round() as usize, 1);
 
-    world
-}
+    if options.serve {
+        if let Err(msg) = server::serve(&options, thread_count) {
+            error!("{}", msg);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-struct PixelChunk {
-    pub x: usize,
-    pub y: usize
-}
+    let config = match RenderConfig::load_or_default(options.config.as_deref()) {
+        Ok(config) => config,
+        Err(msg) => {
+            error!("{}", msg);
+            std::process::exit(1);
+        }
+    };
 
-struct Scene {
-    pub aspect_ratio: f64,
-    pub image_width: usize,
-    pub samples_per_pixel: usize,
-    pub background: Color,
-    pub look_from: Point3,
-    pub look_at: Point3,
-    pub vfov: f64,
-    pub world: std::sync::Arc<World>
-}
+    let scene_name = options.scene.clone().or_else(|| config.scene.clone()).unwrap_or_else(|| String::from("final"));
 
-fn main() {
     // Image
-    let thread_count = 10; // Find maximum thread count for CPU
-    let max_depth = 50;
-    let vup = Vector3::new(0.0, 1.0, 0.0);
-    let dist_to_focus = 10.0; 
-
-    let scene = match 7 {
-
-        0 => {
-            let world = Arc::new(random_scene());
-
-            // Camera
-            let look_from = Point3::new(13.0, 2.0, 3.0);
-            let look_at = Point3::new(0.0, 0.0, 0.0);
-
-            Scene {
-                aspect_ratio: 16.0 / 9.0,
-                image_width: 400,
-                samples_per_pixel: 100,
-                background: Color::new(0.7, 0.8, 1.0),
-                look_from,
-                look_at,
-                vfov: 20.0,
-                world
+    let mut max_depth = config.max_depth.unwrap_or(50);
+
+    // Random scenes are laid out from the seed as well, so the same seed gives the same scene
+    seed_random(options.seed as u64);
+    let scene_build_start = std::time::Instant::now();
+    let is_gltf = scene_name.ends_with(".gltf") || scene_name.ends_with(".glb");
+    let built = match find_scene(&scene_name) {
+        Some(entry) => (entry.build)(),
+        None if is_gltf => load_gltf(&scene_name),
+        None => {
+            error!("Unknown scene {}. Available scenes:", scene_name);
+            for entry in &SCENES {
+                error!("    {}", entry.name);
             }
-        },
-        1 => {
-            let world = Arc::new(two_spheres_scene());
-
-            // Camera
-            let look_from = Point3::new(13.0, 2.0, 3.0);
-            let look_at = Point3::new(0.0, 0.0, 0.0);
-
-            Scene {
-                aspect_ratio: 16.0 / 9.0,
-                image_width: 400,
-                samples_per_pixel: 100,
-                background: Color::new(0.7, 0.8, 1.0),
-                look_from,
-                look_at,
-                vfov: 20.0,
-                world
-            }
-        },
-        2 => {
-            let world = Arc::new(two_perlin_spheres_scene());
-
-            // Camera
-            let look_from = Point3::new(13.0, 2.0, 3.0);
-            let look_at = Point3::new(0.0, 0.0, 0.0);
-
-            Scene {
-                aspect_ratio: 16.0 / 9.0,
-                image_width: 400,
-                samples_per_pixel: 100,
-                background: Color::new(0.7, 0.8, 1.0),
-                look_from,
-                look_at,
-                vfov: 20.0,
-                world
-            }
-        },
-        3 => {
-            let world = Arc::new(earth_scene());
-
-            // Camera
-            let look_from = Point3::new(13.0, 2.0, 3.0);
-            let look_at = Point3::new(0.0, 0.0, 0.0);
-
-            Scene {
-                aspect_ratio: 16.0 / 9.0,
-                image_width: 400,
-                samples_per_pixel: 100,
-                background: Color::new(0.7, 0.8, 1.0),
-                look_from,
-                look_at,
-                vfov: 20.0,
-                world
-            }
-        },
-        4 => {
-            let world = Arc::new(simple_light_scene());
-
-            // Camera
-            let look_from = Point3::new(26.0, 3.0, 6.0);
-            let look_at = Point3::new(0.0, 2.0, 0.0);
-
-            Scene {
-                aspect_ratio: 16.0 / 9.0,
-                image_width: 400,
-                samples_per_pixel: 100,
-                background: Color::new(0.0, 0.0, 0.0),
-                look_from,
-                look_at,
-                vfov: 20.0,
-                world
-            }
-        },
-        5 => {
-            let world = Arc::new(cornell_box_scene());
-
-            // Camera
-            let look_from = Point3::new(278.0, 278.0, -800.0);
-            let look_at = Point3::new(278.0, 278.0, 0.0);
-
-            Scene {
-                aspect_ratio: 1.0,
-                image_width: 600,
-                samples_per_pixel: 200,
-                background: Color::new(0.0, 0.0, 0.0),
-                look_from,
-                look_at,
-                vfov: 40.0,
-                world
-            }
-        },
-        6 => {
-            let world = Arc::new(cornell_box_smoke_scene());
-
-            // Camera
-            let look_from = Point3::new(278.0, 278.0, -800.0);
-            let look_at = Point3::new(278.0, 278.0, 0.0);
-
-            Scene {
-                aspect_ratio: 1.0,
-                image_width: 600,
-                samples_per_pixel: 40,
-                background: Color::new(0.0, 0.0, 0.0),
-                look_from,
-                look_at,
-                vfov: 40.0,
-                world
-            }
-        },
-        7 => {
-            let world = Arc::new(final_scene());
-
-            // Camera
-            let look_from = Point3::new(478.0, 278.0, -600.0);
-            let look_at = Point3::new(278.0, 278.0, 0.0);
-
-            Scene {
-                aspect_ratio: 1.0,
-                image_width: 800,
-                samples_per_pixel: 2000,
-                background: Color::new(0.0, 0.0, 0.0),
-                look_from,
-                look_at,
-                vfov: 40.0,
-                world
-            }
-        },
-
-        _ => {
-            panic!("Unsupported scene selected")
+            std::process::exit(1);
+        }
+    };
+    let mut scene = match built {
+        Ok(scene) => scene,
+        Err(msg) => {
+            error!("{}", msg);
+            std::process::exit(1);
         }
     };
+    let scene_build_time = scene_build_start.elapsed();
+    verbose!("Built scene {} in {:.3} seconds", scene.name, scene_build_time.as_secs_f64());
+
+    let camera_setup = match CameraSetup::new(&config, &options, &mut scene) {
+        Ok(setup) => setup,
+        Err(msg) => {
+            error!("{}", msg);
+            std::process::exit(1);
+        }
+    };
+
+    if options.preview {
+        scene.image_width = usize::max(scene.image_width / 4, 1);
+        scene.samples_per_pixel = usize::min(scene.samples_per_pixel, 8);
+        max_depth = i32::min(max_depth, 8);
+    }
+
+    // Explicit command line settings win over both the config file and preview mode
+    if let Some(width) = options.width {
+        scene.image_width = width;
+    }
+
+    if let Some(samples) = options.samples {
+        scene.samples_per_pixel = samples;
+    }
+
+    if let Some(depth) = options.depth {
+        max_depth = depth;
+    }
     
     let image_width = scene.image_width;
-    let image_height = (scene.image_width as f64 * scene.aspect_ratio) as usize;
+    // Both eyes of a stereo image have the aspect ratio of the scene
+    let image_aspect_ratio = camera_setup.stereo.map_or(scene.aspect_ratio, |stereo| stereo.image_aspect_ratio(scene.aspect_ratio));
+    let image_height = (scene.image_width as f64 / image_aspect_ratio) as usize;
+
+    let crop = options.crop.unwrap_or_else(|| Crop::full(image_width, image_height));
+    if !crop.fits_in(image_width, image_height) {
+        error!("Crop region does not fit in the {}x{} image", image_width, image_height);
+        std::process::exit(1);
+    }
+
+    let region_width = crop.width();
+    let region_height = crop.height();
 
-    let camera = Arc::new(Camera::new(&scene.look_from, &scene.look_at, &vup, scene.vfov, scene.aspect_ratio, 0.1, dist_to_focus, 0.0, 1.0));
+    let importance_map = match &options.importance_map {
+        Some(path) => match load_importance_map(path) {
+            Ok(map) => Some(map),
+            Err(msg) => {
+                error!("{}", msg);
+                std::process::exit(1);
+            }
+        },
+        None => None
+    };
+
+    // Each frame of an animation spans 1/fps of scene time, while a still image covers the interval
+    // the scenes set up their motion over
+    let frames = options.frames.unwrap_or(FrameRange { first: 0, last: 0 });
+    let (shutter_open, shutter_close, _) = config.shutter();
+    if shutter_open > shutter_close {
+        error!("The shutter cannot close before it opens");
+        std::process::exit(1);
+    }
+    let shutter = |frame: usize| {
+        let (frame_start, frame_end) = match options.frames {
+            Some(_) => (frame as f64 / options.fps, (frame + 1) as f64 / options.fps),
+            None => (0.0, 1.0)
+        };
+        let length = frame_end - frame_start;
+        (frame_start + shutter_open * length, frame_start + shutter_close * length)
+    };
+
+    // The scene is not shared with anything yet, so the BVH can still be built in place
+    let world = Arc::get_mut(&mut scene.world).expect("Scene world is already shared");
+    world.build_bvh(shutter(frames.first).0, shutter(frames.last).1);
+
+    if let Some(path) = &options.environment {
+        match EnvironmentMap::load(path, options.environment_rotation) {
+            Ok(map) => world.environment = Some(Environment::Map(map)),
+            Err(msg) => {
+                error!("{}", msg);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(params) = &options.sky {
+        let sky = Sky::new(params);
+        world.add_sun(sky.sun_direction(), sky.sun_angular_diameter(), sky.sun_irradiance());
+        world.environment = Some(Environment::Sky(sky));
+    }
+
+    if let Some(params) = &options.fog {
+        world.set_fog(params, Color::new(1.0, 1.0, 1.0), 0.0);
+    }
 
     // Render
-    println!("P3\n{} {}\n255\n", image_width, image_height);
+    let mut resumed = None;
+
+    if let Some(path) = &options.resume {
+        let checkpoint = match Checkpoint::load(path) {
+            Ok(checkpoint) => checkpoint,
+            Err(msg) => {
+                error!("{}", msg);
+                std::process::exit(1);
+            }
+        };
+
+        let film = Film::new(region_width, region_height, options.filter, &options.aovs);
+        if let Some(reason) = checkpoint.mismatch(scene.name, image_width, image_height, &crop, max_depth, &film) {
+            error!("Cannot resume from {} because {}", path, reason);
+            std::process::exit(1);
+        }
 
-    use std::{time, thread};
-    use std::sync::{Arc, Mutex};
+        info!("Resuming from {} with {} samples per pixel done", path, checkpoint.samples_done);
+        resumed = Some((checkpoint.film, checkpoint.samples_done));
+    }
 
-    let pixel_colors = Arc::new(Mutex::new(vec![vec![Color::new(0.0, 0.0, 0.0); image_height]; image_width]));
-    let mut thread_handles = Vec::new();
-    let mut thread_receivers = Vec::new();
-    let pixels_to_process_count = image_width * image_height;
+    let mut window = if options.window {
+        match PreviewWindow::open(&format!("raytracer - {}", scene.name), region_width, region_height) {
+            Ok(window) => Some(window),
+            Err(msg) => {
+                error!("{}", msg);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
 
-    eprintln!(
+    info!(
         "Rendering {}x{} ({} pixels) image with {} samples per pixel and a max depth of {}, using {} threads", 
-        image_width,
-        image_height,
-        image_width * image_height,
+        region_width,
+        region_height,
+        region_width * region_height,
         scene.samples_per_pixel,
         max_depth,
         thread_count
         );
 
-    use std::time::Instant;
-    use std::sync::mpsc;
-    
-    let now = Instant::now();
+    let pool = match rayon::ThreadPoolBuilder::new().num_threads(thread_count).build() {
+        Ok(pool) => Arc::new(pool),
+        Err(err) => {
+            error!("Could not start the render threads: {}", err);
+            std::process::exit(1);
+        }
+    };
 
-    for i in 0..thread_count {
-        let pixel_colors = Arc::clone(&pixel_colors);
-        let world = scene.world.clone();
-        let camera = Arc::clone(&camera);
-        let samples_per_pixel = scene.samples_per_pixel;
-        let background = scene.background;
-
-        let (tx, rx) = mpsc::channel();
-        thread_receivers.push(rx);
-
-        let handle = thread::spawn(move || {
-            let mut local_pixel_colors = vec![vec![Color::new(0.0, 0.0, 0.0); image_height]; image_width];
-            let mut pixels_left = pixels_to_process_count;
-            let mut last_change = 0;
-
-            for x in 0..image_width {
-                for y in 0..image_height {
-                    let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-
-                    for _s in 0..samples_per_pixel / thread_count {
-                        let u = (x as f64 + random_double()) / (image_width as f64 - 1.0);
-                        let v = (y as f64 + random_double()) / (image_height as f64 - 1.0);
-
-                        let r = camera.get_ray(u, v);
-
-                        pixel_color += ray_color(&r, &background, &world.hittables, max_depth, &world.materials);
-                    }
-
-                    local_pixel_colors[x][y] = pixel_color;
-                    pixels_left -= 1;
-                    last_change += 1;
-
-                    if last_change == 50 {
-                        match tx.send((i, pixels_left)) {
-                            Ok(_) => {
-                            },
-                            Err(msg) => {
-                                eprintln!("{:?}", msg);
-                            }
-                        }
-                        last_change = 0;
-                    }
-                }
+    let image_format = options.format.unwrap_or_else(|| ImageFormat::from_path(options.output.as_deref()));
+
+    let save_checkpoint = |film: &Film, samples_done: usize| {
+        if let Some(path) = &options.checkpoint {
+            let checkpoint = Checkpoint {
+                scene: scene.name.to_string(),
+                image_width,
+                image_height,
+                crop,
+                max_depth,
+                seed: options.seed,
+                samples_done,
+                film: film.clone()
+            };
+
+            match checkpoint.save(path) {
+                Ok(()) => verbose!("Wrote checkpoint with {} samples per pixel", samples_done),
+                Err(err) => error!("Could not write checkpoint {}: {}", path, err)
             }
+        }
+    };
 
-            let mut pixels = pixel_colors.lock().unwrap();
-            for x in 0..image_width {
-                for y in 0..image_height {
-                    pixels[x][y] += local_pixel_colors[x][y];
-                }
+    let mut render_time = Duration::ZERO;
+    let mut output_time = Duration::ZERO;
+    let mut samples_per_pixel = 0;
+    let mut total_samples = 0;
+    let mut invalid_samples = 0;
+    let mut ray_counter = RayCounter::new(max_depth);
+
+    for frame in frames.first..=frames.last {
+        let (time_0, time_1) = shutter(frame);
+        let fraction = |time: f64| match options.frames {
+            Some(_) => frames.fraction(time * options.fps),
+            None => time
+        };
+        let camera = match camera_setup.camera(&scene, time_0, time_1, fraction) {
+            Ok(camera) => camera,
+            Err(msg) => {
+                error!("{}", msg);
+                std::process::exit(1);
             }
-        });
+        };
 
-        thread_handles.push(handle);
-    }
-        
-    let one_second = time::Duration::from_secs(1);
+        let output = match options.frames {
+            Some(_) => options.output.as_deref().map(|path| frame_path(path, frame)),
+            None => options.output.clone()
+        };
 
-    let mut thread_pixel_counts = vec![pixels_to_process_count; thread_count];
+        if options.frames.is_some() {
+            info!("Rendering frame {} ({} of {})", frame, frame - frames.first + 1, frames.len());
+        }
 
-    let handle = thread::spawn(move || {
-        loop {
-            for r in &thread_receivers {
-                if let Ok((t_index, count)) = r.try_recv() {
-                    thread_pixel_counts[t_index] = count;
-                }
-            }
-            
-            eprint!("\rProgress: {:?}", &thread_pixel_counts);
-            //eprint!("\rProgress: {:.2}%", 100.0 - (count as f64 / pixels_to_process_count as f64) * 100.0);
-            
-            let mut done = true;
-            for counts in &thread_pixel_counts {
-                if *counts > 0 {
-                    done = false;
-                    break;
-                }
-            }
+        let (initial_film, initial_samples) = resumed.take()
+            .unwrap_or_else(|| (Film::new(region_width, region_height, options.filter, &options.aovs), 0));
+
+        let job = RenderJob {
+            world: scene.world.clone(),
+            camera,
+            background: scene.background,
+            image_width,
+            image_height,
+            crop,
+            max_depth,
+            filter: options.filter,
+            aovs: options.aovs.clone(),
+            clamp: options.clamp,
+            adaptive: options.adaptive.map(|threshold| AdaptiveSampling::new(threshold, &initial_film)),
+            sample_map: importance_map.as_ref().map(|map| SampleMap::new(map, &crop, image_width, image_height, scene.samples_per_pixel)),
+            sampler: options.sampler,
+            seed: frame_seed(options.seed, frame),
+            spectral: options.spectral
+        };
+
+        let result = render_frame(job, initial_film, initial_samples, scene.samples_per_pixel, &pool, &options, output.as_deref(), image_format, &save_checkpoint, window.as_mut());
+
+        let output_start = Instant::now();
+        let written = write_images(&result.film, &options, output.as_deref(), image_format);
+        save_checkpoint(&result.film, result.samples_per_pixel);
+        output_time += output_start.elapsed();
+
+        // The checkpoint still holds the samples, so the render can be resumed with a working output
+        if let Err(msg) = written {
+            error!("{}", msg);
+            std::process::exit(1);
+        }
 
-            if !done {
-                //thread::sleep(0.1); // Sleep one second
-            } else {
-                break;
-            }
+        render_time += result.render_time;
+        samples_per_pixel = result.samples_per_pixel;
+        total_samples += result.film.total_samples();
+        invalid_samples += result.invalid_samples;
+        ray_counter.merge(&result.ray_counter);
+
+        if result.cancelled {
+            break;
         }
-    });
+    }
 
-    //thread_handles.push(handle);
+    info!("Rendering finished in {} seconds", render_time.as_secs());
 
+    // Every pass traces exactly one sample per pixel, so this only differs from the requested count when
+    // resuming from a checkpoint that already had more samples, or when adaptive sampling or an
+    // importance map skips pixels
+    if options.adaptive.is_some() || options.importance_map.is_some() {
+        verbose!("Traced up to {} samples per pixel, {} in total", samples_per_pixel, total_samples);
+    } else {
+        verbose!("Traced {} samples per pixel, {} in total", samples_per_pixel, total_samples);
+    }
 
-    for handle in thread_handles {
-        handle.join().unwrap();
+    if invalid_samples > 0 {
+        info!("Counted {} samples with NaN or infinite radiance as black", invalid_samples);
+    }
+    verbose!("Wrote images in {:.3} seconds", output_time.as_secs_f64());
+
+    if let Some(path) = options.params_file {
+        let params = RenderParams {
+            scene: scene.name.to_string(),
+            width: image_width,
+            height: image_height,
+            samples_per_pixel,
+            max_depth,
+            thread_count,
+            look_from: scene.look_from,
+            look_at: scene.look_at,
+            vfov: scene.vfov,
+            sampler: options.sampler.name().to_string(),
+            importance_map: options.importance_map.clone(),
+            seed: options.seed,
+            frames: options.frames.map(|frames| [frames.first, frames.last]),
+            fps: options.frames.and(Some(options.fps)),
+            render_time_seconds: render_time.as_secs_f64(),
+            git_hash: env!("GIT_HASH").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string()
+        };
+
+        let json = serde_json::to_string_pretty(&params).expect("Failed to serialize render parameters");
+        if let Err(err) = std::fs::write(&path, json) {
+            error!("Could not write params file {}: {}", path, err);
+        }
     }
 
-    for j in (0..=image_height - 1).rev() {
-        for i in 0..image_width {
-            let colors = pixel_colors.lock().unwrap();
-            colors[i][j].write_color(scene.samples_per_pixel as i32);
+    if let Some(path) = options.stats_file {
+        let stats = RenderStats {
+            scene: scene.name.to_string(),
+            width: region_width,
+            height: region_height,
+            samples_per_pixel,
+            max_depth,
+            thread_count,
+            scene_build_seconds: scene_build_time.as_secs_f64(),
+            render_seconds: render_time.as_secs_f64(),
+            output_seconds: output_time.as_secs_f64(),
+            top_level_hittables: scene.world.hittables.len(),
+            primitives: scene.world.hittables.iter().map(|h| h.primitive_count()).sum(),
+            materials: scene.world.materials.len(),
+            total_rays: ray_counter.total(),
+            rays_per_bounce: ray_counter.per_bounce.clone(),
+            total_samples,
+            invalid_samples,
+            peak_memory_kb: peak_memory_kb()
+        };
+
+        let json = serde_json::to_string_pretty(&stats).expect("Failed to serialize render statistics");
+        if let Err(err) = std::fs::write(&path, json) {
+            error!("Could not write stats file {}: {}", path, err);
         }
     }
 
-    eprintln!("Rendering finished in {} seconds", now.elapsed().as_secs());
+    // Leave the finished image up until the window is closed
+    if let Some(window) = &mut window {
+        window.wait_until_closed();
+    }
 }
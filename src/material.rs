@@ -2,30 +2,161 @@ use crate::math::*;
 use crate::ray::*;
 use crate::hittable::*;
 use crate::texture::*;
+use crate::sampler::*;
+use crate::pdf::*;
+use crate::microfacet::*;
+use crate::thinfilm::*;
+use crate::fresnel::*;
+use crate::spectrum::*;
+use std::sync::OnceLock;
+
+// How a material sends on the light arriving at a surface. Scatters only live for a bounce, so the
+// large ray is kept inline rather than allocated.
+#[allow(clippy::large_enum_variant)]
+pub enum Scatter {
+    // Into a single direction, like a mirror or glass, which no density can describe
+    Specular { ray: Ray, attenuation: Color },
+    // Spread over many directions. The renderer picks one from the PDF and weights it by the
+    // material's scattering_pdf over the density it was picked with.
+    Pdf { pdf: Pdf<'static>, attenuation: Color }
+}
 
 pub enum Material {
     Lambertian { albedo: Texture },
-    Metal { albedo: Color, fuzz: f64 },
-    Dielectric { ir: f64 },
-    DiffuseLight { emit: Texture },
-    Isotropic { albedo: Texture }
+    // Rough diffuse surface like clay or concrete, made of V-shaped grooves whose slopes spread by sigma
+    // degrees. Brighter than Lambertian towards the light and flatter looking on curved objects.
+    OrenNayar { albedo: Texture, sigma: f64 },
+    // Reflects by the Fresnel equations of the conductor, or without one of a metal with the albedo as its
+    // color straight on. The roughness is the brightness of its texture. A thin film on top colors the
+    // reflection.
+    Metal { albedo: Texture, roughness: Texture, film: Option<ThinFilm>, conductor: Option<Conductor> },
+    // Brushed metal, rough across the brushing and smoother along the tangent, which is the direction
+    // it runs in as far as it lies in the surface
+    AnisotropicMetal { albedo: Color, roughness_u: f64, roughness_v: f64, tangent: Vector3 },
+    // Glass with the brightness of the texture as its index of refraction, dispersing light by the Abbe
+    // number (infinite for none, see Medium::ir_at) and absorbing it inside by the coefficients per unit
+    // distance. A thin film on the outside colors the reflection, and where dielectrics overlap the one of
+    // the higher priority fills the overlap, see MediumStack.
+    Dielectric { ir: Texture, absorption: Color, film: Option<ThinFilm>, priority: i32, abbe: f64 },
+    // Emits the texture color times the intensity, from the front face only unless it is two sided
+    DiffuseLight { emit: Texture, intensity: f64, two_sided: bool },
+    Isotropic { albedo: Texture },
+    // Phase function of a medium that scatters mostly forwards for g towards 1, like fog or smoke lit from
+    // behind, and mostly back towards -1, 0 being the same as Isotropic
+    HenyeyGreenstein { albedo: Texture, g: f64 },
+    // Cloth-like sheen from fibers standing up from the surface, brightest at grazing angles, with the
+    // roughness between 0 for a narrow rim and 1 for a wide one. Over a base material, like velvet over a
+    // diffuse color, the base only gets the light the sheen does not reflect, see Material::sheen_weight.
+    Sheen { color: Texture, roughness: f64, base: Option<MaterialHandle> },
+    // The glossy highlight of older renderers, Blinn's variant of Phong around the half vector, sharper the
+    // higher the exponent. Normalized so the exponent only changes its spread and not how bright it is
+    // overall.
+    BlinnPhong { specular: Texture, exponent: f64 },
+    // Either of two materials at each hit, b as often as the brightness of the factor texture says and a
+    // otherwise. Never shaded itself, World::resolve_material picks the side first.
+    Mix { a: MaterialHandle, b: MaterialHandle, factor: Texture },
+    // A thin clear coat of index of refraction ir over a base material, like car paint or lacquered wood.
+    // At each hit the coat reflects as often as its Fresnel reflectance says, as the coat material,
+    // and lets the base shade the hit otherwise.
+    Coated { base: MaterialHandle, coat: MaterialHandle, ir: f64 },
+    // The base material with its shading normal bent by a tangent space normal map, whose red, green and
+    // blue give the normal along the tangent, the bitangent and the surface normal from 0 for -1 to 1 for 1
+    NormalMapped { base: MaterialHandle, normal_map: Texture },
+    // The base material with its shading normal tilted along the slopes of the brightness of a height
+    // texture, as if the surface was raised by it times the scale
+    Bumped { base: MaterialHandle, height: Texture, scale: f64 }
+}
+
+impl Scatter {
+    // With the attenuation turned into the way the ray carries colors
+    fn spectral(self, ray: &Ray) -> Scatter {
+        match self {
+            Scatter::Specular { ray: scattered, attenuation } => Scatter::Specular { ray: scattered, attenuation: ray.spectral(attenuation) },
+            Scatter::Pdf { pdf, attenuation } => Scatter::Pdf { pdf, attenuation: ray.spectral(attenuation) }
+        }
+    }
 }
 
 impl Material {
-    pub fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
+    // The attenuation is in the colors of the ray, a spectrum in spectral mode
+    pub fn scatter(&self, ray: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        let scatter = match self {
+            Material::Lambertian { albedo } | Material::OrenNayar { albedo, .. } => Self::lambertian_scatter(albedo, rec),
+            Material::Metal { albedo, roughness, film, conductor } => {
+                let conductor = conductor.unwrap_or_else(|| Conductor::from_reflectivity(&albedo.get_color_value(rec.u, rec.v, &rec.point)));
+                let mut reflectance = Self::conductor_reflectance(&conductor, ray, rec);
+                if let Some(film) = film {
+                    let cos_theta = Vector3::dot(&-Vector3::normalize(&ray.direction), &rec.normal);
+                    reflectance = film.reflectance(cos_theta, &FilmBase::Conductor { reflectance });
+                }
+                Self::metal_scatter(&reflectance, roughness.get_gray_value(rec.u, rec.v, &rec.point), ray, rec)
+            },
+            Material::AnisotropicMetal { albedo, roughness_u, roughness_v, tangent } => {
+                let frame = Onb::with_tangent(&rec.normal, tangent);
+                let reflectance = Self::conductor_reflectance(&Conductor::from_reflectivity(albedo), ray, rec);
+                Self::microfacet_scatter(&reflectance, frame, Ggx::anisotropic(*roughness_u, *roughness_v), ray)
+            },
+            Material::Dielectric { ir, absorption, film, priority, abbe } => {
+                let this = Medium { material: rec.mat_handle, priority: *priority, ir: ir.get_gray_value(rec.u, rec.v, &rec.point), absorption: *absorption, abbe: *abbe };
+                // Absorbs each wavelength by itself, which colors glass right in spectral mode
+                return Self::dielectric_scatter(this, film, ray, rec, sampler);
+            },
+            Material::DiffuseLight { .. } | Material::Mix { .. } | Material::Coated { .. } | Material::NormalMapped { .. } | Material::Bumped { .. } => None,
+            Material::Isotropic { albedo } =>  Self::isotropic_scatter(albedo, rec),
+            Material::HenyeyGreenstein { albedo, g } => {
+                let attenuation = albedo.get_color_value(rec.u, rec.v, &rec.point);
+                Some(Scatter::Pdf { pdf: Pdf::HenyeyGreenstein { direction: Vector3::normalize(&ray.direction), g: *g }, attenuation })
+            },
+            Material::Sheen { color, roughness, base } => {
+                let attenuation = color.get_color_value(rec.u, rec.v, &rec.point);
+                // Over a base it is only picked as often as its weight says, which is made up for here
+                let attenuation = match base {
+                    Some(_) => attenuation / Self::sheen_weight(&attenuation, *roughness, ray, rec),
+                    None => attenuation
+                };
+                Some(Scatter::Pdf { pdf: Pdf::Cosine { normal: rec.normal }, attenuation })
+            },
+            Material::BlinnPhong { specular, exponent } => {
+                let attenuation = specular.get_color_value(rec.u, rec.v, &rec.point);
+                let outgoing = -Vector3::normalize(&ray.direction);
+                Some(Scatter::Pdf { pdf: Pdf::BlinnPhong { normal: rec.normal, outgoing, exponent: *exponent }, attenuation })
+            }
+        };
+
+        scatter.map(|scatter| scatter.spectral(ray))
+    }
+
+    // Share of the light scattered into the direction of the scattered ray, per unit solid angle
+    pub fn scattering_pdf(&self, ray: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
         match self {
-            Material::Lambertian { albedo } => Self::lambertian_scatter(albedo, ray, rec),
-            Material::Metal { albedo, fuzz } => Self::metal_scatter(albedo, *fuzz, ray, rec),
-            Material::Dielectric { ir } => Self::dielectric_scatter(*ir, ray, rec),
-            Material::DiffuseLight { emit: _ } => None,
-            Material::Isotropic { albedo } =>  Self::isotropic_scatter(albedo, ray, rec)
+            Material::Lambertian { .. } => {
+                let cosine = Vector3::dot(&rec.normal, &Vector3::normalize(&scattered.direction));
+                f64::max(cosine, 0.0) / PI
+            },
+            Material::OrenNayar { sigma, .. } => Self::oren_nayar_pdf(*sigma, ray, rec, scattered),
+            Material::Sheen { roughness, .. } => Self::sheen_pdf(*roughness, ray, rec, scattered),
+            Material::Isotropic { .. } => 1.0 / (4.0 * PI),
+            Material::HenyeyGreenstein { g, .. } => {
+                let cos_theta = Vector3::dot(&Vector3::normalize(&ray.direction), &Vector3::normalize(&scattered.direction));
+                henyey_greenstein(cos_theta, *g)
+            },
+            Material::BlinnPhong { exponent, .. } => Self::blinn_phong_pdf(*exponent, ray, rec, scattered),
+            Material::Metal { roughness, .. } => {
+                let roughness = roughness.get_gray_value(rec.u, rec.v, &rec.point);
+                Self::microfacet_pdf(Onb::new(&rec.normal), Ggx::from_roughness(roughness), ray, scattered)
+            },
+            Material::AnisotropicMetal { roughness_u, roughness_v, tangent, .. } => {
+                let frame = Onb::with_tangent(&rec.normal, tangent);
+                Self::microfacet_pdf(frame, Ggx::anisotropic(*roughness_u, *roughness_v), ray, scattered)
+            },
+            _ => 0.0
         }
     }
 
-    pub fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+    pub fn emitted(&self, rec: &HitRecord) -> Color {
         match self {
-            Material::DiffuseLight { emit } => {
-                emit.get_color_value(u, v, p)
+            Material::DiffuseLight { emit, intensity, two_sided } if *two_sided || rec.front_face => {
+                *intensity * emit.get_color_value(rec.u, rec.v, &rec.point)
             },
             _ => {
                 Color::new(0.0, 0.0, 0.0)
@@ -33,60 +164,267 @@ impl Material {
         }
     }
 
-    fn lambertian_scatter(albedo: &Texture, ray: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let mut scatter_direction = rec.normal + Vector3::random_unit_vector();
-        // Catch degenerate scatter_direction
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
-        }
-        let scattered = Ray::with_time(rec.point, scatter_direction, ray.time);
+    pub fn is_emissive(&self) -> bool {
+        matches!(self, Material::DiffuseLight { .. })
+    }
 
+    // Base color of the surface without any lighting, as used by denoisers
+    pub fn albedo(&self, rec: &HitRecord) -> Color {
+        match self {
+            Material::Lambertian { albedo } | Material::OrenNayar { albedo, .. } | Material::Isotropic { albedo } | Material::HenyeyGreenstein { albedo, .. } | Material::Sheen { color: albedo, .. } | Material::BlinnPhong { specular: albedo, .. } => albedo.get_color_value(rec.u, rec.v, &rec.point),
+            Material::Metal { albedo, .. } => albedo.get_color_value(rec.u, rec.v, &rec.point),
+            Material::AnisotropicMetal { albedo, .. } => *albedo,
+            Material::Dielectric { .. } => Color::new(1.0, 1.0, 1.0),
+            Material::DiffuseLight { emit, .. } => emit.get_color_value(rec.u, rec.v, &rec.point),
+            Material::Mix { .. } | Material::Coated { .. } | Material::NormalMapped { .. } | Material::Bumped { .. } => Color::new(0.0, 0.0, 0.0)
+        }
+    }
 
+    fn lambertian_scatter(albedo: &Texture, rec: &HitRecord) -> Option<Scatter> {
         let attenuation = albedo.get_color_value(rec.u, rec.v, &rec.point);
-        
-        Some((scattered, attenuation))
+
+        Some(Scatter::Pdf { pdf: Pdf::Cosine { normal: rec.normal }, attenuation })
     }
     
-    fn metal_scatter(albedo: &Color, fuzz: f64, ray: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let reflected = Vector3::reflect(&Vector3::normalize(&ray.direction), &rec.normal);
-        let with_fuzz = reflected + fuzz * Vector3::random_in_unit_sphere();
-        let scattered = Ray::with_time(rec.point, with_fuzz, ray.time);
-        
-        if Vector3::dot(&scattered.direction, &rec.normal) > 0.0 {
-            Some((scattered, *albedo))
-        } else {
-            None
+    fn metal_scatter(albedo: &Color, roughness: f64, ray: &Ray, rec: &HitRecord) -> Option<Scatter> {
+        let unit_direction = Vector3::normalize(&ray.direction);
+
+        // Rough metal spreads its reflection over a lobe, so lights can be sampled for it too
+        if roughness > 0.0 {
+            return Self::microfacet_scatter(albedo, Onb::new(&rec.normal), Ggx::from_roughness(roughness), ray);
         }
+
+        let reflected = Vector3::reflect(&unit_direction, &rec.normal);
+        let scattered = ray.continued(rec.point, reflected);
+        Some(Scatter::Specular { ray: scattered, attenuation: *albedo })
     }
 
-    fn dielectric_scatter(ir: f64, ray: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let attenuation = Color::new(1.0, 1.0, 1.0);
-        let refraction_ratio = if rec.front_face { 1.0 / ir } else { ir };
+    // The Fresnel term goes into the attenuation, which is settled before the direction is picked, so
+    // rough metals take it at the angle to the shading normal rather than to each microfacet
+    fn conductor_reflectance(conductor: &Conductor, ray: &Ray, rec: &HitRecord) -> Color {
+        conductor.reflectance(Vector3::dot(&-Vector3::normalize(&ray.direction), &rec.normal))
+    }
+
+    // Reflection off microfacets around the z axis of the frame
+    fn microfacet_scatter(albedo: &Color, frame: Onb, distribution: Ggx, ray: &Ray) -> Option<Scatter> {
+        let outgoing = frame.to_local(&-Vector3::normalize(&ray.direction));
+        Some(Scatter::Pdf { pdf: Pdf::Ggx { frame, outgoing, distribution }, attenuation: *albedo })
+    }
+
+    fn microfacet_pdf(frame: Onb, distribution: Ggx, ray: &Ray, scattered: &Ray) -> f64 {
+        let outgoing = frame.to_local(&-Vector3::normalize(&ray.direction));
+        let incoming = frame.to_local(&Vector3::normalize(&scattered.direction));
+        distribution.reflection(&outgoing, &incoming)
+    }
+
+    fn dielectric_scatter(this: Medium, film: &Option<ThinFilm>, ray: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+
+        // The ray went through the medium it is in since the last hit, and the light lost on the way follows
+        // the Beer-Lambert law. Leaving a dielectric it never entered, it must have started inside.
+        let absorption = ray.spectral(match ray.media.current() {
+            Some(medium) => medium.absorption,
+            None if !rec.front_face => this.absorption,
+            None => Color::new(0.0, 0.0, 0.0)
+        });
+        let distance = rec.t * ray.direction.length();
+        let mut attenuation = Color::new((-absorption.x * distance).exp(), (-absorption.y * distance).exp(), (-absorption.z * distance).exp());
+
+        // The media on the other side of the surface. Where a medium of higher priority overlaps this one
+        // the surface is not there at all, and the ray goes straight on into or out of it.
+        let mut inside = ray.media;
+        let (outside, inside_medium) = if rec.front_face {
+            let outer = ray.media.current().copied();
+            inside.push(this);
+            match outer {
+                Some(outer) if outer.priority > this.priority => {
+                    return Some(Scatter::Specular { ray: Ray { media: inside, ..ray.continued(rec.point, ray.direction) }, attenuation });
+                },
+                outer => (outer, Some(this))
+            }
+        } else {
+            let hidden = ray.media.contains(rec.mat_handle) && ray.media.current().is_some_and(|medium| medium.material.0 != rec.mat_handle.0);
+            inside.remove(rec.mat_handle);
+            if hidden {
+                return Some(Scatter::Specular { ray: Ray { media: inside, ..ray.continued(rec.point, ray.direction) }, attenuation });
+            }
+            (Some(this), inside.current().copied())
+        };
+
+        // Each wavelength bends its own way between dispersive media, so from the first of them on the ray
+        // carries only one of its colors, weighted up for the others it drops. That is the hero wavelength
+        // in spectral mode and a random one of red, green and blue otherwise.
+        let disperses = [outside, inside_medium].iter().flatten().any(|medium| medium.abbe.is_finite());
+        let channel = match ray.channel {
+            None if disperses => {
+                let channel = if ray.wavelengths.is_some() { 0 } else { usize::min((3.0 * sampler.next_1d()) as usize, 2) };
+                let mut mask = Color::new(0.0, 0.0, 0.0);
+                mask[channel] = 3.0;
+                attenuation = attenuation * mask;
+                Some(channel)
+            },
+            channel => channel
+        };
+        let wavelength = channel.map(|channel| ray.wavelengths.map_or(RGB_WAVELENGTHS[channel], |wavelengths| wavelengths.0[channel]));
+        let ir = |medium: Option<Medium>| medium.map_or(1.0, |medium| wavelength.map_or(medium.ir, |wavelength| medium.ir_at(wavelength)));
+
+        let (outside_ir, inside_ir) = (ir(outside), ir(inside_medium));
+        let refraction_ratio = outside_ir / inside_ir;
 
         let unit_direction = Vector3::normalize(&ray.direction);
         let cos_theta = Vector3::dot(&(-unit_direction), &rec.normal).min(1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let direction = {
-            if cannot_refract || Self::reflectance(cos_theta, refraction_ratio) > random_double() {
-                Vector3::reflect(&unit_direction, &rec.normal)
-            } else {
-                Vector3::refract(&unit_direction, &rec.normal, refraction_ratio)
-            }
+        let reflect = match film {
+            // The film reflects colors by different amounts, so pick by the average and weight each
+            // color by how much more or less likely it is than that
+            Some(film) if !cannot_refract => {
+                let reflectance = ray.spectral(film.reflectance(cos_theta, &FilmBase::Dielectric { outside_ir, inside_ir }));
+                let probability = (reflectance.x + reflectance.y + reflectance.z) / 3.0;
+
+                if probability > sampler.next_1d() {
+                    attenuation = attenuation * reflectance / probability;
+                    true
+                } else {
+                    attenuation = attenuation * (Color::new(1.0, 1.0, 1.0) - reflectance) / (1.0 - probability);
+                    false
+                }
+            },
+            _ => cannot_refract || Self::reflectance(cos_theta, refraction_ratio) > sampler.next_1d()
+        };
+
+        let scattered = if reflect {
+            Ray { channel, ..ray.continued(rec.point, Vector3::reflect(&unit_direction, &rec.normal)) }
+        } else {
+            Ray { media: inside, channel, ..ray.continued(rec.point, Vector3::refract(&unit_direction, &rec.normal, refraction_ratio)) }
         };
-        
-        let scattered = Ray::with_time(rec.point, direction, ray.time);
 
-        Some((scattered, attenuation))
+        Some(Scatter::Specular { ray: scattered, attenuation })
+    }
+
+    // The qualitative model of Oren and Nayar, Generalization of Lambert's Reflectance Model. Sampled
+    // like Lambertian, which it is close enough to.
+    fn oren_nayar_pdf(sigma: f64, ray: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let incoming = Vector3::normalize(&scattered.direction);
+        let outgoing = -Vector3::normalize(&ray.direction);
+        let cos_i = Vector3::dot(&rec.normal, &incoming);
+        let cos_o = Vector3::dot(&rec.normal, &outgoing);
+        if cos_i <= 0.0 || cos_o <= 0.0 {
+            return 0.0;
+        }
+
+        let sigma2 = degrees_to_radians(sigma).powi(2);
+        let a = 1.0 - 0.5 * sigma2 / (sigma2 + 0.33);
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+
+        // max(0, cos(phi_i - phi_o)) sin(alpha) tan(beta) comes down to the dot product of the directions
+        // projected onto the surface over the larger of the two cosines
+        let tangent_cosine = Vector3::dot(&(incoming - cos_i * rec.normal), &(outgoing - cos_o * rec.normal));
+        let roughness = f64::max(tangent_cosine, 0.0) / f64::max(cos_i, cos_o);
+
+        cos_i / PI * (a + b * roughness)
+    }
+
+    // With the normalization of Akenine-Möller et al., Real-Time Rendering, so that a white highlight
+    // reflects about all the light straight on
+    fn blinn_phong_pdf(exponent: f64, ray: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let incoming = Vector3::normalize(&scattered.direction);
+        let outgoing = -Vector3::normalize(&ray.direction);
+        let cos_i = Vector3::dot(&rec.normal, &incoming);
+        if cos_i <= 0.0 || Vector3::dot(&rec.normal, &outgoing) <= 0.0 {
+            return 0.0;
+        }
+
+        let half = Vector3::normalize(&(incoming + outgoing));
+        let cos_h = f64::max(Vector3::dot(&rec.normal, &half), 0.0);
+
+        (exponent + 8.0) / (8.0 * PI) * cos_h.powf(exponent) * cos_i
+    }
+
+    // The Charlie sheen of Estevez and Kulla, Production Friendly Microfacet Sheen BRDF, with the visibility
+    // term of Ashikhmin. Sampled like Lambertian, which covers the grazing angles well enough.
+    fn sheen_pdf(roughness: f64, ray: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        Self::charlie_sheen(roughness, &rec.normal, &Vector3::normalize(&scattered.direction), &-Vector3::normalize(&ray.direction))
+    }
+
+    fn charlie_sheen(roughness: f64, normal: &Vector3, incoming: &Vector3, outgoing: &Vector3) -> f64 {
+        let cos_i = Vector3::dot(normal, incoming);
+        let cos_o = Vector3::dot(normal, outgoing);
+        if cos_i <= 0.0 || cos_o <= 0.0 {
+            return 0.0;
+        }
+
+        let half = Vector3::normalize(&(*incoming + *outgoing));
+        let cos_h = Vector3::dot(normal, &half);
+        let sin_h = f64::max(1.0 - cos_h * cos_h, 0.0).sqrt();
+
+        let inv_r = 1.0 / clamp(roughness, 0.07, 1.0);
+        let distribution = (2.0 + inv_r) * sin_h.powf(inv_r) / (2.0 * PI);
+        let visibility = 1.0 / (4.0 * (cos_i + cos_o - cos_i * cos_o));
+
+        distribution * visibility * cos_i
+    }
+
+    // How often sheen over a base is picked instead of the base, the share of the light arriving from the
+    // ray that the sheen reflects in its brightest color. The base takes the rest, so together they never
+    // reflect more than arrives, and the sheen makes up for being picked only this often in its attenuation.
+    // Where the sheen alone reflects more than all of it, at grazing angles, the base gets nothing.
+    pub fn sheen_weight(color: &Color, roughness: f64, ray: &Ray, rec: &HitRecord) -> f64 {
+        let cos_o = Vector3::dot(&-Vector3::normalize(&ray.direction), &rec.normal);
+        clamp(color.x.max(color.y).max(color.z) * Self::sheen_albedo(cos_o, roughness), 0.0, 1.0)
+    }
+
+    // Share of the light arriving at the cosine cos_o to the normal that the sheen reflects in all, which
+    // has no closed form. Integrated over the hemisphere once for a grid of cosines and roughnesses, and
+    // interpolated between them.
+    fn sheen_albedo(cos_o: f64, roughness: f64) -> f64 {
+        const COSINES: usize = 32;
+        const ROUGHNESSES: usize = 16;
+        const MIN_ROUGHNESS: f64 = 0.07;
+        static TABLE: OnceLock<Vec<f64>> = OnceLock::new();
+
+        let table = TABLE.get_or_init(|| {
+            let normal = Vector3::new(0.0, 0.0, 1.0);
+            let (steps_theta, steps_phi) = (32, 64);
+            let mut table = Vec::with_capacity(COSINES * ROUGHNESSES);
+            for j in 0..ROUGHNESSES {
+                let roughness = MIN_ROUGHNESS + (1.0 - MIN_ROUGHNESS) * j as f64 / (ROUGHNESSES - 1) as f64;
+                for i in 0..COSINES {
+                    let cos_o = f64::max(i as f64 / (COSINES - 1) as f64, 0.001);
+                    let outgoing = Vector3::new((1.0 - cos_o * cos_o).sqrt(), 0.0, cos_o);
+
+                    // Midpoints evenly spread in the cosine and the angle around the normal, each standing
+                    // for the same solid angle
+                    let mut sum = 0.0;
+                    for a in 0..steps_theta {
+                        let cos_i = (a as f64 + 0.5) / steps_theta as f64;
+                        let sin_i = (1.0 - cos_i * cos_i).sqrt();
+                        for b in 0..steps_phi {
+                            let phi = 2.0 * PI * (b as f64 + 0.5) / steps_phi as f64;
+                            let incoming = Vector3::new(sin_i * phi.cos(), sin_i * phi.sin(), cos_i);
+                            sum += Self::charlie_sheen(roughness, &normal, &incoming, &outgoing);
+                        }
+                    }
+                    table.push(sum * 2.0 * PI / (steps_theta * steps_phi) as f64);
+                }
+            }
+            table
+        });
+
+        let x = clamp(cos_o, 0.0, 1.0) * (COSINES - 1) as f64;
+        let y = clamp((roughness - MIN_ROUGHNESS) / (1.0 - MIN_ROUGHNESS), 0.0, 1.0) * (ROUGHNESSES - 1) as f64;
+        let (i, j) = (usize::min(x as usize, COSINES - 2), usize::min(y as usize, ROUGHNESSES - 2));
+        let (fx, fy) = (x - i as f64, y - j as f64);
+        let at = |i: usize, j: usize| table[j * COSINES + i];
+
+        (1.0 - fy) * ((1.0 - fx) * at(i, j) + fx * at(i + 1, j)) + fy * ((1.0 - fx) * at(i, j + 1) + fx * at(i + 1, j + 1))
     }
 
-    fn isotropic_scatter(albedo: &Texture, ray: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let scattered = Ray::with_time(rec.point, Vector3::random_in_unit_sphere(), ray.time);
-        Some((scattered, albedo.get_color_value(rec.u, rec.v, &rec.point)))
+    fn isotropic_scatter(albedo: &Texture, rec: &HitRecord) -> Option<Scatter> {
+        Some(Scatter::Pdf { pdf: Pdf::Uniform, attenuation: albedo.get_color_value(rec.u, rec.v, &rec.point) })
     }
 
-    fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
+    pub fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
         // Use Schlick's approximation for reflectance.
         let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
         r0 = r0 * r0;
@@ -1,15 +1,16 @@
 use std::fmt;
 use std::ops;
-use rand::{thread_rng, Rng};
+use std::cell::Cell;
+use serde::{Serialize, Deserialize};
 
-pub const PI: f64 = 3.1415926535897932385;
+pub const PI: f64 = std::f64::consts::PI;
 pub const INFINITY: f64 = f64::INFINITY;
 
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
 
-#[derive(Copy, Clone, Default, Debug)]
+#[derive(Copy, Clone, Default, Debug, Serialize, Deserialize)]
 pub struct Vector3 {
     pub x: f64,
     pub y: f64,
@@ -28,10 +29,6 @@ impl Vector3 {
         }
     }
 
-    pub fn as_array(&self) -> [f64; 3] {
-        [self.x, self.y, self.z]
-    }
-
     pub fn random() -> Vector3 {
         Vector3 {
             x: random_double(),
@@ -57,15 +54,6 @@ impl Vector3 {
         }
     }
 
-    pub fn random_in_hemisphere(normal: &Vector3) -> Vector3 {
-        let in_unit_sphere = Self::random_in_unit_sphere();
-        if Vector3::dot(&in_unit_sphere, normal) > 0.0 {
-            in_unit_sphere
-        } else {
-            -in_unit_sphere
-        }
-    }
-
     pub fn random_in_unit_disk() -> Vector3 {
         loop {
             let p = Vector3::new(random_double_range(-1.0, 1.0), random_double_range(-1.0, 1.0), 0.0);
@@ -83,6 +71,11 @@ impl Vector3 {
         u.x * v.x + u.y * v.y + u.z * v.z 
     }
 
+    // Perceived brightness of a linear Rec. 709 color
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
+
     pub fn length_squared(&self) -> f64 {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
@@ -116,19 +109,8 @@ impl Vector3 {
         r_out_perp + r_out_parallel
     }
 
-    pub fn write_color(&self, samples_per_pixel: i32) { 
-        let scale = 1.0 / samples_per_pixel as f64;
-
-        // Divice the color by the number of samples and gamme-correct for gamme=2.0
-        let r = (self.x * scale).sqrt();
-        let g = (self.y * scale).sqrt();
-        let b = (self.z * scale).sqrt();
-
-        let ir = (256.0 * clamp(r, 0.0, 0.999)) as i32;
-        let ig = (256.0 * clamp(g, 0.0, 0.999)) as i32;
-        let ib = (256.0 * clamp(b, 0.0, 0.999)) as i32;
-
-        println!("{} {} {}", ir, ig, ib);
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
     }
 
     pub fn near_zero(&self) -> bool {
@@ -144,6 +126,30 @@ impl fmt::Display for Vector3 {
     }
 }
 
+impl ops::Index<usize> for Vector3 {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &f64 {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vector3 index out of bounds: {}", axis)
+        }
+    }
+}
+
+impl ops::IndexMut<usize> for Vector3 {
+    fn index_mut(&mut self, axis: usize) -> &mut f64 {
+        match axis {
+            0 => &mut self.x,
+            1 => &mut self.y,
+            2 => &mut self.z,
+            _ => panic!("Vector3 index out of bounds: {}", axis)
+        }
+    }
+}
+
 impl ops::Add for Vector3 {
     type Output = Self;
 
@@ -265,14 +271,83 @@ impl ops::Div<f64> for Vector3 {
     }
 }
 
+// Orthonormal basis around a direction w, for working in coordinates where w is the +z axis
+#[derive(Copy, Clone, Debug)]
+pub struct Onb {
+    pub u: Vector3,
+    pub v: Vector3,
+    pub w: Vector3
+}
+
+impl Onb {
+    // The other two axes follow w smoothly as it turns, without a sudden flip near any axis. See Duff
+    // et al., Building an Orthonormal Basis, Revisited.
+    pub fn new(w: &Vector3) -> Onb {
+        let w = Vector3::normalize(w);
+        let sign = 1.0_f64.copysign(w.z);
+        let a = -1.0 / (sign + w.z);
+        let b = w.x * w.y * a;
+
+        Onb {
+            u: Vector3::new(1.0 + sign * w.x * w.x * a, sign * b, -sign * w.x),
+            v: Vector3::new(b, sign + w.y * w.y * a, -w.y),
+            w
+        }
+    }
+
+    // Basis around w with u along the tangent, as far as it is perpendicular to w, or any u if it is
+    // parallel
+    pub fn with_tangent(w: &Vector3, tangent: &Vector3) -> Onb {
+        let w = Vector3::normalize(w);
+        let u = *tangent - Vector3::dot(tangent, &w) * w;
+        if u.length_squared() < 1.0e-12 {
+            return Onb::new(&w);
+        }
+
+        let u = Vector3::normalize(&u);
+        Onb { u, v: Vector3::cross(&w, &u), w }
+    }
+
+    // From coordinates in this basis to world space
+    pub fn local(&self, a: &Vector3) -> Vector3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+
+    // From world space to coordinates in this basis
+    pub fn to_local(&self, a: &Vector3) -> Vector3 {
+        Vector3::new(Vector3::dot(a, &self.u), Vector3::dot(a, &self.v), Vector3::dot(a, &self.w))
+    }
+}
+
+thread_local! {
+    // Every thread starts out the same, so anything random done before seeding is still repeatable
+    static RANDOM_STATE: Cell<u64> = const { Cell::new(0x853c_49e6_748f_ea9b) };
+}
+
+// Restarts the random numbers of the calling thread. The renderer does this before every sample, so
+// the image does not depend on which thread traced which tile.
+pub fn seed_random(seed: u64) {
+    RANDOM_STATE.with(|state| state.set(seed));
+}
+
+// SplitMix64, small and fast with a state that is cheap to reseed
 pub fn random_double() -> f64 {
-    let mut rng = thread_rng();
-    rng.gen()
+    let state = RANDOM_STATE.with(|state| {
+        let next = state.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+        state.set(next);
+        next
+    });
+
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+
+    (z >> 11) as f64 / (1u64 << 53) as f64
 }
 
 pub fn random_double_range(min: f64, max: f64) -> f64 {
-   let mut rng = thread_rng();
-   rng.gen_range(min..=max)
+    min + (max - min) * random_double()
 }
 
 pub fn random_int_range(min: i32, max: i32) -> i32 {
@@ -285,6 +360,38 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
     else { x }
 }
 
+// Cosine of the half angle of the cone subtended by a sphere of the given radius whose center is at
+// squared distance distance_squared. From inside the sphere it covers every direction, so this is -1.
+pub fn sphere_cos_theta_max(radius: f64, distance_squared: f64) -> f64 {
+    if distance_squared <= radius * radius {
+        return -1.0;
+    }
+
+    (1.0 - radius * radius / distance_squared).sqrt()
+}
+
+// Uniform random direction, in local coordinates around +z, inside the cone subtended by a sphere
+// of the given radius whose center is at squared distance distance_squared along +z. r1 and r2 are
+// uniform random numbers.
+pub fn random_to_sphere(radius: f64, distance_squared: f64, r1: f64, r2: f64) -> Vector3 {
+    let z = 1.0 + r2 * (sphere_cos_theta_max(radius, distance_squared) - 1.0);
+
+    let phi = 2.0 * PI * r1;
+    let x = phi.cos() * (1.0 - z * z).sqrt();
+    let y = phi.sin() * (1.0 - z * z).sqrt();
+
+    Vector3::new(x, y, z)
+}
+
+// Direction around +z with a density proportional to its z component, i.e. cosine weighted over the
+// hemisphere, from two uniform random numbers
+pub fn random_cosine_direction(r1: f64, r2: f64) -> Vector3 {
+    let phi = 2.0 * PI * r1;
+    let r = r2.sqrt();
+
+    Vector3::new(phi.cos() * r, phi.sin() * r, (1.0 - r2).sqrt())
+}
+
 pub fn sphere_uv(p: &Point3) -> (f64, f64) {
     // p: a given point on the sphere of radius one, centered at the origin.
     // u: returned value [0,1] of angle around the Y axis from X=-1.
@@ -298,3 +405,40 @@ pub fn sphere_uv(p: &Point3) -> (f64, f64) {
 
     (phi / (2.0 * PI), theta / PI)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_to_sphere_from_inside_covers_every_direction() {
+        for (r1, r2) in [(0.0, 0.0), (0.3, 0.5), (0.7, 1.0)] {
+            let v = random_to_sphere(2.0, 1.0, r1, r2);
+            assert!((v.length() - 1.0).abs() < 1e-9);
+        }
+
+        assert_eq!(random_to_sphere(2.0, 1.0, 0.0, 1.0).z, -1.0);
+    }
+
+    #[test]
+    fn index_reads_the_components() {
+        let v = Vector3::new(1.0, 2.0, 3.0);
+        assert_eq!((v[0], v[1], v[2]), (1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn index_mut_writes_the_components() {
+        let mut v = Vector3::new(0.0, 0.0, 0.0);
+        v[0] = 4.0;
+        v[1] = 5.0;
+        v[2] += 6.0;
+        assert_eq!((v.x, v.y, v.z), (4.0, 5.0, 6.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Vector3 index out of bounds: 3")]
+    fn index_past_z_panics() {
+        let v = Vector3::new(1.0, 2.0, 3.0);
+        let _ = v[3];
+    }
+}
@@ -0,0 +1,122 @@
+use crate::math::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::aabb::*;
+use crate::bvh::*;
+
+// Texture coordinates of the corners of triangles whose mesh has none, like Hittable::new_triangle
+const DEFAULT_UVS: [(f64, f64); 3] = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)];
+
+// Triangles sharing their vertices, which are stored once in buffers the triangles index into. Each
+// vertex has a position and optionally a normal, which smooths the shading across the triangles, and
+// texture coordinates. The mesh has its own BVH over the triangles, so it takes a single entry in the
+// BVH of the world however many triangles it has.
+pub struct Mesh {
+    positions: Vec<Point3>,
+    normals: Vec<Vector3>,      // One per position, or none to shade the triangles flat
+    uvs: Vec<(f64, f64)>,       // One per position, or none
+    indices: Vec<[u32; 3]>,     // Vertices of each triangle, counterclockwise seen from the front, in BVH order
+    mat_handle: MaterialHandle,
+    tree: BvhTree
+}
+
+impl Mesh {
+    pub fn new(positions: Vec<Point3>, normals: Option<Vec<Vector3>>, uvs: Option<Vec<(f64, f64)>>, indices: Vec<[u32; 3]>, mat_handle: MaterialHandle) -> Result<Mesh, String> {
+        let normals = normals.unwrap_or_default();
+        let uvs = uvs.unwrap_or_default();
+
+        if !normals.is_empty() && normals.len() != positions.len() {
+            return Err(format!("Mesh has {} normals for {} vertices", normals.len(), positions.len()));
+        }
+
+        if !uvs.is_empty() && uvs.len() != positions.len() {
+            return Err(format!("Mesh has {} texture coordinates for {} vertices", uvs.len(), positions.len()));
+        }
+
+        Self::check_indices(&positions, &indices)?;
+
+        let boxes: Vec<AABB> = indices.iter().map(|triangle| {
+            let [a, b, c] = triangle.map(|index| positions[index as usize]);
+            // Padded like Hittable::Triangle, for triangles lying flat along an axis
+            let padding = Vector3::new(0.0001, 0.0001, 0.0001);
+            AABB::new(
+                Point3::new(a.x.min(b.x).min(c.x), a.y.min(b.y).min(c.y), a.z.min(b.z).min(c.z)) - padding,
+                Point3::new(a.x.max(b.x).max(c.x), a.y.max(b.y).max(c.y), a.z.max(b.z).max(c.z)) + padding
+            )
+        }).collect();
+
+        let tree = BvhTree::build(&boxes);
+        let indices = tree.order().iter().map(|&i| indices[i]).collect();
+
+        Ok(Mesh { positions, normals, uvs, indices, mat_handle, tree })
+    }
+
+    // Normal at each vertex averaged over the triangles around it, weighted by their area, for meshes
+    // that come without normals but should be shaded smooth
+    pub fn smooth_normals(positions: &[Point3], indices: &[[u32; 3]]) -> Result<Vec<Vector3>, String> {
+        Self::check_indices(positions, indices)?;
+
+        let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); positions.len()];
+
+        for triangle in indices {
+            let [a, b, c] = triangle.map(|index| positions[index as usize]);
+            // Twice the area long
+            let normal = Vector3::cross(&(b - a), &(c - a));
+            for &index in triangle {
+                normals[index as usize] += normal;
+            }
+        }
+
+        Ok(normals.iter()
+            .map(|normal| if normal.length_squared() > 0.0 { Vector3::normalize(normal) } else { Vector3::new(0.0, 1.0, 0.0) })
+            .collect())
+    }
+
+    fn check_indices(positions: &[Point3], indices: &[[u32; 3]]) -> Result<(), String> {
+        match indices.iter().flatten().find(|&&index| index as usize >= positions.len()) {
+            Some(index) => Err(format!("Mesh refers to vertex {} but has only {}", index, positions.len())),
+            None => Ok(())
+        }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut closest_so_far = t_max;
+        let mut closest = None;
+
+        // Only the closest triangle gets its shading data interpolated
+        self.tree.hit(ray, t_min, &mut closest_so_far, |i, closest_so_far| {
+            if let Some((t, barycentric)) = Hittable::triangle_intersect(&self.vertices(i), ray, t_min, *closest_so_far) {
+                *closest_so_far = t;
+                closest = Some((i, t, barycentric));
+            }
+        });
+
+        let (i, t, barycentric) = closest?;
+        let triangle = self.indices[i];
+        let normals = if self.normals.is_empty() { None } else { Some(triangle.map(|index| self.normals[index as usize])) };
+        let uvs = if self.uvs.is_empty() { DEFAULT_UVS } else { triangle.map(|index| self.uvs[index as usize]) };
+
+        Some(Hittable::triangle_record(&self.vertices(i), normals.as_ref(), &uvs, t, &barycentric, ray, self.mat_handle))
+    }
+
+    fn vertices(&self, i: usize) -> [Point3; 3] {
+        self.indices[i].map(|index| self.positions[index as usize])
+    }
+
+    pub fn bounding_box(&self) -> Option<AABB> {
+        self.tree.bounding_box()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.tree.node_count()
+    }
+}
@@ -0,0 +1,128 @@
+use crate::math::*;
+
+// GGX or Trowbridge-Reitz distribution of microfacet normals, for surfaces made of tiny mirrors. Works
+// in the local space of an Onb around the shading normal, which is the z axis there. See Walter et al.,
+// Microfacet Models for Refraction through Rough Surfaces, and Heitz, Sampling the GGX Distribution of
+// Visible Normals. The slopes can spread differently along the x and y axes, for brushed metal.
+#[derive(Copy, Clone, Debug)]
+pub struct Ggx {
+    pub alpha_x: f64, // Slope spread of the microfacets along x, the square of the roughness
+    pub alpha_y: f64  // And along y
+}
+
+impl Ggx {
+    // Roughness from 0 for a perfect mirror to 1 for a very dull surface, squared so that it looks
+    // about linear
+    pub fn from_roughness(roughness: f64) -> Ggx {
+        Ggx::anisotropic(roughness, roughness)
+    }
+
+    // Rougher along one axis than the other, which stretches highlights along the smoother one
+    pub fn anisotropic(roughness_x: f64, roughness_y: f64) -> Ggx {
+        // Too sharp a lobe only makes the densities overflow
+        let alpha = |roughness: f64| f64::max(clamp(roughness, 0.0, 1.0).powi(2), 1.0e-4);
+        Ggx { alpha_x: alpha(roughness_x), alpha_y: alpha(roughness_y) }
+    }
+
+    // About as spread as a Blinn-Phong highlight of this exponent, from Walter et al.
+    pub fn from_phong_exponent(exponent: f64) -> Ggx {
+        let alpha = f64::max((2.0 / (exponent + 2.0)).sqrt(), 1.0e-4);
+        Ggx { alpha_x: alpha, alpha_y: alpha }
+    }
+
+    // Density of microfacets facing the local half vector, per unit of projected area
+    pub fn d(&self, h: &Vector3) -> f64 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+
+        let x = h.x / self.alpha_x;
+        let y = h.y / self.alpha_y;
+        let denominator = x * x + y * y + h.z * h.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * denominator * denominator)
+    }
+
+    // Smith's shadowing term, how much of the microfacets are hidden by others seen from the direction
+    fn lambda(&self, w: &Vector3) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0.0 {
+            return INFINITY;
+        }
+
+        // The squared tangent of the angle to the normal, scaled by the spread in that direction
+        let x = self.alpha_x * w.x;
+        let y = self.alpha_y * w.y;
+        0.5 * (-1.0 + (1.0 + (x * x + y * y) / cos2).sqrt())
+    }
+
+    // Share of the microfacets visible from one direction
+    pub fn g1(&self, w: &Vector3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Share visible from both the outgoing and the incoming direction, correlated by height
+    pub fn g2(&self, wo: &Vector3, wi: &Vector3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Picks a microfacet normal in proportion to how much of it is seen from the outgoing direction,
+    // which has to be above the surface
+    pub fn sample_visible_normal(&self, wo: &Vector3, r1: f64, r2: f64) -> Vector3 {
+        // Stretch the view so the microfacets become a hemisphere
+        let vh = Vector3::normalize(&Vector3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z));
+
+        let length2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if length2 > 0.0 {
+            Vector3::new(-vh.y, vh.x, 0.0) / length2.sqrt()
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = Vector3::cross(&vh, &t1);
+
+        // A point on the disk, squeezed into the part of it the view sees of the hemisphere
+        let r = r1.sqrt();
+        let phi = 2.0 * PI * r2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+
+        let nh = p1 * t1 + p2 * t2 + f64::max(1.0 - p1 * p1 - p2 * p2, 0.0).sqrt() * vh;
+
+        // And back to the actual slopes
+        Vector3::normalize(&Vector3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, f64::max(nh.z, 0.0)))
+    }
+
+    // Density of the directions reflected off visible normals, per unit solid angle
+    pub fn reflection_pdf(&self, wo: &Vector3, wi: &Vector3) -> f64 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+
+        match half_vector(wo, wi) {
+            Some(h) => self.g1(wo) * self.d(&h) / (4.0 * wo.z),
+            None => 0.0
+        }
+    }
+
+    // The reflectance times the cosine of the incoming direction, without the Fresnel term
+    pub fn reflection(&self, wo: &Vector3, wi: &Vector3) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+
+        match half_vector(wo, wi) {
+            Some(h) => self.d(&h) * self.g2(wo, wi) / (4.0 * wo.z),
+            None => 0.0
+        }
+    }
+}
+
+// The microfacet normal that reflects one direction into the other, none for opposite directions
+fn half_vector(wo: &Vector3, wi: &Vector3) -> Option<Vector3> {
+    let h = *wo + *wi;
+    if h.length_squared() <= 1.0e-12 {
+        return None;
+    }
+
+    Some(Vector3::normalize(&h))
+}
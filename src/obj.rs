@@ -0,0 +1,238 @@
+use crate::math::*;
+use std::collections::HashMap;
+use std::path::Path;
+
+// Material of an MTL file, with the defaults of the format for what it leaves out
+#[derive(Clone, Debug)]
+pub struct ObjMaterial {
+    pub name: String,
+    pub diffuse: Color,                 // Kd
+    pub diffuse_map: Option<String>,    // map_Kd, relative to the working directory
+    pub specular: Color,                // Ks
+    pub shininess: f64,                 // Ns, the Phong exponent
+    pub emission: Color,                // Ke
+    pub ir: f64,                        // Ni
+    pub dissolve: f64,                  // d, or 1 - Tr, below 1 for see through materials
+    pub illum: i32                      // The illumination model, 4, 6, 7 and 9 being glass
+}
+
+// Faces of the model sharing a material, with the vertices they use taken out of the file's lists.
+// Normals and texture coordinates are only kept if every vertex has them.
+pub struct ObjGroup {
+    pub material: Option<String>,
+    pub positions: Vec<Point3>,
+    pub normals: Option<Vec<Vector3>>,
+    pub uvs: Option<Vec<(f64, f64)>>,
+    pub indices: Vec<[u32; 3]>,
+    pub smooth: bool                    // Whether s turned smoothing on for faces without normals
+}
+
+// Triangles of a Wavefront OBJ file grouped by material, and the materials of the MTL files it uses.
+// Polygons are split into fans of triangles. Objects, groups and smoothing groups are not kept apart,
+// only whether smoothing is on at all.
+pub struct ObjModel {
+    pub groups: Vec<ObjGroup>,
+    pub materials: Vec<ObjMaterial>
+}
+
+// A group while its faces are being read, with the index its vertex got for each combination of
+// position, texture coordinates and normal of the file
+#[derive(Default)]
+struct GroupBuilder {
+    material: Option<String>,
+    vertices: HashMap<(usize, Option<usize>, Option<usize>), u32>,
+    positions: Vec<Point3>,
+    normals: Vec<Option<Vector3>>,
+    uvs: Vec<Option<(f64, f64)>>,
+    indices: Vec<[u32; 3]>,
+    smooth: bool
+}
+
+impl ObjModel {
+    pub fn load(path: &str) -> Result<ObjModel, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("Could not read model {}: {}", path, err))?;
+        let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut materials = Vec::new();
+        let mut groups: Vec<GroupBuilder> = Vec::new();
+        let mut current = 0;
+        let mut smooth = false;
+
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: &str| format!("{} in model {} on line {}", message, path, number + 1);
+            let mut tokens = line.split_whitespace();
+            let keyword = match tokens.next() {
+                Some(keyword) if !keyword.starts_with('#') => keyword,
+                _ => continue
+            };
+            let tokens: Vec<&str> = tokens.collect();
+            let numbers = || tokens.iter()
+                .map(|token| token.parse::<f64>().map_err(|_| error(&format!("Invalid number {}", token))))
+                .collect::<Result<Vec<f64>, String>>();
+
+            match keyword {
+                "v" => match numbers()?[..] {
+                    [x, y, z, ..] => positions.push(Point3::new(x, y, z)),
+                    _ => return Err(error("Expected x, y and z of a vertex"))
+                },
+                "vn" => match numbers()?[..] {
+                    [x, y, z] => normals.push(Vector3::new(x, y, z)),
+                    _ => return Err(error("Expected x, y and z of a normal"))
+                },
+                "vt" => match numbers()?[..] {
+                    [u] => uvs.push((u, 0.0)),
+                    [u, v, ..] => uvs.push((u, v)),
+                    _ => return Err(error("Expected u and v of texture coordinates"))
+                },
+                "f" => {
+                    if tokens.len() < 3 {
+                        return Err(error("Expected at least three vertices in a face"));
+                    }
+                    if groups.is_empty() {
+                        groups.push(GroupBuilder::default());
+                    }
+
+                    let group = &mut groups[current];
+                    group.smooth |= smooth;
+
+                    let mut face = Vec::with_capacity(tokens.len());
+                    for token in &tokens {
+                        let mut parts = token.split('/');
+                        let mut index = |count: usize| -> Result<Option<usize>, String> {
+                            match parts.next() {
+                                Some(part) if !part.is_empty() => resolve_index(part, count).map(Some).ok_or_else(|| error(&format!("Invalid index in {}", token))),
+                                _ => Ok(None)
+                            }
+                        };
+                        let position = index(positions.len())?.ok_or_else(|| error(&format!("Missing vertex in {}", token)))?;
+                        let uv = index(uvs.len())?;
+                        let normal = index(normals.len())?;
+
+                        let next = group.positions.len() as u32;
+                        let vertex = *group.vertices.entry((position, uv, normal)).or_insert(next);
+                        if vertex == next {
+                            group.positions.push(positions[position]);
+                            group.uvs.push(uv.map(|uv| uvs[uv]));
+                            group.normals.push(normal.map(|normal| Vector3::normalize(&normals[normal])));
+                        }
+                        face.push(vertex);
+                    }
+
+                    for i in 1..face.len() - 1 {
+                        group.indices.push([face[0], face[i], face[i + 1]]);
+                    }
+                },
+                "usemtl" => {
+                    let name = tokens.join(" ");
+                    current = match groups.iter().position(|group| group.material.as_deref() == Some(name.as_str())) {
+                        Some(index) => index,
+                        None => {
+                            groups.push(GroupBuilder { material: Some(name), ..Default::default() });
+                            groups.len() - 1
+                        }
+                    };
+                },
+                "mtllib" => {
+                    for library in &tokens {
+                        materials.extend(load_mtl(&directory.join(library))?);
+                    }
+                },
+                "s" => smooth = !matches!(tokens.first(), Some(&"off") | Some(&"0") | None),
+                // Objects, groups and anything else do not change how the model renders
+                _ => {}
+            }
+        }
+
+        let groups: Vec<ObjGroup> = groups.into_iter()
+            .filter(|group| !group.indices.is_empty())
+            .map(|group| ObjGroup {
+                material: group.material,
+                positions: group.positions,
+                normals: group.normals.into_iter().collect(),
+                uvs: group.uvs.into_iter().collect(),
+                indices: group.indices,
+                smooth: group.smooth
+            })
+            .collect();
+
+        if groups.is_empty() {
+            return Err(format!("Model {} has no faces", path));
+        }
+
+        trace!("Loaded model {} with {} triangles in {} groups", path, groups.iter().map(|group| group.indices.len()).sum::<usize>(), groups.len());
+
+        Ok(ObjModel { groups, materials })
+    }
+}
+
+// OBJ counts from 1, and back from the end of the list for negative indices
+fn resolve_index(part: &str, count: usize) -> Option<usize> {
+    let index = part.parse::<i64>().ok()?;
+    let index = if index < 0 { count as i64 + index } else { index - 1 };
+    if index >= 0 && (index as usize) < count { Some(index as usize) } else { None }
+}
+
+fn load_mtl(path: &Path) -> Result<Vec<ObjMaterial>, String> {
+    let text = std::fs::read_to_string(path).map_err(|err| format!("Could not read materials {}: {}", path.display(), err))?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut materials: Vec<ObjMaterial> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let error = |message: &str| format!("{} in materials {} on line {}", message, path.display(), number + 1);
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) if !keyword.starts_with('#') => keyword,
+            _ => continue
+        };
+        let tokens: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            materials.push(ObjMaterial {
+                name: tokens.join(" "),
+                diffuse: Color::new(0.8, 0.8, 0.8),
+                diffuse_map: None,
+                specular: Color::new(0.0, 0.0, 0.0),
+                shininess: 0.0,
+                emission: Color::new(0.0, 0.0, 0.0),
+                ir: 1.0,
+                dissolve: 1.0,
+                illum: 1
+            });
+            continue;
+        }
+
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => return Err(error("Expected newmtl before the first material setting"))
+        };
+
+        let number = || tokens.first()
+            .and_then(|token| token.parse::<f64>().ok())
+            .ok_or_else(|| error(&format!("Expected a number after {}", keyword)));
+        // A single value is a gray
+        let color = || match tokens.iter().map(|token| token.parse::<f64>()).collect::<Result<Vec<f64>, _>>() {
+            Ok(values) if values.len() == 3 => Ok(Color::new(values[0], values[1], values[2])),
+            Ok(values) if values.len() == 1 => Ok(Color::new(values[0], values[0], values[0])),
+            _ => Err(error(&format!("Expected a color after {}", keyword)))
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = color()?,
+            "Ks" => material.specular = color()?,
+            "Ke" => material.emission = color()?,
+            "Ns" => material.shininess = number()?,
+            "Ni" => material.ir = number()?,
+            "d" => material.dissolve = number()?,
+            "Tr" => material.dissolve = 1.0 - number()?,
+            "illum" => material.illum = number()? as i32,
+            // Options like -bm come before the file name, which is last
+            "map_Kd" => material.diffuse_map = tokens.last().map(|file| directory.join(file).to_string_lossy().into_owned()),
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
@@ -0,0 +1,262 @@
+use crate::math::*;
+use crate::tonemap::*;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ImageFormat {
+    PpmAscii,   // P3, readable but large
+    PpmBinary,  // P6
+    Png16,      // 16 bits per channel, avoids banding in dark gradients
+    Pfm         // 32 bit floats, for denoisers and analysis
+}
+
+impl ImageFormat {
+    // Picks the format from the file extension, falling back to binary PPM
+    pub fn from_path(path: Option<&str>) -> ImageFormat {
+        match path {
+            Some(path) if path.to_lowercase().ends_with(".png") => ImageFormat::Png16,
+            Some(path) if path.to_lowercase().ends_with(".pfm") => ImageFormat::Pfm,
+            _ => ImageFormat::PpmBinary
+        }
+    }
+
+    // Floating point formats store the linear radiance as is, without any post processing
+    pub fn is_hdr(&self) -> bool {
+        *self == ImageFormat::Pfm
+    }
+}
+
+// Encoding from linear color to the stored pixel values
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Transfer {
+    Gamma(f64),
+    Srgb
+}
+
+impl Transfer {
+    pub fn encode(&self, c: f64) -> f64 {
+        let c = f64::max(c, 0.0);
+
+        match self {
+            Transfer::Gamma(gamma) => c.powf(1.0 / gamma),
+            Transfer::Srgb => {
+                if c <= 0.0031308 {
+                    12.92 * c
+                } else {
+                    1.055 * c.powf(1.0 / 2.4) - 0.055
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Transfer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Transfer, String> {
+        match s {
+            "srgb" => Ok(Transfer::Srgb),
+            _ => match s.parse::<f64>() {
+                Ok(gamma) if gamma > 0.0 => Ok(Transfer::Gamma(gamma)),
+                _ => Err(format!("Invalid gamma {}", s))
+            }
+        }
+    }
+}
+
+// Path for an extra output next to the main image, cornell.png becomes cornell.normal.png
+pub fn sibling_path(path: &str, suffix: &str) -> String {
+    let file_start = path.rfind('/').map_or(0, |i| i + 1);
+    match path[file_start..].rfind('.') {
+        Some(i) => format!("{}.{}{}", &path[..file_start + i], suffix, &path[file_start + i..]),
+        None => format!("{}.{}", path, suffix)
+    }
+}
+
+// Path of an animation frame, with the frame number appended to the file name like frame_0001.png
+pub fn frame_path(path: &str, frame: usize) -> String {
+    let file_start = path.rfind('/').map_or(0, |i| i + 1);
+    match path[file_start..].rfind('.') {
+        Some(i) => format!("{}_{:04}{}", &path[..file_start + i], frame, &path[file_start + i..]),
+        None => format!("{}_{:04}", path, frame)
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ImageFormat, String> {
+        match s {
+            "p3" => Ok(ImageFormat::PpmAscii),
+            "p6" | "ppm" => Ok(ImageFormat::PpmBinary),
+            "png" => Ok(ImageFormat::Png16),
+            "pfm" => Ok(ImageFormat::Pfm),
+            _ => Err(format!("Unknown image format {}", s))
+        }
+    }
+}
+
+// Final pixel colors of a render, stored row by row starting at the top of the image
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<Color>
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![Color::new(0.0, 0.0, 0.0); width * height]
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    // Scales the image by 2^stops
+    pub fn expose(&mut self, stops: f64) {
+        let scale = f64::powf(2.0, stops);
+        for pixel in self.pixels.iter_mut() {
+            *pixel *= scale;
+        }
+    }
+
+    pub fn apply_transfer(&mut self, transfer: Transfer) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = Color::new(transfer.encode(pixel.x), transfer.encode(pixel.y), transfer.encode(pixel.z));
+        }
+    }
+
+    pub fn map<F: Fn(Color) -> Color>(&mut self, f: F) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = f(*pixel);
+        }
+    }
+
+    // Scales the image so that its brightest component becomes 1
+    pub fn normalize(&mut self) {
+        let max = self.pixels.iter().fold(0.0, |max, pixel| f64::max(max, f64::max(pixel.x, f64::max(pixel.y, pixel.z))));
+        if max > 0.0 {
+            self.expose(-max.log2());
+        }
+    }
+
+    pub fn tone_map(&mut self, operator: ToneMap) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = operator.apply(*pixel);
+        }
+    }
+
+    // Quantized to 8 bits per channel with opaque alpha, the layout of canvas image data
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|color| {
+            let [r, g, b] = quantize(color);
+            [r, g, b, 255]
+        }).collect()
+    }
+
+    // Writes the image to the given file, or to stdout when no path is given
+    pub fn save(&self, path: Option<&str>, format: ImageFormat) -> io::Result<()> {
+        match path {
+            Some(path) => {
+                let mut out = BufWriter::new(File::create(path)?);
+                let result = self.write(&mut out, format).and_then(|()| out.flush());
+
+                // Don't leave a truncated image behind
+                if result.is_err() {
+                    drop(out);
+                    let _ = fs::remove_file(path);
+                }
+
+                result
+            },
+            None => {
+                let stdout = io::stdout();
+                let mut out = BufWriter::new(stdout.lock());
+                self.write(&mut out, format)?;
+                out.flush()
+            }
+        }
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W, format: ImageFormat) -> io::Result<()> {
+        match format {
+            ImageFormat::PpmAscii => self.write_ppm_ascii(out),
+            ImageFormat::PpmBinary => self.write_ppm_binary(out),
+            ImageFormat::Png16 => self.write_png16(out),
+            ImageFormat::Pfm => self.write_pfm(out)
+        }
+    }
+
+    fn write_ppm_ascii<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "P3\n{} {}\n255", self.width, self.height)?;
+
+        for color in &self.pixels {
+            let [r, g, b] = quantize(color);
+            writeln!(out, "{} {} {}", r, g, b)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_ppm_binary<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+
+        let bytes: Vec<u8> = self.pixels.iter().flat_map(quantize).collect();
+        out.write_all(&bytes)
+    }
+
+    fn write_png16<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(out, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Sixteen);
+
+        // PNG stores 16 bit samples big endian
+        let bytes: Vec<u8> = self.pixels.iter()
+            .flat_map(quantize_16)
+            .flat_map(u16::to_be_bytes)
+            .collect();
+
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&bytes).map_err(io::Error::other)?;
+        writer.finish().map_err(io::Error::other)
+    }
+
+    fn write_pfm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        // A negative scale marks the data as little endian
+        write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+
+        // PFM rows go from the bottom of the image to the top
+        let mut bytes = Vec::with_capacity(self.width * self.height * 12);
+        for row in self.pixels.chunks(self.width).rev() {
+            for color in row {
+                for c in &[color.x, color.y, color.z] {
+                    bytes.extend_from_slice(&(*c as f32).to_le_bytes());
+                }
+            }
+        }
+
+        out.write_all(&bytes)
+    }
+}
+
+// Maps the already encoded color to 8 bits per channel
+fn quantize(color: &Color) -> [u8; 3] {
+    let to_byte = |c: f64| (256.0 * clamp(c, 0.0, 0.999)) as u8;
+    [to_byte(color.x), to_byte(color.y), to_byte(color.z)]
+}
+
+// Maps the already encoded color to 16 bits per channel
+fn quantize_16(color: &Color) -> [u16; 3] {
+    let to_short = |c: f64| (65535.0 * clamp(c, 0.0, 1.0)).round() as u16;
+    [to_short(color.x), to_short(color.y), to_short(color.z)]
+}
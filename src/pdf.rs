@@ -0,0 +1,124 @@
+use crate::math::*;
+use crate::hittable::*;
+use crate::microfacet::*;
+use crate::sampler::*;
+use crate::scene::*;
+
+// Probability density over directions, used to pick where scattered rays go. Sending more rays where
+// most of the light comes from, and dividing their contribution by the density, gives the same
+// average with less noise. Densities are per unit solid angle.
+pub enum Pdf<'a> {
+    Cosine { normal: Vector3 },                          // Proportional to the cosine to the normal, zero below the surface
+    Uniform,                                             // The same in every direction
+    // Reflected off the microfacets seen from the unit outgoing direction, given in the local space of
+    // the frame around the shading normal
+    Ggx { frame: Onb, outgoing: Vector3, distribution: Ggx },
+    // Reflected off half vectors around the normal spread by the cosine to the power of the exponent, the
+    // unit outgoing direction given in world space
+    BlinnPhong { normal: Vector3, outgoing: Vector3, exponent: f64 },
+    // Scattered by a medium around the unit direction the light was going in, by the Henyey-Greenstein
+    // phase function of asymmetry g
+    HenyeyGreenstein { direction: Vector3, g: f64 },
+    Hittable { object: &'a Hittable, origin: Point3 },   // Towards the visible part of an object, to find lights directly
+    Lights { world: &'a World, origin: Point3 },         // Towards one of the lights of the world, picked at random
+    Mixture(&'a Pdf<'a>, &'a Pdf<'a>)                    // Picks either of two densities half of the time
+}
+
+impl<'a> Pdf<'a> {
+    pub fn value(&self, direction: &Vector3) -> f64 {
+        match self {
+            Pdf::Cosine { normal } => {
+                let cosine = Vector3::dot(&Vector3::normalize(direction), normal);
+                f64::max(cosine, 0.0) / PI
+            },
+            Pdf::Uniform => 1.0 / (4.0 * PI),
+            Pdf::Ggx { frame, outgoing, distribution } => {
+                let incoming = frame.to_local(&Vector3::normalize(direction));
+                distribution.reflection_pdf(outgoing, &incoming)
+            },
+            Pdf::BlinnPhong { normal, outgoing, exponent } => {
+                let h = *outgoing + Vector3::normalize(direction);
+                if h.length_squared() <= 1.0e-12 {
+                    return 0.0;
+                }
+
+                // The density of the half vector, and the reflection about it stretches solid angle by
+                // four times the cosine between the two
+                let h = Vector3::normalize(&h);
+                let cos_o = Vector3::dot(outgoing, &h);
+                let cos_h = Vector3::dot(normal, &h);
+                if cos_o <= 0.0 || cos_h <= 0.0 {
+                    return 0.0;
+                }
+
+                (exponent + 1.0) / (2.0 * PI) * cos_h.powf(*exponent) / (4.0 * cos_o)
+            },
+            Pdf::HenyeyGreenstein { direction: forward, g } => {
+                henyey_greenstein(Vector3::dot(forward, &Vector3::normalize(direction)), *g)
+            },
+            Pdf::Hittable { object, origin } => object.pdf_value(origin, direction),
+            Pdf::Lights { world, origin } => {
+                let sum: f64 = world.lights.iter().map(|light| world.hittables[*light].pdf_value(origin, direction)).sum();
+                sum / world.lights.len() as f64
+            },
+            Pdf::Mixture(first, second) => 0.5 * first.value(direction) + 0.5 * second.value(direction)
+        }
+    }
+
+    pub fn generate(&self, sampler: &mut dyn Sampler) -> Vector3 {
+        match self {
+            Pdf::Cosine { normal } => {
+                let (r1, r2) = sampler.next_2d();
+                Onb::new(normal).local(&random_cosine_direction(r1, r2))
+            },
+            Pdf::Uniform => sampler.unit_vector(),
+            Pdf::Ggx { frame, outgoing, distribution } => {
+                let (r1, r2) = sampler.next_2d();
+                let h = distribution.sample_visible_normal(outgoing, r1, r2);
+                frame.local(&(2.0 * Vector3::dot(outgoing, &h) * h - *outgoing))
+            },
+            Pdf::BlinnPhong { normal, outgoing, exponent } => {
+                let (r1, r2) = sampler.next_2d();
+                let cos_h = r1.powf(1.0 / (exponent + 1.0));
+                let sin_h = f64::max(1.0 - cos_h * cos_h, 0.0).sqrt();
+                let phi = 2.0 * PI * r2;
+
+                let h = Onb::new(normal).local(&Vector3::new(phi.cos() * sin_h, phi.sin() * sin_h, cos_h));
+                2.0 * Vector3::dot(outgoing, &h) * h - *outgoing
+            },
+            Pdf::HenyeyGreenstein { direction: forward, g } => {
+                let (r1, r2) = sampler.next_2d();
+
+                // Inverting the cumulative distribution of the cosine, which is uniform without asymmetry
+                let cos_theta = if g.abs() < 1.0e-3 {
+                    1.0 - 2.0 * r1
+                } else {
+                    let square = (1.0 - g * g) / (1.0 - g + 2.0 * g * r1);
+                    clamp((1.0 + g * g - square * square) / (2.0 * g), -1.0, 1.0)
+                };
+                let sin_theta = f64::max(1.0 - cos_theta * cos_theta, 0.0).sqrt();
+                let phi = 2.0 * PI * r2;
+
+                Onb::new(forward).local(&Vector3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta))
+            },
+            Pdf::Hittable { object, origin } => object.random_direction_to_surface(origin, sampler),
+            Pdf::Lights { world, origin } => {
+                let index = usize::min((sampler.next_1d() * world.lights.len() as f64) as usize, world.lights.len() - 1);
+                world.hittables[world.lights[index]].random_direction_to_surface(origin, sampler)
+            },
+            Pdf::Mixture(first, second) => {
+                if sampler.next_1d() < 0.5 {
+                    first.generate(sampler)
+                } else {
+                    second.generate(sampler)
+                }
+            }
+        }
+    }
+}
+
+// Density of scattering by the cosine of the angle between the directions before and after
+pub fn henyey_greenstein(cos_theta: f64, g: f64) -> f64 {
+    let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())
+}
@@ -2,6 +2,7 @@ use crate::math::*;
 
 const POINT_COUNT: usize = 256;
 
+#[derive(Clone)]
 pub struct Perlin {
     pub ranvec: Vec<Vector3>,
     pub perm_x: Vec<i32>,
@@ -9,13 +10,17 @@ pub struct Perlin {
     pub perm_z: Vec<i32>
 }
 
+impl Default for Perlin {
+    fn default() -> Perlin {
+        Perlin::new()
+    }
+}
+
 impl Perlin {
     pub fn new() -> Perlin {
-        let mut ranvec: Vec<Vector3> = vec![Vector3::new(0.0, 0.0, 0.0); POINT_COUNT];
-        
-        for i in 0..POINT_COUNT {
-            ranvec[i] = Vector3::normalize(&Vector3::random_range(-1.0, 1.0));
-        }
+        let ranvec: Vec<Vector3> = (0..POINT_COUNT)
+            .map(|_| Vector3::normalize(&Vector3::random_range(-1.0, 1.0)))
+            .collect();
 
         let perm_x = Self::perlin_generate_perm();
         let perm_y = Self::perlin_generate_perm();
@@ -51,9 +56,9 @@ impl Perlin {
         for di in 0..2 {
             for dj in 0..2 {
                 for dk in 0..2 {
-                    let x = ((i + di) as i32 & 255) as usize;
-                    let y = ((j + dj) as i32 & 255) as usize;
-                    let z = ((k + dk) as i32 & 255) as usize;
+                    let x = ((i + di) & 255) as usize;
+                    let y = ((j + dj) & 255) as usize;
+                    let z = ((k + dk) & 255) as usize;
 
                     c[di as usize][dj as usize][dk as usize] = self.ranvec[
                         (self.perm_x[x] ^
@@ -73,10 +78,10 @@ impl Perlin {
         let ww = w * w * (3.0 - 2.0 * w);
         let mut accum = 0.0;
 
-        for i in 0..2 {
-            for j in 0..2 {
-                for k in 0..2 {
-                    let val = c[i][j][k];
+        for (i, c_i) in c.iter().enumerate() {
+            for (j, c_ij) in c_i.iter().enumerate() {
+                for (k, c_ijk) in c_ij.iter().enumerate() {
+                    let val = *c_ijk;
                     let i = i as f64;
                     let j = j as f64;
                     let k = k as f64;
@@ -94,32 +99,45 @@ impl Perlin {
     }
     
     pub fn turb(&self, p: &Point3, depth: i32) -> f64 {
+        self.turb_with_accum(*p, depth, 1.0)
+    }
+
+    // Turbulence where the first octave has the given weight, useful when layering several noises.
+    // p is taken by value since it is scaled up in place for every octave.
+    pub fn turb_with_accum(&self, mut p: Point3, depth: i32, start_weight: f64) -> f64 {
         let mut accum = 0.0;
-        let mut temp_p = *p;
-        let mut weight = 1.0;
+        let mut weight = start_weight;
 
         for _i in 0..depth {
-            accum += weight * self.noise(&temp_p);
+            accum += weight * self.noise(&p);
             weight *= 0.5;
-            temp_p *= 2.0;
+            p *= 2.0;
         }
 
         accum.abs()
     }
-   
-    fn perlin_generate_perm() -> Vec<i32> {
-        let mut p: Vec<i32> = vec![0; POINT_COUNT]; 
 
-        for i in 0..POINT_COUNT {
-            p[i] = i as i32;
-        }
+    // Turbulence sampled at a point displaced by the noise itself, which gives swirlier patterns
+    pub fn turb_domain_warped(&self, p: &Point3, depth: i32, warp_strength: f64) -> f64 {
+        // Arbitrary offsets so each axis gets decorrelated noise
+        let warp = Vector3::new(
+            self.noise(p),
+            self.noise(&(*p + Vector3::new(5.2, 1.3, 2.8))),
+            self.noise(&(*p + Vector3::new(1.7, 9.2, 3.4)))
+        );
+
+        self.turb_with_accum(*p + warp_strength * warp, depth, 1.0)
+    }
+
+    fn perlin_generate_perm() -> Vec<i32> {
+        let mut p: Vec<i32> = (0..POINT_COUNT as i32).collect();
 
         Self::permute(&mut p, POINT_COUNT);
         
         p
     }
 
-    fn permute(p: &mut Vec<i32>, n: usize) {
+    fn permute(p: &mut [i32], n: usize) {
         for i in (0..n).rev() {
             let target = random_int_range(0, i as i32) as usize;
             let tmp = p[i];
@@ -128,3 +146,18 @@ impl Perlin {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn domain_warped_turbulence_without_warp_is_plain_turbulence() {
+        let perlin = Perlin::new();
+
+        for p in [Point3::new(0.3, 1.7, -2.2), Point3::new(4.1, 0.0, 0.9)] {
+            assert_eq!(perlin.turb_domain_warped(&p, 7, 0.0), perlin.turb(&p, 7));
+            assert_ne!(perlin.turb_domain_warped(&p, 7, 4.0), perlin.turb(&p, 7));
+        }
+    }
+}
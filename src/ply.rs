@@ -0,0 +1,220 @@
+use crate::math::*;
+use crate::material::*;
+use crate::mesh::*;
+
+// Type of a property of a PLY element
+#[derive(Copy, Clone, Debug)]
+enum Scalar {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64
+}
+
+struct Property {
+    name: String,
+    scalar: Scalar,
+    count: Option<Scalar>   // Type of the length of a list property
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>
+}
+
+// Where the values of the elements are read from after the header
+enum Body<'a> {
+    Ascii(std::str::SplitWhitespace<'a>),
+    Binary { bytes: &'a [u8], offset: usize, big_endian: bool }
+}
+
+// Loads a PLY file, ASCII or binary, as a mesh with its vertices scaled. Vertices are read with their
+// normals and texture coordinates when the file has them, and polygons are split into fans of
+// triangles. Elements other than vertices and faces, and other properties like colors, are skipped.
+// Without normals in the file they can be averaged from the triangles around each vertex, which
+// scans like the Stanford bunny need to look smooth, or the triangles are shaded flat.
+pub fn load_ply(path: &str, scale: f64, smooth: bool, mat_handle: MaterialHandle) -> Result<Mesh, String> {
+    let bytes = std::fs::read(path).map_err(|err| format!("Could not read model {}: {}", path, err))?;
+
+    let header_end = bytes.windows(10).position(|window| window == b"end_header")
+        .and_then(|start| bytes[start..].iter().position(|&byte| byte == b'\n').map(|newline| start + newline + 1))
+        .ok_or_else(|| format!("{} has no PLY header", path))?;
+    let header = String::from_utf8_lossy(&bytes[..header_end]);
+
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err(format!("{} is not a PLY file", path));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens[..] {
+            ["format", name, _] => format = Some(name),
+            ["element", name, count] => elements.push(Element {
+                name: String::from(name),
+                count: count.parse().map_err(|_| format!("Invalid count of {} in {}", name, path))?,
+                properties: Vec::new()
+            }),
+            ["property", "list", count, scalar, name] => {
+                let element = elements.last_mut().ok_or_else(|| format!("Property before any element in {}", path))?;
+                element.properties.push(Property { name: String::from(name), scalar: parse_scalar(scalar, path)?, count: Some(parse_scalar(count, path)?) });
+            },
+            ["property", scalar, name] => {
+                let element = elements.last_mut().ok_or_else(|| format!("Property before any element in {}", path))?;
+                element.properties.push(Property { name: String::from(name), scalar: parse_scalar(scalar, path)?, count: None });
+            },
+            _ => {}
+        }
+    }
+
+    let mut body = match format {
+        Some("ascii") => Body::Ascii(std::str::from_utf8(&bytes[header_end..]).map_err(|_| format!("{} is not valid ASCII", path))?.split_whitespace()),
+        Some("binary_little_endian") => Body::Binary { bytes: &bytes, offset: header_end, big_endian: false },
+        Some("binary_big_endian") => Body::Binary { bytes: &bytes, offset: header_end, big_endian: true },
+        _ => return Err(format!("{} has no known format", path))
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut indices = Vec::new();
+
+    for element in &elements {
+        let find = |names: &[&str]| element.properties.iter().position(|property| names.contains(&property.name.as_str()));
+        let (x, y, z) = (find(&["x"]), find(&["y"]), find(&["z"]));
+        let (nx, ny, nz) = (find(&["nx"]), find(&["ny"]), find(&["nz"]));
+        let (u, v) = (find(&["u", "s", "texture_u", "texture_s"]), find(&["v", "t", "texture_v", "texture_t"]));
+        let face_indices = find(&["vertex_indices", "vertex_index"]);
+
+        let mut values = vec![0.0; element.properties.len()];
+        let mut list = Vec::new();
+        for _ in 0..element.count {
+            for (i, property) in element.properties.iter().enumerate() {
+                match property.count {
+                    Some(count) => {
+                        // Only the vertex indices of faces are kept, other lists are read past
+                        let keep = Some(i) == face_indices;
+                        if keep {
+                            list.clear();
+                        }
+                        let length = body.read(count, path)? as usize;
+                        for _ in 0..length {
+                            let value = body.read(property.scalar, path)?;
+                            if keep {
+                                list.push(value);
+                            }
+                        }
+                    },
+                    None => values[i] = body.read(property.scalar, path)?
+                }
+            }
+
+            match element.name.as_str() {
+                "vertex" => {
+                    let (x, y, z) = match (x, y, z) {
+                        (Some(x), Some(y), Some(z)) => (x, y, z),
+                        _ => return Err(format!("Vertices of {} have no x, y and z", path))
+                    };
+                    positions.push(scale * Point3::new(values[x], values[y], values[z]));
+                    if let (Some(nx), Some(ny), Some(nz)) = (nx, ny, nz) {
+                        normals.push(Vector3::normalize(&Vector3::new(values[nx], values[ny], values[nz])));
+                    }
+                    if let (Some(u), Some(v)) = (u, v) {
+                        uvs.push((values[u], values[v]));
+                    }
+                },
+                "face" => {
+                    if face_indices.is_none() {
+                        return Err(format!("Faces of {} have no vertex indices", path));
+                    }
+                    // Whether they are below the number of vertices is up to Mesh, as faces may come first
+                    if let Some(index) = list.iter().find(|&&index| index < 0.0 || index > u32::MAX as f64 || index.fract() != 0.0) {
+                        return Err(format!("Invalid vertex index {} in {}", index, path));
+                    }
+                    for i in 1..list.len().saturating_sub(1) {
+                        indices.push([list[0] as u32, list[i] as u32, list[i + 1] as u32]);
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+
+    if indices.is_empty() {
+        return Err(format!("Model {} has no faces", path));
+    }
+
+    trace!("Loaded model {} with {} triangles over {} vertices", path, indices.len(), positions.len());
+
+    let normals = if !normals.is_empty() {
+        Some(normals)
+    } else if smooth {
+        Some(Mesh::smooth_normals(&positions, &indices)?)
+    } else {
+        None
+    };
+    let uvs = if uvs.is_empty() { None } else { Some(uvs) };
+    Mesh::new(positions, normals, uvs, indices, mat_handle)
+}
+
+fn parse_scalar(name: &str, path: &str) -> Result<Scalar, String> {
+    match name {
+        "char" | "int8" => Ok(Scalar::Int8),
+        "uchar" | "uint8" => Ok(Scalar::UInt8),
+        "short" | "int16" => Ok(Scalar::Int16),
+        "ushort" | "uint16" => Ok(Scalar::UInt16),
+        "int" | "int32" => Ok(Scalar::Int32),
+        "uint" | "uint32" => Ok(Scalar::UInt32),
+        "float" | "float32" => Ok(Scalar::Float32),
+        "double" | "float64" => Ok(Scalar::Float64),
+        _ => Err(format!("Unknown property type {} in {}", name, path))
+    }
+}
+
+impl<'a> Body<'a> {
+    fn read(&mut self, scalar: Scalar, path: &str) -> Result<f64, String> {
+        match self {
+            Body::Ascii(tokens) => {
+                let token = tokens.next().ok_or_else(|| format!("{} ends early", path))?;
+                token.parse::<f64>().map_err(|_| format!("Invalid number {} in {}", token, path))
+            },
+            Body::Binary { bytes, offset, big_endian } => {
+                let size = match scalar {
+                    Scalar::Int8 | Scalar::UInt8 => 1,
+                    Scalar::Int16 | Scalar::UInt16 => 2,
+                    Scalar::Int32 | Scalar::UInt32 | Scalar::Float32 => 4,
+                    Scalar::Float64 => 8
+                };
+                if *offset + size > bytes.len() {
+                    return Err(format!("{} ends early", path));
+                }
+
+                // Put the bytes in little endian order, the one the values are decoded in
+                let mut raw = [0; 8];
+                raw[..size].copy_from_slice(&bytes[*offset..*offset + size]);
+                if *big_endian {
+                    raw[..size].reverse();
+                }
+                *offset += size;
+
+                Ok(match scalar {
+                    Scalar::Int8 => raw[0] as i8 as f64,
+                    Scalar::UInt8 => raw[0] as f64,
+                    Scalar::Int16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+                    Scalar::UInt16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+                    Scalar::Int32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    Scalar::UInt32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    Scalar::Float32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    Scalar::Float64 => f64::from_le_bytes(raw)
+                })
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use raytracer::logging::*;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Render progress shared between the render threads, which update the counters as they finish
+// tiles, and a reporter thread printing a status line to stderr
+pub struct Progress {
+    total_samples: usize,
+    total_passes: usize,
+    tile_count: usize,
+    samples_done: AtomicUsize,
+    passes_done: AtomicUsize,
+    active_tiles: AtomicUsize,
+    finished: AtomicBool,
+    start: Instant
+}
+
+impl Progress {
+    pub fn new(total_samples: usize, total_passes: usize, tile_count: usize) -> Progress {
+        Progress {
+            total_samples,
+            total_passes,
+            tile_count,
+            samples_done: AtomicUsize::new(0),
+            passes_done: AtomicUsize::new(0),
+            active_tiles: AtomicUsize::new(0),
+            finished: AtomicBool::new(false),
+            start: Instant::now()
+        }
+    }
+
+    pub fn tile_started(&self) {
+        self.active_tiles.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn tile_finished(&self, samples: usize, pass_finished: bool) {
+        self.active_tiles.fetch_sub(1, Ordering::Relaxed);
+        self.samples_done.fetch_add(samples, Ordering::Relaxed);
+        if pass_finished {
+            self.passes_done.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Stops the reporter after it prints the final status
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::SeqCst);
+    }
+
+    // Share of the samples traced so far, from 0 to 1
+    pub fn fraction(&self) -> f64 {
+        let done = self.samples_done.load(Ordering::Relaxed);
+        if self.total_samples > 0 { f64::min(done as f64 / self.total_samples as f64, 1.0) } else { 1.0 }
+    }
+
+    pub fn status(&self) -> String {
+        let done = self.samples_done.load(Ordering::Relaxed);
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let fraction = self.fraction();
+        let rate = if elapsed > 0.0 { done as f64 / elapsed } else { 0.0 };
+
+        let eta = if done == 0 {
+            String::from("--:--")
+        } else {
+            format_duration(elapsed * self.total_samples.saturating_sub(done) as f64 / done as f64)
+        };
+
+        format!(
+            "Progress: {:5.1}% | {:.2}M samples/s | elapsed {} | ETA {} | pass {}/{} | {}/{} tiles active",
+            100.0 * fraction,
+            rate / 1e6,
+            format_duration(elapsed),
+            eta,
+            self.passes_done.load(Ordering::Relaxed),
+            self.total_passes,
+            self.active_tiles.load(Ordering::Relaxed),
+            self.tile_count
+            )
+    }
+}
+
+// Prints the status line a few times per second until the progress is finished. Join the handle
+// before printing anything else, so the last status line is not overwritten.
+pub fn spawn_reporter(progress: Arc<Progress>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut last_length = 0;
+
+        loop {
+            let finished = progress.finished.load(Ordering::SeqCst);
+
+            if log_enabled(Verbosity::Normal) {
+                // Pad with spaces to wipe out the end of a longer previous line
+                let status = progress.status();
+                eprint!("\r{:<width$}", status, width = last_length);
+                last_length = status.len();
+
+                if finished {
+                    eprintln!();
+                }
+            }
+
+            if finished {
+                break;
+            }
+            thread::sleep(Duration::from_millis(250));
+        }
+    })
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
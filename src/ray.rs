@@ -1,9 +1,14 @@
 use crate::math::*;
+use crate::material::*;
+use crate::spectrum::*;
 
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vector3,
-    pub time: f64
+    pub time: f64,
+    pub media: MediumStack, // The dielectrics the ray is inside of
+    pub wavelengths: Option<Wavelengths>, // In spectral mode, what the colors along the ray are
+    pub channel: Option<usize> // Once split up by dispersion, the only one of its colors the ray carries on
 }
 
 impl Ray {
@@ -11,12 +16,98 @@ impl Ray {
         Ray {
             origin,
             direction,
-            time
+            time,
+            media: MediumStack::default(),
+            wavelengths: None,
+            channel: None
         }
     }
 
+    // A ray leaving a hit of this one inside the same dielectrics
+    pub fn continued(&self, origin: Point3, direction: Vector3) -> Ray {
+        Ray {
+            origin,
+            direction,
+            time: self.time,
+            media: self.media,
+            wavelengths: self.wavelengths,
+            channel: self.channel
+        }
+    }
+
+    // An RGB color the way the ray carries colors, a spectrum at its wavelengths in spectral mode
+    pub fn spectral(&self, color: Color) -> Color {
+        match &self.wavelengths {
+            Some(wavelengths) => wavelengths.uplift(&color),
+            None => color
+        }
+    }
 
     pub fn at(&self, t: f64) -> Point3 {
         self.origin + t * self.direction
     }
 }
+
+// A dielectric object a ray went into, by the material of the object
+#[derive(Default, Copy, Clone)]
+pub struct Medium {
+    pub material: MaterialHandle,
+    pub priority: i32,
+    pub ir: f64,
+    pub absorption: Color,
+    pub abbe: f64
+}
+
+impl Medium {
+    // Index of refraction at a wavelength in nanometers. The ir is taken at the sodium D line, and Cauchy's
+    // equation n = A + B / wavelength^2 fitted to the Abbe number (n_D - 1) / (n_F - n_C) gives the rest.
+    pub fn ir_at(&self, wavelength: f64) -> f64 {
+        if !self.abbe.is_finite() {
+            return self.ir;
+        }
+
+        let inverse_square = |wavelength: f64| 1.0 / (wavelength * wavelength * 1.0e-6);
+        let b = (self.ir - 1.0) / (self.abbe * (inverse_square(486.1) - inverse_square(656.3)));
+        self.ir + b * (inverse_square(wavelength) - inverse_square(589.3))
+    }
+}
+
+// Dielectrics a ray is inside of, in the order it went into them. Where they overlap the ray is in the
+// one of the highest priority, the latest one on ties, so liquid filling a glass is nested properly.
+// Rays deeper than MAX_NESTED forget the outermost.
+const MAX_NESTED: usize = 4;
+
+#[derive(Default, Copy, Clone)]
+pub struct MediumStack {
+    media: [Medium; MAX_NESTED],
+    len: usize
+}
+
+impl MediumStack {
+    pub fn push(&mut self, medium: Medium) {
+        if self.len == MAX_NESTED {
+            self.media.rotate_left(1);
+            self.len -= 1;
+        }
+
+        self.media[self.len] = medium;
+        self.len += 1;
+    }
+
+    // Leaves the latest entered medium of the material, if the ray is inside one
+    pub fn remove(&mut self, material: MaterialHandle) {
+        if let Some(index) = self.media[..self.len].iter().rposition(|medium| medium.material.0 == material.0) {
+            self.media[index..self.len].rotate_left(1);
+            self.len -= 1;
+        }
+    }
+
+    pub fn contains(&self, material: MaterialHandle) -> bool {
+        self.media[..self.len].iter().any(|medium| medium.material.0 == material.0)
+    }
+
+    // The medium the ray is actually in, none for air
+    pub fn current(&self) -> Option<&Medium> {
+        self.media[..self.len].iter().max_by_key(|medium| medium.priority)
+    }
+}
@@ -0,0 +1,616 @@
+use crate::math::*;
+use crate::ray::*;
+use crate::camera::*;
+use crate::film::*;
+use crate::output::*;
+use crate::filter::*;
+use crate::stats::*;
+use crate::scene::*;
+use crate::sampler::*;
+use crate::material::*;
+use crate::pdf::*;
+use crate::hittable::*;
+use crate::light::*;
+use crate::fog::*;
+use crate::spectrum::*;
+use serde::{Serialize, Deserialize};
+use std::cell::RefCell;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// Only the first few samples with NaN or infinite radiance of a frame are logged with their paths, which
+// are traced a second time to find them
+const LOGGED_INVALID_SAMPLES: usize = 10;
+
+thread_local! {
+    // Points where the path of the calling thread hit something, while it is being recorded
+    static PATH_VERTICES: RefCell<Option<Vec<Point3>>> = const { RefCell::new(None) };
+}
+
+// Traces a path and returns the radiance along it. For camera rays, aovs receives what the first hit saw
+// and clamp limits the light reaching the first hit, to suppress fireflies.
+#[allow(clippy::too_many_arguments)]
+pub fn ray_color(ray: &Ray, background_color: &Color, world: &World, depth: i32, sampler: &mut dyn Sampler, ray_counter: &mut RayCounter, aovs: Option<&mut AovSample>, clamp: Option<&SampleClamp>) -> Color {
+    let (emitted, reflected) = trace_ray(ray, background_color, world, depth, sampler, ray_counter, aovs, clamp, 1.0);
+    emitted + reflected
+}
+
+// Radiance along a ray, split into the light emitted by what it hits and the light reflected there.
+// Lights are found both by sampling them directly and by scattering into them, and light_weight is the
+// share of a hit light's emission that counts for this ray, see power_heuristic.
+#[allow(clippy::too_many_arguments)]
+fn trace_ray(ray: &Ray, background_color: &Color, world: &World, depth: i32, sampler: &mut dyn Sampler, ray_counter: &mut RayCounter, aovs: Option<&mut AovSample>, clamp: Option<&SampleClamp>, light_weight: f64) -> (Color, Color) {
+    // If we've exceeded the ray bounce limit, no more light is gathered
+    if depth <= 0 {
+        return (Color::new(0.0, 0.0, 0.0), Color::new(0.0, 0.0, 0.0));
+    }
+
+    ray_counter.count(depth);
+
+    // Light scattered towards the ray by the fog along it, from points picked close to the lights
+    let in_scattered = match &world.fog {
+        Some(fog) if !world.lights.is_empty() && depth > 1 => sample_fog_lights(ray, fog, world, depth, sampler, ray_counter),
+        _ => Color::new(0.0, 0.0, 0.0)
+    };
+
+    if let Some(mut rec) = world.hit(ray, 0.001, INFINITY) {
+        PATH_VERTICES.with(|vertices| {
+            if let Some(vertices) = vertices.borrow_mut().as_mut() {
+                vertices.push(rec.point);
+            }
+        });
+
+        let material = world.resolve_material(ray, &mut rec, sampler);
+
+        if let Some(aovs) = aovs {
+            aovs.normal = rec.normal;
+            aovs.depth = rec.t * ray.direction.length();
+            aovs.albedo = material.albedo(&rec);
+            aovs.object_id = Some(rec.object_id);
+            aovs.material_id = Some(rec.mat_handle.0);
+        }
+        
+        let mut emitted = ray.spectral(material.emitted(&rec));
+        if material.is_emissive() && world.is_light(rec.object_id) {
+            emitted = light_weight * emitted;
+        }
+
+        let mut direct = Color::new(0.0, 0.0, 0.0);
+        let mut next = None;
+
+        match material.scatter(ray, &rec, sampler) {
+            Some(Scatter::Specular { ray: scattered, attenuation }) => next = Some((scattered, attenuation, 1.0)),
+            Some(Scatter::Pdf { pdf, attenuation }) => {
+                // Lights beyond the bounce limit are not sampled either
+                let sample_lights = !world.lights.is_empty() && depth > 1;
+                if sample_lights {
+                    direct = sample_light(ray, &rec, material, &pdf, attenuation, world, depth, sampler, ray_counter);
+
+                    // Shared with the points sample_fog_lights picks along the same ray
+                    if let (Some(fog), FOG_OBJECT_ID) = (&world.fog, rec.object_id) {
+                        let (distance_pdf, equiangular_pdf) = fog_distance_pdfs(ray, fog, world, rec.t);
+                        direct = distance_pdf / (distance_pdf + equiangular_pdf) * direct;
+                    }
+                }
+
+                if depth > 1 {
+                    for light in &world.analytic_lights {
+                        direct += sample_analytic_light(light, ray, &rec, material, attenuation, world, depth, sampler, ray_counter);
+                    }
+                }
+
+                let scattered = ray.continued(rec.point, pdf.generate(sampler));
+                let pdf_value = pdf.value(&scattered.direction);
+                let scattering_pdf = material.scattering_pdf(ray, &rec, &scattered);
+
+                // Directions the density never picks, or the material never scatters into, contribute nothing
+                if pdf_value > 0.0 && scattering_pdf > 0.0 {
+                    let light_weight = if sample_lights {
+                        power_heuristic(pdf_value, Pdf::Lights { world, origin: rec.point }.value(&scattered.direction))
+                    } else {
+                        1.0
+                    };
+
+                    next = Some((scattered, scattering_pdf / pdf_value * attenuation, light_weight));
+                }
+            },
+            None => {}
+        }
+
+        direct += in_scattered;
+
+        let mut indirect = Color::new(0.0, 0.0, 0.0);
+        if let Some((scattered, attenuation, light_weight)) = next {
+            // Light emitted at the next hit arrives here directly, anything it reflects is indirect
+            let (next_emitted, next_reflected) = trace_ray(&scattered, background_color, world, depth - 1, sampler, ray_counter, None, None, light_weight);
+            direct += attenuation * next_emitted;
+            indirect = attenuation * next_reflected;
+        }
+
+        if let Some(clamp) = clamp {
+            direct = clamp_radiance(direct, clamp.direct);
+            indirect = clamp_radiance(indirect, clamp.indirect);
+        }
+
+        return (emitted, direct + indirect);
+    } 
+
+    let background = match &world.environment {
+        Some(environment) => environment.radiance(&ray.direction),
+        None => *background_color
+    };
+
+    if let Some(aovs) = aovs {
+        aovs.albedo = background;
+    }
+
+    let background = ray.spectral(background);
+
+    let in_scattered = match clamp {
+        Some(clamp) => clamp_radiance(in_scattered, clamp.direct),
+        None => in_scattered
+    };
+
+    (background, in_scattered)
+}
+
+// Direct light scattered by the fog along a ray, from a point picked by equiangular sampling towards one
+// of the lights, from Kulla and Fajardo, Importance Sampling Techniques for Path Tracing in Participating
+// Media. The fog's own hits only find points near small lights by chance, which leaves light shafts noisy
+// for a long time. Both ways of picking points are weighted against each other by the balance heuristic.
+fn sample_fog_lights(ray: &Ray, fog: &Fog, world: &World, depth: i32, sampler: &mut dyn Sampler, ray_counter: &mut RayCounter) -> Color {
+    let index = usize::min((sampler.next_1d() * world.lights.len() as f64) as usize, world.lights.len() - 1);
+    let (delta, closest, theta_a) = match equiangular_frame(ray, &world.light_anchors[index]) {
+        Some(frame) => frame,
+        None => return Color::new(0.0, 0.0, 0.0)
+    };
+
+    // Uniform in the angle under which the ray is seen from the light
+    let theta = theta_a + sampler.next_1d() * (0.5 * PI - theta_a);
+    let t = (delta + closest * theta.tan()) / ray.direction.length();
+    if !t.is_finite() || t <= 0.001 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    ray_counter.count(depth - 1);
+    if world.hit_objects(ray, 0.001, t).is_some() {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    let mut rec = World::fog_record(fog, ray, t);
+    let material = world.resolve_material(ray, &mut rec, sampler);
+    let light = match material.scatter(ray, &rec, sampler) {
+        Some(Scatter::Pdf { pdf, attenuation }) => sample_light(ray, &rec, material, &pdf, attenuation, world, depth, sampler, ray_counter),
+        _ => return Color::new(0.0, 0.0, 0.0)
+    };
+
+    let (distance_pdf, equiangular_pdf) = fog_distance_pdfs(ray, fog, world, t);
+    fog.density_at(&rec.point) * fog.transmittance(ray, 0.0, t) / (distance_pdf + equiangular_pdf) * light
+}
+
+// Densities per unit distance of scattering at t along the ray in the fog, and of picking it by
+// equiangular sampling towards any of the lights
+fn fog_distance_pdfs(ray: &Ray, fog: &Fog, world: &World, t: f64) -> (f64, f64) {
+    let distance_pdf = fog.density_at(&ray.at(t)) * fog.transmittance(ray, 0.0, t);
+
+    let distance = t * ray.direction.length();
+    let sum: f64 = world.light_anchors.iter()
+        .filter_map(|anchor| equiangular_frame(ray, anchor))
+        .map(|(delta, closest, theta_a)| closest / ((0.5 * PI - theta_a) * (closest * closest + (distance - delta) * (distance - delta))))
+        .sum();
+
+    (distance_pdf, sum / world.lights.len() as f64)
+}
+
+// Where the ray gets closest to a point, as the distance along the ray and from the point, and the angle
+// the start of the ray is seen under from there, none if the ray goes through the point
+fn equiangular_frame(ray: &Ray, point: &Point3) -> Option<(f64, f64, f64)> {
+    let direction = Vector3::normalize(&ray.direction);
+    let to_point = *point - ray.origin;
+    let delta = Vector3::dot(&to_point, &direction);
+    let closest = (to_point - delta * direction).length();
+    if closest < 1.0e-6 {
+        return None;
+    }
+
+    Some((delta, closest, (-delta / closest).atan()))
+}
+
+// Light arriving at a hit straight from a point picked on one of the lights, if nothing blocks the way.
+// Weighted against finding the same light by scattering, which the next hit takes care of.
+#[allow(clippy::too_many_arguments)]
+fn sample_light(ray: &Ray, rec: &HitRecord, material: &Material, pdf: &Pdf, attenuation: Color, world: &World, depth: i32, sampler: &mut dyn Sampler, ray_counter: &mut RayCounter) -> Color {
+    let light_pdf = Pdf::Lights { world, origin: rec.point };
+    let shadow_ray = Ray::with_time(rec.point, light_pdf.generate(sampler), ray.time);
+    let light_pdf_value = light_pdf.value(&shadow_ray.direction);
+    let scattering_pdf = material.scattering_pdf(ray, rec, &shadow_ray);
+
+    if light_pdf_value <= 0.0 || scattering_pdf <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    ray_counter.count(depth - 1);
+
+    match world.hit(&shadow_ray, 0.001, INFINITY) {
+        Some(mut light_rec) if world.is_light(light_rec.object_id) => {
+            let light_material = world.resolve_material(&shadow_ray, &mut light_rec, sampler);
+            let emitted = ray.spectral(light_material.emitted(&light_rec));
+            let weight = power_heuristic(light_pdf_value, pdf.value(&shadow_ray.direction));
+
+            weight * scattering_pdf / light_pdf_value * attenuation * emitted
+        },
+        _ => Color::new(0.0, 0.0, 0.0)
+    }
+}
+
+// Light arriving at a hit from a light without geometry, if nothing blocks the way. Rays never hit such
+// lights, so there is nothing to weight against.
+#[allow(clippy::too_many_arguments)]
+fn sample_analytic_light(light: &Light, ray: &Ray, rec: &HitRecord, material: &Material, attenuation: Color, world: &World, depth: i32, sampler: &mut dyn Sampler, ray_counter: &mut RayCounter) -> Color {
+    let sample = match light.sample(&rec.point, sampler) {
+        Some(sample) => sample,
+        None => return Color::new(0.0, 0.0, 0.0)
+    };
+
+    let shadow_ray = Ray::with_time(rec.point, sample.direction, ray.time);
+    let scattering_pdf = material.scattering_pdf(ray, rec, &shadow_ray);
+    if scattering_pdf <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    ray_counter.count(depth - 1);
+    if world.occluded(&shadow_ray, sample.distance) {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    scattering_pdf * attenuation * ray.spectral(sample.radiance)
+}
+
+// Share of a sample that counts when it could have been found by two strategies, given the densities of
+// the strategy that found it and the other one. Each strategy mostly keeps the samples it is good at
+// finding, so sharp reflections of lights come from scattering and small lights from light sampling.
+// See Veach, Optimally Combining Sampling Techniques for Monte Carlo Rendering.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let pdf_squared = pdf * pdf;
+    pdf_squared / (pdf_squared + other_pdf * other_pdf)
+}
+
+// Scales the color down so that no component exceeds the limit, keeping its hue
+fn clamp_radiance(color: Color, limit: Option<f64>) -> Color {
+    let max = f64::max(color.x, f64::max(color.y, color.z));
+    match limit {
+        Some(limit) if max > limit => color * (limit / max),
+        _ => color
+    }
+}
+
+// Square blocks of the image region handed out to the render threads
+const TILE_SIZE: usize = 32;
+
+#[derive(Copy, Clone)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize
+}
+
+impl Tile {
+    // Covers the region row by row, with smaller tiles along the right and bottom edges
+    pub fn split_region(region_width: usize, region_height: usize) -> Vec<Tile> {
+        let mut tiles = Vec::new();
+        for y0 in (0..region_height).step_by(TILE_SIZE) {
+            for x0 in (0..region_width).step_by(TILE_SIZE) {
+                tiles.push(Tile {
+                    x0,
+                    y0,
+                    x1: usize::min(x0 + TILE_SIZE, region_width),
+                    y1: usize::min(y0 + TILE_SIZE, region_height)
+                });
+            }
+        }
+
+        tiles
+    }
+
+    pub fn pixel_count(&self) -> usize {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+}
+
+// Everything the render threads need to trace samples, shared between them
+pub struct RenderJob {
+    pub world: Arc<World>,
+    pub camera: Camera,
+    pub background: Color,
+    pub image_width: usize,
+    pub image_height: usize,
+    pub crop: Crop,
+    pub max_depth: i32,
+    pub filter: Filter,
+    pub aovs: Vec<Aov>,
+    pub clamp: SampleClamp,
+    pub adaptive: Option<AdaptiveSampling>,
+    pub sample_map: Option<SampleMap>,
+    pub sampler: SamplerKind,
+    pub seed: u32, // Seed of all random numbers of the frame, from frame_seed
+    pub spectral: bool // Trace each path at wavelengths of its own instead of in RGB, see Wavelengths
+}
+
+impl RenderJob {
+    // Traces one sample for every pixel of a tile, the pass-th one of each pixel. The returned film has a
+    // margin around the tile for the filter to splat into, and its top left corner sits at the returned
+    // region position.
+    pub fn render_tile(&self, tile: &Tile, pass: usize, ray_counter: &mut RayCounter, invalid_samples: &AtomicUsize) -> (Film, isize, isize) {
+        let margin = f64::max((self.filter.radius() - 0.5).ceil(), 0.0) as usize;
+        let mut film = Film::new(tile.x1 - tile.x0 + 2 * margin, tile.y1 - tile.y0 + 2 * margin, self.filter, &self.aovs);
+        let mut sampler = self.sampler.create(self.seed);
+
+        for region_y in tile.y0..tile.y1 {
+            for region_x in tile.x0..tile.x1 {
+                if self.adaptive.as_ref().is_some_and(|adaptive| adaptive.is_converged(region_x, region_y)) {
+                    continue;
+                }
+
+                if self.sample_map.as_ref().is_some_and(|map| pass >= map.samples(region_x, region_y)) {
+                    continue;
+                }
+
+                // Offsets inside the pixel, measured from its top left corner like the film
+                let ((offset_x, offset_y), camera_ray) = self.camera_sample(sampler.as_mut(), region_x, region_y, pass);
+                let film_x = (region_x - tile.x0 + margin) as f64 + offset_x;
+                let film_y = (region_y - tile.y0 + margin) as f64 + offset_y;
+                let mut aov_sample = AovSample::default();
+
+                // Where the lens blocks the light the sample is black
+                let (r, weight) = match camera_ray {
+                    Some(ray) => ray,
+                    None => {
+                        film.add_sample(film_x, film_y, Color::new(0.0, 0.0, 0.0));
+                        film.add_aov_sample(region_x - tile.x0 + margin, region_y - tile.y0 + margin, &aov_sample);
+                        continue;
+                    }
+                };
+
+                let aovs = if film.has_aovs() { Some(&mut aov_sample) } else { None };
+                let mut color = self.sample_color(&r, weight, sampler.as_mut(), ray_counter, aovs);
+
+                // A single NaN or infinity would poison the whole pixel average, so such samples count
+                // as black instead
+                if !color.is_finite() {
+                    if invalid_samples.fetch_add(1, Ordering::Relaxed) < LOGGED_INVALID_SAMPLES {
+                        let path: Vec<String> = std::iter::once(r.origin)
+                            .chain(self.trace_path(region_x, region_y, pass))
+                            .map(|vertex| format!("({})", vertex))
+                            .collect();
+                        error!(
+                            "Invalid sample {} at pixel ({}, {}) in pass {}, along the path {}",
+                            color, self.crop.x0 + region_x, self.crop.y0 + region_y, pass, path.join(" -> ")
+                        );
+                    }
+                    color = Color::new(0.0, 0.0, 0.0);
+                }
+
+                film.add_sample(film_x, film_y, color);
+                film.add_aov_sample(region_x - tile.x0 + margin, region_y - tile.y0 + margin, &aov_sample);
+            }
+        }
+
+        (film, tile.x0 as isize - margin as isize, tile.y0 as isize - margin as isize)
+    }
+
+    // Starts the pass-th sample of a pixel of the region and gives its offset inside the pixel and the
+    // camera ray with its weight, if the lens lets it through. Everything about the sample follows from
+    // the pixel and the pass, so it can be traced again.
+    fn camera_sample(&self, sampler: &mut dyn Sampler, region_x: usize, region_y: usize, pass: usize) -> ((f64, f64), Option<(Ray, f64)>) {
+        // Rays count rows from the bottom of the image
+        let x = self.crop.x0 + region_x;
+        let y = self.image_height - 1 - (self.crop.y0 + region_y);
+
+        sampler.start_pixel(self.crop.x0 + region_x, self.crop.y0 + region_y);
+        sampler.start_sample(pass);
+
+        // Everything else random along the path, like distances through volumes, comes from a
+        // stream of its own for every sample, so the image does not depend on the tile order
+        seed_random(sample_seed(self.seed, self.crop.x0 + region_x, self.crop.y0 + region_y, pass));
+
+        let (offset_x, offset_y) = sampler.next_2d();
+        let u = (x as f64 + offset_x) / (self.image_width as f64 - 1.0);
+        let v = (y as f64 + 1.0 - offset_y) / (self.image_height as f64 - 1.0);
+
+        let wavelengths = if self.spectral { Some(Wavelengths::sample(sampler.next_1d())) } else { None };
+        ((offset_x, offset_y), self.camera.get_ray(u, v, wavelengths, sampler))
+    }
+
+    // RGB radiance the camera ray brings to the film
+    fn sample_color(&self, r: &Ray, weight: f64, sampler: &mut dyn Sampler, ray_counter: &mut RayCounter, aovs: Option<&mut AovSample>) -> Color {
+        let mut color = weight * ray_color(r, &self.background, &self.world, self.max_depth, sampler, ray_counter, aovs, Some(&self.clamp));
+        // The camera traced only one of the colors, which stands in for all three
+        if let Some(channel) = r.channel {
+            let mut only = Color::new(0.0, 0.0, 0.0);
+            only[channel] = 3.0 * color[channel];
+            color = only;
+        }
+        if let Some(wavelengths) = &r.wavelengths {
+            color = wavelengths.to_rgb(&color);
+        }
+
+        color
+    }
+
+    // Points the path of a sample hit, found by tracing it again
+    fn trace_path(&self, region_x: usize, region_y: usize, pass: usize) -> Vec<Point3> {
+        let mut sampler = self.sampler.create(self.seed);
+        let (r, weight) = match self.camera_sample(sampler.as_mut(), region_x, region_y, pass) {
+            (_, Some(ray)) => ray,
+            (_, None) => return Vec::new()
+        };
+
+        PATH_VERTICES.with(|vertices| *vertices.borrow_mut() = Some(Vec::new()));
+        self.sample_color(&r, weight, sampler.as_mut(), &mut RayCounter::new(self.max_depth), None);
+        PATH_VERTICES.with(|vertices| vertices.borrow_mut().take().unwrap_or_default())
+    }
+}
+
+// Largest component a sample may carry, for light reaching the first hit directly or after more bounces.
+// Clamping biases the image darker but removes the rare very bright samples that show up as fireflies.
+#[derive(Copy, Clone)]
+pub struct SampleClamp {
+    pub direct: Option<f64>,
+    pub indirect: Option<f64>
+}
+
+// Pixels get at least this many samples before their noise estimate is trusted
+pub const ADAPTIVE_MIN_SAMPLES: u32 = 16;
+
+// Noisy pixels may receive up to this many times the average samples per pixel
+pub const ADAPTIVE_MAX_FACTOR: usize = 4;
+
+// Adaptive sampling stops tracing pixels once their estimate is precise enough, so the samples go to the
+// noisy parts of the image instead. Converged pixels are tracked per pixel of the render region.
+pub struct AdaptiveSampling {
+    pub threshold: f64,
+    width: usize,
+    converged: Vec<AtomicBool>
+}
+
+impl AdaptiveSampling {
+    // Starts out with the pixels of the film that already converged, e.g. when resuming a render
+    pub fn new(threshold: f64, film: &Film) -> AdaptiveSampling {
+        let adaptive = AdaptiveSampling {
+            threshold,
+            width: film.width,
+            converged: (0..film.width * film.height).map(|_| AtomicBool::new(false)).collect()
+        };
+
+        adaptive.update(film);
+        adaptive
+    }
+
+    pub fn is_converged(&self, x: usize, y: usize) -> bool {
+        self.converged[y * self.width + x].load(Ordering::Relaxed)
+    }
+
+    // Checks the pixels again after a pass added samples to them. A pixel stops once it and its neighbors
+    // are all precise enough, since a pixel whose few samples all missed a small light looks perfectly
+    // converged on its own.
+    pub fn update(&self, film: &Film) {
+        for y in 0..film.height {
+            for x in 0..film.width {
+                let x0 = x.saturating_sub(1);
+                let y0 = y.saturating_sub(1);
+                let x1 = usize::min(x + 1, film.width - 1);
+                let y1 = usize::min(y + 1, film.height - 1);
+
+                if (y0..=y1).all(|ny| (x0..=x1).all(|nx| self.is_precise(film, nx, ny))) {
+                    self.converged[y * self.width + x].store(true, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    // The standard error of a pixel is compared against the square root of its brightness, about how a
+    // gamma of 2 spreads out values, so dark pixels are not held to a much stricter standard than bright ones
+    fn is_precise(&self, film: &Film, x: usize, y: usize) -> bool {
+        if film.sample_count(x, y) < ADAPTIVE_MIN_SAMPLES {
+            return false;
+        }
+
+        let error = film.variance(x, y).sqrt();
+        let brightness = f64::max(film.pixel(x, y).luminance(), 1e-4).sqrt();
+        error <= self.threshold * brightness
+    }
+
+    pub fn converged_count(&self) -> usize {
+        self.converged.iter().filter(|converged| converged.load(Ordering::Relaxed)).count()
+    }
+}
+
+// Samples per pixel of the render region, for spending more samples on some parts of the image
+pub struct SampleMap {
+    width: usize,
+    samples: Vec<usize>
+}
+
+impl SampleMap {
+    // Scales the samples per pixel by the brightness of an importance image, stretched over the whole
+    // image, relative to its average over the region. The region gets about as many samples in total as
+    // without the map, and every pixel at least one.
+    pub fn new(importance: &Image, crop: &Crop, image_width: usize, image_height: usize, samples_per_pixel: usize) -> SampleMap {
+        let weight = |region_x: usize, region_y: usize| {
+            let x = (crop.x0 + region_x) * importance.width / image_width;
+            let y = (crop.y0 + region_y) * importance.height / image_height;
+            f64::max(importance.pixel(x, y).luminance(), 0.0)
+        };
+
+        let (width, height) = (crop.width(), crop.height());
+        let weights: Vec<f64> = (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).map(|(x, y)| weight(x, y)).collect();
+        let mean = weights.iter().sum::<f64>() / weights.len() as f64;
+
+        let samples = weights.iter()
+            .map(|weight| if mean > 0.0 { (samples_per_pixel as f64 * weight / mean).round() as usize } else { samples_per_pixel })
+            .map(|samples| usize::max(samples, 1))
+            .collect();
+
+        SampleMap { width, samples }
+    }
+
+    pub fn samples(&self, x: usize, y: usize) -> usize {
+        self.samples[y * self.width + x]
+    }
+
+    pub fn max_samples(&self) -> usize {
+        self.samples.iter().copied().max().unwrap_or(0)
+    }
+
+    // Samples still to trace once every pixel has had the given number of passes
+    pub fn samples_after(&self, passes: usize) -> usize {
+        self.samples.iter().map(|samples| samples.saturating_sub(passes)).sum()
+    }
+}
+
+// Region of the image in pixels, with the origin in the top left corner. x1 and y1 are exclusive.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Crop {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize
+}
+
+impl Crop {
+    pub fn full(width: usize, height: usize) -> Crop {
+        Crop { x0: 0, y0: 0, x1: width, y1: height }
+    }
+
+    pub fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> usize {
+        self.y1 - self.y0
+    }
+
+    pub fn fits_in(&self, width: usize, height: usize) -> bool {
+        self.x1 <= width && self.y1 <= height
+    }
+}
+
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Crop, String> {
+        let values = s.split(',')
+            .map(|v| v.trim().parse::<usize>().map_err(|_| format!("Invalid crop value {}", v)))
+            .collect::<Result<Vec<usize>, String>>()?;
+
+        if let [x0, y0, x1, y1] = values[..] {
+            if x0 < x1 && y0 < y1 {
+                return Ok(Crop { x0, y0, x1, y1 });
+            }
+        }
+
+        Err(format!("Invalid crop region {}", s))
+    }
+}
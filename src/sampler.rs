@@ -0,0 +1,324 @@
+use crate::math::*;
+use crate::bluenoise::*;
+use std::str::FromStr;
+
+// Where the random numbers for camera rays and scattering come from
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SamplerKind {
+    Random, // Independent uniform random numbers
+    Sobol,  // Owen scrambled Sobol points, which cover each pixel more evenly at low sample counts
+    Halton, // Halton points shifted by a random offset per pixel
+    BlueNoise // Sobol points shifted by a blue noise offset per pixel, for fine grained noise at low sample counts
+}
+
+impl SamplerKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SamplerKind::Random => "random",
+            SamplerKind::Sobol => "sobol",
+            SamplerKind::Halton => "halton",
+            SamplerKind::BlueNoise => "bluenoise"
+        }
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SamplerKind, String> {
+        match s {
+            "random" => Ok(SamplerKind::Random),
+            "sobol" => Ok(SamplerKind::Sobol),
+            "halton" => Ok(SamplerKind::Halton),
+            "bluenoise" => Ok(SamplerKind::BlueNoise),
+            _ => Err(format!("Unknown sampler {}", s))
+        }
+    }
+}
+
+impl SamplerKind {
+    // Samplers with the same seed hand out the same numbers, see frame_seed
+    pub fn create(&self, seed: u32) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Random => Box::new(RandomSampler),
+            SamplerKind::Sobol => Box::new(SobolSampler { seed, ..SobolSampler::default() }),
+            SamplerKind::Halton => Box::new(HaltonSampler { seed, ..HaltonSampler::default() }),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler { seed, ..BlueNoiseSampler::default() })
+        }
+    }
+}
+
+// Seed of the random numbers of one frame. Each frame gets numbers of its own, but the same ones every
+// time it is rendered with the same global seed.
+pub fn frame_seed(seed: u32, frame: usize) -> u32 {
+    hash(seed ^ hash(frame as u32))
+}
+
+// Seed of the random number stream of the calling thread for one sample of a pixel, see seed_random
+pub fn sample_seed(seed: u32, x: usize, y: usize, sample_index: usize) -> u64 {
+    ((pixel_hash(x, y, seed) as u64) << 32) | hash(sample_index as u32) as u64
+}
+
+// Hands out the numbers for one sample of a pixel, one dimension after another. The camera takes the
+// first ones for the position in the pixel, the lens and the time, then each bounce takes what its
+// material needs to scatter.
+pub trait Sampler {
+    // Pixels are given in image coordinates from the top left, so a cropped render gets the same
+    // samples as the same region of a full one
+    fn start_pixel(&mut self, x: usize, y: usize);
+
+    // Samples of a pixel are numbered from 0, and low discrepancy samplers spread the first 2^n of them
+    // evenly. Starts over at the first dimension.
+    fn start_sample(&mut self, sample_index: usize);
+
+    fn next_1d(&mut self) -> f64;
+    fn next_2d(&mut self) -> (f64, f64);
+
+    // Uniformly distributed on the unit sphere
+    fn unit_vector(&mut self) -> Vector3 {
+        let (u, v) = self.next_2d();
+        let z = 1.0 - 2.0 * u;
+        let r = f64::max(1.0 - z * z, 0.0).sqrt();
+        let phi = 2.0 * PI * v;
+
+        Vector3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    // Uniformly distributed inside the unit sphere
+    fn in_unit_sphere(&mut self) -> Vector3 {
+        let direction = self.unit_vector();
+        direction * self.next_1d().cbrt()
+    }
+
+    // Uniformly distributed inside the unit disk in the xy plane. The concentric mapping keeps nearby
+    // points together, so the even spread of the samples carries over to the disk.
+    fn in_unit_disk(&mut self) -> Vector3 {
+        let (u, v) = self.next_2d();
+        let a = 2.0 * u - 1.0;
+        let b = 2.0 * v - 1.0;
+
+        if a == 0.0 && b == 0.0 {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+
+        let (r, phi) = if a.abs() > b.abs() {
+            (a, 0.25 * PI * (b / a))
+        } else {
+            (b, 0.5 * PI - 0.25 * PI * (a / b))
+        };
+
+        Vector3::new(r * phi.cos(), r * phi.sin(), 0.0)
+    }
+}
+
+// Draws from the random number stream of the thread, which the renderer seeds for every sample
+pub struct RandomSampler;
+
+impl Sampler for RandomSampler {
+    fn start_pixel(&mut self, _x: usize, _y: usize) {}
+
+    fn start_sample(&mut self, _sample_index: usize) {}
+
+    fn next_1d(&mut self) -> f64 {
+        random_double()
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        (random_double(), random_double())
+    }
+}
+
+// Every dimension is a two dimensional Sobol sequence of its own, decorrelated from the others by
+// shuffling and scrambling with a different seed. See Burley, Practical Hash-based Owen Scrambling.
+#[derive(Default)]
+pub struct SobolSampler {
+    seed: u32,
+    pixel_seed: u32,
+    sample_index: u32,
+    dimension: u32
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel(&mut self, x: usize, y: usize) {
+        self.pixel_seed = pixel_hash(x, y, self.seed);
+    }
+
+    fn start_sample(&mut self, sample_index: usize) {
+        self.sample_index = sample_index as u32;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        self.next_2d().0
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let seed = hash(self.pixel_seed ^ hash(self.dimension));
+        self.dimension += 1;
+        scrambled_sobol(self.sample_index, seed)
+    }
+}
+
+// Each dimension uses the next prime as its base. Every pixel shifts the points by its own random
+// offset per dimension (a Cranley-Patterson rotation), since the same points in every pixel would
+// show up as patterns in the image. Deep bounces beyond the table of bases get random numbers.
+#[derive(Default)]
+pub struct HaltonSampler {
+    seed: u32,
+    pixel_seed: u32,
+    sample_index: u32,
+    dimension: u32
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel(&mut self, x: usize, y: usize) {
+        self.pixel_seed = pixel_hash(x, y, self.seed);
+    }
+
+    fn start_sample(&mut self, sample_index: usize) {
+        self.sample_index = sample_index as u32;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let dimension = self.dimension as usize;
+        self.dimension += 1;
+
+        match HALTON_BASES.get(dimension) {
+            Some(base) => {
+                let offset = to_unit_float(hash(self.pixel_seed ^ hash(dimension as u32)));
+                let value = radical_inverse(self.sample_index, *base) + offset;
+                if value >= 1.0 { value - 1.0 } else { value }
+            },
+            None => random_double()
+        }
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.next_1d(), self.next_1d())
+    }
+}
+
+// Every pixel walks the same Sobol points, shifted by a value from a blue noise mask (Georgiev and
+// Fajardo, Blue-noise Dithered Sampling). Neighboring pixels then get very different samples, which
+// turns the error at low sample counts into fine grain. Each dimension and component reads the mask
+// at a different offset, so they are not correlated.
+#[derive(Default)]
+pub struct BlueNoiseSampler {
+    seed: u32,
+    pixel_x: usize,
+    pixel_y: usize,
+    sample_index: u32,
+    dimension: u32
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_pixel(&mut self, x: usize, y: usize) {
+        self.pixel_x = x;
+        self.pixel_y = y;
+    }
+
+    fn start_sample(&mut self, sample_index: usize) {
+        self.sample_index = sample_index as u32;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        self.next_2d().0
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let seed = hash(self.dimension ^ hash(self.seed ^ 0x5bd1_e995));
+        self.dimension += 1;
+
+        let (x, y) = scrambled_sobol(self.sample_index, seed);
+        let shift = hash(seed) as usize;
+        let offset_x = blue_noise(self.pixel_x + shift % MASK_SIZE, self.pixel_y + (shift >> 8) % MASK_SIZE);
+        let offset_y = blue_noise(self.pixel_x + (shift >> 16) % MASK_SIZE, self.pixel_y + (shift >> 24) % MASK_SIZE);
+
+        ((x + offset_x).fract(), (y + offset_y).fract())
+    }
+}
+
+fn pixel_hash(x: usize, y: usize, seed: u32) -> u32 {
+    hash(hash(hash(x as u32) ^ y as u32) ^ seed)
+}
+
+// Point of a two dimensional Sobol sequence, with the order of the points shuffled and their
+// coordinates Owen scrambled by the seed
+fn scrambled_sobol(sample_index: u32, seed: u32) -> (f64, f64) {
+    let index = nested_uniform_scramble(sample_index, seed);
+    let x = nested_uniform_scramble(sobol_0(index), hash(seed ^ 0x9e37_79b9));
+    let y = nested_uniform_scramble(sobol_1(index), hash(seed ^ 0x7f4a_7c15));
+
+    (to_unit_float(x), to_unit_float(y))
+}
+
+// Points in higher Halton dimensions only fill the square well at sample counts far beyond what a
+// pixel gets, so there is little point in going further
+const HALTON_BASES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131
+];
+
+// Mirrors the digits of the index in the given base around the decimal point
+fn radical_inverse(mut index: u32, base: u32) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut result = 0.0;
+    let mut digit_weight = inverse_base;
+    while index > 0 {
+        result += (index % base) as f64 * digit_weight;
+        index /= base;
+        digit_weight *= inverse_base;
+    }
+
+    result
+}
+
+// First two dimensions of the Sobol sequence as 32 bit fractions. Together they form a (0, 2) sequence,
+// so every power of two prefix puts exactly one point in each cell of any dyadic grid with that many cells.
+fn sobol_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+fn sobol_1(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 1 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+
+    result
+}
+
+// Owen scrambling in base 2, which randomizes the points while keeping their stratification
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+// Integer hash with good avalanche, from Chris Wellons' hash prospector
+pub(crate) fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+fn to_unit_float(x: u32) -> f64 {
+    x as f64 / 4_294_967_296.0
+}
@@ -0,0 +1,1824 @@
+use crate::math::*;
+use crate::ray::*;
+use crate::hittable::*;
+use crate::bvh::*;
+use crate::material::*;
+use crate::texture::*;
+use crate::perlin::*;
+use crate::light::*;
+use crate::environment::*;
+use crate::thinfilm::*;
+use crate::fresnel::*;
+use crate::microfacet::*;
+use crate::volume::*;
+use crate::mesh::*;
+use crate::obj::*;
+use crate::stl::*;
+use crate::ply::*;
+use crate::fog::*;
+use crate::sampler::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Default)]
+pub struct World {
+    pub materials: Vec<Material>,
+    pub hittables: Vec<Hittable>,
+    pub lights: Vec<usize>, // Indices of the hittables added with add_light, which rays can be aimed at directly
+    pub light_anchors: Vec<Point3>, // Center of each light over the shutter, which equiangular sampling in fog aims at, set by build_bvh
+    pub analytic_lights: Vec<Light>, // Lights without geometry, see Light
+    pub environment: Option<Environment>, // Seen by rays leaving the scene instead of the background color
+    pub fog: Option<Fog>, // Fills all the space around the objects, see set_fog
+    bvh: Bvh
+}
+
+// Object id of hits in the fog, which is not any of the hittables and so never a light
+pub const FOG_OBJECT_ID: usize = usize::MAX;
+
+impl World {
+    pub fn new() -> World {
+        World::default()
+    }
+
+    // Has to be called once all the hittables are added, rays only see what the BVH contains
+    pub fn build_bvh(&mut self, time_0: f64, time_1: f64) {
+        self.bvh = Bvh::build(&self.hittables, time_0, time_1);
+        trace!("Built top level BVH over {} objects with {} nodes and a depth of {}", self.hittables.len(), self.bvh.node_count(), self.bvh.depth());
+
+        self.light_anchors = self.lights.iter()
+            .map(|&light| self.hittables[light].bounding_box(time_0, time_1).map_or(Point3::new(0.0, 0.0, 0.0), |aabb| aabb.centroid()))
+            .collect();
+    }
+
+    // The closest hit, which with fog may be a point where the ray scatters in it before getting to any object
+    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let rec = self.bvh.hit(ray, t_min, t_max);
+
+        let (fog, distance) = match &self.fog {
+            Some(fog) => match fog.sample_distance(ray, t_min, random_double()) {
+                Some(distance) => (fog, distance),
+                None => return rec
+            },
+            None => return rec
+        };
+
+        let t = t_min + distance;
+        if t >= rec.as_ref().map_or(t_max, |rec| rec.t) {
+            return rec;
+        }
+
+        Some(Self::fog_record(fog, ray, t))
+    }
+
+    // The closest hit on an object, looking through the fog
+    pub fn hit_objects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.bvh.hit(ray, t_min, t_max)
+    }
+
+    // Hit record for scattering in the fog at t along the ray
+    pub fn fog_record(fog: &Fog, ray: &Ray, t: f64) -> HitRecord {
+        let mut rec = HitRecord::new();
+        rec.t = t;
+        rec.point = ray.at(t);
+        rec.normal = Vector3::new(1.0, 0.0, 0.0);
+        rec.tangent = Vector3::new(0.0, 1.0, 0.0);
+        rec.bitangent = Vector3::new(0.0, 0.0, 1.0);
+        rec.front_face = true;
+        rec.mat_handle = fog.phase_function;
+        rec.object_id = FOG_OBJECT_ID;
+
+        rec
+    }
+
+    // Adds an emitter that is also sampled directly, which makes it far less noisy to light a scene with
+    // than an emitter that is only found by chance. It has to be a shape that can be sampled.
+    pub fn add_light(&mut self, hittable: Hittable) {
+        assert!(hittable.can_sample(), "Lights have to be spheres, rects, triangles or disks");
+        self.lights.push(self.hittables.len());
+        self.hittables.push(hittable);
+    }
+
+    // Loads a Wavefront OBJ model with its vertices scaled, adding its materials to the world, and gives
+    // it back as one hittable to place in the scene, e.g. with Hittable::new_instance. Faces without a
+    // material, or one its MTL files do not have, get the default material.
+    pub fn load_obj(&mut self, path: &str, scale: f64, default_material: MaterialHandle) -> Result<Hittable, String> {
+        let model = ObjModel::load(path)?;
+
+        let mut materials = HashMap::new();
+        for material in &model.materials {
+            materials.insert(material.name.as_str(), self.obj_material(material));
+        }
+
+        let mut meshes = Vec::with_capacity(model.groups.len());
+        for group in model.groups {
+            let mat_handle = match &group.material {
+                Some(name) => match materials.get(name.as_str()) {
+                    Some(mat_handle) => *mat_handle,
+                    None => {
+                        error!("Model {} uses material {} which it does not define", path, name);
+                        default_material
+                    }
+                },
+                None => default_material
+            };
+
+            let positions: Vec<Point3> = group.positions.iter().map(|position| scale * *position).collect();
+            let normals = match group.normals {
+                Some(normals) => Some(normals),
+                None if group.smooth => Some(Mesh::smooth_normals(&positions, &group.indices)?),
+                None => None
+            };
+
+            meshes.push(Hittable::new_mesh(Mesh::new(positions, normals, group.uvs, group.indices, mat_handle)?));
+        }
+
+        if meshes.len() == 1 {
+            Ok(meshes.pop().unwrap())
+        } else {
+            Ok(Hittable::new_bvh(&meshes, 0.0, 1.0))
+        }
+    }
+
+    // The closest material of the renderer to one of an MTL file: glass for the illumination models
+    // with refraction or when it is see through, a light when it emits, and otherwise the Phong model
+    // the format is made for
+    fn obj_material(&mut self, material: &ObjMaterial) -> MaterialHandle {
+        if material.emission.length_squared() > 0.0 {
+            return self.diffuse_light(Texture::SolidColor(material.emission));
+        }
+
+        if material.dissolve < 1.0 || matches!(material.illum, 4 | 6 | 7 | 9) {
+            let ir = if material.ir > 1.0 { material.ir } else { 1.5 };
+            return self.dielectric(ir);
+        }
+
+        let diffuse = match &material.diffuse_map {
+            Some(map) => Texture::load_image(map),
+            None => Texture::SolidColor(material.diffuse)
+        };
+
+        let amount = clamp(material.specular.x.max(material.specular.y).max(material.specular.z), 0.0, 1.0);
+        if amount > 0.0 && material.shininess > 0.0 {
+            self.blinn_phong(diffuse, material.specular / amount, material.shininess, amount)
+        } else {
+            self.lambertian(diffuse)
+        }
+    }
+
+    pub fn add_point_light(&mut self, position: Point3, intensity: Color) {
+        self.analytic_lights.push(Light::Point { position, intensity });
+    }
+
+    // Spot light at position pointing at target, with cone angles in degrees, see Light::Spot
+    pub fn add_spot_light(&mut self, position: Point3, target: Point3, inner_angle: f64, outer_angle: f64, intensity: Color) {
+        self.analytic_lights.push(Light::new_spot(position, target, inner_angle, outer_angle, intensity));
+    }
+
+    // Sun in the given direction, with its angular diameter in degrees, see Light::Sun
+    pub fn add_sun(&mut self, direction: Vector3, angular_diameter: f64, irradiance: Color) {
+        self.analytic_lights.push(Light::new_sun(direction, angular_diameter, irradiance));
+    }
+
+    // Whether anything blocks a shadow ray before it gets to t_max, like a light at that distance
+    pub fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        self.hit(ray, 0.001, t_max).is_some()
+    }
+
+    // Fills the scene with fog of the color, see Fog, scattering forwards for g above 0. Every ray goes
+    // through it, including shadow rays and rays that would leave the scene, so the background and far
+    // away objects fade into it and lights dim with distance. Without a finite scale height it swallows
+    // all the light from the sky and from suns, and rays that leave the scene keep scattering until the
+    // bounce limit.
+    pub fn set_fog(&mut self, params: &FogParams, color: Color, g: f64) {
+        let phase_function = self.henyey_greenstein(Texture::SolidColor(color), g);
+        self.fog = Some(Fog { density: params.density, scale_height: params.scale_height, phase_function });
+    }
+
+    // The material to shade a hit with, choosing a side of mixed and coated materials at random. Bends
+    // the normal of the hit record for normal mapped materials.
+    pub fn resolve_material(&self, ray: &Ray, rec: &mut HitRecord, sampler: &mut dyn Sampler) -> &Material {
+        let mut material = &self.materials[rec.mat_handle.0 - 1];
+        loop {
+            let handle = match material {
+                Material::Mix { a, b, factor } => {
+                    if sampler.next_1d() < factor.get_gray_value(rec.u, rec.v, &rec.point) { b } else { a }
+                },
+                Material::Sheen { color, roughness, base: Some(base) } => {
+                    let color = color.get_color_value(rec.u, rec.v, &rec.point);
+                    if sampler.next_1d() < Material::sheen_weight(&color, *roughness, ray, rec) { return material } else { base }
+                },
+                Material::Coated { base, coat, ir } => {
+                    let cos_theta = f64::min(Vector3::dot(&-Vector3::normalize(&ray.direction), &rec.normal), 1.0);
+                    if sampler.next_1d() < Material::reflectance(cos_theta, *ir) { coat } else { base }
+                },
+                Material::NormalMapped { base, normal_map } => {
+                    let texel = normal_map.get_color_value(rec.u, rec.v, &rec.point);
+                    let normal = (2.0 * texel.x - 1.0) * rec.tangent + (2.0 * texel.y - 1.0) * rec.bitangent + (2.0 * texel.z - 1.0) * rec.normal;
+
+                    // A normal facing away from the ray would turn the surface inside out
+                    if Vector3::dot(&normal, &rec.normal) > 0.0 {
+                        rec.normal = Vector3::normalize(&normal);
+                    }
+                    base
+                },
+                Material::Bumped { base, height, scale } => {
+                    // Slopes by finite differences along the tangents, stepping both the texture coordinates
+                    // and the point so that image and solid textures work alike
+                    const STEP: f64 = 1.0e-3;
+                    let center = height.get_gray_value(rec.u, rec.v, &rec.point);
+                    let slope_u = (height.get_gray_value(rec.u + STEP, rec.v, &(rec.point + STEP * rec.tangent)) - center) / STEP;
+                    let slope_v = (height.get_gray_value(rec.u, rec.v + STEP, &(rec.point + STEP * rec.bitangent)) - center) / STEP;
+
+                    let normal = rec.normal - *scale * (slope_u * rec.tangent + slope_v * rec.bitangent);
+                    rec.normal = Vector3::normalize(&normal);
+                    base
+                },
+                _ => return material
+            };
+
+            material = &self.materials[handle.0 - 1];
+        }
+    }
+
+    // Whether the top level hittable with this index is sampled directly as a light
+    pub fn is_light(&self, index: usize) -> bool {
+        self.lights.contains(&index)
+    }
+
+    pub fn add<T: Into<Material>>(&mut self, material: T) -> MaterialHandle {
+        self.materials.push(material.into());
+        MaterialHandle(self.materials.len())
+    }
+
+    pub fn lambertian(&mut self, albedo: Texture) -> MaterialHandle {
+        self.add(Material::Lambertian { albedo })
+    }
+
+    // Sigma is the spread of the surface slopes in degrees, 0 being the same as Lambertian
+    pub fn oren_nayar(&mut self, albedo: Texture, sigma: f64) -> MaterialHandle {
+        self.add(Material::OrenNayar { albedo, sigma })
+    }
+
+    pub fn metal(&mut self, albedo: Color, roughness: f64) -> MaterialHandle {
+        self.textured_metal(Texture::SolidColor(albedo), Texture::solid(roughness, roughness, roughness))
+    }
+
+    // Metal whose color and roughness vary over the surface, the roughness from a grayscale texture
+    pub fn textured_metal(&mut self, albedo: Texture, roughness: Texture) -> MaterialHandle {
+        self.add(Material::Metal { albedo, roughness, film: None, conductor: None })
+    }
+
+    pub fn dielectric(&mut self, ir: f64) -> MaterialHandle {
+        self.add(Material::Dielectric { ir: Texture::solid(ir, ir, ir), absorption: Color::new(0.0, 0.0, 0.0), film: None, priority: 0, abbe: INFINITY })
+    }
+
+    // Colored glass, the color being what white light turns into after going through a length of
+    // 1 / density of it
+    pub fn tinted_dielectric(&mut self, ir: f64, color: Color, density: f64) -> MaterialHandle {
+        let absorption = |c: f64| -f64::max(c, 1.0e-4).ln() * density;
+        let absorption = Color::new(absorption(color.x), absorption(color.y), absorption(color.z));
+        self.add(Material::Dielectric { ir: Texture::solid(ir, ir, ir), absorption, film: None, priority: 0, abbe: INFINITY })
+    }
+
+    // Glass whose index of refraction varies over the surface, e.g. a Texture::Range from 1.3 to 1.8
+    pub fn textured_dielectric(&mut self, ir: Texture) -> MaterialHandle {
+        self.add(Material::Dielectric { ir, absorption: Color::new(0.0, 0.0, 0.0), film: None, priority: 0, abbe: INFINITY })
+    }
+
+    // The metallic and roughness workflow of texture sets: diffuse where the metallic map is black and
+    // metal of the base color where it is white, with the roughness from a grayscale map
+    pub fn metallic_roughness(&mut self, base_color: Texture, metallic: Texture, roughness: Texture) -> MaterialHandle {
+        let diffuse = self.lambertian(base_color.clone());
+        let metal = self.textured_metal(base_color, roughness);
+        self.mix(diffuse, metal, metallic)
+    }
+
+    // The principled material of glTF and Blender in the terms of the materials here: where it is not
+    // metal, a diffuse base color under a coat reflecting as a dielectric of index of refraction ir, and
+    // where it is, metal of the base color. Both are as rough as the grayscale roughness texture.
+    pub fn principled(&mut self, base_color: Texture, metallic: Texture, roughness: Texture, ir: f64) -> MaterialHandle {
+        let diffuse = self.lambertian(base_color.clone());
+        let coat = self.textured_metal(Texture::solid(1.0, 1.0, 1.0), roughness.clone());
+        let dielectric = self.add(Material::Coated { base: diffuse, coat, ir });
+        let metal = self.textured_metal(base_color, roughness);
+        self.mix(dielectric, metal, metallic)
+    }
+
+    // Shades hits with b where the factor is white and a where it is black, and picks between them
+    // in between
+    pub fn mix(&mut self, a: MaterialHandle, b: MaterialHandle, factor: Texture) -> MaterialHandle {
+        self.add(Material::Mix { a, b, factor })
+    }
+
+    // Metal of measured optical constants, like Conductor::gold()
+    pub fn conductor(&mut self, conductor: Conductor, roughness: f64) -> MaterialHandle {
+        let albedo = Texture::SolidColor(conductor.reflectance(1.0));
+        self.add(Material::Metal { albedo, roughness: Texture::solid(roughness, roughness, roughness), film: None, conductor: Some(conductor) })
+    }
+
+    // Brushed along the tangent, with the roughness along the brushing and across it
+    pub fn anisotropic_metal(&mut self, albedo: Color, roughness_along: f64, roughness_across: f64, tangent: Vector3) -> MaterialHandle {
+        self.add(Material::AnisotropicMetal { albedo, roughness_u: roughness_along, roughness_v: roughness_across, tangent })
+    }
+
+    // Coats a metal or dielectric with a thin film of the given thickness in nanometers and index of
+    // refraction, for soap bubbles and oil slicks
+    pub fn add_thin_film(&mut self, material: MaterialHandle, thickness: f64, ir: f64) {
+        match &mut self.materials[material.0 - 1] {
+            Material::Metal { film, .. } | Material::Dielectric { film, .. } => *film = Some(ThinFilm { thickness, ir }),
+            _ => panic!("Thin films only go on metal and dielectric materials")
+        }
+    }
+
+    // Lets a dielectric fill where it overlaps others of lower priority, like water poured into a glass
+    // modeled as overlapping it slightly. Dielectrics start at priority 0.
+    pub fn set_priority(&mut self, material: MaterialHandle, priority: i32) {
+        match &mut self.materials[material.0 - 1] {
+            Material::Dielectric { priority: p, .. } => *p = priority,
+            _ => panic!("Only dielectric materials have priorities")
+        }
+    }
+
+    // Makes a dielectric split white light into its colors, the more the lower the Abbe number. Crown
+    // glass is around 60, flint glass 30 to 40 and diamond 55 but with a much higher ir. Only one color
+    // makes it through each path, so it takes more samples to converge, and --spectral for clean rainbows.
+    pub fn set_dispersion(&mut self, material: MaterialHandle, abbe: f64) {
+        match &mut self.materials[material.0 - 1] {
+            Material::Dielectric { abbe: a, .. } => *a = abbe,
+            _ => panic!("Only dielectric materials disperse light")
+        }
+    }
+
+    pub fn sheen(&mut self, color: Texture, roughness: f64) -> MaterialHandle {
+        self.add(Material::Sheen { color, roughness, base: None })
+    }
+
+    // Sheen over a base material like velvet over a diffuse color, see Material::Sheen
+    pub fn sheen_over(&mut self, base: MaterialHandle, color: Texture, roughness: f64) -> MaterialHandle {
+        self.add(Material::Sheen { color, roughness, base: Some(base) })
+    }
+
+    // Plastic of the classic Phong style, a diffuse color with a highlight of the specular color on top
+    // that takes the given share of the light
+    pub fn blinn_phong(&mut self, diffuse: Texture, specular: Color, exponent: f64, amount: f64) -> MaterialHandle {
+        let base = self.lambertian(diffuse);
+        let highlight = self.add(Material::BlinnPhong { specular: Texture::SolidColor(specular), exponent });
+        self.mix(base, highlight, Texture::solid(amount, amount, amount))
+    }
+
+    // Phase function for a constant medium, scattering forwards for g above 0 and backwards below it
+    pub fn henyey_greenstein(&mut self, albedo: Texture, g: f64) -> MaterialHandle {
+        self.add(Material::HenyeyGreenstein { albedo, g })
+    }
+
+    // Adds the detail of a tangent space normal map to the base material
+    pub fn normal_mapped(&mut self, base: MaterialHandle, normal_map: Texture) -> MaterialHandle {
+        self.add(Material::NormalMapped { base, normal_map })
+    }
+
+    // Adds relief from the brightness of a height texture to the base material, with the scale being how
+    // high white is raised above black
+    pub fn bumped(&mut self, base: MaterialHandle, height: Texture, scale: f64) -> MaterialHandle {
+        self.add(Material::Bumped { base, height, scale })
+    }
+
+    // Clear coat over the base material, glossy with a roughness above 0
+    pub fn clearcoat(&mut self, base: MaterialHandle, ir: f64, roughness: f64) -> MaterialHandle {
+        let coat = self.metal(Color::new(1.0, 1.0, 1.0), roughness);
+        self.add(Material::Coated { base, coat, ir })
+    }
+
+    // Emits from both sides of a surface
+    pub fn diffuse_light(&mut self, emit: Texture) -> MaterialHandle {
+        self.add(Material::DiffuseLight { emit, intensity: 1.0, two_sided: true })
+    }
+
+    // Emits from the front face only, the outside of spheres and the side rects face along their axis.
+    // Hittable::FlipFace turns a light around.
+    pub fn one_sided_light(&mut self, emit: Texture) -> MaterialHandle {
+        self.textured_light(emit, 1.0)
+    }
+
+    // Emits an image or procedural texture from the front face, made brighter by the intensity, like a
+    // screen or a lamp shade
+    pub fn textured_light(&mut self, emit: Texture, intensity: f64) -> MaterialHandle {
+        self.add(Material::DiffuseLight { emit, intensity, two_sided: false })
+    }
+}
+
+fn two_spheres_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -10.0, 0.0), radius: 10.0 });
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, 10.0, 0.0), radius: 10.0 });
+
+    world
+}
+
+fn two_perlin_spheres_world() -> World {
+    let mut world = World::new();
+
+    // The marble veins are raised a little as well
+    let noise = Texture::Noise(Perlin::new(), 4.0);
+    let marble = world.lambertian(noise.clone());
+    let ground_material = world.bumped(marble, noise, 0.02);
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, 2.0, 0.0), radius: 2.0 });
+
+    world
+}
+
+fn earth_world() -> World {
+    let mut world = World::new();
+
+    let earth_texture = Texture::load_image("textures/earthmap.jpg");
+    let earth_surface = world.lambertian(earth_texture);
+    // Relief from a normal map made out of the brightness of the earth map
+    let earth_material = world.normal_mapped(earth_surface, Texture::load_image("textures/earthnormal.png"));
+    world.hittables.push(Hittable::Sphere { mat_handle: earth_material, center: Point3::new(0.0, 0.0, 0.0), radius: 2.0 });
+
+    world
+}
+
+fn simple_light_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Noise(Perlin::new(), 4.0));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, 2.0, 0.0), radius: 2.0 });
+
+    let diff_light = world.diffuse_light(Texture::solid(4.0, 4.0, 4.0));
+    world.add_light(Hittable::XYRect { mat_handle: diff_light, x0: 3.0, x1: 5.0, y0: 1.0, y1: 3.0, k: -2.0 });
+
+    world
+}
+
+// The simple light scene lit by a point light instead of the rect, which leaves no noise from lighting
+fn point_light_world() -> World {
+    let mut world = World::new();
+
+    let noise = Texture::Noise(Perlin::new(), 4.0);
+    let marble = world.lambertian(noise.clone());
+    let ground_material = world.bumped(marble, noise, 0.02);
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, 2.0, 0.0), radius: 2.0 });
+
+    world.add_point_light(Point3::new(2.0, 7.0, 4.0), Color::new(150.0, 150.0, 150.0));
+
+    world
+}
+
+fn cornell_box_world() -> World {
+    cornell_box_world_with(World::lambertian)
+}
+
+// The Cornell box filled with thin fog, where the light falls through in a cone and the blocks cast
+// shadows through the air
+fn cornell_box_fog_world() -> World {
+    let mut world = cornell_box_world();
+    world.set_fog(&FogParams { density: 0.0005, scale_height: INFINITY }, Color::new(1.0, 1.0, 1.0), 0.0);
+    world
+}
+
+// The Cornell box with matte clay walls and boxes
+fn cornell_box_clay_world() -> World {
+    cornell_box_world_with(|world, albedo| world.oren_nayar(albedo, 30.0))
+}
+
+// The diffuse material of the walls and boxes is made by the given function from their color
+fn cornell_box_world_with(diffuse: impl Fn(&mut World, Texture) -> MaterialHandle) -> World {
+    let mut world = World::new();
+
+    let red = diffuse(&mut world, Texture::solid(0.65, 0.05, 0.05));
+    let white = diffuse(&mut world, Texture::solid(0.73, 0.73, 0.73));
+    let green = diffuse(&mut world, Texture::solid(0.12, 0.45, 0.15));
+    let light = world.one_sided_light(Texture::solid(15.0, 15.0, 15.0));
+
+    world.hittables.push(Hittable::YZRect { mat_handle: green, y0: 0.0,     y1: 555.0, z0: 0.0,     z1: 555.0, k: 555.0 });
+    world.hittables.push(Hittable::YZRect { mat_handle: red,   y0: 0.0,     y1: 555.0, z0: 0.0,     z1: 555.0, k: 0.0 });
+    // Shining down into the box, not up at the ceiling
+    world.add_light(Hittable::new_flip_face(Hittable::XZRect { mat_handle: light, x0: 213.0,   x1: 343.0, z0: 227.0,   z1: 332.0, k: 554.0 }));
+    world.hittables.push(Hittable::XZRect { mat_handle: white, x0: 0.0,     x1: 555.0, z0: 0.0,     z1: 555.0, k: 0.0 });
+    world.hittables.push(Hittable::XZRect { mat_handle: white, x0: 0.0,     x1: 555.0, z0: 0.0,     z1: 555.0, k: 555.0 });
+    world.hittables.push(Hittable::XYRect { mat_handle: white, x0: 0.0,     x1: 555.0, y0: 0.0,     y1: 555.0, k: 555.0 });
+
+    let box1 = Hittable::new_box(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 330.0, 165.0), white);
+    let box1 = Hittable::new_rotate_y(15.0, box1);
+    let box1 = Hittable::Translate { offset: Vector3::new(265.0, 0.0, 295.0), ptr: Box::new(box1) };
+    world.hittables.push(box1);
+
+    let box2 = Hittable::new_box(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 165.0, 165.0), white);
+    let box2 = Hittable::new_rotate_y(-18.0, box2);
+    let box2 = Hittable::Translate { offset: Vector3::new(130.0, 0.0, 65.0), ptr: Box::new(box2) };
+    world.hittables.push(box2);
+
+    world
+}
+
+// The Cornell box lit by a spot light from the front top corner instead of the ceiling light, like a
+// product shot of the tall box
+fn cornell_box_spot_world() -> World {
+    let mut world = World::new();
+
+    let red = world.lambertian(Texture::solid(0.65, 0.05, 0.05));
+    let white = world.lambertian(Texture::solid(0.73, 0.73, 0.73));
+    let green = world.lambertian(Texture::solid(0.12, 0.45, 0.15));
+
+    world.hittables.push(Hittable::YZRect { mat_handle: green, y0: 0.0,     y1: 555.0, z0: 0.0,     z1: 555.0, k: 555.0 });
+    world.hittables.push(Hittable::YZRect { mat_handle: red,   y0: 0.0,     y1: 555.0, z0: 0.0,     z1: 555.0, k: 0.0 });
+    world.hittables.push(Hittable::XZRect { mat_handle: white, x0: 0.0,     x1: 555.0, z0: 0.0,     z1: 555.0, k: 0.0 });
+    world.hittables.push(Hittable::XZRect { mat_handle: white, x0: 0.0,     x1: 555.0, z0: 0.0,     z1: 555.0, k: 555.0 });
+    world.hittables.push(Hittable::XYRect { mat_handle: white, x0: 0.0,     x1: 555.0, y0: 0.0,     y1: 555.0, k: 555.0 });
+
+    let box1 = Hittable::new_box(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 330.0, 165.0), white);
+    let box1 = Hittable::new_rotate_y(15.0, box1);
+    let box1 = Hittable::Translate { offset: Vector3::new(265.0, 0.0, 295.0), ptr: Box::new(box1) };
+    world.hittables.push(box1);
+
+    let box2 = Hittable::new_box(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 165.0, 165.0), white);
+    let box2 = Hittable::new_rotate_y(-18.0, box2);
+    let box2 = Hittable::Translate { offset: Vector3::new(130.0, 0.0, 65.0), ptr: Box::new(box2) };
+    world.hittables.push(box2);
+
+    world.add_spot_light(Point3::new(100.0, 500.0, 50.0), Point3::new(350.0, 150.0, 380.0), 15.0, 25.0, Color::new(1.5e6, 1.5e6, 1.5e6));
+
+    world
+}
+
+fn cornell_box_smoke_world() -> World {
+    let mut world = World::new();
+
+    let red = world.lambertian(Texture::solid(0.65, 0.05, 0.05));
+    let white = world.lambertian(Texture::solid(0.73, 0.73, 0.73));
+    let green = world.lambertian(Texture::solid(0.12, 0.45, 0.15));
+    let light = world.one_sided_light(Texture::solid(7.0, 7.0, 7.0));
+
+    world.hittables.push(Hittable::YZRect { mat_handle: green, y0: 0.0,     y1: 555.0, z0: 0.0,     z1: 555.0, k: 555.0 });
+    world.hittables.push(Hittable::YZRect { mat_handle: red,   y0: 0.0,     y1: 555.0, z0: 0.0,     z1: 555.0, k: 0.0 });
+    world.add_light(Hittable::new_flip_face(Hittable::XZRect { mat_handle: light, x0: 113.0,   x1: 443.0, z0: 127.0,   z1: 432.0, k: 554.0 }));
+    world.hittables.push(Hittable::XZRect { mat_handle: white, x0: 0.0,     x1: 555.0, z0: 0.0,     z1: 555.0, k: 0.0 });
+    world.hittables.push(Hittable::XZRect { mat_handle: white, x0: 0.0,     x1: 555.0, z0: 0.0,     z1: 555.0, k: 555.0 });
+    world.hittables.push(Hittable::XYRect { mat_handle: white, x0: 0.0,     x1: 555.0, y0: 0.0,     y1: 555.0, k: 555.0 });
+
+    let box1_phase = world.add(Material::Isotropic { albedo: Texture::solid(0.0, 0.0, 0.0) });
+    let box1 = Hittable::new_box(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 330.0, 165.0), white);
+    let box1 = Hittable::new_rotate_y(15.0, box1);
+    let box1 = Hittable::Translate { offset: Vector3::new(265.0, 0.0, 295.0), ptr: Box::new(box1) };
+    let box1 = Hittable::new_constant_medium(box1, 0.01, box1_phase);
+    world.hittables.push(box1);
+    
+    let box2_phase = world.add(Material::Isotropic { albedo: Texture::solid(1.0, 1.0, 1.0) });
+    let box2 = Hittable::new_box(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 165.0, 165.0), white);
+    let box2 = Hittable::new_rotate_y(-18.0, box2);
+    let box2 = Hittable::Translate { offset: Vector3::new(130.0, 0.0, 65.0), ptr: Box::new(box2) };
+    let box2 = Hittable::new_constant_medium(box2, 0.01, box2_phase);
+    world.hittables.push(box2);
+
+    world
+}
+
+fn final_world() -> World {
+    let mut world = World::new();
+
+    let mut boxes1 = Vec::new();
+    let ground = world.lambertian(Texture::solid(0.48, 0.83, 0.53));
+
+    const BOXES_PER_SIDE: usize = 20;
+
+    for i in 0..BOXES_PER_SIDE {
+        for j in 0..BOXES_PER_SIDE {
+            let w = 100.0;
+            let x0 = -1000.0 + i as f64 * w;
+            let z0 = -1000.0 + j as f64 * w;
+            let y0 = 0.0;
+            let x1 = x0 + w;
+            let y1 = random_double_range(1.0, 101.0);
+            let z1 = z0 + w;
+
+            boxes1.push(Hittable::new_box(Point3::new(x0, y0, z0), Point3::new(x1, y1, z1), ground));
+        }
+    }
+
+    world.hittables.push(Hittable::new_bvh(&boxes1, 0.0, 1.0));
+
+    let light = world.one_sided_light(Texture::solid(7.0, 7.0, 7.0));
+    world.add_light(Hittable::new_flip_face(Hittable::XZRect { mat_handle: light, x0: 123.0, x1: 423.0, z0: 147.0, z1: 412.0, k: 554.0 }));
+
+    let center_1 = Point3::new(400.0, 400.0, 200.0);
+    let center_2 = center_1 + Vector3::new(30.0, 0.0, 0.0);
+    let moving_sphere_material = world.lambertian(Texture::solid(0.7, 0.3, 0.1));
+    world.hittables.push(Hittable::MovingSphere { mat_handle: moving_sphere_material, center_0: center_1, center_1: center_2, time_0: 0.0, time_1: 1.0, radius: 50.0 });
+
+    let dielectric = world.dielectric(1.5);
+    world.hittables.push(Hittable::Sphere { mat_handle: dielectric, center: Point3::new(260.0, 150.0, 45.0), radius: 50.0 });
+
+    let metal = world.metal(Color::new(0.8, 0.8, 0.9), 1.0);
+    world.hittables.push(Hittable::Sphere { mat_handle: metal, center: Point3::new(0.0, 150.0, 145.0), radius: 50.0 });
+
+    let boundary = Hittable::Sphere { mat_handle: dielectric, center: Point3::new(360.0, 150.0, 145.0), radius: 70.0 };
+    world.hittables.push(boundary.clone());
+    let phase = world.add(Material::Isotropic { albedo: Texture::solid(0.2, 0.4, 0.9) });
+    world.hittables.push(Hittable::new_constant_medium(boundary, 0.2, phase));
+
+    let boundary = Hittable::Sphere { mat_handle: dielectric, center: Point3::new(0.0, 0.0, 0.0), radius: 5000.0 };
+    let phase = world.add(Material::Isotropic { albedo: Texture::solid(1.0, 1.0, 1.0) });
+    world.hittables.push(Hittable::new_constant_medium(boundary, 0.0001, phase));
+
+    let emat = world.lambertian(Texture::load_image("textures/earthmap.jpg"));
+    world.hittables.push(Hittable::Sphere { mat_handle: emat, center: Point3::new(400.0, 200.0, 400.0), radius: 100.0 });
+    let pertext = world.lambertian(Texture::Noise(Perlin::new(), 0.1));
+    world.hittables.push(Hittable::Sphere { mat_handle: pertext, center: Point3::new(220.0, 280.0, 300.0), radius: 80.0 });
+
+    let mut boxes2 = Vec::new();
+    let white = world.lambertian(Texture::solid(0.73, 0.73, 0.73));
+    let ns = 1000;
+
+    for _ in 0..ns {
+        boxes2.push(Hittable::Sphere { mat_handle: white, center: Point3::random_range(0.0, 165.0), radius: 10.0 });
+    }
+
+    world.hittables.push(Hittable::new_instance(&Hittable::new_bvh(&boxes2, 0.0, 1.0), 15.0, Vector3::new(-100.0, 270.0, 395.0)));
+
+    world
+}
+
+// A single cluster of spheres with its own BVH, instanced over a grid with different rotations
+fn instances_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let mut spheres = Vec::new();
+    let cluster_materials = [
+        world.lambertian(Texture::solid(0.8, 0.3, 0.2)),
+        world.lambertian(Texture::solid(0.2, 0.5, 0.8)),
+        world.metal(Color::new(0.8, 0.8, 0.7), 0.1)
+    ];
+
+    for i in 0..500 {
+        let center = Point3::random_range(-1.0, 1.0);
+        if center.length() < 1.0 {
+            let center = Point3::new(center.x, center.y + 1.0, center.z);
+            spheres.push(Hittable::Sphere { mat_handle: cluster_materials[i % cluster_materials.len()], center, radius: 0.1 });
+        }
+    }
+
+    let cluster = Hittable::new_bvh(&spheres, 0.0, 1.0);
+    for a in -5..5 {
+        for b in -5..5 {
+            let offset = Vector3::new(2.5 * a as f64, 0.0, 2.5 * b as f64);
+            world.hittables.push(Hittable::new_instance(&cluster, random_double_range(0.0, 360.0), offset));
+        }
+    }
+
+    world
+}
+
+// Spheres of the same colored glass, the tint getting deeper with their size
+fn glass_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let glass = world.tinted_dielectric(1.5, Color::new(0.3, 0.6, 0.9), 1.0);
+    for (x, radius) in [(-3.0, 0.5), (-1.0, 1.0), (2.0, 1.5)] {
+        world.hittables.push(Hittable::Sphere { mat_handle: glass, center: Point3::new(x, radius, 0.0), radius });
+    }
+
+    world
+}
+
+// Overlapping dielectrics: on the left a glass ball filled with water around an air bubble, each
+// overlapping the one around it, and on the right a glass ball half sunk into a block of water
+fn nested_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let glass = world.dielectric(1.5);
+    let water = world.tinted_dielectric(1.33, Color::new(0.4, 0.7, 0.9), 0.5);
+    let air = world.dielectric(1.0);
+    world.set_priority(glass, 1);
+    world.set_priority(water, 2);
+    world.set_priority(air, 3);
+
+    let center = Point3::new(-1.8, 1.2, 0.0);
+    world.hittables.push(Hittable::Sphere { mat_handle: glass, center, radius: 1.2 });
+    world.hittables.push(Hittable::Sphere { mat_handle: water, center, radius: 1.1 });
+    world.hittables.push(Hittable::Sphere { mat_handle: air, center: center + Vector3::new(0.2, 0.3, 0.3), radius: 0.35 });
+
+    // The ball sits over the water, where the block goes through it
+    let sunken_glass = world.dielectric(1.5);
+    let pool = world.tinted_dielectric(1.33, Color::new(0.4, 0.7, 0.9), 0.5);
+    world.set_priority(sunken_glass, 2);
+    world.set_priority(pool, 1);
+    world.hittables.push(Hittable::new_box(Point3::new(0.6, 0.0, -1.5), Point3::new(3.6, 1.0, 1.5), pool));
+    world.hittables.push(Hittable::Sphere { mat_handle: sunken_glass, center: Point3::new(2.1, 1.0, 0.0), radius: 1.0 });
+
+    world
+}
+
+// Balls of crown glass, dense flint glass and diamond in front of thin strips of light, which come
+// out fringed with color through them. Best rendered with --spectral.
+fn dispersion_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.1, 0.1, 0.1), Color::new(0.6, 0.6, 0.6)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let strip = world.diffuse_light(Texture::solid(6.0, 6.0, 6.0));
+    for i in -6..=6 {
+        let x = i as f64 * 0.8;
+        world.hittables.push(Hittable::XYRect { mat_handle: strip, x0: x - 0.06, x1: x + 0.06, y0: 0.0, y1: 5.0, k: -6.0 });
+    }
+
+    for (x, ir, abbe) in [(-2.6, 1.52, 60.0), (0.0, 1.75, 25.0), (2.6, 2.42, 55.0)] {
+        let glass = world.dielectric(ir);
+        world.set_dispersion(glass, abbe);
+        world.hittables.push(Hittable::Sphere { mat_handle: glass, center: Point3::new(x, 1.1, 0.0), radius: 1.1 });
+    }
+
+    world
+}
+
+// Mixed materials: a floor with polished tiles between dull ones, and a painted metal sphere that
+// rusted in patches
+fn mix_world() -> World {
+    let mut world = World::new();
+
+    let dull = world.lambertian(Texture::solid(0.6, 0.6, 0.6));
+    let polished = world.metal(Color::new(0.7, 0.7, 0.7), 0.05);
+    let floor = world.mix(dull, polished, Texture::Checker(Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0)));
+    world.hittables.push(Hittable::Sphere { mat_handle: floor, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let paint = world.metal(Color::new(0.7, 0.15, 0.1), 0.3);
+    let rust = world.oren_nayar(Texture::solid(0.35, 0.15, 0.05), 40.0);
+    let rusted = world.mix(paint, rust, Texture::Noise(Perlin::new(), 2.0));
+    world.hittables.push(Hittable::Sphere { mat_handle: rusted, center: Point3::new(0.0, 2.0, 0.0), radius: 2.0 });
+
+    world
+}
+
+// Clear coated materials: red car paint over flakes of metal, and a lacquered marble sphere
+fn clearcoat_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let pigment = world.lambertian(Texture::solid(0.6, 0.03, 0.03));
+    let flakes = world.metal(Color::new(0.8, 0.2, 0.2), 0.4);
+    let paint = world.mix(pigment, flakes, Texture::solid(0.3, 0.3, 0.3));
+    let car_paint = world.clearcoat(paint, 1.5, 0.0);
+    world.hittables.push(Hittable::Sphere { mat_handle: car_paint, center: Point3::new(0.0, 1.0, -1.2), radius: 1.0 });
+
+    let marble = world.lambertian(Texture::Noise(Perlin::new(), 4.0));
+    let lacquer = world.clearcoat(marble, 1.5, 0.1);
+    world.hittables.push(Hittable::Sphere { mat_handle: lacquer, center: Point3::new(0.0, 1.0, 1.2), radius: 1.0 });
+
+    world
+}
+
+// A metal globe, its color from the earth map and polished in some places more than others
+fn textured_metal_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let globe = world.textured_metal(Texture::load_image("textures/earthmap.jpg"), Texture::Noise(Perlin::new(), 3.0));
+    world.hittables.push(Hittable::Sphere { mat_handle: globe, center: Point3::new(0.0, 2.0, 0.0), radius: 2.0 });
+
+    world
+}
+
+// One sphere of a texture set with metallic continents and a roughness map, and glass with a varying
+// index of refraction
+fn texture_maps_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let earth = Texture::load_image("textures/earthmap.jpg");
+    let roughness = Texture::Range { texture: Box::new(Texture::Noise(Perlin::new(), 3.0)), low: 0.05, high: 0.4 };
+    let globe = world.metallic_roughness(earth.clone(), earth, roughness);
+    world.hittables.push(Hittable::Sphere { mat_handle: globe, center: Point3::new(0.0, 1.5, -1.6), radius: 1.5 });
+
+    let ir = Texture::Range { texture: Box::new(Texture::Noise(Perlin::new(), 1.0)), low: 1.3, high: 1.8 };
+    let glass = world.textured_dielectric(ir);
+    world.hittables.push(Hittable::Sphere { mat_handle: glass, center: Point3::new(0.0, 1.5, 1.6), radius: 1.5 });
+
+    world
+}
+
+// A dark room lit by a screen showing the earth map, which colors the floor and a sphere in front of it
+fn screen_world() -> World {
+    let mut world = World::new();
+
+    let floor = world.lambertian(Texture::solid(0.5, 0.5, 0.5));
+    world.hittables.push(Hittable::XZRect { mat_handle: floor, x0: -10.0, x1: 10.0, z0: -10.0, z1: 10.0, k: 0.0 });
+
+    let screen = world.textured_light(Texture::load_image("textures/earthmap.jpg"), 2.0);
+    world.add_light(Hittable::XYRect { mat_handle: screen, x0: -2.0, x1: 2.0, y0: 0.5, y1: 2.5, k: -2.0 });
+
+    let sphere = world.lambertian(Texture::solid(0.73, 0.73, 0.73));
+    world.hittables.push(Hittable::Sphere { mat_handle: sphere, center: Point3::new(0.0, 0.6, 0.5), radius: 0.6 });
+
+    world
+}
+
+// Gold, copper, aluminium and silver from their measured complex indices of refraction
+fn metals_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let metals = [Conductor::gold(), Conductor::copper(), Conductor::aluminium(), Conductor::silver()];
+    for (i, conductor) in metals.iter().enumerate() {
+        let material = world.conductor(*conductor, 0.15);
+        world.hittables.push(Hittable::Sphere { mat_handle: material, center: Point3::new(0.0, 0.8, 2.7 - 1.8 * i as f64), radius: 0.8 });
+    }
+
+    world
+}
+
+// A soap bubble and an oily steel ball, colored by the interference in their thin films
+fn thin_film_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    // Air inside a film of soapy water
+    let bubble = world.dielectric(1.0);
+    world.add_thin_film(bubble, 400.0, 1.33);
+    world.hittables.push(Hittable::Sphere { mat_handle: bubble, center: Point3::new(0.0, 1.2, 1.2), radius: 1.0 });
+
+    let steel = world.metal(Color::new(0.55, 0.55, 0.55), 0.05);
+    world.add_thin_film(steel, 320.0, 1.45);
+    world.hittables.push(Hittable::Sphere { mat_handle: steel, center: Point3::new(0.0, 1.0, -1.2), radius: 1.0 });
+
+    world
+}
+
+// Cloth: deep red velvet with a bright rim, and a sphere of nothing but sheen
+fn velvet_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let red = world.lambertian(Texture::solid(0.3, 0.02, 0.04));
+    let velvet = world.sheen_over(red, Texture::solid(1.0, 0.6, 0.7), 0.3);
+    world.hittables.push(Hittable::Sphere { mat_handle: velvet, center: Point3::new(0.0, 1.0, -1.2), radius: 1.0 });
+
+    let sheen = world.sheen(Texture::solid(0.9, 0.9, 0.9), 0.5);
+    world.hittables.push(Hittable::Sphere { mat_handle: sheen, center: Point3::new(0.0, 1.0, 1.2), radius: 1.0 });
+
+    world
+}
+
+// Blinn-Phong highlights of growing exponents in the back row, and in front the same red plastic with
+// GGX highlights about as spread
+fn blinn_phong_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::solid(0.4, 0.4, 0.4));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let exponents = [10.0, 50.0, 200.0, 1000.0];
+    for (i, exponent) in exponents.iter().enumerate() {
+        let z = 2.4 - 1.6 * i as f64;
+
+        let phong = world.blinn_phong(Texture::solid(0.5, 0.05, 0.05), Color::new(1.0, 1.0, 1.0), *exponent, 0.2);
+        world.hittables.push(Hittable::Sphere { mat_handle: phong, center: Point3::new(-1.0, 0.7, z), radius: 0.7 });
+
+        let alpha = Ggx::from_phong_exponent(*exponent).alpha_x;
+        let diffuse = world.lambertian(Texture::solid(0.5, 0.05, 0.05));
+        let highlight = world.metal(Color::new(1.0, 1.0, 1.0), alpha.sqrt());
+        let microfacet = world.mix(diffuse, highlight, Texture::solid(0.2, 0.2, 0.2));
+        world.hittables.push(Hittable::Sphere { mat_handle: microfacet, center: Point3::new(1.0, 0.7, z), radius: 0.7 });
+    }
+
+    let light = world.diffuse_light(Texture::solid(8.0, 8.0, 8.0));
+    world.add_light(Hittable::Sphere { mat_handle: light, center: Point3::new(6.0, 5.0, 2.0), radius: 1.0 });
+
+    world
+}
+
+// Two balls of fog in front of lights behind them, the left one scattering the same in all directions
+// and the right one forwards, which makes it glow around the light
+fn fog_halo_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::solid(0.3, 0.3, 0.3));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let light = world.diffuse_light(Texture::solid(20.0, 20.0, 20.0));
+    for (z, g) in [(1.5, 0.0), (-1.5, 0.8)].iter() {
+        world.add_light(Hittable::Sphere { mat_handle: light, center: Point3::new(-3.0, 1.2, *z), radius: 0.2 });
+
+        let boundary = Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, 1.2, *z), radius: 1.2 };
+        let phase = world.henyey_greenstein(Texture::solid(0.9, 0.9, 0.9), *g);
+        world.hittables.push(Hittable::new_constant_medium(boundary, 0.6, phase));
+    }
+
+    world
+}
+
+// A cloud read from a voxel grid, lit by a bright light and the sky, scattering a little forwards
+fn voxel_cloud_world() -> Result<World, String> {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::solid(0.4, 0.45, 0.35));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let grid = VoxelGrid::load("volumes/cloud.vol")?;
+    let phase = world.henyey_greenstein(Texture::solid(0.95, 0.95, 0.95), 0.3);
+    let cloud = Hittable::new_voxel_medium(grid, 8.0, phase);
+    world.hittables.push(Hittable::Translate { offset: Vector3::new(0.0, 1.0, 0.0), ptr: Box::new(cloud) });
+
+    let light = world.diffuse_light(Texture::solid(15.0, 14.0, 12.0));
+    world.add_light(Hittable::Sphere { mat_handle: light, center: Point3::new(3.0, 6.0, 4.0), radius: 1.0 });
+
+    Ok(world)
+}
+
+// Triangles: a flat shaded octahedron next to one with normals at its vertices that shade it round,
+// the earth mapped onto a triangle by its texture coordinates, and a triangle of light above
+fn triangles_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let corners = [
+        Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0), Vector3::new(-1.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0)
+    ];
+    let (up, down) = (Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+    let flat = world.lambertian(Texture::solid(0.8, 0.3, 0.2));
+    let smooth = world.lambertian(Texture::solid(0.2, 0.4, 0.8));
+
+    for (center, mat_handle, smooth_shading) in [(Point3::new(-2.4, 1.0, 0.0), flat, false), (Point3::new(2.4, 1.0, 0.0), smooth, true)] {
+        for i in 0..4 {
+            let (a, b) = (corners[i], corners[(i + 1) % 4]);
+            for face in [[a, b, up], [b, a, down]] {
+                let normals = if smooth_shading { Some(face) } else { None };
+                world.hittables.push(Hittable::Triangle {
+                    mat_handle,
+                    vertices: [center + face[0], center + face[1], center + face[2]],
+                    normals,
+                    uvs: [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]
+                });
+            }
+        }
+    }
+
+    let earth = world.lambertian(Texture::load_image("textures/earthmap.jpg"));
+    world.hittables.push(Hittable::Triangle {
+        mat_handle: earth,
+        vertices: [Point3::new(-1.2, 0.2, -1.0), Point3::new(1.2, 0.2, -1.0), Point3::new(0.0, 2.2, -1.0)],
+        normals: None,
+        uvs: [(0.0, 0.0), (1.0, 0.0), (0.5, 1.0)]
+    });
+
+    let light = world.diffuse_light(Texture::solid(6.0, 6.0, 6.0));
+    world.add_light(Hittable::new_triangle(Point3::new(-1.5, 5.0, 2.0), Point3::new(0.0, 5.0, -1.0), Point3::new(1.5, 5.0, 2.0), light));
+
+    world
+}
+
+// A model loaded from an OBJ file with its MTL materials: a plastic ball shaded smooth, a cube with
+// the earth mapped onto each face and a glass gem
+fn obj_world() -> Result<World, String> {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let default_material = world.lambertian(Texture::solid(0.5, 0.5, 0.5));
+    let model = world.load_obj("models/shapes.obj", 1.0, default_material)?;
+    world.hittables.push(model);
+
+    let light = world.diffuse_light(Texture::solid(15.0, 14.0, 12.0));
+    world.add_light(Hittable::Sphere { mat_handle: light, center: Point3::new(3.0, 8.0, 6.0), radius: 1.0 });
+
+    Ok(world)
+}
+
+// Models from 3D printing and scanning: a gear from an STL file shaded flat, and a lumpy ball from a
+// PLY file without normals, which get averaged at its vertices to shade it smooth
+fn mesh_files_world() -> Result<World, String> {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let steel = world.metal(Color::new(0.7, 0.7, 0.75), 0.25);
+    let gear = load_stl("models/gear.stl", 1.2, false, steel)?;
+    world.hittables.push(Hittable::new_instance(&Hittable::new_mesh(gear), 10.0, Vector3::new(-1.5, 0.0, 0.0)));
+
+    let clay = world.lambertian(Texture::solid(0.8, 0.5, 0.3));
+    let blob = load_ply("models/blob.ply", 1.0, true, clay)?;
+    world.hittables.push(Hittable::new_instance(&Hittable::new_mesh(blob), 0.0, Vector3::new(1.5, 1.1, 0.0)));
+
+    let light = world.diffuse_light(Texture::solid(15.0, 14.0, 12.0));
+    world.add_light(Hittable::Sphere { mat_handle: light, center: Point3::new(3.0, 8.0, 6.0), radius: 1.0 });
+
+    Ok(world)
+}
+
+// Disks: the earth mapped around a ring by its polar texture coordinates, a glass ball on a round table
+// top, and a round light facing down over them
+fn disks_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let earth = world.lambertian(Texture::load_image("textures/earthmap.jpg"));
+    world.hittables.push(Hittable::new_annulus(Point3::new(-1.8, 1.6, 0.0), Vector3::new(0.3, 0.0, 1.0), 0.5, 1.5, earth));
+
+    let wood = world.lambertian(Texture::solid(0.6, 0.35, 0.2));
+    world.hittables.push(Hittable::new_disk(Point3::new(1.8, 1.0, 0.0), Vector3::new(0.0, 1.0, 0.0), 1.2, wood));
+    let glass = world.dielectric(1.5);
+    world.hittables.push(Hittable::Sphere { mat_handle: glass, center: Point3::new(1.8, 1.5, 0.0), radius: 0.5 });
+
+    let light = world.diffuse_light(Texture::solid(10.0, 10.0, 10.0));
+    world.add_light(Hittable::new_disk(Point3::new(0.0, 5.0, 2.0), Vector3::new(0.0, -1.0, 0.0), 1.0, light));
+
+    world
+}
+
+// Cylinders: capped columns, an open metal can whose inside shows, a glass rod lying across them and
+// the earth mapped around a drum by its texture coordinates
+fn cylinders_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let stone = world.lambertian(Texture::solid(0.8, 0.78, 0.72));
+    for x in [-3.0, 3.0] {
+        world.hittables.push(Hittable::new_cylinder(Point3::new(x, 0.0, -2.0), Point3::new(x, 3.0, -2.0), 0.35, true, stone));
+    }
+
+    let tin = world.metal(Color::new(0.8, 0.8, 0.85), 0.1);
+    world.hittables.push(Hittable::new_cylinder(Point3::new(-1.3, 0.0, 0.5), Point3::new(-1.3, 1.2, 0.5), 0.5, false, tin));
+
+    let glass = world.dielectric(1.5);
+    world.hittables.push(Hittable::new_cylinder(Point3::new(-2.0, 0.15, 2.0), Point3::new(2.0, 0.15, 1.2), 0.15, true, glass));
+
+    let earth = world.lambertian(Texture::load_image("textures/earthmap.jpg"));
+    world.hittables.push(Hittable::new_cylinder(Point3::new(1.3, 0.0, 0.3), Point3::new(1.3, 1.4, 0.3), 0.7, true, earth));
+
+    let light = world.diffuse_light(Texture::solid(10.0, 10.0, 10.0));
+    world.add_light(Hittable::new_disk(Point3::new(0.0, 6.0, 3.0), Vector3::new(0.0, -1.0, 0.0), 1.2, light));
+
+    world
+}
+
+// Positions and triangles of a torus lying around the y axis, with the tube around it split into the
+// given number of segments and each of them into sides. Vertices are shared all the way around.
+fn torus(major_radius: f64, minor_radius: f64, segments: u32, sides: u32) -> (Vec<Point3>, Vec<[u32; 3]>) {
+    let mut positions = Vec::with_capacity((segments * sides) as usize);
+    for i in 0..segments {
+        let phi = 2.0 * PI * i as f64 / segments as f64;
+        for j in 0..sides {
+            let theta = 2.0 * PI * j as f64 / sides as f64;
+            let r = major_radius + minor_radius * theta.cos();
+            positions.push(Point3::new(r * phi.cos(), minor_radius * theta.sin(), -r * phi.sin()));
+        }
+    }
+
+    let vertex = |i: u32, j: u32| (i % segments) * sides + j % sides;
+    let mut indices = Vec::with_capacity((2 * segments * sides) as usize);
+    for i in 0..segments {
+        for j in 0..sides {
+            indices.push([vertex(i, j), vertex(i + 1, j), vertex(i + 1, j + 1)]);
+            indices.push([vertex(i, j), vertex(i + 1, j + 1), vertex(i, j + 1)]);
+        }
+    }
+
+    (positions, indices)
+}
+
+// Meshes: a coarse torus shaded flat next to a fine one with normals averaged at its vertices, and
+// copies of it standing around them that share its vertex buffers and BVH
+fn meshes_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let flat = world.lambertian(Texture::solid(0.8, 0.3, 0.2));
+    let (positions, indices) = torus(1.0, 0.4, 16, 8);
+    let coarse = Mesh::new(positions, None, None, indices, flat).expect("Torus should be a valid mesh");
+    world.hittables.push(Hittable::new_instance(&Hittable::new_mesh(coarse), 0.0, Vector3::new(-1.6, 0.4, 0.0)));
+
+    let gold = world.metal(Color::new(0.8, 0.6, 0.2), 0.2);
+    let (positions, indices) = torus(1.0, 0.4, 128, 64);
+    let normals = Mesh::smooth_normals(&positions, &indices).expect("Torus should be a valid mesh");
+    let fine = Hittable::new_mesh(Mesh::new(positions, Some(normals), None, indices, gold).expect("Torus should be a valid mesh"));
+    world.hittables.push(Hittable::new_instance(&fine, 0.0, Vector3::new(1.6, 0.4, 0.0)));
+
+    for i in 0..5 {
+        let angle = -60.0 + 30.0 * i as f64;
+        let position = Vector3::new(7.0 * degrees_to_radians(angle).sin(), 0.4, -7.0 * degrees_to_radians(angle).cos());
+        world.hittables.push(Hittable::new_instance(&fine, angle, position));
+    }
+
+    let light = world.diffuse_light(Texture::solid(15.0, 14.0, 12.0));
+    world.add_light(Hittable::Sphere { mat_handle: light, center: Point3::new(3.0, 8.0, 6.0), radius: 1.0 });
+
+    world
+}
+
+// Brushed aluminium under a small light, a floor brushed along x and a sphere brushed from pole to pole
+fn brushed_world() -> World {
+    let mut world = World::new();
+
+    let floor = world.anisotropic_metal(Color::new(0.8, 0.8, 0.8), 0.1, 0.5, Vector3::new(1.0, 0.0, 0.0));
+    world.hittables.push(Hittable::Sphere { mat_handle: floor, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    let aluminium = world.anisotropic_metal(Color::new(0.91, 0.92, 0.92), 0.05, 0.4, Vector3::new(0.0, 1.0, 0.0));
+    world.hittables.push(Hittable::Sphere { mat_handle: aluminium, center: Point3::new(0.0, 1.0, 0.0), radius: 1.0 });
+
+    let light = world.diffuse_light(Texture::solid(20.0, 20.0, 20.0));
+    world.add_light(Hittable::Sphere { mat_handle: light, center: Point3::new(-3.0, 5.0, 3.0), radius: 0.5 });
+
+    world
+}
+
+fn random_world() -> World {
+    let mut world = World::new();
+
+    let ground_material = world.lambertian(Texture::Checker(Color::new(0.2, 0.5, 0.5), Color::new(0.9, 0.9, 0.9)));
+    world.hittables.push(Hittable::Sphere { mat_handle: ground_material, center: Point3::new(0.0, -1000.0, 0.0), radius: 1000.0 });
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat = random_double();
+            let center = Point3::new(a as f64 + 0.9 * random_double(), 0.2, b as f64 + 0.9 * random_double());
+
+            if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                
+                if choose_mat  < 0.8 {
+                    let albedo = Color::random();
+                    let sphere_material = world.lambertian(Texture::SolidColor(albedo));
+                    let center2 = center + Vector3::new(0.0, random_double_range(0.0, 0.5), 0.0);
+                    world.hittables.push(Hittable::MovingSphere { mat_handle: sphere_material, center_0: center, center_1: center2, time_0: 0.0, time_1: 1.0, radius: 0.2 });
+                } else if choose_mat < 0.95 {
+                    let albedo = Color::random_range(0.5, 1.0); 
+                    let roughness = random_double_range(0.0, 0.5);
+                    let sphere_material = world.metal(albedo, roughness);
+                    world.hittables.push(Hittable::Sphere { mat_handle: sphere_material, center, radius: 0.2 });
+                } else {
+                    let sphere_material = world.dielectric(1.5);
+                    world.hittables.push(Hittable::Sphere { mat_handle: sphere_material, center, radius: 0.2 });
+                }
+            }
+        }
+    }
+
+    let material1 = world.dielectric(1.5);
+    world.hittables.push(Hittable::Sphere { mat_handle: material1, center: Point3::new(0.0, 1.0, 0.0), radius: 1.0 });
+
+    let material2 = world.lambertian(Texture::solid(0.4, 0.2, 0.1));
+    world.hittables.push(Hittable::Sphere { mat_handle: material2, center: Point3::new(-4.0, 1.0, 0.0), radius: 1.0 });
+
+    let material3 = world.metal(Color::new(0.7, 0.6, 0.5), 0.0);
+    world.hittables.push(Hittable::Sphere { mat_handle: material3, center: Point3::new(4.0, 1.0, 0.0), radius: 1.0 });
+
+    world
+}
+
+pub struct Scene {
+    pub name: &'static str,
+    pub aspect_ratio: f64,
+    pub image_width: usize,
+    pub samples_per_pixel: usize,
+    pub background: Color,
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub vfov: f64,
+    pub world: Arc<World>
+}
+
+fn random_scene() -> Result<Scene, String> {
+    let world = Arc::new(random_world());
+
+    // Camera
+    let look_from = Point3::new(13.0, 2.0, 3.0);
+    let look_at = Point3::new(0.0, 0.0, 0.0);
+
+    Ok(Scene {
+        name: "random",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from,
+        look_at,
+        vfov: 20.0,
+        world
+    })
+}
+
+// The random scene in the afternoon sun, which casts soft shadows under the spheres
+fn random_sun_scene() -> Result<Scene, String> {
+    let mut world = random_world();
+    world.add_sun(Vector3::new(-1.0, 1.2, 0.6), 2.0, Color::new(2.5, 2.3, 2.0));
+
+    Ok(Scene {
+        name: "random_sun",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.35, 0.45, 0.6),
+        look_from: Point3::new(13.0, 2.0, 3.0),
+        look_at: Point3::new(0.0, 0.0, 0.0),
+        vfov: 20.0,
+        world: Arc::new(world)
+    })
+}
+
+fn two_spheres_scene() -> Result<Scene, String> {
+    let world = Arc::new(two_spheres_world());
+
+    // Camera
+    let look_from = Point3::new(13.0, 2.0, 3.0);
+    let look_at = Point3::new(0.0, 0.0, 0.0);
+
+    Ok(Scene {
+        name: "two_spheres",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from,
+        look_at,
+        vfov: 20.0,
+        world
+    })
+}
+
+fn two_perlin_spheres_scene() -> Result<Scene, String> {
+    let world = Arc::new(two_perlin_spheres_world());
+
+    // Camera
+    let look_from = Point3::new(13.0, 2.0, 3.0);
+    let look_at = Point3::new(0.0, 0.0, 0.0);
+
+    Ok(Scene {
+        name: "perlin",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from,
+        look_at,
+        vfov: 20.0,
+        world
+    })
+}
+
+fn earth_scene() -> Result<Scene, String> {
+    let world = Arc::new(earth_world());
+
+    // Camera
+    let look_from = Point3::new(13.0, 2.0, 3.0);
+    let look_at = Point3::new(0.0, 0.0, 0.0);
+
+    Ok(Scene {
+        name: "earth",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from,
+        look_at,
+        vfov: 20.0,
+        world
+    })
+}
+
+fn simple_light_scene() -> Result<Scene, String> {
+    let world = Arc::new(simple_light_world());
+
+    // Camera
+    let look_from = Point3::new(26.0, 3.0, 6.0);
+    let look_at = Point3::new(0.0, 2.0, 0.0);
+
+    Ok(Scene {
+        name: "simple_light",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.0, 0.0, 0.0),
+        look_from,
+        look_at,
+        vfov: 20.0,
+        world
+    })
+}
+
+fn point_light_scene() -> Result<Scene, String> {
+    let world = Arc::new(point_light_world());
+
+    // Camera
+    let look_from = Point3::new(26.0, 3.0, 6.0);
+    let look_at = Point3::new(0.0, 2.0, 0.0);
+
+    Ok(Scene {
+        name: "point_light",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.0, 0.0, 0.0),
+        look_from,
+        look_at,
+        vfov: 20.0,
+        world
+    })
+}
+
+fn cornell_box_scene() -> Result<Scene, String> {
+    let world = Arc::new(cornell_box_world());
+
+    // Camera
+    let look_from = Point3::new(278.0, 278.0, -800.0);
+    let look_at = Point3::new(278.0, 278.0, 0.0);
+
+    Ok(Scene {
+        name: "cornell",
+        aspect_ratio: 1.0,
+        image_width: 600,
+        samples_per_pixel: 200,
+        background: Color::new(0.0, 0.0, 0.0),
+        look_from,
+        look_at,
+        vfov: 40.0,
+        world
+    })
+}
+
+fn cornell_box_fog_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "cornell_fog",
+        aspect_ratio: 1.0,
+        image_width: 600,
+        samples_per_pixel: 200,
+        background: Color::new(0.0, 0.0, 0.0),
+        look_from: Point3::new(278.0, 278.0, -800.0),
+        look_at: Point3::new(278.0, 278.0, 0.0),
+        vfov: 40.0,
+        world: Arc::new(cornell_box_fog_world())
+    })
+}
+
+fn cornell_box_clay_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "cornell_clay",
+        aspect_ratio: 1.0,
+        image_width: 600,
+        samples_per_pixel: 200,
+        background: Color::new(0.0, 0.0, 0.0),
+        look_from: Point3::new(278.0, 278.0, -800.0),
+        look_at: Point3::new(278.0, 278.0, 0.0),
+        vfov: 40.0,
+        world: Arc::new(cornell_box_clay_world())
+    })
+}
+
+fn cornell_box_spot_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "cornell_spot",
+        aspect_ratio: 1.0,
+        image_width: 600,
+        samples_per_pixel: 200,
+        background: Color::new(0.0, 0.0, 0.0),
+        look_from: Point3::new(278.0, 278.0, -800.0),
+        look_at: Point3::new(278.0, 278.0, 0.0),
+        vfov: 40.0,
+        world: Arc::new(cornell_box_spot_world())
+    })
+}
+
+fn cornell_box_smoke_scene() -> Result<Scene, String> {
+    let world = Arc::new(cornell_box_smoke_world());
+
+    // Camera
+    let look_from = Point3::new(278.0, 278.0, -800.0);
+    let look_at = Point3::new(278.0, 278.0, 0.0);
+
+    Ok(Scene {
+        name: "cornell_smoke",
+        aspect_ratio: 1.0,
+        image_width: 600,
+        samples_per_pixel: 40,
+        background: Color::new(0.0, 0.0, 0.0),
+        look_from,
+        look_at,
+        vfov: 40.0,
+        world
+    })
+}
+
+fn final_scene() -> Result<Scene, String> {
+    let world = Arc::new(final_world());
+
+    // Camera
+    let look_from = Point3::new(478.0, 278.0, -600.0);
+    let look_at = Point3::new(278.0, 278.0, 0.0);
+
+    Ok(Scene {
+        name: "final",
+        aspect_ratio: 1.0,
+        image_width: 800,
+        samples_per_pixel: 2000,
+        background: Color::new(0.0, 0.0, 0.0),
+        look_from,
+        look_at,
+        vfov: 40.0,
+        world
+    })
+}
+
+fn instances_scene() -> Result<Scene, String> {
+    let world = Arc::new(instances_world());
+
+    // Camera
+    let look_from = Point3::new(18.0, 6.0, 14.0);
+    let look_at = Point3::new(-1.0, 0.5, -1.0);
+
+    Ok(Scene {
+        name: "instances",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from,
+        look_at,
+        vfov: 30.0,
+        world
+    })
+}
+
+fn nested_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "nested",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from: Point3::new(0.0, 3.0, 12.0),
+        look_at: Point3::new(0.0, 0.9, 0.0),
+        vfov: 30.0,
+        world: Arc::new(nested_world())
+    })
+}
+
+fn dispersion_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "dispersion",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 400,
+        background: Color::new(0.02, 0.02, 0.03),
+        look_from: Point3::new(0.0, 2.0, 12.0),
+        look_at: Point3::new(0.0, 1.1, 0.0),
+        vfov: 30.0,
+        world: Arc::new(dispersion_world())
+    })
+}
+
+fn glass_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "glass",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from: Point3::new(0.0, 3.0, 12.0),
+        look_at: Point3::new(0.0, 0.8, 0.0),
+        vfov: 30.0,
+        world: Arc::new(glass_world())
+    })
+}
+
+fn mix_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "mix",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from: Point3::new(13.0, 2.0, 3.0),
+        look_at: Point3::new(0.0, 1.0, 0.0),
+        vfov: 25.0,
+        world: Arc::new(mix_world())
+    })
+}
+
+fn clearcoat_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "clearcoat",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from: Point3::new(13.0, 2.0, 3.0),
+        look_at: Point3::new(0.0, 1.0, 0.0),
+        vfov: 20.0,
+        world: Arc::new(clearcoat_world())
+    })
+}
+
+fn textured_metal_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "textured_metal",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from: Point3::new(13.0, 2.0, 3.0),
+        look_at: Point3::new(0.0, 1.5, 0.0),
+        vfov: 25.0,
+        world: Arc::new(textured_metal_world())
+    })
+}
+
+fn texture_maps_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "texture_maps",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from: Point3::new(13.0, 2.0, 3.0),
+        look_at: Point3::new(0.0, 1.5, 0.0),
+        vfov: 25.0,
+        world: Arc::new(texture_maps_world())
+    })
+}
+
+fn screen_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "screen",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.0, 0.0, 0.0),
+        look_from: Point3::new(3.0, 2.5, 7.0),
+        look_at: Point3::new(0.0, 1.0, 0.0),
+        vfov: 35.0,
+        world: Arc::new(screen_world())
+    })
+}
+
+fn metals_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "metals",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from: Point3::new(13.0, 2.0, 3.0),
+        look_at: Point3::new(0.0, 0.8, 0.0),
+        vfov: 25.0,
+        world: Arc::new(metals_world())
+    })
+}
+
+fn thin_film_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "thin_film",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from: Point3::new(13.0, 2.0, 3.0),
+        look_at: Point3::new(0.0, 1.0, 0.0),
+        vfov: 20.0,
+        world: Arc::new(thin_film_world())
+    })
+}
+
+fn velvet_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "velvet",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from: Point3::new(13.0, 2.0, 3.0),
+        look_at: Point3::new(0.0, 1.0, 0.0),
+        vfov: 20.0,
+        world: Arc::new(velvet_world())
+    })
+}
+
+fn brushed_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "brushed",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.05, 0.05, 0.06),
+        look_from: Point3::new(8.0, 3.0, 6.0),
+        look_at: Point3::new(0.0, 0.8, 0.0),
+        vfov: 25.0,
+        world: Arc::new(brushed_world())
+    })
+}
+
+fn blinn_phong_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "blinn_phong",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.05, 0.05, 0.06),
+        look_from: Point3::new(13.0, 4.0, 3.0),
+        look_at: Point3::new(0.0, 0.7, 0.0),
+        vfov: 25.0,
+        world: Arc::new(blinn_phong_world())
+    })
+}
+
+fn fog_halo_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "fog_halo",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 200,
+        background: Color::new(0.0, 0.0, 0.0),
+        look_from: Point3::new(12.0, 2.0, 0.0),
+        look_at: Point3::new(0.0, 1.2, 0.0),
+        vfov: 25.0,
+        world: Arc::new(fog_halo_world())
+    })
+}
+
+fn voxel_cloud_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "voxel_cloud",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 200,
+        background: Color::new(0.5, 0.65, 0.9),
+        look_from: Point3::new(8.0, 2.0, 5.0),
+        look_at: Point3::new(0.0, 1.0, 0.0),
+        vfov: 25.0,
+        world: Arc::new(voxel_cloud_world()?)
+    })
+}
+
+fn triangles_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "triangles",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.1, 0.12, 0.15),
+        look_from: Point3::new(0.0, 3.0, 10.0),
+        look_at: Point3::new(0.0, 1.0, 0.0),
+        vfov: 35.0,
+        world: Arc::new(triangles_world())
+    })
+}
+
+fn obj_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "obj",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from: Point3::new(2.0, 3.5, 9.0),
+        look_at: Point3::new(0.0, 0.8, 0.0),
+        vfov: 35.0,
+        world: Arc::new(obj_world()?)
+    })
+}
+
+fn mesh_files_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "mesh_files",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from: Point3::new(0.0, 4.0, 8.0),
+        look_at: Point3::new(0.0, 0.6, 0.0),
+        vfov: 35.0,
+        world: Arc::new(mesh_files_world()?)
+    })
+}
+
+fn disks_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "disks",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.1, 0.12, 0.15),
+        look_from: Point3::new(0.0, 3.0, 10.0),
+        look_at: Point3::new(0.0, 1.0, 0.0),
+        vfov: 35.0,
+        world: Arc::new(disks_world())
+    })
+}
+
+fn cylinders_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "cylinders",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.1, 0.12, 0.15),
+        look_from: Point3::new(0.0, 3.5, 10.0),
+        look_at: Point3::new(0.0, 1.0, 0.0),
+        vfov: 35.0,
+        world: Arc::new(cylinders_world())
+    })
+}
+
+fn meshes_scene() -> Result<Scene, String> {
+    Ok(Scene {
+        name: "meshes",
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 400,
+        samples_per_pixel: 100,
+        background: Color::new(0.7, 0.8, 1.0),
+        look_from: Point3::new(0.0, 3.0, 8.0),
+        look_at: Point3::new(0.0, 0.4, -1.5),
+        vfov: 40.0,
+        world: Arc::new(meshes_world())
+    })
+}
+
+pub struct SceneEntry {
+    pub name: &'static str,
+    pub build: fn() -> Result<Scene, String>,
+    // The scene loads model or volume files, which the browser build has no file system to read from.
+    // Image textures are fine, they fall back to a flat color there.
+    pub needs_files: bool
+}
+
+impl SceneEntry {
+    pub fn is_available(&self) -> bool {
+        !(cfg!(target_arch = "wasm32") && self.needs_files)
+    }
+}
+
+// Built-in scenes that can be selected by name
+pub static SCENES: [SceneEntry; 35] = [
+    SceneEntry { name: "random", build: random_scene, needs_files: false },
+    SceneEntry { name: "random_sun", build: random_sun_scene, needs_files: false },
+    SceneEntry { name: "two_spheres", build: two_spheres_scene, needs_files: false },
+    SceneEntry { name: "perlin", build: two_perlin_spheres_scene, needs_files: false },
+    SceneEntry { name: "earth", build: earth_scene, needs_files: false },
+    SceneEntry { name: "simple_light", build: simple_light_scene, needs_files: false },
+    SceneEntry { name: "point_light", build: point_light_scene, needs_files: false },
+    SceneEntry { name: "cornell", build: cornell_box_scene, needs_files: false },
+    SceneEntry { name: "cornell_clay", build: cornell_box_clay_scene, needs_files: false },
+    SceneEntry { name: "cornell_fog", build: cornell_box_fog_scene, needs_files: false },
+    SceneEntry { name: "cornell_spot", build: cornell_box_spot_scene, needs_files: false },
+    SceneEntry { name: "cornell_smoke", build: cornell_box_smoke_scene, needs_files: false },
+    SceneEntry { name: "final", build: final_scene, needs_files: false },
+    SceneEntry { name: "instances", build: instances_scene, needs_files: false },
+    SceneEntry { name: "glass", build: glass_scene, needs_files: false },
+    SceneEntry { name: "nested", build: nested_scene, needs_files: false },
+    SceneEntry { name: "dispersion", build: dispersion_scene, needs_files: false },
+    SceneEntry { name: "mix", build: mix_scene, needs_files: false },
+    SceneEntry { name: "clearcoat", build: clearcoat_scene, needs_files: false },
+    SceneEntry { name: "brushed", build: brushed_scene, needs_files: false },
+    SceneEntry { name: "textured_metal", build: textured_metal_scene, needs_files: false },
+    SceneEntry { name: "texture_maps", build: texture_maps_scene, needs_files: false },
+    SceneEntry { name: "screen", build: screen_scene, needs_files: false },
+    SceneEntry { name: "velvet", build: velvet_scene, needs_files: false },
+    SceneEntry { name: "thin_film", build: thin_film_scene, needs_files: false },
+    SceneEntry { name: "metals", build: metals_scene, needs_files: false },
+    SceneEntry { name: "blinn_phong", build: blinn_phong_scene, needs_files: false },
+    SceneEntry { name: "fog_halo", build: fog_halo_scene, needs_files: false },
+    SceneEntry { name: "voxel_cloud", build: voxel_cloud_scene, needs_files: true },
+    SceneEntry { name: "triangles", build: triangles_scene, needs_files: false },
+    SceneEntry { name: "meshes", build: meshes_scene, needs_files: false },
+    SceneEntry { name: "obj", build: obj_scene, needs_files: true },
+    SceneEntry { name: "mesh_files", build: mesh_files_scene, needs_files: true },
+    SceneEntry { name: "disks", build: disks_scene, needs_files: false },
+    SceneEntry { name: "cylinders", build: cylinders_scene, needs_files: false }
+];
+
+// Scenes that cannot be built on this platform are not found
+pub fn find_scene(name: &str) -> Option<&'static SceneEntry> {
+    SCENES.iter().find(|entry| entry.name == name && entry.is_available())
+}
@@ -0,0 +1,367 @@
+use crate::cli::Options;
+use crate::config::RenderConfig;
+use crate::progress::Progress;
+use crate::{develop_image, trace_passes, CameraSetup, FrameState};
+use raytracer::film::Film;
+use raytracer::math::*;
+use raytracer::output::ImageFormat;
+use raytracer::render::{Crop, RenderJob, Tile};
+use raytracer::sampler::frame_seed;
+use raytracer::scene::*;
+use serde::Serialize;
+use std::io::Read;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Instant;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+// Request bodies are small JSON documents, anything bigger is a mistake
+const MAX_BODY_SIZE: u64 = 1 << 20;
+
+// Where a render submitted to the server is at. Renders run one at a time on all threads, in the order
+// they were submitted.
+enum RenderState {
+    Queued,
+    Rendering { frame: Arc<FrameState>, progress: Arc<Progress>, samples_per_pixel: usize, start: Instant },
+    Finished { frame: Arc<FrameState>, samples_per_pixel: usize, render_seconds: f64 },
+    Cancelled,
+    // The render could not be set up or panicked, for example when an asset of the scene could not be loaded
+    Failed { message: String }
+}
+
+struct RenderEntry {
+    id: usize,
+    scene: String,
+    config: RenderConfig,
+    state: Mutex<RenderState>
+}
+
+// Reply to status requests
+#[derive(Serialize)]
+struct RenderStatus {
+    id: usize,
+    scene: String,
+    state: &'static str,
+    progress: f64,
+    samples_done: usize,
+    samples_per_pixel: Option<usize>,
+    render_seconds: Option<f64>,
+    error: Option<String>
+}
+
+impl RenderEntry {
+    fn status(&self) -> RenderStatus {
+        let mut status = RenderStatus {
+            id: self.id,
+            scene: self.scene.clone(),
+            state: "queued",
+            progress: 0.0,
+            samples_done: 0,
+            samples_per_pixel: None,
+            render_seconds: None,
+            error: None
+        };
+
+        match &*self.state.lock().unwrap() {
+            RenderState::Queued => {},
+            RenderState::Rendering { frame, progress, samples_per_pixel, start } => {
+                status.state = "rendering";
+                status.progress = progress.fraction();
+                status.samples_done = frame.samples_done.load(Ordering::SeqCst);
+                status.samples_per_pixel = Some(*samples_per_pixel);
+                status.render_seconds = Some(start.elapsed().as_secs_f64());
+            },
+            RenderState::Finished { frame, samples_per_pixel, render_seconds } => {
+                let samples_done = frame.samples_done.load(Ordering::SeqCst);
+                status.state = if frame.cancelled.load(Ordering::SeqCst) { "cancelled" } else { "done" };
+                status.progress = samples_done as f64 / usize::max(*samples_per_pixel, 1) as f64;
+                status.samples_done = samples_done;
+                status.samples_per_pixel = Some(*samples_per_pixel);
+                status.render_seconds = Some(*render_seconds);
+            },
+            RenderState::Cancelled => status.state = "cancelled",
+            RenderState::Failed { message } => {
+                status.state = "failed";
+                status.error = Some(message.clone());
+            }
+        }
+
+        status
+    }
+
+    // The film of a render that has started, finished or not
+    fn frame(&self) -> Option<Arc<FrameState>> {
+        match &*self.state.lock().unwrap() {
+            RenderState::Rendering { frame, .. } | RenderState::Finished { frame, .. } => Some(Arc::clone(frame)),
+            RenderState::Queued | RenderState::Cancelled | RenderState::Failed { .. } => None
+        }
+    }
+}
+
+// Runs the render service until the process is stopped. Post processing, the pixel filter and the camera
+// options such as --lens come from the command line, while each request picks its scene and render settings.
+//
+//     GET    /scenes               names of the built-in scenes
+//     POST   /renders              queue a render, the body takes the same settings as render.toml as JSON
+//     GET    /renders              status of all renders
+//     GET    /renders/<id>         status of one render
+//     GET    /renders/<id>/image   the image so far, as PNG or in the format given with ?format=
+//     DELETE /renders/<id>         stop a queued or running render, or forget a finished one
+pub fn serve(options: &Options, thread_count: usize) -> Result<(), String> {
+    let server = Server::http(&options.listen).map_err(|err| format!("Could not listen on {}: {}", options.listen, err))?;
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(thread_count).build()
+        .map_err(|err| format!("Could not start the render threads: {}", err))?;
+
+    info!("Listening on http://{} with {} render threads", options.listen, thread_count);
+
+    let renders: Mutex<Vec<Arc<RenderEntry>>> = Mutex::new(Vec::new());
+    let (sender, receiver) = mpsc::channel::<Arc<RenderEntry>>();
+    let mut next_id = 1;
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            for entry in receiver {
+                // A failing render only takes its own entry down, the queue keeps going
+                let message = match panic::catch_unwind(AssertUnwindSafe(|| render(&entry, &pool, options))) {
+                    Ok(Ok(())) => continue,
+                    Ok(Err(message)) => message,
+                    Err(payload) => payload.downcast_ref::<&str>().map(|msg| msg.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| String::from("Unknown error"))
+                };
+                error!("Render {} failed: {}", entry.id, message);
+                *entry.state.lock().unwrap_or_else(|err| err.into_inner()) = RenderState::Failed { message };
+            }
+        });
+
+        for mut request in server.incoming_requests() {
+            let url = request.url().to_string();
+            let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+            let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+            let find = |id: &str| {
+                let id = id.parse::<usize>().ok()?;
+                renders.lock().unwrap().iter().find(|entry| entry.id == id).cloned()
+            };
+
+            let response = match (request.method(), segments.as_slice()) {
+                (Method::Get, ["scenes"]) => {
+                    let names: Vec<&str> = SCENES.iter().map(|entry| entry.name).collect();
+                    json_response(200, &names)
+                },
+                (Method::Post, ["renders"]) => match parse_request(&mut request) {
+                    Ok((scene, config)) => {
+                        let entry = Arc::new(RenderEntry { id: next_id, scene, config, state: Mutex::new(RenderState::Queued) });
+                        next_id += 1;
+
+                        info!("Queued render {} of {}", entry.id, entry.scene);
+                        renders.lock().unwrap().push(Arc::clone(&entry));
+                        let status = entry.status();
+                        match sender.send(entry) {
+                            Ok(()) => json_response(202, &status),
+                            Err(_) => {
+                                renders.lock().unwrap().retain(|other| other.id != status.id);
+                                error_response(500, "The render thread stopped")
+                            }
+                        }
+                    },
+                    Err(msg) => error_response(400, &msg)
+                },
+                (Method::Get, ["renders"]) => {
+                    let statuses: Vec<RenderStatus> = renders.lock().unwrap().iter().map(|entry| entry.status()).collect();
+                    json_response(200, &statuses)
+                },
+                (Method::Get, ["renders", id]) => match find(id) {
+                    Some(entry) => json_response(200, &entry.status()),
+                    None => error_response(404, "No such render")
+                },
+                (Method::Get, ["renders", id, "image"]) => match find(id) {
+                    Some(entry) => image_response(&entry, query, options),
+                    None => error_response(404, "No such render")
+                },
+                (Method::Delete, ["renders", id]) => match find(id) {
+                    Some(entry) => {
+                        let remove = {
+                            let mut state = entry.state.lock().unwrap();
+                            match &*state {
+                                RenderState::Queued => {
+                                    *state = RenderState::Cancelled;
+                                    false
+                                },
+                                RenderState::Rendering { frame, .. } => {
+                                    frame.cancelled.store(true, Ordering::SeqCst);
+                                    false
+                                },
+                                RenderState::Finished { .. } | RenderState::Cancelled | RenderState::Failed { .. } => true
+                            }
+                        };
+
+                        if remove {
+                            renders.lock().unwrap().retain(|other| other.id != entry.id);
+                        }
+                        json_response(200, &entry.status())
+                    },
+                    None => error_response(404, "No such render")
+                },
+                _ => error_response(404, "Not found")
+            };
+
+            if let Err(err) = request.respond(response) {
+                verbose!("Could not send response: {}", err);
+            }
+        }
+
+        // The listener only stops on errors, let the render thread finish its queue
+        drop(sender);
+    });
+
+    Ok(())
+}
+
+// Reads the render settings from the request body. An empty body renders the default scene.
+fn parse_request(request: &mut Request) -> Result<(String, RenderConfig), String> {
+    let mut body = String::new();
+    request.as_reader().take(MAX_BODY_SIZE).read_to_string(&mut body)
+        .map_err(|err| format!("Could not read the request: {}", err))?;
+
+    let config: RenderConfig = if body.trim().is_empty() {
+        RenderConfig::default()
+    } else {
+        serde_json::from_str(&body).map_err(|err| format!("Invalid render settings: {}", err))?
+    };
+
+    let scene = config.scene.clone().unwrap_or_else(|| String::from("final"));
+    if find_scene(&scene).is_none() {
+        return Err(format!("Unknown scene {}", scene));
+    }
+
+    if config.width == Some(0) || config.samples_per_pixel == Some(0) {
+        return Err(String::from("The width and samples per pixel must be positive"));
+    }
+
+    let (shutter_open, shutter_close, _) = config.shutter();
+    if shutter_open > shutter_close {
+        return Err(String::from("The shutter cannot close before it opens"));
+    }
+
+    Ok((scene, config))
+}
+
+fn render(entry: &RenderEntry, pool: &rayon::ThreadPool, options: &Options) -> Result<(), String> {
+    if let RenderState::Cancelled = *entry.state.lock().unwrap() {
+        return Ok(());
+    }
+
+    // Random scenes are laid out the same as from the command line
+    seed_random(options.seed as u64);
+    let mut scene = (find_scene(&entry.scene).expect("Scene was checked when queued").build)()?;
+    let camera_setup = CameraSetup::new(&entry.config, options, &mut scene)?;
+    let max_depth = entry.config.max_depth.unwrap_or(50);
+    let image_width = scene.image_width;
+    // Both eyes of a stereo image have the aspect ratio of the scene
+    let image_aspect_ratio = camera_setup.stereo.map_or(scene.aspect_ratio, |stereo| stereo.image_aspect_ratio(scene.aspect_ratio));
+    let image_height = (scene.image_width as f64 / image_aspect_ratio) as usize;
+    let samples_per_pixel = scene.samples_per_pixel;
+
+    let (shutter_open, shutter_close, _) = entry.config.shutter();
+    Arc::get_mut(&mut scene.world).expect("Scene world is already shared").build_bvh(shutter_open, shutter_close);
+
+    // A still, so the camera moves over the same time as the scene, like from the command line
+    let camera = camera_setup.camera(&scene, shutter_open, shutter_close, |time| time)?;
+    let crop = Crop::full(image_width, image_height);
+    let job = RenderJob {
+        world: scene.world.clone(),
+        camera,
+        background: scene.background,
+        image_width,
+        image_height,
+        crop,
+        max_depth,
+        filter: options.filter,
+        aovs: Vec::new(),
+        clamp: options.clamp,
+        adaptive: None,
+        sample_map: None,
+        sampler: options.sampler,
+        seed: frame_seed(options.seed, 0),
+        spectral: options.spectral
+    };
+
+    let film = Film::new(image_width, image_height, options.filter, &[]);
+    let frame = Arc::new(FrameState::new(film, 0, max_depth));
+    let tile_count = Tile::split_region(image_width, image_height).len();
+    let progress = Arc::new(Progress::new(image_width * image_height * samples_per_pixel, samples_per_pixel, tile_count));
+    let start = Instant::now();
+
+    // Cancelling while the scene was built leaves nothing to render
+    {
+        let mut state = entry.state.lock().unwrap();
+        if let RenderState::Cancelled = *state {
+            return Ok(());
+        }
+        *state = RenderState::Rendering {
+            frame: Arc::clone(&frame),
+            progress: Arc::clone(&progress),
+            samples_per_pixel,
+            start
+        };
+    }
+
+    info!("Rendering {} ({}x{}, {} samples per pixel)", entry.id, image_width, image_height, samples_per_pixel);
+    trace_passes(&job, &frame, 0..samples_per_pixel, None, pool, &progress);
+    progress.finish();
+
+    let render_seconds = start.elapsed().as_secs_f64();
+    if frame.cancelled.load(Ordering::SeqCst) {
+        info!("Stopped render {} after {:.1} seconds", entry.id, render_seconds);
+    } else {
+        info!("Finished render {} in {:.1} seconds", entry.id, render_seconds);
+    }
+    *entry.state.lock().unwrap() = RenderState::Finished { frame, samples_per_pixel, render_seconds };
+
+    Ok(())
+}
+
+fn image_response(entry: &RenderEntry, query: &str, options: &Options) -> Response<std::io::Cursor<Vec<u8>>> {
+    let format = match query.split('&').find_map(|pair| pair.strip_prefix("format=")) {
+        Some(name) => match name.parse::<ImageFormat>() {
+            Ok(format) => format,
+            Err(msg) => return error_response(400, &msg)
+        },
+        None => ImageFormat::Png16
+    };
+
+    let frame = match entry.frame() {
+        Some(frame) => frame,
+        None if entry.status().state == "failed" => return error_response(409, "The render failed"),
+        None => return error_response(409, "The render has not started")
+    };
+
+    let image = develop_image(&frame.film.lock().unwrap(), options, format);
+    let mut data = Vec::new();
+    if let Err(err) = image.write(&mut data, format) {
+        return error_response(500, &format!("Could not encode the image: {}", err));
+    }
+
+    let content_type = match format {
+        ImageFormat::Png16 => "image/png",
+        ImageFormat::PpmAscii | ImageFormat::PpmBinary => "image/x-portable-pixmap",
+        ImageFormat::Pfm => "image/x-portable-floatmap"
+    };
+
+    Response::from_data(data).with_header(header("Content-Type", content_type))
+}
+
+fn json_response<T: Serialize>(status: u16, value: &T) -> Response<std::io::Cursor<Vec<u8>>> {
+    let json = serde_json::to_vec_pretty(value).expect("Failed to serialize response");
+    Response::from_data(json).with_status_code(status).with_header(header("Content-Type", "application/json"))
+}
+
+fn error_response(status: u16, message: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    json_response(status, &serde_json::json!({ "error": message }))
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("Invalid header")
+}
@@ -0,0 +1,169 @@
+use crate::math::*;
+use std::str::FromStr;
+
+// Brings the sky luminance of the model, in thousands of candela per square meter, into the range of
+// the emitters of the built-in scenes
+const SKY_SCALE: f64 = 0.05;
+
+// Irradiance of the sun straight overhead before the atmosphere takes its share, on the same scale
+const SUN_IRRADIANCE: f64 = 4.0;
+
+// The real sun is about half a degree across
+const SUN_ANGULAR_DIAMETER: f64 = 0.53;
+
+// Position of the sun and how hazy the air is, e.g. 30,120,3 on the command line
+#[derive(Copy, Clone, Debug)]
+pub struct SkyParams {
+    pub elevation: f64, // Degrees above the horizon
+    pub azimuth: f64,   // Degrees around the up axis, from -z towards +x
+    pub turbidity: f64  // From 2 for a clear day to about 10 for haze
+}
+
+impl FromStr for SkyParams {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SkyParams, String> {
+        let values = s.split(',')
+            .map(|value| value.trim().parse::<f64>().map_err(|_| format!("Invalid number {}", value)))
+            .collect::<Result<Vec<f64>, String>>()?;
+
+        let params = match values[..] {
+            [elevation, azimuth] => SkyParams { elevation, azimuth, turbidity: 3.0 },
+            [elevation, azimuth, turbidity] => SkyParams { elevation, azimuth, turbidity },
+            _ => return Err(format!("Expected elevation,azimuth[,turbidity] but got {}", s))
+        };
+
+        if !(0.0..=90.0).contains(&params.elevation) {
+            return Err(String::from("The sun elevation must be between 0 and 90 degrees"));
+        }
+
+        if !(1.7..=10.0).contains(&params.turbidity) {
+            return Err(String::from("The turbidity must be between 1.7 and 10"));
+        }
+
+        Ok(params)
+    }
+}
+
+// Daylight sky from Preetham et al., A Practical Analytic Model for Daylight. Luminance and
+// chromaticity across the sky follow the Perez formula, fitted to the sun position and turbidity.
+// The sun itself is not part of it, add it as a light with sun_direction and sun_irradiance.
+pub struct Sky {
+    sun_direction: Vector3,
+    turbidity: f64,
+    coefficients: [[f64; 5]; 3],    // Perez A to E for the luminance Y and the chromaticities x and y
+    zenith: [f64; 3],               // Y, x and y straight up
+    normalization: [f64; 3]         // The Perez formula at the zenith, which the zenith values belong to
+}
+
+impl Sky {
+    pub fn new(params: &SkyParams) -> Sky {
+        let t = params.turbidity;
+        let elevation = degrees_to_radians(params.elevation);
+        let azimuth = degrees_to_radians(params.azimuth);
+        let theta_sun = 0.5 * PI - elevation;
+
+        let coefficients = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529]
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+        let theta = [theta_sun * theta_sun * theta_sun, theta_sun * theta_sun, theta_sun, 1.0];
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let row = |r: usize| m[r].iter().zip(theta.iter()).map(|(a, b)| a * b).sum::<f64>();
+            t * t * row(0) + t * row(1) + row(2)
+        };
+
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886]
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688]
+        ]);
+
+        let mut normalization = [0.0; 3];
+        for (value, coefficients) in normalization.iter_mut().zip(coefficients.iter()) {
+            *value = perez(coefficients, 0.0, theta_sun);
+        }
+
+        Sky {
+            sun_direction: Vector3::new(azimuth.sin() * elevation.cos(), elevation.sin(), -azimuth.cos() * elevation.cos()),
+            turbidity: t,
+            coefficients,
+            zenith: [zenith_luminance, zenith_x, zenith_y],
+            normalization
+        }
+    }
+
+    pub fn sun_direction(&self) -> Vector3 {
+        self.sun_direction
+    }
+
+    pub fn sun_angular_diameter(&self) -> f64 {
+        SUN_ANGULAR_DIAMETER
+    }
+
+    // Sunlight after the way through the atmosphere, which scatters away blue light first and more of
+    // it the lower the sun. Rayleigh scattering by air and aerosol extinction by Ångström's formula,
+    // over the relative air mass of Kasten and Young.
+    pub fn sun_irradiance(&self) -> Color {
+        let elevation = f64::asin(self.sun_direction.y).to_degrees();
+        let air_mass = 1.0 / (elevation.to_radians().sin() + 0.50572 * (elevation + 6.07995).powf(-1.6364));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+
+        // Wavelengths in micrometers standing in for red, green and blue
+        let transmittance = |wavelength: f64| {
+            let rayleigh = 0.008735 * wavelength.powf(-4.08);
+            let aerosol = beta * wavelength.powf(-1.3);
+            (-air_mass * (rayleigh + aerosol)).exp()
+        };
+
+        SUN_IRRADIANCE * Color::new(transmittance(0.68), transmittance(0.55), transmittance(0.44))
+    }
+
+    pub fn radiance(&self, direction: &Vector3) -> Color {
+        let d = Vector3::normalize(direction);
+
+        // The model only covers the sky, below the horizon the ground reflects part of the horizon
+        let (cos_theta, ground) = if d.y > 0.0 { (d.y, 1.0) } else { (0.0, 0.3) };
+        let d = Vector3::normalize(&Vector3::new(d.x, f64::max(d.y, 0.0), d.z));
+
+        // Keep away from the horizon, where the Perez formula blows up
+        let theta = f64::acos(f64::max(cos_theta, 0.01));
+        let gamma = f64::acos(clamp(Vector3::dot(&d, &self.sun_direction), -1.0, 1.0));
+
+        let value = |channel: usize| self.zenith[channel] * perez(&self.coefficients[channel], theta, gamma) / self.normalization[channel];
+        let (luminance, x, y) = (value(0), value(1), value(2));
+
+        ground * SKY_SCALE * xyy_to_rgb(luminance, x, y)
+    }
+}
+
+fn perez(coefficients: &[f64; 5], theta: f64, gamma: f64) -> f64 {
+    let [a, b, c, d, e] = *coefficients;
+    (1.0 + a * (b / theta.cos()).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos() * gamma.cos())
+}
+
+// From luminance and CIE xy chromaticity to linear Rec. 709 RGB
+fn xyy_to_rgb(luminance: f64, x: f64, y: f64) -> Color {
+    if y <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+
+    Color::new(
+        f64::max(3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z, 0.0),
+        f64::max(-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z, 0.0),
+        f64::max(0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z, 0.0)
+    )
+}
@@ -0,0 +1,115 @@
+use crate::math::*;
+
+// Visible range of wavelengths in nanometers
+pub const WAVELENGTH_MIN: f64 = 380.0;
+pub const WAVELENGTH_MAX: f64 = 720.0;
+
+// Wavelengths in nanometers standing in for red, green and blue outside spectral mode
+pub const RGB_WAVELENGTHS: [f64; 3] = [650.0, 550.0, 450.0];
+
+// Wavelengths in nanometers a path carries in spectral mode, stored in the x, y and z of its colors. The
+// first is the hero wavelength picked at random, the other two follow it evenly spread over the visible
+// range. See Wilkie et al., Hero Wavelength Spectral Sampling.
+#[derive(Copy, Clone, Debug)]
+pub struct Wavelengths(pub [f64; 3]);
+
+impl Wavelengths {
+    pub fn sample(r: f64) -> Wavelengths {
+        let range = WAVELENGTH_MAX - WAVELENGTH_MIN;
+        let hero = r * range;
+        let wavelength = |i: usize| WAVELENGTH_MIN + (hero + i as f64 * range / 3.0) % range;
+        Wavelengths([wavelength(0), wavelength(1), wavelength(2)])
+    }
+
+    // The color as a spectrum at the wavelengths, built from white and the primaries and their mixes like
+    // in Smits, An RGB to Spectrum Conversion for Reflectances. Works for emission too, which it scales.
+    pub fn uplift(&self, color: &Color) -> Color {
+        let (r, g, b) = (color.x, color.y, color.z);
+        let mut result = Color::new(0.0, 0.0, 0.0);
+
+        for (i, wavelength) in self.0.iter().enumerate() {
+            let basis = |table: &[f64; 10]| smits_basis(table, *wavelength);
+
+            result[i] = if r <= g && r <= b {
+                r * basis(&WHITE) + if g <= b {
+                    (g - r) * basis(&CYAN) + (b - g) * basis(&BLUE)
+                } else {
+                    (b - r) * basis(&CYAN) + (g - b) * basis(&GREEN)
+                }
+            } else if g <= r && g <= b {
+                g * basis(&WHITE) + if r <= b {
+                    (r - g) * basis(&MAGENTA) + (b - r) * basis(&BLUE)
+                } else {
+                    (b - g) * basis(&MAGENTA) + (r - b) * basis(&RED)
+                }
+            } else {
+                b * basis(&WHITE) + if r <= g {
+                    (r - b) * basis(&YELLOW) + (g - r) * basis(&GREEN)
+                } else {
+                    (g - b) * basis(&YELLOW) + (r - g) * basis(&RED)
+                }
+            };
+        }
+
+        result
+    }
+
+    // Linear sRGB of the radiance at the wavelengths, through CIE XYZ. Balanced so that a spectrum of 1
+    // everywhere comes out white, like the colors it was uplifted from.
+    pub fn to_rgb(&self, radiance: &Color) -> Color {
+        let mut xyz = Color::new(0.0, 0.0, 0.0);
+        for (i, wavelength) in self.0.iter().enumerate() {
+            xyz += radiance[i] * color_matching(*wavelength);
+        }
+        xyz = (WAVELENGTH_MAX - WAVELENGTH_MIN) / 3.0 * xyz;
+
+        let rgb = Color::new(
+            3.2404542 * xyz.x - 1.5371385 * xyz.y - 0.4985314 * xyz.z,
+            -0.9692660 * xyz.x + 1.8760108 * xyz.y + 0.0415560 * xyz.z,
+            0.0556434 * xyz.x - 0.2040259 * xyz.y + 1.0572252 * xyz.z
+        );
+
+        Color::new(rgb.x / WHITE_RGB[0], rgb.y / WHITE_RGB[1], rgb.z / WHITE_RGB[2])
+    }
+}
+
+// Linear sRGB of a spectrum of 1 over the visible range
+const WHITE_RGB: [f64; 3] = [128.359, 101.528, 97.066];
+
+// The CIE 1931 color matching functions, by the piecewise Gaussian fit of Wyman et al., Simple Analytic
+// Approximations to the CIE XYZ Color Matching Functions
+fn color_matching(wavelength: f64) -> Color {
+    let g = |mean: f64, below: f64, above: f64| {
+        let t = (wavelength - mean) / if wavelength < mean { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+
+    Color::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8)
+    )
+}
+
+// Smits' spectra over ten even bins of the visible range, interpolated between the bin centers
+fn smits_basis(table: &[f64; 10], wavelength: f64) -> f64 {
+    let bin = (wavelength - WAVELENGTH_MIN) / (WAVELENGTH_MAX - WAVELENGTH_MIN) * 10.0 - 0.5;
+    if bin <= 0.0 {
+        return table[0];
+    }
+    if bin >= 9.0 {
+        return table[9];
+    }
+
+    let i = bin as usize;
+    let t = bin - i as f64;
+    (1.0 - t) * table[i] + t * table[i + 1]
+}
+
+const WHITE: [f64; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const CYAN: [f64; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const MAGENTA: [f64; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const YELLOW: [f64; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const RED: [f64; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const GREEN: [f64; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const BLUE: [f64; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
@@ -0,0 +1,84 @@
+use crate::math::*;
+use serde::{Serialize, Deserialize};
+
+// Number of rays traced at each bounce, where bounce 0 are the camera rays
+#[derive(Clone)]
+pub struct RayCounter {
+    pub per_bounce: Vec<u64>
+}
+
+impl RayCounter {
+    pub fn new(max_depth: i32) -> RayCounter {
+        RayCounter {
+            per_bounce: vec![0; i32::max(max_depth, 0) as usize]
+        }
+    }
+
+    // Counts a ray with the given remaining depth, as passed to ray_color
+    pub fn count(&mut self, depth: i32) {
+        let bounce = self.per_bounce.len() - depth as usize;
+        self.per_bounce[bounce] += 1;
+    }
+
+    pub fn merge(&mut self, other: &RayCounter) {
+        for (count, other_count) in self.per_bounce.iter_mut().zip(other.per_bounce.iter()) {
+            *count += *other_count;
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.per_bounce.iter().sum()
+    }
+}
+
+// Everything needed to reproduce a render, written next to the image with --params-file
+#[derive(Serialize, Deserialize)]
+pub struct RenderParams {
+    pub scene: String,
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: usize,
+    pub max_depth: i32,
+    pub thread_count: usize,
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub vfov: f64,
+    pub sampler: String,
+    pub importance_map: Option<String>,
+    pub seed: u32,
+    pub frames: Option<[usize; 2]>,
+    pub fps: Option<f64>,
+    pub render_time_seconds: f64,
+    pub git_hash: String,
+    pub version: String
+}
+
+// Performance numbers for a finished render, written as JSON with --stats-file
+#[derive(Serialize)]
+pub struct RenderStats {
+    pub scene: String,
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: usize,
+    pub max_depth: i32,
+    pub thread_count: usize,
+    pub scene_build_seconds: f64,
+    pub render_seconds: f64,
+    pub output_seconds: f64,
+    pub top_level_hittables: usize,
+    pub primitives: usize,
+    pub materials: usize,
+    pub total_rays: u64,
+    pub rays_per_bounce: Vec<u64>,
+    pub total_samples: u64,
+    pub invalid_samples: usize,
+    pub peak_memory_kb: Option<u64>
+}
+
+// Peak resident memory of the process, only available on Linux
+pub fn peak_memory_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+
+    line.split_whitespace().nth(1)?.parse().ok()
+}
@@ -0,0 +1,102 @@
+use crate::math::*;
+use crate::material::*;
+use crate::mesh::*;
+use std::collections::HashMap;
+
+// Loads an STL file, binary or ASCII, as a mesh with its vertices scaled. STL stores every triangle
+// with its own corners, which are welded where they are at exactly the same spot so the triangles share
+// them. The normal stored with each triangle only decides which way it faces, since many programs
+// write the corners in any order. For smooth surfaces like scans the normals at the vertices can be
+// averaged from the triangles around them, otherwise the triangles are shaded flat as printed.
+pub fn load_stl(path: &str, scale: f64, smooth: bool, mat_handle: MaterialHandle) -> Result<Mesh, String> {
+    let bytes = std::fs::read(path).map_err(|err| format!("Could not read model {}: {}", path, err))?;
+
+    // Binary files may start with "solid" too, but only they have the size their triangle count gives
+    let is_binary = bytes.len() >= 84 && {
+        let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+        bytes.len() == 84 + 50 * count
+    };
+
+    let facets = if is_binary {
+        read_binary(&bytes)
+    } else if bytes.starts_with(b"solid") {
+        read_ascii(&String::from_utf8_lossy(&bytes), path)?
+    } else {
+        return Err(format!("{} is not an STL file", path));
+    };
+
+    if facets.is_empty() {
+        return Err(format!("Model {} has no triangles", path));
+    }
+
+    let mut positions = Vec::new();
+    let mut indices = Vec::with_capacity(facets.len());
+    let mut welded: HashMap<[u64; 3], u32> = HashMap::new();
+
+    for (normal, corners) in &facets {
+        let mut triangle = [0; 3];
+        for (index, corner) in triangle.iter_mut().zip(corners) {
+            // Adding 0 turns -0 into 0, which would otherwise not weld with it
+            let key = [(corner.x + 0.0).to_bits(), (corner.y + 0.0).to_bits(), (corner.z + 0.0).to_bits()];
+            *index = *welded.entry(key).or_insert_with(|| {
+                positions.push(scale * *corner);
+                (positions.len() - 1) as u32
+            });
+        }
+
+        let winding = Vector3::cross(&(corners[1] - corners[0]), &(corners[2] - corners[0]));
+        if Vector3::dot(&winding, normal) < 0.0 {
+            triangle.swap(1, 2);
+        }
+        indices.push(triangle);
+    }
+
+    trace!("Loaded model {} with {} triangles over {} vertices", path, indices.len(), positions.len());
+
+    let normals = if smooth { Some(Mesh::smooth_normals(&positions, &indices)?) } else { None };
+    Mesh::new(positions, normals, None, indices, mat_handle)
+}
+
+// An 80 byte header, the number of triangles, and for each its normal and corners as 32 bit floats
+// followed by two bytes of attributes, all little endian
+fn read_binary(bytes: &[u8]) -> Vec<(Vector3, [Point3; 3])> {
+    let float = |offset: usize| f32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]) as f64;
+    let vector = |offset: usize| Vector3::new(float(offset), float(offset + 4), float(offset + 8));
+
+    (84..bytes.len())
+        .step_by(50)
+        .map(|offset| (vector(offset), [vector(offset + 12), vector(offset + 24), vector(offset + 36)]))
+        .collect()
+}
+
+// Triangles of the form: facet normal x y z, outer loop, three lines of vertex x y z, endloop, endfacet
+fn read_ascii(text: &str, path: &str) -> Result<Vec<(Vector3, [Point3; 3])>, String> {
+    let mut facets = Vec::new();
+    let mut normal = Vector3::new(0.0, 0.0, 0.0);
+    let mut corners = Vec::with_capacity(3);
+
+    for (number, line) in text.lines().enumerate() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let vector = |values: &[&str]| match values.iter().map(|value| value.parse::<f64>()).collect::<Result<Vec<f64>, _>>() {
+            Ok(values) if values.len() == 3 => Ok(Vector3::new(values[0], values[1], values[2])),
+            _ => Err(format!("Expected x, y and z in model {} on line {}", path, number + 1))
+        };
+
+        match tokens.first() {
+            Some(&"facet") if tokens.get(1) == Some(&"normal") => {
+                normal = vector(&tokens[2..])?;
+                corners.clear();
+            },
+            Some(&"vertex") => corners.push(vector(&tokens[1..])?),
+            Some(&"endfacet") => {
+                if corners.len() != 3 {
+                    return Err(format!("Expected three vertices in a facet of model {} ending on line {}", path, number + 1));
+                }
+                facets.push((normal, [corners[0], corners[1], corners[2]]));
+            },
+            _ => {}
+        }
+    }
+
+    Ok(facets)
+}
@@ -1,24 +1,76 @@
 use crate::math::*;
 use crate::perlin::Perlin;
+use std::sync::Arc;
 
+// Cloning is cheap, image data is shared between the clones
+#[derive(Clone)]
 pub enum Texture {
     SolidColor(Color),
     Checker(Color, Color),
     Noise(Perlin, f64),
-    Image(usize, usize, usize, Vec<u8>) // width, height, bytes per scanline, data
+    Image(usize, usize, usize, Arc<Vec<u8>>), // width, height, bytes per scanline, data
+    // Brightness of a texture stretched from low to high, for scalar parameters beyond 0 to 1 like an
+    // index of refraction, or to keep a roughness map within a range
+    Range { texture: Box<Texture>, low: f64, high: f64 },
+    // One channel of a texture as a gray, like the roughness in the green and the metalness in the blue of
+    // the metallic-roughness maps of glTF
+    Channel { texture: Box<Texture>, channel: usize },
+    // A texture times a color, like the base color factor of a glTF material over its texture
+    Scaled { texture: Box<Texture>, scale: Color },
+    // Blinn-Phong highlight on top of another texture, for non-PBR previews. The texture lies flat facing
+    // up along y, seen from straight above and lit from light_dir.
+    Phong { diffuse: Box<Texture>, specular_color: Color, shininess: f64, light_dir: Vector3 }
 }
 
 impl Texture {
+    pub fn solid(r: f64, g: f64, b: f64) -> Texture {
+        Texture::SolidColor(Color::new(r, g, b))
+    }
+
+    // Brightness of the texture, for scalar parameters like roughness that a grayscale image can drive
+    pub fn get_gray_value(&self, u: f64, v: f64, p: &Point3) -> f64 {
+        let color = self.get_color_value(u, v, p);
+        (color.x + color.y + color.z) / 3.0
+    }
+
+    // Images are read as RGB whatever channels they have, an alpha channel is dropped
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn load_image(path: &str) -> Texture {
-        let img = match stb_image::image::load(path) {
+        Self::image_texture(stb_image::image::load_with_depth(path, 3, false), path)
+    }
+
+    // An image file that is already in memory, like the ones packed into glTF files. The name is only for
+    // the log.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_image_from_memory(bytes: &[u8], name: &str) -> Texture {
+        Self::image_texture(stb_image::image::load_from_memory_with_depth(bytes, 3, false), name)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn image_texture(result: stb_image::image::LoadResult, name: &str) -> Texture {
+        let img = match result {
             stb_image::image::LoadResult::Error(err) => {
-                panic!(err);
+                panic!("{}", err);
             },
             stb_image::image::LoadResult::ImageU8(image) => image,
             stb_image::image::LoadResult::ImageF32(_) => { panic!("Wrong image format!") }
         };
 
-        Texture::Image(img.width as usize, img.height as usize, 3 * img.width as usize, img.data)
+        trace!("Loaded texture {} ({}x{})", name, img.width, img.height);
+
+        Texture::Image(img.width as usize, img.height as usize, 3 * img.width as usize, Arc::new(img.data))
+    }
+
+    // The browser build has no file system or C image decoder, so image textures become a flat color
+    #[cfg(target_arch = "wasm32")]
+    pub fn load_image(path: &str) -> Texture {
+        error!("Image textures are not supported in the browser, drawing {} as a flat color", path);
+        Texture::solid(0.5, 0.5, 0.5)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn load_image_from_memory(_bytes: &[u8], name: &str) -> Texture {
+        Self::load_image(name)
     }
 }
 
@@ -64,13 +116,62 @@ impl ColorValue for Texture {
 
                 let color_scale = 1.0 / 255.0;
                 let pixel: [f64; 3] = unsafe {
-                    let ptr: *const u8 = data.as_ptr().offset((j * bytes_per_scanline + i * 3) as isize);
+                    let ptr: *const u8 = data.as_ptr().add(j * bytes_per_scanline + i * 3);
 
                     [color_scale * *ptr as f64, color_scale * *ptr.offset(1) as f64, color_scale * *ptr.offset(2) as f64]
                 };
 
                 Color::new(pixel[0], pixel[1], pixel[2])
+            },
+            Texture::Range { texture, low, high } => {
+                let value = low + (high - low) * texture.get_gray_value(u, v, p);
+                Color::new(value, value, value)
+            },
+            Texture::Channel { texture, channel } => {
+                let value = texture.get_color_value(u, v, p)[*channel];
+                Color::new(value, value, value)
+            },
+            Texture::Scaled { texture, scale } => texture.get_color_value(u, v, p) * *scale,
+            Texture::Phong { diffuse, specular_color, shininess, light_dir } => {
+                // Without the normal of the hit the texture stands in for the surface: it spans -1 to 1 across
+                // x and z, with the light a unit away along light_dir from its center, so the light comes in
+                // from a different direction at each texel and the highlight is a spot where it reflects up
+                let up = Vector3::new(0.0, 1.0, 0.0);
+                let to_light = Vector3::normalize(&(Vector3::normalize(light_dir) - Vector3::new(2.0 * u - 1.0, 0.0, 2.0 * v - 1.0)));
+                let half = Vector3::normalize(&(to_light + up));
+                let specular = f64::max(Vector3::dot(&half, &up), 0.0).powf(*shininess);
+
+                diffuse.get_color_value(u, v, p) + specular * *specular_color
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sharp_phong_highlight_is_a_small_spot() {
+        let texture = Texture::Phong {
+            diffuse: Box::new(Texture::solid(0.0, 0.0, 0.0)),
+            specular_color: Color::new(1.0, 1.0, 1.0),
+            shininess: 100.0,
+            light_dir: Vector3::new(0.0, 1.0, 0.0)
+        };
+
+        // Texels at least half as bright as the peak, which is in the middle under the light
+        let n = 200;
+        let p = Point3::new(0.0, 0.0, 0.0);
+        let peak = texture.get_color_value(0.5, 0.5, &p).x;
+        let lit = (0..n * n)
+            .filter(|i| {
+                let (u, v) = ((i % n) as f64 / n as f64, (i / n) as f64 / n as f64);
+                texture.get_color_value(u, v, &p).x >= 0.5 * peak
+            })
+            .count();
+
+        assert!((peak - 1.0).abs() < 1.0e-9);
+        assert!(lit > 0 && (lit as f64) < 0.05 * (n * n) as f64);
+    }
+}
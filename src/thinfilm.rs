@@ -0,0 +1,79 @@
+use crate::math::*;
+use crate::spectrum::*;
+
+// A transparent film a few hundred nanometers thick on top of a surface, like soap or oil. Light
+// reflected off its top and off the surface below interferes, which colors the reflection depending on
+// the thickness and the angle it is seen at.
+#[derive(Copy, Clone, Debug)]
+pub struct ThinFilm {
+    pub thickness: f64, // Nanometers
+    pub ir: f64         // Index of refraction of the film
+}
+
+// What is under the film
+pub enum FilmBase {
+    // A dielectric of this index of refraction, the light coming from a medium of the other index
+    Dielectric { outside_ir: f64, inside_ir: f64 },
+    // Metal reflecting this share of the light, taken to turn the phase around like a perfect conductor
+    Conductor { reflectance: Color }
+}
+
+impl ThinFilm {
+    // Share of the light reflected at this cosine of the angle to the normal, per color channel, from
+    // the sum of all the reflections inside the film (Airy), averaged over both polarizations
+    pub fn reflectance(&self, cos_theta: f64, base: &FilmBase) -> Color {
+        let outside_ir = match base {
+            FilmBase::Dielectric { outside_ir, .. } => *outside_ir,
+            FilmBase::Conductor { .. } => 1.0
+        };
+
+        let cos_1 = clamp(cos_theta, 0.0, 1.0);
+        let sin_1 = (1.0 - cos_1 * cos_1).sqrt();
+
+        // Snell's law down into the film and on into the base
+        let sin_2 = outside_ir * sin_1 / self.ir;
+        if sin_2 >= 1.0 {
+            return Color::new(1.0, 1.0, 1.0);
+        }
+        let cos_2 = (1.0 - sin_2 * sin_2).sqrt();
+
+        // Amplitude reflection coefficients at the top and the bottom of the film for s and p polarized light
+        let top = fresnel_amplitudes(outside_ir, self.ir, cos_1, cos_2);
+        let bottom = |channel: usize| match base {
+            FilmBase::Dielectric { inside_ir, .. } => {
+                let sin_3 = self.ir * sin_2 / inside_ir;
+                if sin_3 >= 1.0 {
+                    return (1.0, 1.0);
+                }
+                fresnel_amplitudes(self.ir, *inside_ir, cos_2, (1.0 - sin_3 * sin_3).sqrt())
+            },
+            FilmBase::Conductor { reflectance } => {
+                let amplitude = -f64::max(reflectance[channel], 0.0).sqrt();
+                (amplitude, amplitude)
+            }
+        };
+
+        let mut result = Color::new(0.0, 0.0, 0.0);
+        for (channel, wavelength) in RGB_WAVELENGTHS.iter().enumerate() {
+            // Phase lag of the light going down through the film and back up
+            let phase = 4.0 * PI * self.ir * self.thickness * cos_2 / wavelength;
+            let (bottom_s, bottom_p) = bottom(channel);
+
+            result[channel] = 0.5 * (airy(top.0, bottom_s, phase) + airy(top.1, bottom_p, phase));
+        }
+
+        result
+    }
+}
+
+fn fresnel_amplitudes(ir_1: f64, ir_2: f64, cos_1: f64, cos_2: f64) -> (f64, f64) {
+    let s = (ir_1 * cos_1 - ir_2 * cos_2) / (ir_1 * cos_1 + ir_2 * cos_2);
+    let p = (ir_2 * cos_1 - ir_1 * cos_2) / (ir_2 * cos_1 + ir_1 * cos_2);
+    (s, p)
+}
+
+// Reflectance of a film with real amplitude coefficients r1 on top and r2 below
+fn airy(r1: f64, r2: f64, phase: f64) -> f64 {
+    let interference = 2.0 * r1 * r2 * phase.cos();
+    clamp((r1 * r1 + r2 * r2 + interference) / (1.0 + r1 * r1 * r2 * r2 + interference), 0.0, 1.0)
+}
@@ -0,0 +1,68 @@
+use crate::math::*;
+use std::str::FromStr;
+
+// Operators mapping unbounded HDR radiance into the [0,1] range before quantization
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ToneMap {
+    Clamp,      // Leave the color alone, anything above 1 clips to white
+    Reinhard,
+    Aces,
+    Filmic
+}
+
+impl ToneMap {
+    pub fn apply(&self, color: Color) -> Color {
+        match self {
+            ToneMap::Clamp => color,
+            ToneMap::Reinhard => Color::new(reinhard(color.x), reinhard(color.y), reinhard(color.z)),
+            ToneMap::Aces => Color::new(aces(color.x), aces(color.y), aces(color.z)),
+            ToneMap::Filmic => {
+                // Scale so that the white point maps to exactly 1
+                const WHITE_POINT: f64 = 11.2;
+                let white_scale = 1.0 / hable(WHITE_POINT);
+                Color::new(hable(color.x), hable(color.y), hable(color.z)) * white_scale
+            }
+        }
+    }
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ToneMap, String> {
+        match s {
+            "none" | "clamp" => Ok(ToneMap::Clamp),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "aces" => Ok(ToneMap::Aces),
+            "filmic" => Ok(ToneMap::Filmic),
+            _ => Err(format!("Unknown tone mapping operator {}", s))
+        }
+    }
+}
+
+fn reinhard(x: f64) -> f64 {
+    x / (1.0 + x)
+}
+
+// Krzysztof Narkowicz's curve fit of the ACES filmic reference transform
+fn aces(x: f64) -> f64 {
+    const A: f64 = 2.51;
+    const B: f64 = 0.03;
+    const C: f64 = 2.43;
+    const D: f64 = 0.59;
+    const E: f64 = 0.14;
+
+    clamp((x * (A * x + B)) / (x * (C * x + D) + E), 0.0, 1.0)
+}
+
+// John Hable's filmic curve from Uncharted 2
+fn hable(x: f64) -> f64 {
+    const A: f64 = 0.15; // Shoulder strength
+    const B: f64 = 0.50; // Linear strength
+    const C: f64 = 0.10; // Linear angle
+    const D: f64 = 0.20; // Toe strength
+    const E: f64 = 0.02; // Toe numerator
+    const F: f64 = 0.30; // Toe denominator
+
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
@@ -0,0 +1,97 @@
+use crate::math::*;
+
+// Densities of a medium sampled on a regular grid of voxels over a box, like a frame of a smoke or
+// cloud simulation. Stored with x changing fastest, then y, then z.
+pub struct VoxelGrid {
+    pub width: usize,
+    pub height: usize,
+    pub depth: usize,
+    pub densities: Vec<f32>,
+    pub minimum: Point3,
+    pub maximum: Point3,
+    pub max_density: f64
+}
+
+impl VoxelGrid {
+    pub fn new(width: usize, height: usize, depth: usize, densities: Vec<f32>, minimum: Point3, maximum: Point3) -> VoxelGrid {
+        assert_eq!(densities.len(), width * height * depth, "Voxel grid needs one density per voxel");
+
+        let max_density = densities.iter().fold(0.0_f32, |max, density| max.max(*density)) as f64;
+        VoxelGrid { width, height, depth, densities, minimum, maximum, max_density }
+    }
+
+    // Reads the binary grid volume format of Mitsuba: "VOL", version 3, the encoding (1 for 32 bit
+    // floats), the resolution along x, y and z, the number of channels and the bounding box, all little
+    // endian, followed by the data. Only the first channel is kept.
+    pub fn load(path: &str) -> Result<VoxelGrid, String> {
+        let bytes = std::fs::read(path).map_err(|err| format!("Could not read voxel grid {}: {}", path, err))?;
+
+        if bytes.len() < 48 || &bytes[0..3] != b"VOL" || bytes[3] != 3 {
+            return Err(format!("{} is not a version 3 VOL file", path));
+        }
+
+        let int = |offset: usize| i32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+        let float = |offset: usize| f32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+
+        if int(4) != 1 {
+            return Err(format!("{} has encoding {}, only 32 bit floats are supported", path, int(4)));
+        }
+
+        let (width, height, depth, channels) = (int(8), int(12), int(16), int(20));
+        if width <= 0 || height <= 0 || depth <= 0 || channels <= 0 {
+            return Err(format!("{} has an empty grid", path));
+        }
+        let (width, height, depth, channels) = (width as usize, height as usize, depth as usize, channels as usize);
+
+        // The header is not to be trusted, a huge resolution must not wrap around to a small size
+        let voxels = width.checked_mul(height).and_then(|area| area.checked_mul(depth));
+        let data_size = voxels.and_then(|voxels| voxels.checked_mul(channels)).and_then(|values| values.checked_mul(4));
+        let (voxels, data_size) = match (voxels, data_size) {
+            (Some(voxels), Some(data_size)) => (voxels, data_size),
+            _ => return Err(format!("{} has a {}x{}x{} grid that is too big", path, width, height, depth))
+        };
+        if bytes.len() - 48 < data_size {
+            return Err(format!("{} is shorter than its {}x{}x{} grid", path, width, height, depth));
+        }
+
+        let minimum = Point3::new(float(24) as f64, float(28) as f64, float(32) as f64);
+        let maximum = Point3::new(float(36) as f64, float(40) as f64, float(44) as f64);
+        let densities = (0..voxels).map(|i| float(48 + 4 * i * channels).max(0.0)).collect();
+
+        trace!("Loaded voxel grid {} ({}x{}x{})", path, width, height, depth);
+
+        Ok(VoxelGrid::new(width, height, depth, densities, minimum, maximum))
+    }
+
+    // Density at a point, trilinearly filtered between the centers of the voxels and zero outside the box
+    pub fn density(&self, p: &Point3) -> f64 {
+        let extent = self.maximum - self.minimum;
+        let local = |axis: usize, resolution: usize| (p[axis] - self.minimum[axis]) / extent[axis] * resolution as f64 - 0.5;
+
+        let (x, y, z) = (local(0, self.width), local(1, self.height), local(2, self.depth));
+        if x < -0.5 || y < -0.5 || z < -0.5 || x > self.width as f64 - 0.5 || y > self.height as f64 - 0.5 || z > self.depth as f64 - 0.5 {
+            return 0.0;
+        }
+
+        let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+        let (fx, fy, fz) = (x - x0, y - y0, z - z0);
+
+        let voxel = |i: f64, j: f64, k: f64| {
+            let i = clamp(i, 0.0, (self.width - 1) as f64) as usize;
+            let j = clamp(j, 0.0, (self.height - 1) as f64) as usize;
+            let k = clamp(k, 0.0, (self.depth - 1) as f64) as usize;
+            self.densities[(k * self.height + j) * self.width + i] as f64
+        };
+
+        let mut density = 0.0;
+        for (dk, wz) in [(0.0, 1.0 - fz), (1.0, fz)].iter() {
+            for (dj, wy) in [(0.0, 1.0 - fy), (1.0, fy)].iter() {
+                for (di, wx) in [(0.0, 1.0 - fx), (1.0, fx)].iter() {
+                    density += wx * wy * wz * voxel(x0 + di, y0 + dj, z0 + dk);
+                }
+            }
+        }
+
+        density
+    }
+}
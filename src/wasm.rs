@@ -0,0 +1,115 @@
+use crate::camera::*;
+use crate::film::*;
+use crate::filter::*;
+use crate::math::*;
+use crate::output::*;
+use crate::render::*;
+use crate::sampler::*;
+use crate::scene::*;
+use crate::stats::*;
+use crate::tonemap::*;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use wasm_bindgen::prelude::*;
+
+// Progressive renderer driven from JavaScript. There are no threads in the browser, so the page
+// traces a few tiles at a time between animation frames and draws the image in progress.
+#[wasm_bindgen]
+pub struct WebRenderer {
+    job: RenderJob,
+    film: Film,
+    tiles: Vec<Tile>,
+    next_tile: usize,
+    samples_done: usize,
+    ray_counter: RayCounter,
+    invalid_samples: AtomicUsize
+}
+
+#[wasm_bindgen]
+impl WebRenderer {
+    #[wasm_bindgen(constructor)]
+    pub fn new(scene_name: &str, image_width: usize, max_depth: i32) -> Result<WebRenderer, JsValue> {
+        let entry = find_scene(scene_name).ok_or_else(|| JsValue::from_str(&format!("Unknown scene {}", scene_name)))?;
+        // Random scenes are laid out the same as from the command line with the default seed
+        seed_random(0);
+        let mut scene = (entry.build)().map_err(|msg| JsValue::from_str(&msg))?;
+        let image_height = (image_width as f64 / scene.aspect_ratio) as usize;
+
+        // Same camera defaults as the command line
+        let vup = Vector3::new(0.0, 1.0, 0.0);
+        let camera = Camera::new(&scene.look_from, &scene.look_at, &vup, scene.vfov, scene.aspect_ratio, 0.1, 10.0, 0.0, 1.0);
+        Arc::get_mut(&mut scene.world).expect("Scene world is already shared").build_bvh(camera.time_0, camera.time_1);
+
+        let filter = Filter::Box(0.5);
+        let job = RenderJob {
+            world: scene.world,
+            camera,
+            background: scene.background,
+            image_width,
+            image_height,
+            crop: Crop::full(image_width, image_height),
+            max_depth,
+            filter,
+            aovs: Vec::new(),
+            clamp: SampleClamp { direct: None, indirect: None },
+            adaptive: None,
+            sample_map: None,
+            sampler: SamplerKind::Sobol,
+            seed: frame_seed(0, 0),
+            spectral: false
+        };
+
+        Ok(WebRenderer {
+            job,
+            film: Film::new(image_width, image_height, filter, &[]),
+            tiles: Tile::split_region(image_width, image_height),
+            next_tile: 0,
+            samples_done: 0,
+            ray_counter: RayCounter::new(max_depth),
+            invalid_samples: AtomicUsize::new(0)
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.job.image_width
+    }
+
+    pub fn height(&self) -> usize {
+        self.job.image_height
+    }
+
+    // Samples per pixel of every finished pass
+    pub fn samples_done(&self) -> usize {
+        self.samples_done
+    }
+
+    // Traces one sample per pixel of the next tile, starting another pass after the last tile
+    pub fn render_tile(&mut self) {
+        let tile = self.tiles[self.next_tile];
+        let (tile_film, x0, y0) = self.job.render_tile(&tile, self.samples_done, &mut self.ray_counter, &self.invalid_samples);
+        self.film.add_tile(&tile_film, x0, y0);
+
+        self.next_tile += 1;
+        if self.next_tile == self.tiles.len() {
+            self.next_tile = 0;
+            self.samples_done += 1;
+        }
+    }
+
+    // The image so far as RGBA bytes for canvas image data, developed like the command line does
+    pub fn pixels(&self, tone_map: &str, exposure: f64) -> Result<Vec<u8>, JsValue> {
+        let tone_map: ToneMap = tone_map.parse().map_err(|_| JsValue::from_str(&format!("Unknown tone mapping {}", tone_map)))?;
+
+        let mut image = self.film.develop();
+        image.expose(exposure);
+        image.tone_map(tone_map);
+        image.apply_transfer(Transfer::Gamma(2.0));
+
+        Ok(image.to_rgba8())
+    }
+}
+
+#[wasm_bindgen]
+pub fn scene_names() -> Vec<JsValue> {
+    SCENES.iter().filter(|entry| entry.is_available()).map(|entry| JsValue::from_str(entry.name)).collect()
+}
@@ -0,0 +1,60 @@
+use raytracer::output::Image;
+
+// Window showing the image while it converges, for the --window option. Only available when built
+// with the window feature, otherwise opening it fails with a hint on how to get it.
+#[cfg(feature = "window")]
+pub struct PreviewWindow {
+    window: minifb::Window,
+    buffer: Vec<u32>
+}
+
+#[cfg(feature = "window")]
+impl PreviewWindow {
+    pub fn open(title: &str, width: usize, height: usize) -> Result<PreviewWindow, String> {
+        let window = minifb::Window::new(title, width, height, minifb::WindowOptions::default())
+            .map_err(|err| format!("Could not open a window: {}", err))?;
+
+        Ok(PreviewWindow { window, buffer: vec![0; width * height] })
+    }
+
+    // Closing the window or pressing escape stops the render
+    pub fn is_open(&self) -> bool {
+        self.window.is_open() && !self.window.is_key_down(minifb::Key::Escape)
+    }
+
+    pub fn show(&mut self, image: &Image) {
+        for (pixel, rgba) in self.buffer.iter_mut().zip(image.to_rgba8().chunks(4)) {
+            *pixel = (rgba[0] as u32) << 16 | (rgba[1] as u32) << 8 | rgba[2] as u32;
+        }
+
+        if let Err(err) = self.window.update_with_buffer(&self.buffer, image.width, image.height) {
+            error!("Could not update the window: {}", err);
+        }
+    }
+
+    // Keeps the final image up until the window is closed
+    pub fn wait_until_closed(&mut self) {
+        self.window.set_target_fps(30);
+        while self.is_open() {
+            self.window.update();
+        }
+    }
+}
+
+#[cfg(not(feature = "window"))]
+pub struct PreviewWindow;
+
+#[cfg(not(feature = "window"))]
+impl PreviewWindow {
+    pub fn open(_title: &str, _width: usize, _height: usize) -> Result<PreviewWindow, String> {
+        Err(String::from("This build has no window support, rebuild with --features window"))
+    }
+
+    pub fn is_open(&self) -> bool {
+        false
+    }
+
+    pub fn show(&mut self, _image: &Image) {}
+
+    pub fn wait_until_closed(&mut self) {}
+}
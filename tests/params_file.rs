@@ -0,0 +1,29 @@
+use raytracer::stats::RenderParams;
+use std::process::Command;
+
+#[test]
+fn params_file_round_trips() {
+    let directory = std::env::temp_dir().join(format!("raytracer-params-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let image = directory.join("render.png");
+    let params_file = directory.join("render.json");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_raytracer"))
+        .args(["two_spheres", "--width", "32", "--samples", "2", "--seed", "7", "--quiet"])
+        .arg("--output").arg(&image)
+        .arg("--params-file").arg(&params_file)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let json = std::fs::read_to_string(&params_file).unwrap();
+    let params: RenderParams = serde_json::from_str(&json).unwrap();
+    assert_eq!(params.scene, "two_spheres");
+    assert_eq!((params.width, params.samples_per_pixel, params.seed), (32, 2, 7));
+
+    // Written back out it is the same document, so nothing was lost on the way in
+    let written: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(serde_json::to_value(&params).unwrap(), written);
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
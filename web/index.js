@@ -0,0 +1,64 @@
+import init, { WebRenderer, scene_names } from './pkg/raytracer.js';
+
+// Time spent tracing tiles before handing the thread back to the browser to draw
+const FRAME_BUDGET_MS = 30;
+
+const canvas = document.getElementById('canvas');
+const context = canvas.getContext('2d');
+const sceneSelect = document.getElementById('scene');
+const widthInput = document.getElementById('width');
+const samplesInput = document.getElementById('samples');
+const toneMapSelect = document.getElementById('tonemap');
+const status = document.getElementById('status');
+
+let renderer = null;
+let targetSamples = 0;
+let startTime = 0;
+
+function start() {
+    if (renderer) {
+        renderer.free();
+    }
+
+    renderer = new WebRenderer(sceneSelect.value, parseInt(widthInput.value), 50);
+    targetSamples = parseInt(samplesInput.value);
+    startTime = performance.now();
+
+    canvas.width = renderer.width();
+    canvas.height = renderer.height();
+}
+
+function draw() {
+    const pixels = new Uint8ClampedArray(renderer.pixels(toneMapSelect.value, 0.0));
+    context.putImageData(new ImageData(pixels, renderer.width(), renderer.height()), 0, 0);
+}
+
+function step() {
+    if (renderer && renderer.samples_done() < targetSamples) {
+        const frameStart = performance.now();
+        while (renderer.samples_done() < targetSamples && performance.now() - frameStart < FRAME_BUDGET_MS) {
+            renderer.render_tile();
+        }
+
+        draw();
+
+        const seconds = (performance.now() - startTime) / 1000;
+        status.textContent = `${renderer.samples_done()}/${targetSamples} samples per pixel, ${seconds.toFixed(1)} s`;
+    }
+
+    requestAnimationFrame(step);
+}
+
+await init();
+
+for (const name of scene_names()) {
+    const option = document.createElement('option');
+    option.textContent = name;
+    sceneSelect.appendChild(option);
+}
+
+document.getElementById('render').addEventListener('click', start);
+toneMapSelect.addEventListener('change', () => renderer && draw());
+
+start();
+requestAnimationFrame(step);